@@ -0,0 +1,104 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Build Script
+//! Gives the C library a soname carrying its ABI version, so that programs
+//! linked against one version are not handed an incompatible one.
+//!
+//! Also records what `--version` reports about the build: the git commit,
+//! the build date (from `SOURCE_DATE_EPOCH` if set, so that reproducible
+//! builds agree), the secp256k1 crate's version and the enabled features.
+//!
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Must match `ffi::PACTHASH_ABI_VERSION`
+const ABI_VERSION: u32 = 1;
+
+/// The commit being built, marked dirty if the tree has changes
+fn git_commit(dir: &str) -> String {
+    let run = |args: &[&str]| {
+        Command::new("git").arg("-C").arg(dir).args(args).output().ok()
+               .and_then(|out| if out.status.success() { String::from_utf8(out.stdout).ok() } else { None })
+    };
+    match run(&["rev-parse", "HEAD"]) {
+        Some(commit) => {
+            let dirty = match run(&["status", "--porcelain", "--untracked-files=no"]) {
+                Some(status) => !status.trim().is_empty(),
+                None => false
+            };
+            format!("{}{}", commit.trim(), if dirty { "-dirty" } else { "" })
+        }
+        None => "unknown".to_owned()
+    }
+}
+
+/// A Unix time as a UTC date, YYYY-MM-DD
+fn format_date(secs: u64) -> String {
+    // Days since 1970-01-01 to a civil date, per Howard Hinnant's algorithm
+    let z = secs / 86400 + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// The version of a package in Cargo.lock
+fn locked_version(dir: &str, package: &str) -> String {
+    let mut lock = String::new();
+    if File::open(Path::new(dir).join("Cargo.lock")).and_then(|mut f| f.read_to_string(&mut lock)).is_err() {
+        return "unknown".to_owned();
+    }
+    let name = format!("name = \"{}\"", package);
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() == name {
+            if let Some(version) = lines.next() {
+                return version.trim().trim_left_matches("version = ").trim_matches('"').to_owned();
+            }
+        }
+    }
+    "unknown".to_owned()
+}
+
+fn main() {
+    if env::var("CARGO_CFG_TARGET_OS").map(|os| os == "linux").unwrap_or(false) {
+        println!("cargo:rustc-cdylib-link-arg=-Wl,-soname,libpacthash.so.{}", ABI_VERSION);
+    }
+
+    let dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let date = match env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.parse::<u64>().ok()) {
+        Some(secs) => secs,
+        None => SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    };
+    let mut features: Vec<String> = env::vars().map(|(key, _)| key)
+                                               .filter(|key| key.starts_with("CARGO_FEATURE_"))
+                                               .map(|key| key["CARGO_FEATURE_".len()..].to_lowercase().replace('_', "-"))
+                                               .collect();
+    features.sort();
+    println!("cargo:rustc-env=PACTHASH_GIT_COMMIT={}", git_commit(&dir));
+    println!("cargo:rustc-env=PACTHASH_BUILD_DATE={}", format_date(date));
+    println!("cargo:rustc-env=PACTHASH_SECP256K1_VERSION={}", locked_version(&dir, "secp256k1"));
+    println!("cargo:rustc-env=PACTHASH_FEATURES={}", if features.is_empty() { "none".to_owned() } else { features.join(",") });
+}
@@ -0,0 +1,40 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Assembling any string, and disassembling any bytes, neither of which
+//! may panic. What disassembles must assemble to a script with the same
+//! disassembly; not always the same bytes, since pushes are assembled in
+//! their shortest form.
+//!
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate bitcoin;
+extern crate pacthash;
+
+use bitcoin::blockdata::script::Script;
+use pacthash::asm;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        let _ = asm::assemble(s);
+        let _ = asm::Encoding::Hex.decode(s);
+        let _ = asm::Encoding::Base64.decode(s);
+    }
+    let script = Script::from(data.to_vec());
+    let text = asm::disassemble(&script);
+    if let Ok(reassembled) = asm::assemble(&text) {
+        assert_eq!(asm::disassemble(&reassembled), text);
+    }
+});
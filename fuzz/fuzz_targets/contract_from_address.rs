@@ -0,0 +1,35 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Parsing any string as the address a contract pays to, which must not
+//! panic whichever network it is expected on
+//!
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate pacthash;
+
+use pacthash::chain;
+use pacthash::contract::{Contract, Nonce, NONCE_LEN};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < NONCE_LEN + 1 {
+        return;
+    }
+    let chain = chain::ALL[data[0] as usize % chain::ALL.len()];
+    let nonce = Nonce::from(&data[1..NONCE_LEN + 1]);
+    if let Ok(s) = std::str::from_utf8(&data[NONCE_LEN + 1..]) {
+        let _ = Contract::from_p2sh_base58_str(s, nonce, chain);
+    }
+});
@@ -0,0 +1,35 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Making a text contract of any string, which must not panic, and must
+//! hold the string's bytes when it succeeds
+//!
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate pacthash;
+
+use pacthash::contract::{Contract, Nonce, NONCE_LEN};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < NONCE_LEN {
+        return;
+    }
+    let nonce = Nonce::from(&data[..NONCE_LEN]);
+    if let Ok(s) = std::str::from_utf8(&data[NONCE_LEN..]) {
+        if let Ok(contract) = Contract::from_ascii_str(s, nonce) {
+            assert_eq!(contract.data(), s.as_bytes());
+        }
+    }
+});
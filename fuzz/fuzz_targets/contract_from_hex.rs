@@ -0,0 +1,31 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Parsing any string as a hex contract, which must not panic, and must
+//! give back the same hex when it succeeds
+//!
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate pacthash;
+
+use pacthash::contract::Contract;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        if let Ok(contract) = Contract::from_hex(s) {
+            assert_eq!(format!("{:x}", contract), s.to_lowercase());
+        }
+    }
+});
@@ -0,0 +1,49 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Contracts of every type, and their nonces, must serialize back to the
+//! bytes they were parsed from
+//!
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate pacthash;
+extern crate rustc_serialize as serialize;
+
+use pacthash::contract::{Contract, Nonce, CONTRACT_LEN, NONCE_LEN};
+use serialize::hex::ToHex;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < CONTRACT_LEN - 3 {
+        return;
+    }
+    let ty: &[u8] = match data[0] % 3 {
+        0 => b"TEXT",
+        1 => b"P2PH",
+        _ => b"P2SH"
+    };
+    let mut bytes = ty.to_vec();
+    bytes.extend(&data[1..CONTRACT_LEN - 3]);
+    let hex = bytes.to_hex();
+
+    let contract = Contract::from_hex(&hex).unwrap();
+    assert_eq!(&contract.to_bytes()[..], &bytes[..]);
+    assert_eq!(contract.serialize(), bytes);
+    assert_eq!(format!("{:x}", contract), hex);
+    assert_eq!(contract.type_name().as_bytes(), ty);
+
+    let nonce = Nonce::from_contract(&contract);
+    assert_eq!(&nonce[..], &bytes[4..4 + NONCE_LEN]);
+    assert_eq!(&Nonce::from_hex(&format!("{:x}", nonce)).unwrap()[..], &nonce[..]);
+});
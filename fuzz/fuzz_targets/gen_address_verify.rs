@@ -0,0 +1,70 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! The address the server derives for a key must be the P2PKH address of
+//! the key tweaked directly, and must pass the server's verification
+//!
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate bitcoin;
+extern crate pacthash;
+extern crate rustc_serialize as serialize;
+extern crate secp256k1;
+
+use bitcoin::network::constants::Network;
+use bitcoin::util::address::Address;
+use bitcoin::util::base58::ToBase58;
+use bitcoin::util::contracthash::tweak_keys;
+use pacthash::context;
+use pacthash::contract::{Contract, Nonce, NONCE_LEN};
+use pacthash::server::Server;
+use secp256k1::key::{PublicKey, SecretKey};
+use serialize::hex::ToHex;
+use serialize::json::Json;
+
+use std::collections::BTreeMap;
+
+fn params(fields: &[(&str, &str)]) -> Json {
+    let mut ret = BTreeMap::new();
+    for &(name, value) in fields {
+        ret.insert(name.to_owned(), Json::String(value.to_owned()));
+    }
+    Json::Object(ret)
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 32 + NONCE_LEN + 20 {
+        return;
+    }
+    let secp = context::secp();
+    let secret = match SecretKey::from_slice(secp, &data[..32]) {
+        Ok(secret) => secret,
+        Err(_) => { return; }
+    };
+    let key = PublicKey::from_secret_key(secp, &secret).unwrap();
+    let nonce = Nonce::from(&data[32..32 + NONCE_LEN]);
+    let text: String = data[32 + NONCE_LEN..32 + NONCE_LEN + 20].iter().map(|&b| (b'a' + b % 26) as char).collect();
+    let contract = format!("{:x}", Contract::from_ascii_str(&text, nonce).unwrap());
+    let key_hex = key.serialize_vec(secp, true)[..].to_hex();
+
+    let expected = Address::from_key(Network::Bitcoin, &tweak_keys(secp, &[key], &Contract::from_hex(&contract).unwrap().to_bytes()[..]).unwrap()[0], true);
+    let server = Server::new(Network::Bitcoin, None);
+    let result = server.call("gen_address", &params(&[("contract", &contract), ("key", &key_hex)])).unwrap();
+    let address = result.find("p2pkh").and_then(|a| a.as_string()).unwrap().to_owned();
+    assert_eq!(address, expected.to_base58check());
+
+    let verified = server.call("verify", &params(&[("contract", &contract), ("key", &key_hex), ("address", &address)])).unwrap();
+    assert_eq!(verified.find("valid").and_then(|v| v.as_boolean()), Some(true));
+});
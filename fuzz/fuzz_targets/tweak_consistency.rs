@@ -0,0 +1,48 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Tweaking a secret key must give the secret key of the tweaked public
+//! key, so that what -c mode spends is what -g mode paid
+//!
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate bitcoin;
+extern crate pacthash;
+extern crate secp256k1;
+
+use bitcoin::util::contracthash::tweak_keys;
+use pacthash::context;
+use secp256k1::key::{PublicKey, SecretKey};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 32 {
+        return;
+    }
+    let secp = context::secp();
+    let (key, contract) = data.split_at(32);
+    let secret = match SecretKey::from_slice(secp, key) {
+        Ok(secret) => secret,
+        Err(_) => { return; }
+    };
+    let public = PublicKey::from_secret_key(secp, &secret).unwrap();
+    match (context::tweak_secret_key(&secret, contract), tweak_keys(secp, &[public], contract)) {
+        (Ok(tweaked_secret), Ok(tweaked_public)) => {
+            assert_eq!(PublicKey::from_secret_key(secp, &tweaked_secret).unwrap(), tweaked_public[0]);
+        }
+        // A tweak can only fail (with negligible probability) for both
+        (Err(_), Err(_)) => {}
+        (secret, public) => panic!("secret tweak gave {:?} but public tweak gave {:?}", secret.is_ok(), public.is_ok())
+    }
+});
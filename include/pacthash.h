@@ -0,0 +1,83 @@
+#ifndef PACTHASH_H
+#define PACTHASH_H
+
+/* Generated with cbindgen from src/ffi.rs; do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The version of this interface, bumped when a function changes or goes.
+// build.rs gives it to the soname too.
+#define PACTHASH_ABI_VERSION 1
+
+// An opaque handle to what calls share: the network and a secp context
+typedef struct PacthashContext PacthashContext;
+
+// The version of this interface
+uint32_t pacthash_abi_version(void);
+
+// Create a context for mainnet, or testnet if `testnet` is nonzero. It is
+// freed with `pacthash_context_free`, and may be shared between threads.
+PacthashContext *pacthash_context_new(int testnet);
+
+// Free a context
+void pacthash_context_free(PacthashContext *ctx);
+
+// The tweaked script and its addresses, given `script` (hex or asm), or
+// the tweaked key and its P2PKH address, given `key`, as JSON
+char *pacthash_ctx_gen_address(const PacthashContext *ctx,
+                               const char *contract,
+                               const char *script,
+                               const char *key);
+
+// The tweaked private key of a WIF key, with its public key and P2PKH
+// address, as JSON
+char *pacthash_ctx_tweak_privkey(const PacthashContext *ctx, const char *contract, const char *privkey);
+
+// Whether an address commits to a contract under `script` or `key`:
+// 1 if it does, 0 if it does not, and -1 on error
+int pacthash_ctx_verify(const PacthashContext *ctx,
+                        const char *contract,
+                        const char *script,
+                        const char *key,
+                        const char *address);
+
+// The parts of a contract, as JSON
+char *pacthash_ctx_decode_contract(const PacthashContext *ctx, const char *contract);
+
+// Build a contract from `ascii` text, or the P2SH or P2PH `address` it
+// pays to, and a hex nonce, giving its parts (and hex) as JSON
+char *pacthash_ctx_make_contract(const PacthashContext *ctx,
+                                 const char *ascii,
+                                 const char *address,
+                                 const char *nonce);
+
+// `pacthash_ctx_gen_address` with a context made for the call
+char *pacthash_gen_address(const char *contract, const char *script, const char *key, int testnet);
+
+// `pacthash_ctx_tweak_privkey` with a context made for the call
+char *pacthash_tweak_privkey(const char *contract, const char *privkey, int testnet);
+
+// `pacthash_ctx_verify` with a context made for the call
+int pacthash_verify(const char *contract,
+                    const char *script,
+                    const char *key,
+                    const char *address,
+                    int testnet);
+
+// `pacthash_ctx_decode_contract` with a context made for the call
+char *pacthash_decode_contract(const char *contract);
+
+// `pacthash_ctx_make_contract` with a context made for the call
+char *pacthash_make_contract(const char *ascii, const char *address, const char *nonce, int testnet);
+
+// The error of the last call on this thread to fail, or NULL. The string
+// belongs to the library and lasts until the next call fails.
+const char *pacthash_last_error(void);
+
+// Free a string returned by this library
+void pacthash_string_free(char *s);
+
+#endif /* PACTHASH_H */
@@ -0,0 +1,86 @@
+# PactHash
+# Written in 2015 by
+#   Andrew Poelstra <apoelstra@wpsoftware.net>
+#
+# To the extent possible under law, the author(s) have dedicated all
+# copyright and related and neighboring rights to this software to
+# the public domain worldwide. This software is distributed without
+# any warranty.
+#
+# You should have received a copy of the CC0 Public Domain Dedication
+# along with this software.
+# If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+#
+
+"""Tests of the pacthash Python module. Run them after `maturin develop`
+with `python -m unittest discover python/tests`."""
+
+import unittest
+
+import pacthash
+
+NONCE = "00112233445566778899aabbccddeeff"
+# The generator, and the WIF of its private key, 1
+KEY = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+PRIVKEY = "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn"
+
+
+class TestNonce(unittest.TestCase):
+    def test_round_trip(self):
+        nonce = pacthash.Nonce(NONCE)
+        self.assertEqual(nonce.hex(), NONCE)
+        self.assertEqual(pacthash.Nonce(nonce.data), nonce)
+        self.assertEqual(repr(nonce), "Nonce('%s')" % NONCE)
+
+    def test_random(self):
+        nonce = pacthash.Nonce.random()
+        self.assertEqual(len(nonce.data), pacthash.NONCE_LEN)
+        self.assertNotEqual(nonce, pacthash.Nonce.random())
+
+    def test_bad_length(self):
+        self.assertRaises(ValueError, pacthash.Nonce, "0011")
+
+
+class TestContract(unittest.TestCase):
+    def test_round_trip(self):
+        contract = pacthash.Contract.from_ascii("AContractOfTwentyChr", pacthash.Nonce(NONCE))
+        self.assertEqual(contract.type, "TEXT")
+        self.assertEqual(contract.text, "AContractOfTwentyChr")
+        self.assertEqual(contract.data, b"AContractOfTwentyChr")
+        self.assertEqual(contract.nonce, pacthash.Nonce(NONCE))
+        self.assertEqual(pacthash.Contract(contract.hex()), contract)
+
+    def test_bad_contract(self):
+        self.assertRaises(ValueError, pacthash.Contract, "00")
+        self.assertRaises(ValueError, pacthash.Contract.from_ascii, "too short", pacthash.Nonce(NONCE))
+
+
+class TestTweak(unittest.TestCase):
+    def setUp(self):
+        self.contract = pacthash.Contract.from_ascii("AContractOfTwentyChr", pacthash.Nonce(NONCE))
+
+    def test_key_round_trip(self):
+        # The tweaked private key is that of the tweaked public key
+        address = pacthash.gen_address(self.contract, key=KEY)
+        privkey = pacthash.tweak_privkey(self.contract, PRIVKEY)
+        self.assertEqual(privkey["pubkey"], address["key"])
+        self.assertEqual(privkey["p2pkh"], address["p2pkh"])
+        self.assertTrue(pacthash.verify(self.contract, address["p2pkh"], key=KEY))
+        other = pacthash.Contract.from_ascii("AnotherContractOf20.", pacthash.Nonce(NONCE))
+        self.assertFalse(pacthash.verify(other, address["p2pkh"], key=KEY))
+
+    def test_script(self):
+        # Scripts may be given as asm or hex
+        address = pacthash.gen_address(self.contract, script="1 %s 1 OP_CHECKMULTISIG" % KEY)
+        self.assertEqual(pacthash.gen_address(self.contract, script="5121%s51ae" % KEY), address)
+        self.assertEqual(address["keys"], [{"original": KEY, "tweaked": pacthash.gen_address(self.contract, key=KEY)["key"]}])
+        for ty in ["p2sh", "p2wsh", "p2sh_p2wsh"]:
+            self.assertTrue(pacthash.verify(self.contract, address[ty], script="5121%s51ae" % KEY))
+
+    def test_errors(self):
+        self.assertRaises(pacthash.Error, pacthash.tweak_privkey, self.contract, "not a key")
+        self.assertRaises(pacthash.Error, pacthash.gen_address, self.contract)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,230 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Script Assembly
+//! Conversion of scripts to and from a human-readable form, and to
+//! and from the text encodings used by other tools
+//!
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::{Builder, Instruction, Script};
+use serialize::base64::{self, FromBase64, ToBase64};
+use serialize::hex::{self, FromHex, ToHex};
+
+use std::fmt::Write;
+
+/// Script assembly or decoding error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// Token was not an opcode, a number or hex data
+    BadToken(String),
+    /// Hex decoding error
+    Hex(hex::FromHexError),
+    /// Base64 decoding error
+    Base64(base64::FromBase64Error)
+}
+
+/// Text encoding of a serialized script
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    /// Hexadecimal
+    Hex,
+    /// Standard base64, as used by some RPC interfaces and PSBT tooling
+    Base64
+}
+
+impl Encoding {
+    /// Interpret the name of an encoding as given on the command line
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name {
+            "hex" => Some(Encoding::Hex),
+            "base64" => Some(Encoding::Base64),
+            _ => None
+        }
+    }
+
+    /// The name of the encoding as given on the command line
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Encoding::Hex => "hex",
+            Encoding::Base64 => "base64"
+        }
+    }
+
+    /// Encode a script
+    pub fn encode(&self, script: &Script) -> String {
+        match *self {
+            Encoding::Hex => script[..].to_hex(),
+            Encoding::Base64 => script[..].to_base64(base64::STANDARD)
+        }
+    }
+
+    /// Decode a script
+    pub fn decode(&self, s: &str) -> Result<Script, Error> {
+        let data = match *self {
+            Encoding::Hex => try!(s.from_hex().map_err(Error::Hex)),
+            Encoding::Base64 => try!(s.from_base64().map_err(Error::Base64))
+        };
+        Ok(Script::from(data))
+    }
+}
+
+/// Adds instructions to push an integer onto a script. This should be
+/// `Builder::push_int`, but that maps 1-16 to the wrong opcodes.
+pub fn push_int(builder: Builder, n: i64) -> Builder {
+    if n == -1 {
+        builder.push_opcode(opcodes::All::OP_PUSHNUM_NEG1)
+    } else if n >= 1 && n <= 16 {
+        builder.push_opcode(opcodes::All::from(opcodes::All::OP_PUSHNUM_1 as u8 + n as u8 - 1))
+    } else if n == 0 {
+        builder.push_opcode(opcodes::All::OP_PUSHBYTES_0)
+    } else {
+        builder.push_scriptint(n)
+    }
+}
+
+/// Returns the human-readable name of a non-push opcode
+fn opcode_name(op: opcodes::All) -> String {
+    match op.classify() {
+        opcodes::Class::PushNum(-1) => "OP_1NEGATE".to_owned(),
+        opcodes::Class::PushNum(n) => format!("OP_{}", n),
+        // Use the soft-forked names rather than the old NOPs
+        _ if op == opcodes::All::OP_NOP2 => "OP_CHECKLOCKTIMEVERIFY".to_owned(),
+        _ if op == opcodes::All::OP_NOP3 => "OP_CHECKSEQUENCEVERIFY".to_owned(),
+        _ => format!("{:?}", op)
+    }
+}
+
+/// Disassemble a script, e.g. `OP_2 <pk> <pk> <pk> OP_3 OP_CHECKMULTISIG`,
+/// with pushes written as hex, in a form that `assemble` will read back
+pub fn disassemble(script: &Script) -> String {
+    let mut ret = String::new();
+    for instruction in script.into_iter() {
+        if !ret.is_empty() {
+            ret.push(' ');
+        }
+        match instruction {
+            Instruction::PushBytes(data) => {
+                if data.is_empty() {
+                    ret.push_str("OP_0");
+                } else {
+                    let mut hex = String::with_capacity(2 * data.len());
+                    for ch in data {
+                        write!(hex, "{:02x}", *ch).unwrap();
+                    }
+                    // Bracket short all-digit pushes so `assemble` does not read them as numbers
+                    if hex.len() <= 10 && hex.chars().all(|c| c.is_digit(10)) {
+                        write!(ret, "<{}>", hex).unwrap();
+                    } else {
+                        ret.push_str(&hex);
+                    }
+                }
+            }
+            Instruction::Op(op) => ret.push_str(&opcode_name(op)),
+            Instruction::Error(_) => {
+                ret.push_str("[error]");
+                break;
+            }
+        }
+    }
+    ret
+}
+
+/// Looks up a non-push opcode by name, with or without the `OP_` prefix
+fn opcode_from_name(name: &str) -> Option<opcodes::All> {
+    let name = if name.starts_with("OP_") { &name[3..] } else { name };
+    match name {
+        "0" | "FALSE" => return Some(opcodes::All::OP_PUSHBYTES_0),
+        "TRUE" => return Some(opcodes::All::OP_PUSHNUM_1),
+        _ => {}
+    }
+    for byte in 0..256 {
+        let op = opcodes::All::from(byte as u8);
+        if let opcodes::Class::PushBytes(_) = op.classify() {
+            continue;
+        }
+        if &opcode_name(op)[3..] == name {
+            return Some(op);
+        }
+    }
+    None
+}
+
+/// Assemble a script from whitespace-separated tokens. Each token is an
+/// opcode name (`OP_CHECKMULTISIG` or `CHECKMULTISIG`), a decimal number
+/// (`2`), or hex data to be pushed (`02ab...`, optionally as `<02ab...>`).
+pub fn assemble(s: &str) -> Result<Script, Error> {
+    let mut ret = Builder::new();
+    for token in s.split_whitespace() {
+        if let Some(op) = opcode_from_name(token) {
+            ret = ret.push_opcode(op);
+            continue;
+        }
+
+        let digits = if token.starts_with('-') { &token[1..] } else { token };
+        if !digits.is_empty() && digits.len() <= 10 && digits.chars().all(|c| c.is_digit(10)) {
+            match token.parse::<i64>() {
+                Ok(n) => { ret = push_int(ret, n); continue; }
+                Err(_) => { return Err(Error::BadToken(token.to_owned())); }
+            }
+        }
+
+        let hex = if token.starts_with('<') && token.ends_with('>') && token.len() > 1 {
+            &token[1..token.len() - 1]
+        } else {
+            token
+        };
+        match hex.from_hex() {
+            Ok(ref data) if !data.is_empty() => { ret = ret.push_slice(data); }
+            _ => { return Err(Error::BadToken(token.to_owned())); }
+        }
+    }
+    Ok(ret.into_script())
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::FromHex;
+
+    use super::*;
+
+    #[test]
+    fn small_numbers() {
+        let script = assemble("-1 0 1 16 17").unwrap();
+        assert_eq!(script[..].to_vec(), "4f0051600111".from_hex().unwrap());
+        assert_eq!(disassemble(&script), "OP_1NEGATE OP_0 OP_1 OP_16 <11>");
+        assert_eq!(assemble(&disassemble(&script)).unwrap(), script);
+    }
+
+    #[test]
+    fn multisig_round_trip() {
+        let asm = "OP_2 02f1ff1f5ba2115a07d69e5bcd3d4b3da2bad5babca3a3bbb68eaa4d5c8e3634e3 \
+                   03b3bd772f6fe2e9cbf5b5cbaf963a2fb5f61b2f2c6d751f7bef1398796bd2c9b6 OP_2 OP_CHECKMULTISIG";
+        let script = assemble(asm).unwrap();
+        assert_eq!(script[..][0], 0x52);
+        assert_eq!(script[..][69], 0x52);
+        assert_eq!(disassemble(&script), asm.split_whitespace().collect::<Vec<_>>().join(" "));
+        assert_eq!(assemble(&disassemble(&script)).unwrap(), script);
+    }
+
+    #[test]
+    fn bad_tokens() {
+        match assemble("OP_2 OP_FROB") {
+            Err(Error::BadToken(ref token)) if token == "OP_FROB" => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+        assert!(assemble("<>").is_err());
+        assert!(assemble("abc").is_err());
+    }
+}
@@ -0,0 +1,339 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+
+//! # Atomic Writes
+//! Writing files so that a crash never leaves half of one behind. The new
+//! contents go to a temporary file beside the old (the path with `.tmp`
+//! appended), which is synced and then renamed over it, so a reader sees
+//! either the old file or the new one. A crash can at worst leave the
+//! temporary file lying about, which `fsck` cleans up.
+//!
+//! `fsck` also repairs files written before writes were atomic, which a
+//! crash could leave with their last record cut short: such a record is
+//! cut off, and the original kept beside the file with `.torn` appended.
+//!
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// What `fsck` found wrong with a file, and did about it
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Finding {
+    /// Neither the file nor a temporary file for it exists
+    Missing,
+    /// A complete temporary file was left by a write which never replaced
+    /// the file; it has been moved into place
+    Restored,
+    /// A temporary file was left by a write which never finished; the file
+    /// itself is intact, and the temporary file has been removed
+    StaleTemp,
+    /// The file ended with a partial record, which has been cut off
+    Torn(String),
+    /// The file is damaged in a way which cutting off its end does not fix
+    Unrepairable
+}
+
+/// The temporary file a write to some path goes through
+pub fn temp_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".tmp")
+}
+
+/// A path with a suffix appended to its file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Write a file atomically. Unless `replace` is set this fails if the file
+/// already exists, and if `owner_only` is set a file being created is made
+/// readable only by its owner.
+pub fn write(path: &Path, data: &[u8], replace: bool, owner_only: bool) -> io::Result<()> {
+    write_with(path, replace, owner_only, |file| file.write_all(data))
+}
+
+/// Write a file atomically as `write` does, with what `fill` writes to it,
+/// so that the data need not all be held at once
+pub fn write_with<F: FnOnce(&mut File) -> io::Result<()>>(path: &Path, replace: bool, owner_only: bool, fill: F) -> io::Result<()> {
+    let temp = temp_path(path);
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    if owner_only {
+        set_owner_only(&mut options);
+    }
+    {
+        let mut file = try!(options.open(&temp));
+        let filled = fill(&mut file).and_then(|_| file.sync_all());
+        if let Err(e) = filled {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+    }
+    let moved = if replace {
+        fs::rename(&temp, path)
+    } else {
+        // Unlike a rename, a link will not clobber a file created meanwhile
+        fs::hard_link(&temp, path).and_then(|_| fs::remove_file(&temp))
+    };
+    if let Err(e) = moved {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    sync_dir(path)
+}
+
+/// Sync the directory holding a file, so that a rename into it is durable
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if dir != Path::new("") => File::open(dir).and_then(|d| d.sync_all()),
+        _ => File::open(".").and_then(|d| d.sync_all())
+    }
+}
+
+/// Directories cannot be opened to be synced outside of Unix
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Restrict a file being created to its owner
+#[cfg(unix)]
+fn set_owner_only(options: &mut OpenOptions) {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600);
+}
+
+/// Restrict a file being created to its owner
+#[cfg(not(unix))]
+fn set_owner_only(_: &mut OpenOptions) {}
+
+/// Read a file as text, or `None` if it does not exist
+fn read_if_exists(path: &Path) -> io::Result<Option<String>> {
+    let mut text = String::new();
+    match File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+        Ok(_) => Ok(Some(text)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e)
+    }
+}
+
+/// Check a file of records, one per line, for the damage a crash can
+/// leave, repairing it unless `dry_run` is set. `valid` says whether some
+/// text is a complete, well-formed file of the right kind.
+pub fn fsck<F: Fn(&str) -> bool>(path: &Path, valid: F, dry_run: bool) -> io::Result<Vec<Finding>> {
+    let mut ret = vec![];
+    let temp = temp_path(path);
+    let temp_text = try!(read_if_exists(&temp));
+    let mut text = try!(read_if_exists(path));
+
+    match (text.is_some(), temp_text) {
+        (_, None) => {}
+        (true, Some(_)) => {
+            ret.push(Finding::StaleTemp);
+            if !dry_run {
+                try!(fs::remove_file(&temp));
+            }
+        }
+        (false, Some(temp_text)) => {
+            // Only the rename was missed if the temporary file is whole
+            if valid(&temp_text) {
+                ret.push(Finding::Restored);
+                if !dry_run {
+                    try!(fs::rename(&temp, path));
+                    try!(sync_dir(path));
+                }
+                text = Some(temp_text);
+            } else {
+                ret.push(Finding::StaleTemp);
+                if !dry_run {
+                    try!(fs::remove_file(&temp));
+                }
+            }
+        }
+    }
+
+    let text = match text {
+        Some(text) => text,
+        None => {
+            if ret.is_empty() {
+                ret.push(Finding::Missing);
+            }
+            return Ok(ret);
+        }
+    };
+    if valid(&text) && (text.is_empty() || text.ends_with('\n')) {
+        return Ok(ret);
+    }
+    // A torn write leaves a prefix of the file, ending partway through a line
+    let kept = match text.trim_right_matches('\n').rfind('\n') {
+        Some(end) => &text[..end + 1],
+        None => ""
+    };
+    if kept.is_empty() || !valid(kept) {
+        // A file which is not made of lines may simply lack a final newline
+        if !valid(&text) {
+            ret.push(Finding::Unrepairable);
+        }
+        return Ok(ret);
+    }
+    ret.push(Finding::Torn(text[kept.len()..].trim_right_matches('\n').to_owned()));
+    if !dry_run {
+        try!(write(&with_suffix(path, ".torn"), text.as_bytes(), true, true));
+        try!(write(path, kept.as_bytes(), true, true));
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::*;
+
+    const WHOLE: &'static str = "record 1\nrecord 2\n";
+    const TORN: &'static str = "record 1\nrecord 2\nreco";
+
+    /// A file of "record" lines, each ending with a newline
+    fn valid(text: &str) -> bool {
+        text.is_empty() || (text.ends_with('\n') && text.lines().all(|line| line.starts_with("record ")))
+    }
+
+    /// A fresh directory for a test, and the path of a file in it
+    fn setup(name: &str) -> (PathBuf, PathBuf) {
+        let dir = env::temp_dir().join(format!("pacthash-atomic-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("file");
+        (dir, path)
+    }
+
+    fn create(path: &Path, text: &str) {
+        File::create(path).and_then(|mut f| f.write_all(text.as_bytes())).unwrap();
+    }
+
+    fn contents(path: &Path) -> Option<String> {
+        read_if_exists(path).unwrap()
+    }
+
+    #[test]
+    fn writes() {
+        let (dir, path) = setup("write");
+        write(&path, WHOLE.as_bytes(), false, true).unwrap();
+        assert_eq!(contents(&path).unwrap(), WHOLE);
+        assert!(contents(&temp_path(&path)).is_none());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        // Without `replace` an existing file is kept, and no temporary file
+        // is left behind
+        let e = write(&path, b"record 3\n", false, true).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(contents(&path).unwrap(), WHOLE);
+        assert!(contents(&temp_path(&path)).is_none());
+
+        write(&path, b"record 3\n", true, true).unwrap();
+        assert_eq!(contents(&path).unwrap(), "record 3\n");
+        assert!(contents(&temp_path(&path)).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn whole_temp_restored() {
+        for &dry_run in &[true, false] {
+            let (dir, path) = setup(if dry_run { "restore-dry" } else { "restore" });
+            create(&temp_path(&path), WHOLE);
+            assert_eq!(fsck(&path, valid, dry_run).unwrap(), vec![Finding::Restored]);
+            if dry_run {
+                assert!(contents(&path).is_none());
+                assert_eq!(contents(&temp_path(&path)).unwrap(), WHOLE);
+            } else {
+                assert_eq!(contents(&path).unwrap(), WHOLE);
+                assert!(contents(&temp_path(&path)).is_none());
+                assert_eq!(fsck(&path, valid, false).unwrap(), vec![]);
+            }
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn torn_temp_discarded() {
+        for &dry_run in &[true, false] {
+            let (dir, path) = setup(if dry_run { "torn-temp-dry" } else { "torn-temp" });
+            create(&temp_path(&path), TORN);
+            assert_eq!(fsck(&path, valid, dry_run).unwrap(), vec![Finding::StaleTemp]);
+            assert!(contents(&path).is_none());
+            if dry_run {
+                assert_eq!(contents(&temp_path(&path)).unwrap(), TORN);
+            } else {
+                assert!(contents(&temp_path(&path)).is_none());
+                assert_eq!(fsck(&path, valid, false).unwrap(), vec![Finding::Missing]);
+            }
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn stale_temp_beside_file() {
+        for &dry_run in &[true, false] {
+            let (dir, path) = setup(if dry_run { "stale-dry" } else { "stale" });
+            create(&path, WHOLE);
+            // Even a whole temporary file loses to the file it never replaced
+            create(&temp_path(&path), "record 3\n");
+            assert_eq!(fsck(&path, valid, dry_run).unwrap(), vec![Finding::StaleTemp]);
+            assert_eq!(contents(&path).unwrap(), WHOLE);
+            assert_eq!(contents(&temp_path(&path)).is_some(), dry_run);
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn torn_file() {
+        for &dry_run in &[true, false] {
+            let (dir, path) = setup(if dry_run { "torn-dry" } else { "torn" });
+            create(&path, TORN);
+            assert_eq!(fsck(&path, valid, dry_run).unwrap(), vec![Finding::Torn("reco".to_owned())]);
+            let torn = dir.join("file.torn");
+            if dry_run {
+                assert_eq!(contents(&path).unwrap(), TORN);
+                assert!(contents(&torn).is_none());
+            } else {
+                assert_eq!(contents(&path).unwrap(), "record 1\nrecord 2\n");
+                assert_eq!(contents(&torn).unwrap(), TORN);
+                assert_eq!(fsck(&path, valid, false).unwrap(), vec![]);
+            }
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn unrepairable_and_missing() {
+        let (dir, path) = setup("damaged");
+        assert_eq!(fsck(&path, valid, false).unwrap(), vec![Finding::Missing]);
+        create(&path, "garbage\nrecord 1\n");
+        assert_eq!(fsck(&path, valid, false).unwrap(), vec![Finding::Unrepairable]);
+        assert_eq!(contents(&path).unwrap(), "garbage\nrecord 1\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,553 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # BIP32
+//! Hierarchical deterministic key derivation, enough to derive the keys
+//! that contracts are committed to from extended keys
+//!
+
+use bitcoin::network::constants::Network;
+use bitcoin::util::base58::{self, FromBase58, ToBase58};
+use bitcoin::util::hash::Hash160;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha512;
+use secp256k1::{self, Secp256k1};
+use secp256k1::key::{PublicKey, SecretKey};
+use serialize::hex::{FromHex, ToHex};
+
+use std::fmt;
+
+/// Child numbers at or above this are hardened
+pub const HARDENED: u32 = 0x80000000;
+
+/// Version bytes of a mainnet xpub
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xB2, 0x1E];
+/// Version bytes of a testnet tpub
+const TPUB_VERSION: [u8; 4] = [0x04, 0x35, 0x87, 0xCF];
+/// Version bytes of a mainnet xprv
+const XPRV_VERSION: [u8; 4] = [0x04, 0x88, 0xAD, 0xE4];
+/// Version bytes of a testnet tprv
+const TPRV_VERSION: [u8; 4] = [0x04, 0x35, 0x83, 0x94];
+
+/// BIP32-related error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// Secp256k1 error, e.g. a derived key was out of range
+    Secp(secp256k1::Error),
+    /// Tried to derive a hardened child from a public key
+    CannotDeriveHardenedChild(u32),
+    /// Derivation path could not be parsed
+    BadPath(String)
+}
+
+/// Whether a derivation path ends in a wildcard, and of which sort
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Wildcard {
+    /// The path is a single key
+    None,
+    /// The path ends in `*`, to be replaced by a child index
+    Normal,
+    /// The path ends in `*h`, to be replaced by a hardened child index
+    Hardened
+}
+
+/// A derivation path, written as in output descriptors, e.g. `m/45h/0/2/17`
+/// or `m/45h/0/*`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DerivationPath {
+    /// Child numbers to derive, in order
+    pub children: Vec<u32>,
+    /// Wildcard following the children
+    pub wildcard: Wildcard
+}
+
+/// Whether a path component is marked hardened, returning it without the mark
+fn strip_hardened(component: &str) -> (&str, bool) {
+    if component.ends_with('\'') || component.ends_with('h') || component.ends_with('H') {
+        (&component[..component.len() - 1], true)
+    } else {
+        (component, false)
+    }
+}
+
+/// Format child numbers as a path, e.g. `m/45h/0/2`
+pub fn format_path(children: &[u32]) -> String {
+    let mut ret = "m".to_owned();
+    for &n in children {
+        if n >= HARDENED {
+            ret.push_str(&format!("/{}h", n - HARDENED));
+        } else {
+            ret.push_str(&format!("/{}", n));
+        }
+    }
+    ret
+}
+
+impl DerivationPath {
+    /// Parse a path; hardened components may be marked with `h`, `H` or `'`
+    pub fn parse(s: &str) -> Result<DerivationPath, Error> {
+        let mut components = s.split('/').peekable();
+        if components.next() != Some("m") {
+            return Err(Error::BadPath(s.to_owned()));
+        }
+        let mut ret = DerivationPath { children: vec![], wildcard: Wildcard::None };
+        while let Some(component) = components.next() {
+            let (number, hardened) = strip_hardened(component);
+            if number == "*" {
+                // Wildcards are only allowed at the end
+                if components.peek().is_some() {
+                    return Err(Error::BadPath(s.to_owned()));
+                }
+                ret.wildcard = if hardened { Wildcard::Hardened } else { Wildcard::Normal };
+                break;
+            }
+            match number.parse::<u32>() {
+                Ok(n) if n < HARDENED => ret.children.push(if hardened { n + HARDENED } else { n }),
+                _ => { return Err(Error::BadPath(s.to_owned())); }
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Whether any step of the path, including the wildcard, is hardened
+    pub fn is_hardened(&self) -> bool {
+        self.wildcard == Wildcard::Hardened || self.children.iter().any(|&n| n >= HARDENED)
+    }
+
+    /// The child numbers of the path with `index` substituted for the
+    /// wildcard, if there is one
+    pub fn at_index(&self, index: u32) -> Vec<u32> {
+        let mut ret = self.children.clone();
+        match self.wildcard {
+            Wildcard::None => {}
+            Wildcard::Normal => ret.push(index),
+            Wildcard::Hardened => ret.push(index + HARDENED)
+        }
+        ret
+    }
+}
+
+/// The origin of a key: the fingerprint of the master key it was derived
+/// from, and its path from there, written `[d34db33f/45h/0]` as in output
+/// descriptors
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct KeySource {
+    /// Fingerprint of the master key
+    pub fingerprint: [u8; 4],
+    /// Child numbers from the master key
+    pub path: Vec<u32>
+}
+
+impl KeySource {
+    /// Parse an origin without its brackets, e.g. `d34db33f/45h/0`
+    pub fn parse(s: &str) -> Result<KeySource, Error> {
+        let (fingerprint, path) = match s.find('/') {
+            Some(slash) => (&s[..slash], &s[slash..]),
+            None => (s, "")
+        };
+        let mut ret = KeySource { fingerprint: [0; 4], path: vec![] };
+        match fingerprint.from_hex() {
+            Ok(ref data) if data.len() == 4 => ret.fingerprint.clone_from_slice(data),
+            _ => { return Err(Error::BadPath(s.to_owned())); }
+        }
+        let path = try!(DerivationPath::parse(&format!("m{}", path)));
+        if path.wildcard != Wildcard::None {
+            return Err(Error::BadPath(s.to_owned()));
+        }
+        ret.path = path.children;
+        Ok(ret)
+    }
+
+    /// The origin of a key derived from this one
+    pub fn child(&self, path: &[u32]) -> KeySource {
+        let mut ret = self.clone();
+        ret.path.extend(path.iter().cloned());
+        ret
+    }
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // `format_path` starts with `m`, which origins leave out
+        write!(f, "[{}{}]", self.fingerprint[..].to_hex(), &format_path(&self.path)[1..])
+    }
+}
+
+/// Extended public key
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ExtendedPubKey {
+    /// The network this key is used on
+    pub network: Network,
+    /// How many derivations this key is from the master
+    pub depth: u8,
+    /// First four bytes of the parent key's hash160
+    pub parent_fingerprint: [u8; 4],
+    /// The child number this key was derived with
+    pub child_number: u32,
+    /// Chain code
+    pub chain_code: [u8; 32],
+    /// The public key itself
+    pub public_key: PublicKey
+}
+
+/// Extended private key
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ExtendedPrivKey {
+    /// The network this key is used on
+    pub network: Network,
+    /// How many derivations this key is from the master
+    pub depth: u8,
+    /// First four bytes of the parent key's hash160
+    pub parent_fingerprint: [u8; 4],
+    /// The child number this key was derived with
+    pub child_number: u32,
+    /// Chain code
+    pub chain_code: [u8; 32],
+    /// The secret key itself
+    pub secret_key: SecretKey
+}
+
+/// Serialize a child number big-endian
+fn child_number_bytes(n: u32) -> [u8; 4] {
+    [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+/// Split a 64-byte HMAC-SHA512 output into a tweak key and a chain code
+fn split_hmac(secp: &Secp256k1, hmac: &mut Hmac<Sha512>) -> Result<(SecretKey, [u8; 32]), Error> {
+    let mut result = [0; 64];
+    hmac.raw_result(&mut result);
+    let tweak = try!(SecretKey::from_slice(secp, &result[..32]).map_err(Error::Secp));
+    let mut chain_code = [0; 32];
+    chain_code.clone_from_slice(&result[32..]);
+    Ok((tweak, chain_code))
+}
+
+impl ExtendedPubKey {
+    /// The origin of this key as far as it can be told from the key alone:
+    /// a master key is its own origin, and any other key is treated as one
+    pub fn own_source(&self, secp: &Secp256k1) -> KeySource {
+        KeySource { fingerprint: self.fingerprint(secp), path: vec![] }
+    }
+
+    /// First four bytes of the hash160 of the key, identifying it as a parent
+    pub fn fingerprint(&self, secp: &Secp256k1) -> [u8; 4] {
+        let hash = Hash160::from_data(&self.public_key.serialize_vec(secp, true)[..]);
+        [hash[0], hash[1], hash[2], hash[3]]
+    }
+
+    /// Derive a non-hardened child key
+    pub fn ckd_pub(&self, secp: &Secp256k1, n: u32) -> Result<ExtendedPubKey, Error> {
+        if n >= HARDENED {
+            return Err(Error::CannotDeriveHardenedChild(n));
+        }
+        let mut hmac = Hmac::new(Sha512::new(), &self.chain_code[..]);
+        hmac.input(&self.public_key.serialize_vec(secp, true)[..]);
+        hmac.input(&child_number_bytes(n));
+        let (tweak, chain_code) = try!(split_hmac(secp, &mut hmac));
+
+        let mut public_key = self.public_key;
+        try!(public_key.add_exp_assign(secp, &tweak).map_err(Error::Secp));
+        Ok(ExtendedPubKey {
+            network: self.network,
+            depth: self.depth.wrapping_add(1),
+            parent_fingerprint: self.fingerprint(secp),
+            child_number: n,
+            chain_code: chain_code,
+            public_key: public_key
+        })
+    }
+
+    /// Derive the key at a path of child numbers below this one
+    pub fn derive_path(&self, secp: &Secp256k1, path: &[u32]) -> Result<ExtendedPubKey, Error> {
+        let mut ret = *self;
+        for &n in path {
+            ret = try!(ret.ckd_pub(secp, n));
+        }
+        Ok(ret)
+    }
+}
+
+impl ExtendedPrivKey {
+    /// Generate a master key from a seed
+    pub fn new_master(secp: &Secp256k1, network: Network, seed: &[u8]) -> Result<ExtendedPrivKey, Error> {
+        let mut hmac = Hmac::new(Sha512::new(), b"Bitcoin seed");
+        hmac.input(seed);
+        let (secret_key, chain_code) = try!(split_hmac(secp, &mut hmac));
+        Ok(ExtendedPrivKey {
+            network: network,
+            depth: 0,
+            parent_fingerprint: [0; 4],
+            child_number: 0,
+            chain_code: chain_code,
+            secret_key: secret_key
+        })
+    }
+
+    /// The extended public key corresponding to this key
+    pub fn to_xpub(&self, secp: &Secp256k1) -> Result<ExtendedPubKey, Error> {
+        Ok(ExtendedPubKey {
+            network: self.network,
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            public_key: try!(PublicKey::from_secret_key(secp, &self.secret_key).map_err(Error::Secp))
+        })
+    }
+
+    /// Derive a child key, hardened or not
+    pub fn ckd_priv(&self, secp: &Secp256k1, n: u32) -> Result<ExtendedPrivKey, Error> {
+        let xpub = try!(self.to_xpub(secp));
+        let mut hmac = Hmac::new(Sha512::new(), &self.chain_code[..]);
+        if n >= HARDENED {
+            hmac.input(&[0]);
+            hmac.input(&self.secret_key[..]);
+        } else {
+            hmac.input(&xpub.public_key.serialize_vec(secp, true)[..]);
+        }
+        hmac.input(&child_number_bytes(n));
+        let (tweak, chain_code) = try!(split_hmac(secp, &mut hmac));
+
+        let mut secret_key = self.secret_key;
+        try!(secret_key.add_assign(secp, &tweak).map_err(Error::Secp));
+        Ok(ExtendedPrivKey {
+            network: self.network,
+            depth: self.depth.wrapping_add(1),
+            parent_fingerprint: xpub.fingerprint(secp),
+            child_number: n,
+            chain_code: chain_code,
+            secret_key: secret_key
+        })
+    }
+
+    /// Derive the key at a path of child numbers below this one
+    pub fn derive_path(&self, secp: &Secp256k1, path: &[u32]) -> Result<ExtendedPrivKey, Error> {
+        let mut ret = *self;
+        for &n in path {
+            ret = try!(ret.ckd_priv(secp, n));
+        }
+        Ok(ret)
+    }
+}
+
+impl ToBase58 for ExtendedPubKey {
+    fn base58_layout(&self) -> Vec<u8> {
+        let secp = Secp256k1::without_caps();
+        let mut ret = Vec::with_capacity(78);
+        ret.extend(match self.network {
+            Network::Bitcoin => &XPUB_VERSION,
+            Network::Testnet => &TPUB_VERSION
+        }.iter().cloned());
+        ret.push(self.depth);
+        ret.extend(self.parent_fingerprint.iter().cloned());
+        ret.extend(child_number_bytes(self.child_number).iter().cloned());
+        ret.extend(self.chain_code.iter().cloned());
+        ret.extend(self.public_key.serialize_vec(&secp, true).iter().cloned());
+        ret
+    }
+}
+
+impl FromBase58 for ExtendedPubKey {
+    fn from_base58_layout(data: Vec<u8>) -> Result<ExtendedPubKey, base58::Error> {
+        if data.len() != 78 {
+            return Err(base58::Error::InvalidLength(data.len()));
+        }
+        let network = if &data[0..4] == &XPUB_VERSION[..] {
+            Network::Bitcoin
+        } else if &data[0..4] == &TPUB_VERSION[..] {
+            Network::Testnet
+        } else {
+            return Err(base58::Error::InvalidVersion(data[0..4].to_owned()));
+        };
+        let secp = Secp256k1::without_caps();
+        let public_key = try!(PublicKey::from_slice(&secp, &data[45..78])
+                                  .map_err(|e| base58::Error::Other(e.to_string())));
+
+        let mut parent_fingerprint = [0; 4];
+        parent_fingerprint.clone_from_slice(&data[5..9]);
+        let mut chain_code = [0; 32];
+        chain_code.clone_from_slice(&data[13..45]);
+        Ok(ExtendedPubKey {
+            network: network,
+            depth: data[4],
+            parent_fingerprint: parent_fingerprint,
+            child_number: ((data[9] as u32) << 24) | ((data[10] as u32) << 16) |
+                          ((data[11] as u32) << 8) | data[12] as u32,
+            chain_code: chain_code,
+            public_key: public_key
+        })
+    }
+}
+
+impl ToBase58 for ExtendedPrivKey {
+    fn base58_layout(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(78);
+        ret.extend(match self.network {
+            Network::Bitcoin => &XPRV_VERSION,
+            Network::Testnet => &TPRV_VERSION
+        }.iter().cloned());
+        ret.push(self.depth);
+        ret.extend(self.parent_fingerprint.iter().cloned());
+        ret.extend(child_number_bytes(self.child_number).iter().cloned());
+        ret.extend(self.chain_code.iter().cloned());
+        ret.push(0);
+        ret.extend(self.secret_key[..].iter().cloned());
+        ret
+    }
+}
+
+impl FromBase58 for ExtendedPrivKey {
+    fn from_base58_layout(data: Vec<u8>) -> Result<ExtendedPrivKey, base58::Error> {
+        if data.len() != 78 {
+            return Err(base58::Error::InvalidLength(data.len()));
+        }
+        let network = if &data[0..4] == &XPRV_VERSION[..] {
+            Network::Bitcoin
+        } else if &data[0..4] == &TPRV_VERSION[..] {
+            Network::Testnet
+        } else {
+            return Err(base58::Error::InvalidVersion(data[0..4].to_owned()));
+        };
+        if data[45] != 0 {
+            return Err(base58::Error::Other("extended private key is not zero-prefixed".to_owned()));
+        }
+        let secp = Secp256k1::without_caps();
+        let secret_key = try!(SecretKey::from_slice(&secp, &data[46..78])
+                                  .map_err(|e| base58::Error::Other(e.to_string())));
+
+        let mut parent_fingerprint = [0; 4];
+        parent_fingerprint.clone_from_slice(&data[5..9]);
+        let mut chain_code = [0; 32];
+        chain_code.clone_from_slice(&data[13..45]);
+        Ok(ExtendedPrivKey {
+            network: network,
+            depth: data[4],
+            parent_fingerprint: parent_fingerprint,
+            child_number: ((data[9] as u32) << 24) | ((data[10] as u32) << 16) |
+                          ((data[11] as u32) << 8) | data[12] as u32,
+            chain_code: chain_code,
+            secret_key: secret_key
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::network::constants::Network;
+    use bitcoin::util::base58::{FromBase58, ToBase58};
+    use secp256k1::Secp256k1;
+    use serialize::hex::FromHex;
+
+    use super::*;
+
+    /// Checks each key of a BIP32 test vector chain, given as a path and the
+    /// expected xprv and xpub, deriving every step from the one before
+    fn check_chain(seed: &str, master_fingerprint: &str, chain: &[(&str, &str, &str)]) {
+        let secp = Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(&secp, Network::Bitcoin, &seed.from_hex().unwrap()).unwrap();
+        assert_eq!(master.to_xpub(&secp).unwrap().fingerprint(&secp).to_vec(), master_fingerprint.from_hex().unwrap());
+
+        let mut parent: Option<ExtendedPrivKey> = None;
+        for &(path, xprv, xpub) in chain {
+            let path = DerivationPath::parse(path).unwrap();
+            let sk = master.derive_path(&secp, &path.children).unwrap();
+            let pk = sk.to_xpub(&secp).unwrap();
+            assert_eq!(sk.to_base58check(), xprv);
+            assert_eq!(pk.to_base58check(), xpub);
+
+            // Serialization round-trip
+            let decoded_sk: ExtendedPrivKey = FromBase58::from_base58check(xprv).unwrap();
+            let decoded_pk: ExtendedPubKey = FromBase58::from_base58check(xpub).unwrap();
+            assert_eq!(decoded_sk, sk);
+            assert_eq!(decoded_pk, pk);
+            assert_eq!(decoded_sk.to_base58check(), xprv);
+            assert_eq!(decoded_pk.to_base58check(), xpub);
+
+            if let Some(parent) = parent {
+                let parent_pk = parent.to_xpub(&secp).unwrap();
+                assert_eq!(pk.parent_fingerprint, parent_pk.fingerprint(&secp));
+                assert_eq!(parent.ckd_priv(&secp, pk.child_number).unwrap(), sk);
+                // Public derivation agrees with private derivation where it is possible
+                if pk.child_number < HARDENED {
+                    assert_eq!(parent_pk.ckd_pub(&secp, pk.child_number).unwrap(), pk);
+                } else {
+                    assert!(parent_pk.ckd_pub(&secp, pk.child_number).is_err());
+                }
+            } else {
+                assert_eq!(pk.parent_fingerprint, [0; 4]);
+            }
+            parent = Some(sk);
+        }
+    }
+
+    #[test]
+    fn test_vector_1() {
+        check_chain("000102030405060708090a0b0c0d0e0f", "3442193e", &[
+            ("m",
+             "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi",
+             "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8"),
+            ("m/0h",
+             "xprv9uHRZZhk6KAJC1avXpDAp4MDc3sQKNxDiPvvkX8Br5ngLNv1TxvUxt4cV1rGL5hj6KCesnDYUhd7oWgT11eZG7XnxHrnYeSvkzY7d2bhkJ7",
+             "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw"),
+            ("m/0h/1",
+             "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs",
+             "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ"),
+            ("m/0h/1/2h",
+             "xprv9z4pot5VBttmtdRTWfWQmoH1taj2axGVzFqSb8C9xaxKymcFzXBDptWmT7FwuEzG3ryjH4ktypQSAewRiNMjANTtpgP4mLTj34bhnZX7UiM",
+             "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5"),
+            ("m/0h/1/2h/2",
+             "xprvA2JDeKCSNNZky6uBCviVfJSKyQ1mDYahRjijr5idH2WwLsEd4Hsb2Tyh8RfQMuPh7f7RtyzTtdrbdqqsunu5Mm3wDvUAKRHSC34sJ7in334",
+             "xpub6FHa3pjLCk84BayeJxFW2SP4XRrFd1JYnxeLeU8EqN3vDfZmbqBqaGJAyiLjTAwm6ZLRQUMv1ZACTj37sR62cfN7fe5JnJ7dh8zL4fiyLHV"),
+            ("m/0h/1/2h/2/1000000000",
+             "xprvA41z7zogVVwxVSgdKUHDy1SKmdb533PjDz7J6N6mV6uS3ze1ai8FHa8kmHScGpWmj4WggLyQjgPie1rFSruoUihUZREPSL39UNdE3BBDu76",
+             "xpub6H1LXWLaKsWFhvm6RVpEL9P4KfRZSW7abD2ttkWP3SSQvnyA8FSVqNTEcYFgJS2UaFcxupHiYkro49S8yGasTvXEYBVPamhGW6cFJodrTHy")
+        ]);
+    }
+
+    #[test]
+    fn test_vector_2() {
+        check_chain("fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a29f9c999693908d8a8784817e7b7875726f6c696663605d5a5754514e4b484542", "bd16bee5", &[
+            ("m",
+             "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U",
+             "xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB"),
+            ("m/0",
+             "xprv9vHkqa6EV4sPZHYqZznhT2NPtPCjKuDKGY38FBWLvgaDx45zo9WQRUT3dKYnjwih2yJD9mkrocEZXo1ex8G81dwSM1fwqWpWkeS3v86pgKt",
+             "xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH"),
+            ("m/0/2147483647h",
+             "xprv9wSp6B7kry3Vj9m1zSnLvN3xH8RdsPP1Mh7fAaR7aRLcQMKTR2vidYEeEg2mUCTAwCd6vnxVrcjfy2kRgVsFawNzmjuHc2YmYRmagcEPdU9",
+             "xpub6ASAVgeehLbnwdqV6UKMHVzgqAG8Gr6riv3Fxxpj8ksbH9ebxaEyBLZ85ySDhKiLDBrQSARLq1uNRts8RuJiHjaDMBU4Zn9h8LZNnBC5y4a"),
+            ("m/0/2147483647h/1",
+             "xprv9zFnWC6h2cLgpmSA46vutJzBcfJ8yaJGg8cX1e5StJh45BBciYTRXSd25UEPVuesF9yog62tGAQtHjXajPPdbRCHuWS6T8XA2ECKADdw4Ef",
+             "xpub6DF8uhdarytz3FWdA8TvFSvvAh8dP3283MY7p2V4SeE2wyWmG5mg5EwVvmdMVCQcoNJxGoWaU9DCWh89LojfZ537wTfunKau47EL2dhHKon"),
+            ("m/0/2147483647h/1/2147483646h",
+             "xprvA1RpRA33e1JQ7ifknakTFpgNXPmW2YvmhqLQYMmrj4xJXXWYpDPS3xz7iAxn8L39njGVyuoseXzU6rcxFLJ8HFsTjSyQbLYnMpCqE2VbFWc",
+             "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL"),
+            ("m/0/2147483647h/1/2147483646h/2",
+             "xprvA2nrNbFZABcdryreWet9Ea4LvTJcGsqrMzxHx98MMrotbir7yrKCEXw7nadnHM8Dq38EGfSh6dqA9QWTyefMLEcBYJUuekgW4BYPJcr9E7j",
+             "xpub6FnCn6nSzZAw5Tw7cgR9bi15UV96gLZhjDstkXXxvCLsUXBGXPdSnLFbdpq8p9HmGsApME5hQTZ3emM2rnY5agb9rXpVGyy3bdW6EEgAtqt")
+        ]);
+    }
+
+    #[test]
+    fn paths() {
+        let path = DerivationPath::parse("m/45h/0'/2H/*").unwrap();
+        assert_eq!(path.children, vec![45 + HARDENED, HARDENED, 2 + HARDENED]);
+        assert_eq!(path.wildcard, Wildcard::Normal);
+        assert_eq!(format_path(&path.at_index(7)), "m/45h/0h/2h/7");
+        assert!(DerivationPath::parse("m/*/1").is_err());
+        assert!(DerivationPath::parse("m/2147483648").is_err());
+        assert!(DerivationPath::parse("45/0").is_err());
+    }
+}
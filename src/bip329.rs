@@ -0,0 +1,37 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # BIP329
+//! Wallet labels in the format of BIP329, one JSON object per line, so that
+//! wallets such as Sparrow and Bitcoin Core can tell which contract each
+//! commitment address belongs to.
+//!
+
+use serialize::json::Json;
+
+use std::collections::BTreeMap;
+
+/// The label record of an address
+pub fn address_label(address: &str, label: &str) -> Json {
+    let mut record = BTreeMap::new();
+    record.insert("type".to_owned(), Json::String("addr".to_owned()));
+    record.insert("ref".to_owned(), Json::String(address.to_owned()));
+    record.insert("label".to_owned(), Json::String(label.to_owned()));
+    Json::Object(record)
+}
+
+/// A file of label records
+pub fn to_jsonl(records: &[Json]) -> String {
+    records.iter().map(|record| format!("{}\n", record)).collect()
+}
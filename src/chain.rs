@@ -0,0 +1,512 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+
+//! # Chains
+//! The chains addresses and private keys may be encoded for. Contract
+//! hashing does not care which chain a key is used on, so chains which
+//! copied Bitcoin's script and keys, such as Litecoin, differ only in the
+//! version bytes of their base58 addresses and WIF keys and in the prefix
+//! of their segwit addresses. Each chain is used in the mode (mainnet or
+//! testnet) of a Bitcoin network, which decides everything else, such as
+//! the versions of extended keys.
+//!
+//! Chains other than the built-in ones are declared in a registry file (by
+//! default `~/.pacthash-chains`), one per line, as a name, the mode, the
+//! pubkey hash, script hash and WIF version bytes in decimal, and the
+//! segwit prefix:
+//!
+//! ```text
+//! # name      mode     p2pkh  p2sh  wif  hrp
+//! mycoin      mainnet  50     55    178  my
+//! mycoin-test testnet  111    196   239  tmy
+//! ```
+//!
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::network::constants::Network;
+use bitcoin::util::address::{self, Address, Privkey};
+use bitcoin::util::base58::{self, FromBase58, ToBase58};
+use bitcoin::util::hash::Hash160;
+use secp256k1::Secp256k1;
+use secp256k1::key::{PublicKey, SecretKey};
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use segwit;
+
+/// Chain registry error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// The registry file could not be read
+    Io(String),
+    /// A line of the registry file was not a chain
+    BadLine(usize),
+    /// A chain's address versions are the same, or its segwit prefix is
+    /// not one bech32 allows (line number)
+    BadPrefixes(usize),
+    /// A name is taken, by a built-in chain or an earlier line
+    BadName(String)
+}
+
+/// The parameters of a chain declared in a registry file
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Params {
+    /// The name of the chain
+    pub name: String,
+    /// The Bitcoin network whose mode the chain is used in
+    pub network: Network,
+    /// Base58 prefix of pubkey hash addresses
+    pub pubkey_hash: u8,
+    /// Base58 prefix of script hash addresses
+    pub script_hash: u8,
+    /// Base58 prefix of WIF private keys
+    pub wif: u8,
+    /// Human-readable part of segwit addresses
+    pub hrp: String
+}
+
+/// A chain
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Chain {
+    /// Bitcoin
+    Bitcoin,
+    /// The Bitcoin testnet
+    Testnet,
+    /// The default signet, or any custom signet; signets differ only in the
+    /// challenge their blocks meet, and share the addresses of testnet
+    Signet,
+    /// A local regtest chain, whose base58 addresses and keys are those of
+    /// testnet
+    Regtest,
+    /// Litecoin
+    Litecoin,
+    /// The Litecoin testnet
+    LitecoinTestnet,
+    /// A chain declared in a registry file
+    Custom(&'static Params)
+}
+
+/// Every chain, in the order they are listed in
+pub const ALL: [Chain; 6] = [Chain::Bitcoin, Chain::Testnet, Chain::Signet, Chain::Regtest, Chain::Litecoin, Chain::LitecoinTestnet];
+
+impl Chain {
+    /// Base58 prefixes of the chain: pubkey hash, script hash and WIF
+    fn prefixes(&self) -> (u8, u8, u8) {
+        match *self {
+            Chain::Bitcoin => (0, 5, 128),
+            Chain::Testnet | Chain::Signet | Chain::Regtest => (111, 196, 239),
+            Chain::Litecoin => (48, 50, 176),
+            Chain::LitecoinTestnet => (111, 58, 239),
+            Chain::Custom(params) => (params.pubkey_hash, params.script_hash, params.wif)
+        }
+    }
+
+    /// The script hash prefix the chain used before it had one of its own,
+    /// which its addresses are still decoded with
+    fn legacy_script_hash(&self) -> Option<u8> {
+        match *self {
+            Chain::Litecoin => Some(5),
+            Chain::LitecoinTestnet => Some(196),
+            _ => None
+        }
+    }
+
+    /// The human-readable part of the chain's segwit addresses
+    pub fn hrp(&self) -> &'static str {
+        match *self {
+            Chain::Bitcoin => "bc",
+            Chain::Testnet | Chain::Signet => "tb",
+            Chain::Regtest => "bcrt",
+            Chain::Litecoin => "ltc",
+            Chain::LitecoinTestnet => "tltc",
+            Chain::Custom(params) => &params.hrp
+        }
+    }
+
+    /// The name of the chain, as `--chain` takes it
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Chain::Bitcoin => "bitcoin",
+            Chain::Testnet => "testnet",
+            Chain::Signet => "signet",
+            Chain::Regtest => "regtest",
+            Chain::Litecoin => "litecoin",
+            Chain::LitecoinTestnet => "litecoin-testnet",
+            Chain::Custom(params) => &params.name
+        }
+    }
+
+    /// The built-in chain of a name
+    pub fn from_name(name: &str) -> Option<Chain> {
+        ALL.iter().find(|chain| chain.name() == name).cloned()
+    }
+
+    /// The Bitcoin chain of a network, as the tool's mode selects it
+    pub fn from_network(network: Network) -> Chain {
+        match network {
+            Network::Bitcoin => Chain::Bitcoin,
+            Network::Testnet => Chain::Testnet
+        }
+    }
+
+    /// The Bitcoin network whose mode the chain is used in
+    pub fn network(&self) -> Network {
+        match *self {
+            Chain::Bitcoin | Chain::Litecoin => Network::Bitcoin,
+            Chain::Testnet | Chain::Signet | Chain::Regtest | Chain::LitecoinTestnet => Network::Testnet,
+            Chain::Custom(params) => params.network
+        }
+    }
+
+    /// Whether the chain is Bitcoin's own, which e.g. Liquid pegs into
+    pub fn is_bitcoin(&self) -> bool {
+        match *self {
+            Chain::Bitcoin | Chain::Testnet | Chain::Signet | Chain::Regtest => true,
+            _ => false
+        }
+    }
+
+    /// The port the chain's node usually serves RPC on. Declared chains are
+    /// taken to use Bitcoin's port for their mode.
+    pub fn rpc_port(&self) -> u16 {
+        match *self {
+            Chain::Bitcoin => 8332,
+            Chain::Testnet => 18332,
+            Chain::Signet => 38332,
+            Chain::Regtest => 18443,
+            Chain::Litecoin => 9332,
+            Chain::LitecoinTestnet => 19332,
+            Chain::Custom(params) => Chain::from_network(params.network).rpc_port()
+        }
+    }
+
+    /// Encode a base58 address for the chain
+    pub fn encode_address(&self, address: &Address) -> String {
+        let (pubkey_hash, script_hash, _) = self.prefixes();
+        let mut data = Vec::with_capacity(21);
+        data.push(match address.ty {
+            address::Type::PubkeyHash => pubkey_hash,
+            address::Type::ScriptHash => script_hash
+        });
+        data.extend(address.hash[..].iter().cloned());
+        data.to_base58check()
+    }
+
+    /// Decode a base58 address of the chain, refusing those of other chains
+    pub fn decode_address(&self, s: &str) -> Result<Address, base58::Error> {
+        let data: Vec<u8> = try!(FromBase58::from_base58check(s));
+        if data.len() != 21 {
+            return Err(base58::Error::InvalidLength(data.len()));
+        }
+        let (pubkey_hash, script_hash, _) = self.prefixes();
+        let ty = if data[0] == pubkey_hash {
+            address::Type::PubkeyHash
+        } else if data[0] == script_hash || Some(data[0]) == self.legacy_script_hash() {
+            address::Type::ScriptHash
+        } else {
+            return Err(base58::Error::InvalidVersion(vec![data[0]]));
+        };
+        Ok(Address {
+            ty: ty,
+            network: self.network(),
+            hash: Hash160::from(&data[1..])
+        })
+    }
+
+    /// The P2PKH address of a key
+    pub fn p2pkh_address(&self, key: &PublicKey, compressed: bool) -> String {
+        self.encode_address(&Address::from_key(self.network(), key, compressed))
+    }
+
+    /// The P2SH address of a redeem script
+    pub fn p2sh_address(&self, script: &Script) -> String {
+        self.encode_address(&Address::from_script(self.network(), script))
+    }
+
+    /// The P2WSH address of a witness script
+    pub fn p2wsh_address(&self, script: &Script) -> String {
+        segwit::encode_address(self.hrp(), 0, &segwit::witness_program(script)[..])
+    }
+
+    /// The P2SH-P2WSH address of a witness script
+    pub fn p2sh_p2wsh_address(&self, script: &Script) -> String {
+        self.p2sh_address(&segwit::p2wsh_script_pubkey(script))
+    }
+
+    /// Encode a private key as WIF for the chain
+    pub fn encode_privkey(&self, key: &Privkey) -> String {
+        let (_, _, wif) = self.prefixes();
+        let mut data = Vec::with_capacity(34);
+        data.push(wif);
+        data.extend(key.key[..].iter().cloned());
+        if key.compressed {
+            data.push(1);
+        }
+        data.to_base58check()
+    }
+
+    /// Decode a WIF private key of the chain, refusing those of other chains
+    pub fn decode_privkey(&self, s: &str) -> Result<Privkey, base58::Error> {
+        let data: Vec<u8> = try!(FromBase58::from_base58check(s));
+        let compressed = match data.len() {
+            33 => false,
+            34 if data[33] == 1 => true,
+            _ => { return Err(base58::Error::InvalidLength(data.len())); }
+        };
+        if data[0] != self.prefixes().2 {
+            return Err(base58::Error::InvalidVersion(vec![data[0]]));
+        }
+        let key = try!(SecretKey::from_slice(&Secp256k1::without_caps(), &data[1..33])
+                           .map_err(|_| base58::Error::Other("secret key out of range".to_owned())));
+        Ok(Privkey {
+            compressed: compressed,
+            network: self.network(),
+            key: key
+        })
+    }
+}
+
+/// The chains of a registry file, along with the built-in ones
+#[derive(Clone, Debug, Default)]
+pub struct Registry {
+    chains: Vec<Chain>
+}
+
+impl Registry {
+    /// Read a registry file. The chains it declares are kept for the rest
+    /// of the process, so that they may be passed around as freely as the
+    /// built-in ones.
+    pub fn read(filename: &str) -> Result<Registry, Error> {
+        let mut text = String::new();
+        try!(File::open(filename).and_then(|mut f| f.read_to_string(&mut text))
+                 .map_err(|e| Error::Io(format!("{}: {}", filename, e))));
+        Registry::parse(&text)
+    }
+
+    /// Parse the text of a registry file
+    pub fn parse(text: &str) -> Result<Registry, Error> {
+        let mut ret = Registry::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 6 {
+                return Err(Error::BadLine(n + 1));
+            }
+            let network = match fields[1] {
+                "mainnet" => Network::Bitcoin,
+                "testnet" => Network::Testnet,
+                _ => { return Err(Error::BadLine(n + 1)); }
+            };
+            let (pubkey_hash, script_hash, wif) = match (fields[2].parse(), fields[3].parse(), fields[4].parse()) {
+                (Ok(pubkey_hash), Ok(script_hash), Ok(wif)) => (pubkey_hash, script_hash, wif),
+                _ => { return Err(Error::BadLine(n + 1)); }
+            };
+            // Addresses are told apart by their version, and a segwit
+            // prefix is 1 to 83 printable ASCII characters
+            let hrp = fields[5].to_lowercase();
+            if pubkey_hash == script_hash || hrp.len() > 83 || hrp.bytes().any(|b| b < 33 || b > 126) {
+                return Err(Error::BadPrefixes(n + 1));
+            }
+            if ret.get(fields[0]).is_some() {
+                return Err(Error::BadName(fields[0].to_owned()));
+            }
+            let params = Params {
+                name: fields[0].to_owned(),
+                network: network,
+                pubkey_hash: pubkey_hash,
+                script_hash: script_hash,
+                wif: wif,
+                hrp: hrp
+            };
+            ret.chains.push(Chain::Custom(Box::leak(Box::new(params))));
+        }
+        Ok(ret)
+    }
+
+    /// The chain of a name, built-in or declared
+    pub fn get(&self, name: &str) -> Option<Chain> {
+        Chain::from_name(name).or_else(|| self.chains.iter().find(|chain| chain.name() == name).cloned())
+    }
+
+    /// The names of every chain, built-in ones first
+    pub fn names(&self) -> Vec<&'static str> {
+        ALL.iter().chain(self.chains.iter()).map(|chain| chain.name()).collect()
+    }
+}
+
+/// The default registry file, `~/.pacthash-chains`
+pub fn default_path() -> PathBuf {
+    let mut ret = env::home_dir().unwrap_or(PathBuf::from("."));
+    ret.push(".pacthash-chains");
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::util::address;
+    use bitcoin::util::base58;
+    use secp256k1::Secp256k1;
+    use secp256k1::key::{PublicKey, SecretKey};
+    use serialize::hex::FromHex;
+
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::process;
+
+    use segwit;
+
+    use super::*;
+
+    /// A registry declaring a mainnet and a testnet chain
+    const REGISTRY: &'static str = "# name      mode     p2pkh  p2sh  wif  hrp\n\
+                                    mycoin      mainnet  50     55    178  MY\n\
+                                    \n\
+                                    mycoin-test testnet  111    196   239  tmy\n";
+
+    /// The key whose secret is 1, and its hash160
+    fn key() -> (SecretKey, PublicKey) {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&secp, &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                                                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]).unwrap();
+        let public = PublicKey::from_secret_key(&secp, &secret).unwrap();
+        (secret, public)
+    }
+    const HASH: &'static str = "751e76e8199196d454941c45d1b3a323f1433bd6";
+
+    #[test]
+    fn registry_file() {
+        let path = env::temp_dir().join(format!("pacthash-chains-test-{}", process::id()));
+        File::create(&path).and_then(|mut f| f.write_all(REGISTRY.as_bytes())).unwrap();
+        let registry = Registry::read(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        let registry = registry.unwrap();
+
+        let names = registry.names();
+        assert_eq!(&names[..ALL.len()], &["bitcoin", "testnet", "signet", "regtest", "litecoin", "litecoin-testnet"]);
+        assert_eq!(&names[ALL.len()..], &["mycoin", "mycoin-test"]);
+        let mycoin = registry.get("mycoin").unwrap();
+        assert_eq!(mycoin.network(), Network::Bitcoin);
+        assert_eq!(mycoin.hrp(), "my");
+        assert_eq!(registry.get("mycoin-test").unwrap().network(), Network::Testnet);
+        assert_eq!(registry.get("litecoin"), Some(Chain::Litecoin));
+        assert!(registry.get("othercoin").is_none());
+
+        match Registry::read("/nonexistent/pacthash-chains") {
+            Err(Error::Io(_)) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn malformed_lines() {
+        // Line numbers count comments and blank lines
+        for &(text, line) in &[("mycoin mainnet 50 55 178", 1),
+                               ("# comment\n\nmycoin mainnet 50 55 178 my extra", 3),
+                               ("mycoin regtest 50 55 178 my", 1),
+                               ("mycoin mainnet 50 55 256 my", 1),
+                               ("mycoin mainnet 50 -5 178 my", 1),
+                               ("mycoin mainnet 0x32 55 178 my", 1)] {
+            match Registry::parse(text) {
+                Err(Error::BadLine(n)) => assert_eq!(n, line),
+                other => panic!("unexpected result {:?} for {:?}", other, text)
+            }
+        }
+        // Versions which cannot tell addresses apart, or a bad segwit prefix
+        let long_hrp = format!("mycoin mainnet 50 55 178 {}", (0..84).map(|_| "m").collect::<String>());
+        for text in &["mycoin mainnet 50 50 178 my", "mycoin mainnet 50 55 178 m\u{e9}", &long_hrp[..]] {
+            match Registry::parse(text) {
+                Err(Error::BadPrefixes(1)) => {}
+                other => panic!("unexpected result {:?} for {:?}", other, text)
+            }
+        }
+        // Names may not be taken twice, or shadow a built-in chain
+        for text in &["mycoin mainnet 50 55 178 my\nmycoin testnet 111 196 239 tmy", "litecoin mainnet 50 55 178 my"] {
+            match Registry::parse(text) {
+                Err(Error::BadName(_)) => {}
+                other => panic!("unexpected result {:?} for {:?}", other, text)
+            }
+        }
+    }
+
+    #[test]
+    fn custom_chain_roundtrip() {
+        let registry = Registry::parse(REGISTRY).unwrap();
+        let mycoin = registry.get("mycoin").unwrap();
+        let (secret, public) = key();
+
+        // WIF keys keep their compression, and are refused by other chains
+        for &compressed in &[true, false] {
+            let privkey = Privkey { compressed: compressed, network: Network::Bitcoin, key: secret };
+            let wif = mycoin.encode_privkey(&privkey);
+            let decoded = mycoin.decode_privkey(&wif).unwrap();
+            assert_eq!((decoded.compressed, decoded.network, decoded.key), (compressed, Network::Bitcoin, secret));
+            match Chain::Bitcoin.decode_privkey(&wif) {
+                Err(base58::Error::InvalidVersion(ref v)) if *v == vec![178] => {}
+                other => panic!("unexpected result {:?}", other.map(|_| ()))
+            }
+        }
+        assert_eq!(mycoin.encode_privkey(&Privkey { compressed: true, network: Network::Bitcoin, key: secret }),
+                   "TLDEmj98AN5UT7MjSVFxhyqhJLm7bVGPydtCbrfrQB9h8RTVrfwH");
+
+        // Base58 addresses decode to what they were made from
+        let p2pkh = mycoin.p2pkh_address(&public, true);
+        assert_eq!(p2pkh, "MJaRnao1s62a2zAKSkmG582KbLKianqb7v");
+        let decoded = mycoin.decode_address(&p2pkh).unwrap();
+        assert_eq!(decoded.ty, address::Type::PubkeyHash);
+        assert_eq!(&decoded.hash[..], &HASH.from_hex().unwrap()[..]);
+        let script = Script::from(format!("21{}ac", "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798").from_hex().unwrap());
+        let p2sh = mycoin.p2sh_address(&script);
+        assert_eq!(mycoin.decode_address(&p2sh).unwrap().ty, address::Type::ScriptHash);
+        assert_eq!(mycoin.encode_address(&mycoin.decode_address(&p2sh).unwrap()), p2sh);
+        // ... and are refused by chains with other versions
+        for chain in &[Chain::Bitcoin, Chain::Testnet, registry.get("mycoin-test").unwrap()] {
+            match chain.decode_address(&p2pkh) {
+                Err(base58::Error::InvalidVersion(_)) => {}
+                other => panic!("unexpected result {:?}", other.map(|_| ()))
+            }
+        }
+
+        // Segwit addresses use the declared prefix, lowercased
+        let p2wsh = mycoin.p2wsh_address(&script);
+        assert!(p2wsh.starts_with("my1"));
+        assert_eq!(segwit::decode_address("my", &p2wsh), Some((0, segwit::witness_program(&script)[..].to_vec())));
+        assert_eq!(segwit::decode_address("bc", &p2wsh), None);
+    }
+
+    #[test]
+    fn builtin_chains() {
+        let (secret, public) = key();
+        let privkey = Privkey { compressed: true, network: Network::Bitcoin, key: secret };
+        assert_eq!(Chain::Bitcoin.p2pkh_address(&public, true), "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH");
+        assert_eq!(Chain::Litecoin.p2pkh_address(&public, true), "LVuDpNCSSj6pQ7t9Pv6d6sUkLKoqDEVUnJ");
+        assert_eq!(Chain::Bitcoin.encode_privkey(&privkey), "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn");
+        assert_eq!(Chain::Litecoin.encode_privkey(&privkey), "T33ydQRKp4FCW5LCLLUB7deioUMoveiwekdwUwyfRDeGZm76aUjV");
+        // No two built-in chains of a mode share all their prefixes, but
+        // testnet, signet and regtest share their base58 ones
+        for chain in &ALL {
+            let (pubkey_hash, script_hash, _) = chain.prefixes();
+            assert!(pubkey_hash != script_hash);
+            assert_eq!(Chain::from_name(chain.name()), Some(*chain));
+        }
+        assert_eq!(Chain::Regtest.p2pkh_address(&public, true), Chain::Testnet.p2pkh_address(&public, true));
+    }
+}
@@ -0,0 +1,383 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Checks
+//! Sanity checks on tweaked redemption scripts
+//!
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::{self, Instruction, Script};
+
+use std::{fmt, mem};
+
+/// Maximum size of a P2SH redemption script, from `MAX_SCRIPT_ELEMENT_SIZE`
+pub const MAX_REDEEM_SCRIPT_LEN: usize = 520;
+/// Maximum number of sigops in a standard P2SH redemption script
+pub const MAX_P2SH_SIGOPS: usize = 15;
+/// Number of sigops a CHECKMULTISIG costs when the key count is not known
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+/// Maximum number of keys of a standard bare CHECKMULTISIG output script
+pub const MAX_BARE_MULTISIG_KEYS: usize = 3;
+
+/// A problem found with a script
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Warning {
+    /// Script is too large to be used as a P2SH redemption script
+    ScriptTooLarge(usize),
+    /// Script has more sigops than a standard P2SH redemption script may
+    TooManySigops(usize),
+    /// A CHECKMULTISIG uses more keys than a standard P2SH redemption script
+    /// may, one sigop for each
+    TooManyMultisigKeys(usize),
+    /// A CHECKMULTISIG uses more keys than a standard bare output script may,
+    /// so the script can only be paid to through P2SH or P2WSH
+    BareMultisigKeys(usize),
+    /// The key at the second byte offset repeats the key at the first. Since
+    /// the tweak for a key depends only on the key and the contract, every
+    /// occurrence of a repeated key is tweaked to the same new key.
+    DuplicateKey(usize, usize),
+    /// The script has compressed keys, the first at the first byte offset,
+    /// and uncompressed ones, the first at the second. Uncompressed keys
+    /// are nonstandard in P2WSH, so such a script may only be paid to as
+    /// P2SH, unlike scripts of one kind of key or the other.
+    MixedKeyCompression(usize, usize)
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Warning::ScriptTooLarge(n) => write!(f, "script is {} bytes, which exceeds the {}-byte P2SH limit", n, MAX_REDEEM_SCRIPT_LEN),
+            Warning::TooManySigops(n) => write!(f, "script has {} sigops, which exceeds the standard P2SH limit of {}", n, MAX_P2SH_SIGOPS),
+            Warning::TooManyMultisigKeys(n) => write!(f, "CHECKMULTISIG with {} keys exceeds the standard P2SH limit of {}", n, MAX_P2SH_SIGOPS),
+            Warning::BareMultisigKeys(n) => write!(f, "CHECKMULTISIG with {} keys is nonstandard as a bare output script (maximum {}); pay to it by P2SH or P2WSH",
+                                                   n, MAX_BARE_MULTISIG_KEYS),
+            Warning::DuplicateKey(first, dup) => write!(f, "key at byte {} duplicates the key at byte {}; both receive the same tweak", dup, first),
+            Warning::MixedKeyCompression(compressed, uncompressed) => write!(f, "key at byte {} is uncompressed while the key at byte {} is compressed; \
+                                                                                 uncompressed keys are nonstandard in P2WSH", uncompressed, compressed)
+        }
+    }
+}
+
+/// Counts the sigops in a script, using the accurate counting which is
+/// applied to P2SH redemption scripts
+pub fn sigop_count(script: &Script) -> usize {
+    let mut ret = 0;
+    let mut last_pushnum = None;
+    for instruction in script.into_iter() {
+        let mut pushnum = None;
+        match instruction {
+            Instruction::Op(op) => {
+                match op.classify() {
+                    opcodes::Class::PushNum(n) => { pushnum = Some(n); }
+                    opcodes::Class::Ordinary(opcodes::Ordinary::OP_CHECKSIG) |
+                    opcodes::Class::Ordinary(opcodes::Ordinary::OP_CHECKSIGVERIFY) => { ret += 1; }
+                    opcodes::Class::Ordinary(opcodes::Ordinary::OP_CHECKMULTISIG) |
+                    opcodes::Class::Ordinary(opcodes::Ordinary::OP_CHECKMULTISIGVERIFY) => {
+                        ret += match last_pushnum {
+                            Some(n) if n >= 1 && n <= 16 => n as usize,
+                            _ => MAX_PUBKEYS_PER_MULTISIG
+                        };
+                    }
+                    _ => {}
+                }
+            }
+            Instruction::PushBytes(_) => {}
+            // Unparseable remainder; nothing more can be counted
+            Instruction::Error(_) => break
+        }
+        last_pushnum = pushnum;
+    }
+    ret
+}
+
+/// Decodes a push of a minimally-encoded number, as used for the key
+/// counts of a CHECKMULTISIG too large for the `OP_1`-`OP_16` opcodes
+fn push_number(data: &[u8]) -> Option<i64> {
+    let len = data.len();
+    if len == 0 || len > 4 {
+        return None;
+    }
+    // A last byte of 0x00 or 0x80 is only needed to hold the sign bit
+    if data[len - 1] & 0x7f == 0 && (len == 1 || data[len - 2] & 0x80 == 0) {
+        return None;
+    }
+    script::read_scriptint(data).ok()
+}
+
+/// Runs all sanity checks on a script, returning every problem found.
+/// `untemplate` refuses uncompressed keys, so scripts tweaked from a
+/// template never mix key compression, but other scripts may.
+pub fn check_script(script: &Script) -> Vec<Warning> {
+    let mut ret = vec![];
+
+    if script.len() > MAX_REDEEM_SCRIPT_LEN {
+        ret.push(Warning::ScriptTooLarge(script.len()));
+    }
+
+    let sigops = sigop_count(script);
+    if sigops > MAX_P2SH_SIGOPS {
+        ret.push(Warning::TooManySigops(sigops));
+    }
+
+    let start = script[..].as_ptr() as usize;
+    // The key counts a CHECKMULTISIG at this point would take: the number
+    // just pushed, or after an `OP_ENDIF`, the number ending each branch, as
+    // in Liquid watchman scripts where both multisigs share one CHECKMULTISIG
+    let mut last_numbers: Vec<i64> = vec![];
+    let mut branch_numbers: Vec<i64> = vec![];
+    let mut seen_keys: Vec<(&[u8], usize)> = vec![];
+    let mut first_uncompressed = None;
+    for instruction in script.into_iter() {
+        let mut numbers = vec![];
+        match instruction {
+            Instruction::Op(op) => {
+                match op.classify() {
+                    opcodes::Class::PushNum(n) => { numbers.push(n as i64); }
+                    opcodes::Class::Ordinary(opcodes::Ordinary::OP_IF) |
+                    opcodes::Class::Ordinary(opcodes::Ordinary::OP_NOTIF) => {
+                        branch_numbers.clear();
+                    }
+                    opcodes::Class::Ordinary(opcodes::Ordinary::OP_ELSE) => {
+                        branch_numbers.extend(last_numbers.iter().cloned());
+                    }
+                    opcodes::Class::Ordinary(opcodes::Ordinary::OP_ENDIF) => {
+                        branch_numbers.extend(last_numbers.iter().cloned());
+                        numbers = mem::replace(&mut branch_numbers, vec![]);
+                    }
+                    opcodes::Class::Ordinary(opcodes::Ordinary::OP_CHECKMULTISIG) |
+                    opcodes::Class::Ordinary(opcodes::Ordinary::OP_CHECKMULTISIGVERIFY) => {
+                        for &n in &last_numbers {
+                            if n > MAX_P2SH_SIGOPS as i64 {
+                                ret.push(Warning::TooManyMultisigKeys(n as usize));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Instruction::PushBytes(data) => {
+                if let Some(n) = push_number(data) {
+                    numbers.push(n);
+                }
+                let is_key = match (data.len(), data.first()) {
+                    (33, Some(&2)) | (33, Some(&3)) => true,
+                    _ => false
+                };
+                if is_key {
+                    let offset = data.as_ptr() as usize - start;
+                    if let Some(&(_, first)) = seen_keys.iter().find(|&&(key, _)| key == data) {
+                        ret.push(Warning::DuplicateKey(first, offset));
+                    }
+                    seen_keys.push((data, offset));
+                }
+                // Uncompressed keys, and the hybrid encoding which also gives y
+                match (data.len(), data.first()) {
+                    (65, Some(&4)) | (65, Some(&6)) | (65, Some(&7)) if first_uncompressed.is_none() => {
+                        first_uncompressed = Some(data.as_ptr() as usize - start);
+                    }
+                    _ => {}
+                }
+            }
+            Instruction::Error(_) => break
+        }
+        last_numbers = numbers;
+    }
+    if let (Some(&(_, compressed)), Some(uncompressed)) = (seen_keys.first(), first_uncompressed) {
+        ret.push(Warning::MixedKeyCompression(compressed, uncompressed));
+    }
+
+    ret
+}
+
+/// Runs the checks on a script used directly as an output script, rather
+/// than as a redemption script, returning every problem found. Only a plain
+/// `m <keys> n CHECKMULTISIG` is standard bare, with at most three keys.
+pub fn check_bare_script(script: &Script) -> Vec<Warning> {
+    // The key count, if the script ends with a CHECKMULTISIG
+    let mut n_keys = None;
+    let mut last_number = None;
+    for instruction in script.into_iter() {
+        let mut number = None;
+        n_keys = None;
+        match instruction {
+            Instruction::Op(op) => {
+                match op.classify() {
+                    opcodes::Class::PushNum(n) => { number = Some(n as i64); }
+                    opcodes::Class::Ordinary(opcodes::Ordinary::OP_CHECKMULTISIG) => { n_keys = last_number; }
+                    _ => {}
+                }
+            }
+            Instruction::PushBytes(data) => { number = push_number(data); }
+            Instruction::Error(_) => { return vec![]; }
+        }
+        last_number = number;
+    }
+    match n_keys {
+        Some(n) if n > MAX_BARE_MULTISIG_KEYS as i64 => vec![Warning::BareMultisigKeys(n as usize)],
+        _ => vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::opcodes;
+    use bitcoin::blockdata::script::{Builder, Script};
+    use secp256k1::Secp256k1;
+    use secp256k1::key::{PublicKey, SecretKey};
+    use serialize::hex::FromHex;
+
+    use asm;
+    use template;
+    use super::*;
+
+    /// A `threshold`-of-`n` CHECKMULTISIG script with distinct dummy keys
+    fn multisig(threshold: i64, n: usize) -> Script {
+        let mut ret = asm::push_int(Builder::new(), threshold);
+        for i in 0..n {
+            let mut key = "02".from_hex().unwrap();
+            key.extend(vec![i as u8 + 1; 32]);
+            ret = ret.push_slice(&key);
+        }
+        asm::push_int(ret, n as i64).push_opcode(opcodes::All::OP_CHECKMULTISIG).into_script()
+    }
+
+    #[test]
+    fn standard_multisig() {
+        assert_eq!(check_script(&multisig(2, 3)), vec![]);
+        assert_eq!(check_script(&multisig(15, 15)), vec![]);
+        assert_eq!(sigop_count(&multisig(2, 3)), 3);
+    }
+
+    #[test]
+    fn script_too_large() {
+        let mut builder = Builder::new();
+        for _ in 0..10 {
+            builder = builder.push_slice(&[0xab; 60]);
+        }
+        let script = builder.push_opcode(opcodes::All::OP_DROP).into_script();
+        assert_eq!(check_script(&script), vec![Warning::ScriptTooLarge(script.len())]);
+    }
+
+    #[test]
+    fn too_many_sigops() {
+        let mut builder = Builder::new();
+        for _ in 0..16 {
+            builder = builder.push_opcode(opcodes::All::OP_CHECKSIGVERIFY);
+        }
+        let script = builder.into_script();
+        assert_eq!(sigop_count(&script), 16);
+        assert_eq!(check_script(&script), vec![Warning::TooManySigops(16)]);
+    }
+
+    #[test]
+    fn too_many_multisig_keys() {
+        // 16 keys fit in OP_16
+        let warnings = check_script(&multisig(2, 16));
+        assert!(warnings.contains(&Warning::TooManyMultisigKeys(16)));
+        assert!(warnings.contains(&Warning::TooManySigops(16)));
+        // 17 to 20 keys can only be counted by a data push, and a data-pushed
+        // count costs the full 20 sigops
+        for n in 17..21 {
+            let warnings = check_script(&multisig(2, n));
+            assert!(warnings.contains(&Warning::TooManyMultisigKeys(n)));
+            assert!(warnings.contains(&Warning::TooManySigops(MAX_PUBKEYS_PER_MULTISIG)));
+        }
+    }
+
+    #[test]
+    fn bare_multisig_keys() {
+        // Up to three keys are standard bare, while P2SH allows fifteen
+        for n in 1..4 {
+            assert_eq!(check_bare_script(&multisig(1, n)), vec![]);
+        }
+        for &n in &[4, 15, 16, 20] {
+            assert_eq!(check_bare_script(&multisig(2, n)), vec![Warning::BareMultisigKeys(n)]);
+        }
+        assert_eq!(check_script(&multisig(2, 4)), vec![]);
+        // Other scripts are not multisigs at all
+        let key = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        assert_eq!(check_bare_script(&asm::assemble(&format!("{} OP_CHECKSIG", key)).unwrap()), vec![]);
+    }
+
+    #[test]
+    fn liquid_fedpeg_keys() {
+        let secp = Secp256k1::new();
+        let keys: Vec<_> = (1..18).map(|i| {
+            let sk = SecretKey::from_slice(&secp, &[i as u8; 32]).unwrap();
+            PublicKey::from_secret_key(&secp, &sk).unwrap()
+        }).collect();
+
+        // Each branch's key count is checked at the shared CHECKMULTISIG, and
+        // a tweaked watchman script never goes through `untemplate`, so its
+        // counts may be data pushes
+        for &(normal, emergency, bad) in &[(15, 2, None), (16, 2, Some(16)), (2, 16, Some(16)), (17, 2, Some(17))] {
+            let script = template::liquid_fedpeg(&secp, 2, &keys[..normal], 1, &keys[17 - emergency..], 4032);
+            assert!(template::is_liquid_fedpeg(&secp, &script));
+            let (new_script, _, _) = template::tweak_script(&secp, &script, b"fedpeg test").unwrap();
+            let warnings = check_script(&new_script);
+            assert_eq!(warnings.contains(&Warning::TooManyMultisigKeys(16)), bad == Some(16));
+            assert_eq!(warnings.contains(&Warning::TooManyMultisigKeys(17)), bad == Some(17));
+        }
+    }
+
+    #[test]
+    fn mixed_key_compression() {
+        let secp = Secp256k1::new();
+        let compressed = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        let uncompressed = "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798\
+                            483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+        let script = asm::assemble(&format!("1 {} {} 2 OP_CHECKMULTISIG", compressed, uncompressed)).unwrap();
+        assert_eq!(check_script(&script), vec![Warning::MixedKeyCompression(2, 36)]);
+        // Either kind of key alone is fine
+        let script = asm::assemble(&format!("1 {} {} 2 OP_CHECKMULTISIG", uncompressed, uncompressed.replace("0479", "0679"))).unwrap();
+        assert_eq!(check_script(&script), vec![]);
+        // Tweaking a template refuses uncompressed keys, so only scripts
+        // from elsewhere can mix them
+        let script = asm::assemble(&format!("1 {} {} 2 OP_CHECKMULTISIG", uncompressed, compressed)).unwrap();
+        assert_eq!(check_script(&script), vec![Warning::MixedKeyCompression(68, 2)]);
+        assert!(template::tweak_script(&secp, &script, b"mixed key test").is_err());
+    }
+
+    #[test]
+    fn duplicate_key() {
+        let secp = Secp256k1::new();
+        let key_1 = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let key_2 = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        let script = asm::assemble(&format!("2 {} {} {} 3 OP_CHECKMULTISIG", key_1, key_2, key_1)).unwrap();
+        assert_eq!(check_script(&script), vec![Warning::DuplicateKey(2, 70)]);
+
+        let (new_script, locations, tweaked) = template::tweak_script(&secp, &script, b"duplicate key test").unwrap();
+        assert_eq!(locations.len(), 3);
+        assert_eq!(locations[0].key, locations[2].key);
+        assert_eq!(tweaked[0], tweaked[2]);
+        assert!(tweaked[0] != tweaked[1]);
+        assert!(tweaked[0] != locations[0].key);
+        // The modified script repeats the same tweaked key at the same offsets
+        assert_eq!(check_script(&new_script), vec![Warning::DuplicateKey(2, 70)]);
+        assert_eq!(&new_script[2..35], &tweaked[0].serialize_vec(&secp, true)[..]);
+        assert_eq!(&new_script[70..103], &tweaked[0].serialize_vec(&secp, true)[..]);
+    }
+
+    #[test]
+    fn push_numbers() {
+        assert_eq!(push_number(&[17]), Some(17));
+        assert_eq!(push_number(&[0x80, 0x00]), Some(128));
+        assert_eq!(push_number(&[0x81]), Some(-1));
+        // Non-minimal encodings are not numbers
+        assert_eq!(push_number(&[17, 0x00]), None);
+        assert_eq!(push_number(&[0x00]), None);
+        assert_eq!(push_number(&[]), None);
+        assert_eq!(push_number(&[1, 2, 3, 4, 5]), None);
+    }
+}
@@ -0,0 +1,114 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+
+//! # Clipboard
+//! Putting a result on the system clipboard rather than on the screen. The
+//! clipboard is reached through whichever of the usual programs is there:
+//! `wl-copy` under Wayland, `xclip` or `xsel` under X, `pbcopy` on macOS
+//! and `clip` on Windows. Secrets are cleared again after a while, unless
+//! something else has been copied over them by then.
+//!
+
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// How long a secret is left on the clipboard
+pub const CLEAR_SECONDS: u64 = 45;
+
+/// Clipboard error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// None of the clipboard programs could be run
+    NoTool,
+    /// A clipboard program failed
+    Failed(String)
+}
+
+/// The programs which copy stdin to the clipboard, and which print it, to
+/// try in order
+fn tools() -> Vec<(&'static [&'static str], &'static [&'static str])> {
+    let mut ret: Vec<(&'static [&'static str], &'static [&'static str])> = vec![];
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        ret.push((&["wl-copy"], &["wl-paste", "--no-newline"]));
+    }
+    if env::var_os("DISPLAY").is_some() {
+        ret.push((&["xclip", "-selection", "clipboard"], &["xclip", "-selection", "clipboard", "-o"]));
+        ret.push((&["xsel", "--clipboard", "--input"], &["xsel", "--clipboard", "--output"]));
+    }
+    if cfg!(target_os = "macos") {
+        ret.push((&["pbcopy"], &["pbpaste"]));
+    }
+    if cfg!(windows) {
+        // Windows has no program to print the clipboard
+        ret.push((&["clip"], &[]));
+    }
+    ret
+}
+
+/// Run a clipboard program, giving it some input
+fn run(command: &[&str], input: &str) -> Result<(), Error> {
+    let mut child = try!(Command::new(command[0]).args(&command[1..])
+                             .stdin(Stdio::piped())
+                             .stdout(Stdio::null())
+                             .stderr(Stdio::null())
+                             .spawn()
+                             .map_err(|_| Error::NoTool));
+    try!(child.stdin.take().unwrap().write_all(input.as_bytes())
+              .map_err(|e| Error::Failed(format!("{}: {}", command[0], e))));
+    match child.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(Error::Failed(format!("{} failed ({})", command[0], status))),
+        Err(e) => Err(Error::Failed(format!("{}: {}", command[0], e)))
+    }
+}
+
+/// Put some text on the clipboard
+pub fn copy(text: &str) -> Result<(), Error> {
+    for (copy, _) in tools() {
+        match run(copy, text) {
+            Err(Error::NoTool) => continue,
+            result => { return result; }
+        }
+    }
+    Err(Error::NoTool)
+}
+
+/// The text on the clipboard, if it can be read
+fn paste() -> Option<String> {
+    for (_, paste) in tools() {
+        if paste.is_empty() {
+            continue;
+        }
+        match Command::new(paste[0]).args(&paste[1..]).stderr(Stdio::null()).output() {
+            Ok(ref output) if output.status.success() => { return String::from_utf8(output.stdout.clone()).ok(); }
+            Ok(_) => { return None; }
+            Err(_) => continue
+        }
+    }
+    None
+}
+
+/// Wait, then clear the clipboard if it still holds some text. Where the
+/// clipboard cannot be read it is cleared regardless.
+pub fn clear_after(text: &str, seconds: u64) {
+    thread::sleep(Duration::from_secs(seconds));
+    match paste() {
+        Some(ref current) if current != text => {}
+        _ => { let _ = copy(""); }
+    }
+}
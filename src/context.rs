@@ -0,0 +1,106 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Secp256k1 Context
+//! The one signing and verification context every tweak shares. Building
+//! a context means computing its precomputed tables, which costs far more
+//! than a tweak, so it is built the first time it is asked for and then
+//! kept for the life of the process. It is randomized once, when built,
+//! for side-channel resistance.
+//!
+//! Secret keys are tweaked, and sign, with signing contexts of their own
+//! instead, one per thread so that threads using them at once never wait
+//! on each other. Each is re-randomized before every tweak or signature,
+//! from a generator its thread keeps open, so that a long-running server
+//! does not do all of its secret-key arithmetic under the same blinding.
+//!
+
+use bitcoin::util::contracthash;
+use secp256k1::{ContextFlag, Secp256k1};
+use secp256k1::key::SecretKey;
+
+use std::cell::RefCell;
+use std::sync::{Once, ONCE_INIT};
+
+use osrng::OsRng;
+
+static INIT: Once = ONCE_INIT;
+static mut CONTEXT: *const Secp256k1 = 0 as *const Secp256k1;
+
+thread_local! {
+    /// This thread's signing context, and the generator it is re-randomized
+    /// from, if one could be opened
+    static SIGNING: RefCell<(Secp256k1, Option<OsRng>)> = RefCell::new((Secp256k1::with_caps(ContextFlag::SignOnly), OsRng::new().ok()))
+}
+
+/// The shared context, built on first use
+pub fn secp() -> &'static Secp256k1 {
+    unsafe {
+        INIT.call_once(|| {
+            let mut secp = Secp256k1::new();
+            // Without an RNG the context still works, only unblinded
+            if let Ok(mut rng) = OsRng::new() {
+                secp.randomize(&mut rng);
+            }
+            CONTEXT = Box::into_raw(Box::new(secp));
+        });
+        &*CONTEXT
+    }
+}
+
+/// Do something with a secret key in this thread's signing context,
+/// re-randomizing it first. `f` must not itself call this.
+pub fn with_signing<T, F: FnOnce(&Secp256k1) -> T>(f: F) -> T {
+    SIGNING.with(|signing| {
+        let mut signing = signing.borrow_mut();
+        let (ref mut secp, ref mut rng) = *signing;
+        // Without an RNG the context keeps its last blinding
+        if let Some(ref mut rng) = *rng {
+            secp.randomize(rng);
+        }
+        f(secp)
+    })
+}
+
+/// Tweak a secret key by a contract, re-randomizing this thread's signing
+/// context first
+pub fn tweak_secret_key(key: &SecretKey, contract: &[u8]) -> Result<SecretKey, contracthash::Error> {
+    with_signing(|secp| contracthash::tweak_secret_key(secp, key, contract))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::util::contracthash;
+    use secp256k1::key::SecretKey;
+
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn tweaks_agree_across_threads() {
+        let key = SecretKey::from_slice(secp(), &[1; 32]).unwrap();
+        let contract = b"a contract, on any thread";
+        let expected = contracthash::tweak_secret_key(secp(), &key, contract).unwrap();
+        let threads: Vec<_> = (0..4).map(|_| thread::spawn(move || {
+            // Every tweak gets a fresh blinding, and the same result
+            (0..3).map(|_| tweak_secret_key(&key, contract).unwrap()).collect::<Vec<_>>()
+        })).collect();
+        for thread in threads {
+            for tweaked in thread.join().unwrap() {
+                assert_eq!(tweaked, expected);
+            }
+        }
+    }
+}
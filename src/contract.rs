@@ -16,13 +16,20 @@
 //! Support for Elements Alpha contracts
 //!
 
+use bitcoin::blockdata::script::Script;
 use bitcoin::network::constants::Network;
 use bitcoin::util::address::{self, Address};
 use bitcoin::util::base58::{self, FromBase58};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use secp256k1::Secp256k1;
 use serialize::hex::{self, FromHex};
 
 use std::fmt;
 
+use chain::Chain;
+use elements::ConfidentialAddress;
+
 /// Total length of a contract in bytes
 pub const CONTRACT_LEN: usize = 40;
 /// Length of the data portion of the contract in bytes
@@ -36,16 +43,19 @@ enum Type {
     /// Pay-to-pubkeyhash Bitcoin script
     PubkeyHash,
     /// P2SH Bitcoin script
-    ScriptHash
+    ScriptHash,
+    /// A type defined by a plugin, by its tag
+    Custom([u8; 4])
 }
 
 impl Type {
     /// Serialize the type in a way that can be used for contracthash key tweaking
-    pub fn serialize(&self) -> &'static [u8; 4] {
+    pub fn serialize(&self) -> [u8; 4] {
         match *self {
-            Type::Text => b"TEXT",
-            Type::PubkeyHash => b"P2PH",
-            Type::ScriptHash => b"P2SH"
+            Type::Text => *b"TEXT",
+            Type::PubkeyHash => *b"P2PH",
+            Type::ScriptHash => *b"P2SH",
+            Type::Custom(tag) => tag
         }
     }
 
@@ -91,6 +101,18 @@ impl Nonce {
     pub fn from_contract(contract: &Contract) -> Nonce {
         contract.nonce
     }
+
+    /// Why the nonce is too predictable to hide its contract, if it is.
+    /// Nonces made by pacthash are random; this catches ones typed in.
+    pub fn weakness(&self) -> Option<&'static str> {
+        if self.0.iter().all(|&b| b == 0) {
+            Some("it is all zeros")
+        } else if self.0.iter().all(|&b| b == self.0[0]) {
+            Some("it is one byte repeated")
+        } else {
+            None
+        }
+    }
 }
 
 impl fmt::LowerHex for Nonce {
@@ -128,21 +150,98 @@ pub enum Error {
 impl Contract {
     /// Serialize the contract in a way that can be used for contracthash key tweaking
     pub fn serialize(&self) -> Vec<u8> {
-        let ty = self.ty.serialize();
-        let mut ret = Vec::with_capacity(ty.len() + self.nonce.len() + self.data.len());
-        ret.extend(&ty[..]);
-        ret.extend(&self.nonce[..]);
-        ret.extend(&self.data[..]);
+        self.to_bytes()[..].to_owned()
+    }
+
+    /// Serialize the contract into a buffer, without allocating
+    pub fn serialize_into(&self, buf: &mut [u8; CONTRACT_LEN]) {
+        buf[0..4].copy_from_slice(&self.ty.serialize());
+        buf[4..20].copy_from_slice(&self.nonce[..]);
+        buf[20..].copy_from_slice(&self.data[..]);
+    }
+
+    /// The serialized contract, as an array rather than a `Vec`; this is
+    /// what tweaks should be given
+    pub fn to_bytes(&self) -> [u8; CONTRACT_LEN] {
+        let mut ret = [0; CONTRACT_LEN];
+        self.serialize_into(&mut ret);
+        ret
+    }
+
+    /// The four-byte name of the contract's type: TEXT, P2PH, P2SH or the
+    /// tag of a plugin's type
+    pub fn type_name(&self) -> String {
+        String::from_utf8_lossy(&self.ty.serialize()).into_owned()
+    }
+
+    /// The tag of the contract's type, if a plugin defines it
+    pub fn custom_tag(&self) -> Option<[u8; 4]> {
+        match self.ty {
+            Type::Custom(tag) => Some(tag),
+            _ => None
+        }
+    }
+
+    /// The data of the contract: its text, or the hash it pays to
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The scriptPubKey a P2PH or P2SH contract pays to on the sidechain,
+    /// which is what claims a peg-in to it; text contracts have none
+    pub fn destination_script(&self) -> Option<Script> {
+        let mut script = match self.ty {
+            Type::Text | Type::Custom(_) => { return None; }
+            // OP_DUP OP_HASH160 <hash>
+            Type::PubkeyHash => vec![0x76, 0xa9, 0x14],
+            // OP_HASH160 <hash>
+            Type::ScriptHash => vec![0xa9, 0x14]
+        };
+        script.extend(&self.data[..]);
+        match self.ty {
+            // OP_EQUALVERIFY OP_CHECKSIG
+            Type::PubkeyHash => script.extend(&[0x88, 0xac]),
+            // OP_EQUAL
+            _ => script.push(0x87)
+        }
+        Some(Script::from(script))
+    }
+
+    /// The SHA256 of the serialized contract, which an explicit on-chain
+    /// commitment to it holds
+    pub fn commitment_hash(&self) -> [u8; 32] {
+        let mut sha = Sha256::new();
+        sha.input(&self.to_bytes()[..]);
+        let mut ret = [0; 32];
+        sha.result(&mut ret);
         ret
     }
 
+    /// The OP_RETURN scriptPubKey committing to the contract explicitly,
+    /// for counterparties who want that rather than a tweak
+    pub fn op_return_script(&self) -> Script {
+        // OP_RETURN <32 bytes>
+        let mut script = vec![0x6a, 0x20];
+        script.extend(&self.commitment_hash()[..]);
+        Script::from(script)
+    }
+
     /// Decode a hex string as a contract
     pub fn from_hex(data: &str) -> Result<Contract, Error> {
+        Contract::from_hex_custom(data, &[])
+    }
+
+    /// Decode a hex string as a contract, which may also be of one of the
+    /// types plugins define with these tags
+    pub fn from_hex_custom(data: &str, tags: &[[u8; 4]]) -> Result<Contract, Error> {
         let bytes = try!(data.from_hex().map_err(Error::Hex));
         if bytes.len() != CONTRACT_LEN {
             return Err(Error::BadLength(bytes.len()));
         }
-        let ty = try!(Type::deserialize(&bytes[0..4]));
+        let ty = match tags.iter().find(|tag| tag[..] == bytes[0..4]) {
+            Some(&tag) => Type::Custom(tag),
+            None => try!(Type::deserialize(&bytes[0..4]))
+        };
 
         Ok(Contract {
             ty: ty,
@@ -151,9 +250,16 @@ impl Contract {
         })
     }
 
-    /// Decode a P2SH address as a contract
-    pub fn from_p2sh_base58_str(s: &str, nonce: Nonce, expected_network: Network) -> Result<Contract, Error> {
-        let addr: Address = try!(FromBase58::from_base58check(s).map_err(Error::Base58));
+    /// Decode a P2SH address of a chain as a contract. On Bitcoin's own
+    /// chains Elements confidential addresses are accepted too; their
+    /// blinding key is not part of the contract.
+    pub fn from_p2sh_base58_str(s: &str, nonce: Nonce, chain: Chain) -> Result<Contract, Error> {
+        let expected_network = chain.network();
+        let addr: Address = match ConfidentialAddress::from_base58check(&Secp256k1::without_caps(), s) {
+            Ok(confidential) if chain.is_bitcoin() => confidential.address,
+            _ if chain.is_bitcoin() => try!(FromBase58::from_base58check(s).map_err(Error::Base58)),
+            _ => try!(chain.decode_address(s).map_err(Error::Base58))
+        };
         if addr.network != expected_network {
             return Err(Error::WrongNetwork(addr.network, expected_network));
         }
@@ -180,15 +286,109 @@ impl Contract {
             })
         }
     }
+
+    /// A contract of a type a plugin defines, from the data the plugin
+    /// encoded its payload as
+    pub fn from_custom(tag: [u8; 4], nonce: Nonce, data: &[u8]) -> Result<Contract, Error> {
+        if data.len() != DATA_LEN {
+            Err(Error::BadLength(data.len()))
+        } else {
+            Ok(Contract {
+                ty: Type::Custom(tag),
+                nonce: nonce,
+                data: data.to_owned()
+            })
+        }
+    }
 }
 
 impl fmt::LowerHex for Contract {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // TODO no need to allocate here, I'm just being lazy
-        for ch in &self.serialize()[..] {
+        for ch in &self.to_bytes()[..] {
             try!(write!(f, "{:02x}", *ch));
         }
         Ok(())
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::script::Script;
+    use bitcoin::network::constants::Network;
+    use bitcoin::util::address::Address;
+    use bitcoin::util::base58::ToBase58;
+    use rand::{Rng, SeedableRng, StdRng};
+
+    use chain::Chain;
+    use super::*;
+
+    const ROUNDS: usize = 256;
+
+    fn rng() -> StdRng {
+        let seed: &[_] = &[1, 8, 7];
+        SeedableRng::from_seed(seed)
+    }
+
+    #[test]
+    fn nonce_round_trip() {
+        let mut rng = rng();
+        for _ in 0..ROUNDS {
+            let nonce: Nonce = rng.gen();
+            let hex = format!("{:x}", nonce);
+            assert_eq!(hex.len(), 2 * NONCE_LEN);
+            let parsed = Nonce::from_hex(&hex).unwrap();
+            assert!(parsed == nonce);
+            assert_eq!(parsed.serialize(), &nonce[..]);
+        }
+    }
+
+    #[test]
+    fn contract_round_trip() {
+        let mut rng = rng();
+        let tag = *b"TEST";
+        for _ in 0..ROUNDS {
+            let nonce: Nonce = rng.gen();
+            let text: String = (0..DATA_LEN).map(|_| (b' ' + rng.gen_range(0, 95)) as char).collect();
+            let data: Vec<u8> = rng.gen_iter().take(DATA_LEN).collect();
+            let contracts = [Contract::from_ascii_str(&text, nonce).unwrap(),
+                             Contract::from_custom(tag, nonce, &data).unwrap()];
+            for contract in &contracts {
+                let hex = format!("{:x}", contract);
+                assert_eq!(hex.len(), 2 * CONTRACT_LEN);
+                let parsed = Contract::from_hex_custom(&hex, &[tag]).unwrap();
+                assert!(parsed == *contract);
+                assert_eq!(parsed.serialize(), &contract.to_bytes()[..]);
+                assert!(Nonce::from_contract(&parsed) == nonce);
+            }
+            // Without the plugin's tag the custom contract is refused
+            match Contract::from_hex(&format!("{:x}", contracts[1])) {
+                Err(Error::BadType(ref ty)) if ty[..] == tag[..] => {}
+                _ => panic!("custom contract parsed without its tag")
+            }
+        }
+    }
+
+    #[test]
+    fn address_round_trip() {
+        let mut rng = rng();
+        for _ in 0..ROUNDS {
+            let nonce: Nonce = rng.gen();
+            let script_data: Vec<u8> = rng.gen_iter().take(40).collect();
+            let address = Address::from_script(Network::Testnet, &Script::from(script_data));
+            let contract = Contract::from_p2sh_base58_str(&address.to_base58check(), nonce,
+                                                          Chain::from_network(Network::Testnet)).unwrap();
+            assert_eq!(contract.type_name(), "P2SH");
+            assert_eq!(contract.data(), &address.hash[..]);
+            assert_eq!(contract.destination_script(), Some(address.script_pubkey()));
+            let parsed = Contract::from_hex(&format!("{:x}", contract)).unwrap();
+            assert!(parsed == contract);
+            // An address of the other network is refused
+            match Contract::from_p2sh_base58_str(&address.to_base58check(), nonce,
+                                                 Chain::from_network(Network::Bitcoin)) {
+                Err(Error::WrongNetwork(Network::Testnet, Network::Bitcoin)) => {}
+                _ => panic!("testnet address accepted as mainnet")
+            }
+        }
+    }
+}
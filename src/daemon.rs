@@ -0,0 +1,237 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Daemon
+//! What running the server as a long-lived service needs: a config file, a
+//! pidfile, and noticing SIGTERM and SIGHUP. Signal handlers only set flags,
+//! which the accept loop polls; anything more is unsafe inside a handler.
+//!
+//! The config file has one `key = value` setting per line, and `#` comments:
+//!
+//! * `network`: `bitcoin` (the default) or `testnet`
+//! * `socket`: path of a unix socket to serve JSON-RPC on
+//! * `http`: address to serve HTTP on, e.g. `127.0.0.1:8080`
+//! * `allow-privkeys`: `yes` to serve `tweak_privkey` over HTTP
+//! * `tokens`: file of the bearer tokens HTTP requests must carry
+//! * `redeem-script`: redeem script to tweak, in hex or asm
+//! * `rate-limit`: requests a second each client may make, as
+//!   `RATE[/BURST]`
+//! * `global-rate-limit`: requests a second all clients together may make
+//! * `log-level`: `error`, `warn`, `info` (the default) or `debug`
+//! * `log-format`: `text` (the default) or `json`
+//! * `log-file`: file to append log lines to, rather than stderr; it is
+//!   reopened on reload, so that it can be rotated
+//!
+//! HTTP is served without TLS, so with `allow-privkeys` or `tokens` the
+//! `http` address must be a loopback one; a proxy in front may terminate
+//! TLS for clients elsewhere.
+//!
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::network::constants::Network;
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+use asm;
+use log;
+use ratelimit::Rate;
+use server;
+
+/// Signal numbers, which are the same on every Unix we run on
+const SIGHUP: i32 = 1;
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+
+/// Set when the daemon has been asked to stop
+static STOP: AtomicBool = ATOMIC_BOOL_INIT;
+/// Set when the daemon has been asked to reload its config
+static RELOAD: AtomicBool = ATOMIC_BOOL_INIT;
+
+extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+/// Daemon error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// A file could not be read or written
+    Io(String),
+    /// A config line was not a `key = value` setting
+    BadLine(usize, String),
+    /// A setting had a value it cannot take
+    BadValue(String, String),
+    /// A setting is not known
+    UnknownKey(String),
+    /// Neither `socket` nor `http` was set
+    NoListener,
+    /// Private keys or tokens would be served over plaintext HTTP on an
+    /// address other machines can reach
+    NotLoopback(String),
+    /// The pidfile names a process which is still running
+    AlreadyRunning(String)
+}
+
+/// The settings of a config file
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The network addresses are for
+    pub network: Network,
+    /// Path of the unix socket to serve on, if any
+    pub socket: Option<String>,
+    /// Address to serve HTTP on, if any
+    pub http: Option<String>,
+    /// Whether `tweak_privkey` is served over HTTP
+    pub allow_privkeys: bool,
+    /// File of the tokens HTTP requests must carry, if any
+    pub tokens: Option<String>,
+    /// The redeem script to tweak, if any
+    pub script: Option<Script>,
+    /// The rate each client is limited to, if any
+    pub rate_limit: Option<Rate>,
+    /// The rate all clients together are limited to, if any
+    pub global_rate_limit: Option<Rate>,
+    /// The least important level logged
+    pub log_level: String,
+    /// The format of log lines
+    pub log_format: String,
+    /// File to append log lines to, if any
+    pub log_file: Option<String>
+}
+
+/// Read a config file
+pub fn read_config(filename: &str) -> Result<Config, Error> {
+    let mut text = String::new();
+    try!(File::open(filename).and_then(|mut f| f.read_to_string(&mut text))
+             .map_err(|e| Error::Io(format!("{}: {}", filename, e))));
+    let mut ret = Config {
+        network: Network::Bitcoin,
+        socket: None,
+        http: None,
+        allow_privkeys: false,
+        tokens: None,
+        script: None,
+        rate_limit: None,
+        global_rate_limit: None,
+        log_level: "info".to_owned(),
+        log_format: "text".to_owned(),
+        log_file: None
+    };
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(2, '=');
+        let (key, value) = match (fields.next(), fields.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => { return Err(Error::BadLine(n + 1, line.to_owned())); }
+        };
+        let bad_value = || Error::BadValue(key.to_owned(), value.to_owned());
+        match key {
+            "network" => {
+                ret.network = match value {
+                    "bitcoin" => Network::Bitcoin,
+                    "testnet" => Network::Testnet,
+                    _ => { return Err(bad_value()); }
+                };
+            }
+            "socket" => ret.socket = Some(value.to_owned()),
+            "http" => ret.http = Some(value.to_owned()),
+            "tokens" => ret.tokens = Some(value.to_owned()),
+            "log-file" => ret.log_file = Some(value.to_owned()),
+            "rate-limit" => ret.rate_limit = Some(try!(Rate::parse(value).map_err(|_| bad_value()))),
+            "global-rate-limit" => ret.global_rate_limit = Some(try!(Rate::parse(value).map_err(|_| bad_value()))),
+            "log-level" => {
+                if log::Level::from_name(value).is_none() {
+                    return Err(bad_value());
+                }
+                ret.log_level = value.to_owned();
+            }
+            "log-format" => {
+                if value != "text" && value != "json" {
+                    return Err(bad_value());
+                }
+                ret.log_format = value.to_owned();
+            }
+            "allow-privkeys" => {
+                ret.allow_privkeys = match value {
+                    "yes" => true,
+                    "no" => false,
+                    _ => { return Err(bad_value()); }
+                };
+            }
+            "redeem-script" => {
+                match asm::Encoding::Hex.decode(value).or_else(|_| asm::assemble(value)) {
+                    Ok(script) => ret.script = Some(script),
+                    Err(_) => { return Err(bad_value()); }
+                }
+            }
+            _ => { return Err(Error::UnknownKey(key.to_owned())); }
+        }
+    }
+    if ret.socket.is_none() && ret.http.is_none() {
+        return Err(Error::NoListener);
+    }
+    if let Some(ref http) = ret.http {
+        if (ret.allow_privkeys || ret.tokens.is_some()) && !server::is_loopback(http) {
+            return Err(Error::NotLoopback(http.clone()));
+        }
+    }
+    Ok(ret)
+}
+
+extern "C" fn on_signal(signum: i32) {
+    match signum {
+        SIGHUP => RELOAD.store(true, Ordering::SeqCst),
+        _ => STOP.store(true, Ordering::SeqCst)
+    }
+}
+
+/// Catch SIGTERM and SIGINT as requests to stop, and SIGHUP as a request
+/// to reload
+pub fn install_handlers() {
+    unsafe {
+        signal(SIGTERM, on_signal);
+        signal(SIGINT, on_signal);
+        signal(SIGHUP, on_signal);
+    }
+}
+
+/// Whether the daemon has been asked to stop
+pub fn stopping() -> bool {
+    STOP.load(Ordering::SeqCst)
+}
+
+/// Whether the daemon has been asked to reload since this was last called
+pub fn take_reload() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+
+/// Write our pid to a pidfile, unless it names a process still running.
+/// Running processes are looked for in /proc, so elsewhere than Linux a
+/// stale pidfile is simply overwritten.
+pub fn write_pidfile(filename: &str) -> Result<(), Error> {
+    let mut old = String::new();
+    if File::open(filename).and_then(|mut f| f.read_to_string(&mut old)).is_ok() {
+        let old = old.trim();
+        if !old.is_empty() && fs::metadata(format!("/proc/{}", old)).is_ok() {
+            return Err(Error::AlreadyRunning(old.to_owned()));
+        }
+    }
+    File::create(filename).and_then(|mut f| writeln!(f, "{}", process::id()))
+                          .map_err(|e| Error::Io(format!("{}: {}", filename, e)))
+}
@@ -0,0 +1,107 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Duplicate Detection
+//! Noticing when a batch names the same contract, nonce or address more
+//! than once. Contracts are compared without their nonces, since the same
+//! contract under a second nonce is a second address for one customer.
+//! Only a digest of each value is kept, so that a batch of any size can be
+//! checked as it streams past.
+//!
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use serialize::hex::ToHex;
+
+use std::collections::HashMap;
+
+use contract::{Contract, Nonce};
+
+/// What a batch has more than one of
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Kind {
+    /// A contract's type and data
+    Contract,
+    /// A nonce
+    Nonce,
+    /// A resulting address
+    Address
+}
+
+impl Kind {
+    /// The name of the kind, for reports
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Kind::Contract => "contract",
+            Kind::Nonce => "nonce",
+            Kind::Address => "address"
+        }
+    }
+}
+
+/// A value seen again
+#[derive(Clone, Debug)]
+pub struct Duplicate {
+    /// What the value is
+    pub kind: Kind,
+    /// The value, as printed
+    pub value: String,
+    /// The item it was first seen at
+    pub first: usize,
+    /// The item it was seen at again
+    pub again: usize
+}
+
+/// The values of a batch seen so far, and which were seen twice
+#[derive(Clone, Debug, Default)]
+pub struct Tracker {
+    seen: HashMap<(Kind, [u8; 32]), usize>,
+    duplicates: Vec<Duplicate>
+}
+
+impl Tracker {
+    /// A tracker which has seen nothing
+    pub fn new() -> Tracker {
+        Tracker::default()
+    }
+
+    /// Note a value of some item, returning the item it was first seen at
+    /// if this is not the first time
+    pub fn add(&mut self, kind: Kind, value: String, item: usize) -> Option<usize> {
+        let mut digest = [0; 32];
+        let mut sha = Sha256::new();
+        sha.input(value.as_bytes());
+        sha.result(&mut digest);
+        if let Some(&first) = self.seen.get(&(kind, digest)) {
+            self.duplicates.push(Duplicate { kind: kind, value: value, first: first, again: item });
+            return Some(first);
+        }
+        self.seen.insert((kind, digest), item);
+        None
+    }
+
+    /// Note the contract of some item, and its nonce, returning whether
+    /// either had been seen before
+    pub fn add_contract(&mut self, contract: &Contract, item: usize) -> bool {
+        let payload = format!("{} {}", contract.type_name(), contract.data().to_hex());
+        let contract_seen = self.add(Kind::Contract, payload, item).is_some();
+        let nonce_seen = self.add(Kind::Nonce, format!("{:x}", Nonce::from_contract(contract)), item).is_some();
+        contract_seen || nonce_seen
+    }
+
+    /// The values seen more than once, in the order they were seen again
+    pub fn duplicates(&self) -> &[Duplicate] {
+        &self.duplicates
+    }
+}
@@ -0,0 +1,187 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Output Descriptors
+//! Describing the outputs paying to tweaked keys and modified scripts as
+//! output descriptors, with their checksums, and the `importdescriptors`
+//! requests which have Bitcoin Core watch them. Tweaked keys are not
+//! derivable from any wallet key, so they appear as plain hex keys. Wallets
+//! from before descriptors (and forks of them) instead take `importmulti`
+//! requests, which name the scripts and keys directly.
+//!
+
+use bitcoin::blockdata::script::Script;
+use secp256k1::Secp256k1;
+use secp256k1::key::PublicKey;
+use serialize::hex::ToHex;
+use serialize::json::Json;
+
+use std::collections::BTreeMap;
+
+use template;
+
+/// The characters descriptors may use, in the order the checksum values them
+const INPUT_CHARSET: &'static str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+/// The checksum character set, indexed by 5-bit value
+const CHECKSUM_CHARSET: &'static [u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+/// Generator coefficients of the descriptor checksum
+const GENERATOR: [u64; 5] = [0xf5dee51989, 0xa9fdca3312, 0x1bab10e32d, 0x3706b1677a, 0x644d626ffd];
+
+/// How a modified script is paid to
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Wrapper {
+    /// P2SH, `sh(...)`
+    Sh,
+    /// P2WSH, `wsh(...)`
+    Wsh,
+    /// P2SH-wrapped P2WSH, `sh(wsh(...))`
+    ShWsh
+}
+
+/// Computes the descriptor checksum polynomial over some 5-bit values
+fn polymod(values: &[u64]) -> u64 {
+    let mut chk: u64 = 1;
+    for &v in values {
+        let b = chk >> 35;
+        chk = ((chk & 0x7ffffffff) << 5) ^ v;
+        for (i, gen) in GENERATOR.iter().enumerate() {
+            if (b >> i) & 1 == 1 {
+                chk ^= *gen;
+            }
+        }
+    }
+    chk
+}
+
+/// Append the `#checksum` to a descriptor, or `None` if it has a character
+/// descriptors cannot use
+pub fn with_checksum(desc: &str) -> Option<String> {
+    // Each character gives its low five bits, and each three characters
+    // the groups of their high bits
+    let mut values = vec![];
+    let mut groups = vec![];
+    for c in desc.chars() {
+        let v = match INPUT_CHARSET.find(c) {
+            Some(v) => v as u64,
+            None => { return None; }
+        };
+        values.push(v & 31);
+        groups.push(v >> 5);
+        if groups.len() == 3 {
+            values.push(groups[0] * 9 + groups[1] * 3 + groups[2]);
+            groups.clear();
+        }
+    }
+    match groups.len() {
+        1 => values.push(groups[0]),
+        2 => values.push(groups[0] * 3 + groups[1]),
+        _ => {}
+    }
+    values.extend([0; 8].iter().cloned());
+    let chk = polymod(&values) ^ 1;
+
+    let mut ret = desc.to_owned();
+    ret.push('#');
+    for i in 0..8 {
+        ret.push(CHECKSUM_CHARSET[((chk >> (5 * (7 - i))) & 31) as usize] as char);
+    }
+    Some(ret)
+}
+
+/// The descriptor of the P2PKH output of a key
+pub fn pkh(secp: &Secp256k1, key: &PublicKey) -> String {
+    format!("pkh({})", key.serialize_vec(secp, true)[..].to_hex())
+}
+
+/// The descriptor of a script paid to in some way. Multisigs and P2PK
+/// scripts are described by their keys, so Core knows what signs for them;
+/// anything else only by the scriptPubKey, which is enough to watch it.
+pub fn script(secp: &Secp256k1, script: &Script, script_pubkey: &Script, wrapper: Wrapper) -> String {
+    let keys: Vec<PublicKey> = template::locate_keys(secp, script).into_iter().map(|location| location.key).collect();
+    let hex_keys: Vec<String> = keys.iter().map(|key| key.serialize_vec(secp, true)[..].to_hex()).collect();
+    let inner = if template::is_p2pk(secp, script) && script.len() == 35 {
+        Some(format!("pk({})", hex_keys[0]))
+    } else {
+        (1..keys.len() + 1).find(|&m| template::multisig(secp, m, &keys) == *script)
+                           .map(|m| format!("multi({},{})", m, hex_keys.join(",")))
+    };
+    match (inner, wrapper) {
+        (Some(inner), Wrapper::Sh) => format!("sh({})", inner),
+        (Some(inner), Wrapper::Wsh) => format!("wsh({})", inner),
+        (Some(inner), Wrapper::ShWsh) => format!("sh(wsh({}))", inner),
+        (None, _) => format!("raw({})", script_pubkey[..].to_hex())
+    }
+}
+
+/// An `importdescriptors` request watching a descriptor (without its
+/// checksum) from some time, or `now`, on. Watch-only wallets take these
+/// as they are.
+pub fn import_request(desc: &str, label: &str, timestamp: Option<u64>) -> Json {
+    let mut request = BTreeMap::new();
+    request.insert("desc".to_owned(), Json::String(with_checksum(desc).unwrap()));
+    request.insert("timestamp".to_owned(), match timestamp {
+        Some(time) => Json::U64(time),
+        None => Json::String("now".to_owned())
+    });
+    request.insert("label".to_owned(), Json::String(label.to_owned()));
+    request.insert("internal".to_owned(), Json::Boolean(false));
+    Json::Object(request)
+}
+
+/// An `importmulti` request watching an address, with the scripts and keys
+/// that let the wallet treat it as solvable
+pub fn importmulti_request(secp: &Secp256k1, address: &str, redeem_script: Option<&Script>, witness_script: Option<&Script>,
+                           keys: &[PublicKey], label: &str, timestamp: Option<u64>) -> Json {
+    let mut script_pubkey = BTreeMap::new();
+    script_pubkey.insert("address".to_owned(), Json::String(address.to_owned()));
+    let mut request = BTreeMap::new();
+    request.insert("scriptPubKey".to_owned(), Json::Object(script_pubkey));
+    if let Some(script) = redeem_script {
+        request.insert("redeemscript".to_owned(), Json::String(script[..].to_hex()));
+    }
+    if let Some(script) = witness_script {
+        request.insert("witnessscript".to_owned(), Json::String(script[..].to_hex()));
+    }
+    request.insert("pubkeys".to_owned(), Json::Array(keys.iter().map(|key| Json::String(key.serialize_vec(secp, true)[..].to_hex())).collect()));
+    request.insert("timestamp".to_owned(), match timestamp {
+        Some(time) => Json::U64(time),
+        None => Json::String("now".to_owned())
+    });
+    request.insert("label".to_owned(), Json::String(label.to_owned()));
+    request.insert("watchonly".to_owned(), Json::Boolean(true));
+    Json::Object(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::with_checksum;
+
+    #[test]
+    fn checksum() {
+        // BIP380's example, and the descriptors of Bitcoin Core's documentation
+        let vectors = [
+            ("raw(deadbeef)", "89f8spxm"),
+            ("sh(multi(2,[00000000/111'/222]xprvA1RpRA33e1JQ7ifknakTFpgNXPmW2YvmhqLQYMmrj4xJXXWYpDPS3xz7iAxn8L39njGVyuoseXzU6rcxFLJ8HFsTjSyQbLYnMpCqE2VbFWc,\
+              xprv9uPDJpEQgRQfDcW7BkF7eTya6RPxXeJCqCJGHuCJ4GiRVLzkTXBAJMu2qaMWPrS7AANYqdq6vcBcBUdJCVVFceUvJFjaPdGZ2y9WACViL4L/0))", "ggrsrxfy"),
+            ("sh(multi(2,[00000000/111'/222]xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL,\
+              xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y/0))", "tjg09x5t")
+        ];
+        for &(desc, checksum) in &vectors {
+            assert_eq!(with_checksum(desc), Some(format!("{}#{}", desc, checksum)));
+        }
+        // Characters outside the descriptor character set have no checksum
+        assert_eq!(with_checksum("raw(deadbeef)\u{e9}"), None);
+        assert_eq!(with_checksum("raw(deadbeef)\n"), None);
+    }
+}
@@ -0,0 +1,323 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Electrum
+//! Support for the Electrum server protocol, which indexes coins by the
+//! "scripthash" of their scriptPubKey rather than by address, and a client
+//! for asking a server about them. The protocol is newline-separated
+//! JSON-RPC over TCP. TLS is not supported, so servers which only offer
+//! SSL ports must be reached through a local tunnel. Addresses and keys
+//! can also be handed to the Electrum wallet itself as a wallet file.
+//!
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::util::address::{Address, Privkey};
+use bitcoin::util::base58::ToBase58;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use serialize::hex::ToHex;
+use secp256k1::Secp256k1;
+use secp256k1::key::PublicKey;
+use serialize::json::Json;
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+/// Protocol version requested of servers; 1.4 is the first where
+/// `server.version` must come first
+const PROTOCOL_VERSION: &'static str = "1.4";
+
+/// Wallet file version written; Electrum upgrades older files itself
+const SEED_VERSION: u64 = 18;
+
+/// Electrum error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// Server was not of the form `[tcp://]host:port`
+    BadServer(String),
+    /// Server was an `ssl://` one, which is unsupported
+    SslUnsupported(String),
+    /// Connection failed or was closed
+    Io(String),
+    /// Server reported an error, with its code and message
+    Server(i64, String),
+    /// Server's reply could not be understood
+    BadResponse(String)
+}
+
+/// Balance of a scriptPubKey, in satoshis
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Balance {
+    /// Held by confirmed coins
+    pub confirmed: i64,
+    /// Change to that by mempool transactions, which may be negative
+    pub unconfirmed: i64
+}
+
+/// A transaction in the history of a scriptPubKey
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HistoryItem {
+    /// Txid, as displayed
+    pub txid: String,
+    /// Height of its block, or 0 (-1 if it has unconfirmed parents) for one
+    /// still in the mempool
+    pub height: i64
+}
+
+/// A connection to an Electrum server
+pub struct Client {
+    /// Replies from the server
+    reader: BufReader<TcpStream>,
+    /// Requests to the server
+    writer: TcpStream,
+    /// Id of the next request
+    next_id: u64
+}
+
+/// The scripthash Electrum servers know a scriptPubKey by: its SHA256,
+/// byte-reversed and hex-encoded
+pub fn scripthash(script_pubkey: &Script) -> String {
+    let mut hash = [0; 32];
+    let mut engine = Sha256::new();
+    engine.input(&script_pubkey[..]);
+    engine.result(&mut hash);
+    hash.reverse();
+    hash.to_hex()
+}
+
+impl Client {
+    /// Connect to a `host:port` or `tcp://host:port` server and agree on the
+    /// protocol version
+    pub fn connect(server: &str) -> Result<Client, Error> {
+        if server.starts_with("ssl://") {
+            return Err(Error::SslUnsupported(server.to_owned()));
+        }
+        let address = if server.starts_with("tcp://") { &server[6..] } else { server };
+        if !address.contains(':') {
+            return Err(Error::BadServer(server.to_owned()));
+        }
+        let writer = try!(TcpStream::connect(address).map_err(|e| Error::Io(format!("unable to connect to {}: {}", address, e))));
+        let reader = BufReader::new(try!(writer.try_clone().map_err(|e| Error::Io(e.to_string()))));
+        let mut ret = Client {
+            reader: reader,
+            writer: writer,
+            next_id: 0
+        };
+        try!(ret.call("server.version", vec![Json::String("pacthash".to_owned()), Json::String(PROTOCOL_VERSION.to_owned())]));
+        Ok(ret)
+    }
+
+    /// Call a method, returning its result
+    pub fn call(&mut self, method: &str, params: Vec<Json>) -> Result<Json, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut request = BTreeMap::new();
+        request.insert("jsonrpc".to_owned(), Json::String("2.0".to_owned()));
+        request.insert("id".to_owned(), Json::U64(id));
+        request.insert("method".to_owned(), Json::String(method.to_owned()));
+        request.insert("params".to_owned(), Json::Array(params));
+        let line = format!("{}\n", Json::Object(request));
+        try!(self.writer.write_all(line.as_bytes()).map_err(|e| Error::Io(e.to_string())));
+
+        loop {
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => { return Err(Error::Io("connection closed".to_owned())); }
+                Ok(_) => {}
+                Err(e) => { return Err(Error::Io(e.to_string())); }
+            }
+            if let Some(result) = parse_reply(&line, id) {
+                return result;
+            }
+        }
+    }
+
+    /// The balance of a scriptPubKey
+    pub fn balance(&mut self, script_pubkey: &Script) -> Result<Balance, Error> {
+        let result = try!(self.call("blockchain.scripthash.get_balance", vec![Json::String(scripthash(script_pubkey))]));
+        parse_balance(&result)
+    }
+
+    /// The transactions paying to or spending from a scriptPubKey
+    pub fn history(&mut self, script_pubkey: &Script) -> Result<Vec<HistoryItem>, Error> {
+        let result = try!(self.call("blockchain.scripthash.get_history", vec![Json::String(scripthash(script_pubkey))]));
+        parse_history(&result)
+    }
+}
+
+/// Read a line from the server as the reply to the request with some id,
+/// returning `None` if it is a reply to something else
+fn parse_reply(line: &str, id: u64) -> Option<Result<Json, Error>> {
+    let json = match Json::from_str(line) {
+        Ok(json) => json,
+        Err(_) => { return Some(Err(Error::BadResponse(line.to_owned()))); }
+    };
+    // Subscription notifications have no id and are skipped
+    if json.find("id").and_then(|i| i.as_u64()) != Some(id) {
+        return None;
+    }
+    if let Some(error) = json.find("error").and_then(|e| e.as_object()) {
+        let code = error.get("code").and_then(|c| c.as_i64()).unwrap_or(0);
+        let message = error.get("message").and_then(|m| m.as_string()).unwrap_or("").to_owned();
+        return Some(Err(Error::Server(code, message)));
+    }
+    Some(match json.find("result") {
+        Some(result) => Ok(result.clone()),
+        None => Err(Error::BadResponse(line.to_owned()))
+    })
+}
+
+/// Read the result of a `blockchain.scripthash.get_balance` call
+fn parse_balance(result: &Json) -> Result<Balance, Error> {
+    match (result.find("confirmed").and_then(|c| c.as_i64()), result.find("unconfirmed").and_then(|u| u.as_i64())) {
+        (Some(confirmed), Some(unconfirmed)) => Ok(Balance { confirmed: confirmed, unconfirmed: unconfirmed }),
+        _ => Err(Error::BadResponse(result.to_string()))
+    }
+}
+
+/// Read the result of a `blockchain.scripthash.get_history` call
+fn parse_history(result: &Json) -> Result<Vec<HistoryItem>, Error> {
+    let items = match result.as_array() {
+        Some(items) => items,
+        None => { return Err(Error::BadResponse(result.to_string())); }
+    };
+    let mut ret = Vec::with_capacity(items.len());
+    for item in items {
+        match (item.find("tx_hash").and_then(|t| t.as_string()), item.find("height").and_then(|h| h.as_i64())) {
+            (Some(txid), Some(height)) => ret.push(HistoryItem { txid: txid.to_owned(), height: height }),
+            _ => { return Err(Error::BadResponse(result.to_string())); }
+        }
+    }
+    Ok(ret)
+}
+
+/// The fields common to all "imported" wallet files
+fn imported_wallet(addresses: BTreeMap<String, Json>) -> BTreeMap<String, Json> {
+    let mut ret = BTreeMap::new();
+    ret.insert("wallet_type".to_owned(), Json::String("imported".to_owned()));
+    ret.insert("seed_version".to_owned(), Json::U64(SEED_VERSION));
+    ret.insert("use_encryption".to_owned(), Json::Boolean(false));
+    ret.insert("addresses".to_owned(), Json::Object(addresses));
+    ret
+}
+
+/// A watch-only wallet file of some addresses. Electrum only imports
+/// scripts as bare addresses, so it can watch but not spend them.
+pub fn watch_only_wallet(addresses: &[String]) -> Json {
+    let addresses = addresses.iter().map(|address| (address.clone(), Json::Object(BTreeMap::new()))).collect();
+    Json::Object(imported_wallet(addresses))
+}
+
+/// A wallet file holding some private keys, spent from as P2PKH
+pub fn key_wallet(secp: &Secp256k1, keys: &[Privkey]) -> Result<Json, ::secp256k1::Error> {
+    let mut addresses = BTreeMap::new();
+    let mut keypairs = BTreeMap::new();
+    for key in keys {
+        let public_key = try!(PublicKey::from_secret_key(secp, &key.key));
+        let hex = public_key.serialize_vec(secp, key.compressed)[..].to_hex();
+        let mut info = BTreeMap::new();
+        info.insert("type".to_owned(), Json::String("p2pkh".to_owned()));
+        info.insert("pubkey".to_owned(), Json::String(hex.clone()));
+        addresses.insert(Address::from_key(key.network, &public_key, key.compressed).to_base58check(), Json::Object(info));
+        keypairs.insert(hex, Json::String(format!("p2pkh:{}", key.to_base58check())));
+    }
+    let mut keystore = BTreeMap::new();
+    keystore.insert("type".to_owned(), Json::String("imported".to_owned()));
+    keystore.insert("keypairs".to_owned(), Json::Object(keypairs));
+    let mut ret = imported_wallet(addresses);
+    ret.insert("keystore".to_owned(), Json::Object(keystore));
+    Ok(Json::Object(ret))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::script::Script;
+    use serialize::hex::FromHex;
+    use serialize::json::Json;
+
+    use super::*;
+
+    fn result(line: &str, id: u64) -> Json {
+        match parse_reply(line, id) {
+            Some(Ok(result)) => result,
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn scripthashes() {
+        // The example from the protocol documentation, for P2PKH to
+        // 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa
+        let script_pubkey = Script::from("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac".from_hex().unwrap());
+        assert_eq!(scripthash(&script_pubkey), "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161");
+    }
+
+    #[test]
+    fn replies() {
+        let version = result(r#"{"jsonrpc": "2.0", "result": ["ElectrumX 1.16.0", "1.4"], "id": 0}"#, 0);
+        assert_eq!(version.as_array().map(|a| a.len()), Some(2));
+
+        // Notifications, and replies to other requests, are skipped
+        assert!(parse_reply(r#"{"jsonrpc": "2.0", "method": "blockchain.headers.subscribe", "params": [{"height": 800000, "hex": "00"}]}"#, 1).is_none());
+        assert!(parse_reply(r#"{"jsonrpc": "2.0", "result": null, "id": 0}"#, 1).is_none());
+
+        match parse_reply(r#"{"jsonrpc": "2.0", "error": {"code": 1, "message": "unsupported protocol version: 1.4"}, "id": 0}"#, 0) {
+            Some(Err(Error::Server(1, ref message))) if message == "unsupported protocol version: 1.4" => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+        match parse_reply("<html>400 Bad Request</html>", 0) {
+            Some(Err(Error::BadResponse(_))) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+        match parse_reply(r#"{"jsonrpc": "2.0", "id": 0}"#, 0) {
+            Some(Err(Error::BadResponse(_))) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn balances() {
+        let balance = result(r#"{"jsonrpc": "2.0", "result": {"confirmed": 103873966, "unconfirmed": -23684}, "id": 3}"#, 3);
+        assert_eq!(parse_balance(&balance).unwrap(), Balance { confirmed: 103873966, unconfirmed: -23684 });
+
+        let bad = result(r#"{"jsonrpc": "2.0", "result": {"confirmed": "103873966"}, "id": 3}"#, 3);
+        match parse_balance(&bad) {
+            Err(Error::BadResponse(_)) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn histories() {
+        let history = result(r#"{"jsonrpc": "2.0", "id": 4, "result": [
+            {"height": 200004, "tx_hash": "acc3758bd2a26f869fcc67d48ff30b96464d476bca82c1cd6656e7d506816412"},
+            {"height": 215008, "tx_hash": "f3e1bf48975b8d6060a9de8884296abb80be618dc00ae3cb2f6cee3085e09403"},
+            {"fee": 20000, "height": 0, "tx_hash": "9fbed79a1e970343fcd39f4a2d830a6bde6de0754ed2da70f489d0303ed558ec"}
+        ]}"#, 4);
+        let items = parse_history(&history).unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0], HistoryItem { txid: "acc3758bd2a26f869fcc67d48ff30b96464d476bca82c1cd6656e7d506816412".to_owned(), height: 200004 });
+        assert_eq!(items[2].height, 0);
+
+        assert!(parse_history(&result(r#"{"jsonrpc": "2.0", "result": [], "id": 4}"#, 4)).unwrap().is_empty());
+        for bad in &[r#"{"jsonrpc": "2.0", "result": {}, "id": 4}"#,
+                     r#"{"jsonrpc": "2.0", "result": [{"height": 1}], "id": 4}"#] {
+            match parse_history(&result(bad, 4)) {
+                Err(Error::BadResponse(_)) => {}
+                other => panic!("unexpected result {:?}", other)
+            }
+        }
+    }
+}
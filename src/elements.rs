@@ -0,0 +1,246 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Elements Addresses
+//! Confidential addresses of Elements chains, which prefix an ordinary
+//! base58 address with a blinding public key, and the SLIP-0077 derivation
+//! of their blinding keys. Only the legacy base58 form is supported, not
+//! blech32.
+//!
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::network::constants::Network;
+use bitcoin::util::address::{self, Address};
+use bitcoin::util::base58::{self, FromBase58, ToBase58};
+use bitcoin::util::hash::Hash160;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use secp256k1::{self, Secp256k1};
+use secp256k1::key::{PublicKey, SecretKey};
+
+/// Length of the payload of a confidential address: prefix, version,
+/// blinding key and hash
+const CONFIDENTIAL_LEN: usize = 1 + 1 + 33 + 20;
+
+/// An Elements chain
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ElementsNetwork {
+    /// The Liquid sidechain of Bitcoin
+    Liquid,
+    /// The Liquid testnet
+    LiquidTestnet,
+    /// A default `elementsregtest` chain
+    ElementsRegtest
+}
+
+impl ElementsNetwork {
+    /// Base58 prefixes of the chain: pubkey hash, script hash and blinded
+    fn prefixes(&self) -> (u8, u8, u8) {
+        match *self {
+            ElementsNetwork::Liquid => (57, 39, 12),
+            ElementsNetwork::LiquidTestnet => (36, 19, 23),
+            ElementsNetwork::ElementsRegtest => (235, 75, 4)
+        }
+    }
+
+    /// The Elements chain for a Bitcoin network, as the tool's mode
+    /// selects it
+    pub fn from_network(network: Network) -> ElementsNetwork {
+        match network {
+            Network::Bitcoin => ElementsNetwork::Liquid,
+            Network::Testnet => ElementsNetwork::LiquidTestnet
+        }
+    }
+
+    /// The Bitcoin network whose mode the chain is used in
+    pub fn network(&self) -> Network {
+        match *self {
+            ElementsNetwork::Liquid => Network::Bitcoin,
+            _ => Network::Testnet
+        }
+    }
+}
+
+/// A confidential address
+#[derive(Clone, PartialEq, Eq)]
+pub struct ConfidentialAddress {
+    /// Chain of the address
+    pub chain: ElementsNetwork,
+    /// Key which outputs to the address are blinded to
+    pub blinding_key: PublicKey,
+    /// The address without its blinding key, with the network of the chain
+    pub address: Address
+}
+
+impl ConfidentialAddress {
+    /// Decode a base58 confidential address of any supported chain
+    pub fn from_base58check(secp: &Secp256k1, s: &str) -> Result<ConfidentialAddress, base58::Error> {
+        let data: Vec<u8> = try!(FromBase58::from_base58check(s));
+        if data.len() != CONFIDENTIAL_LEN {
+            return Err(base58::Error::InvalidLength(data.len()));
+        }
+        for &chain in &[ElementsNetwork::Liquid, ElementsNetwork::LiquidTestnet, ElementsNetwork::ElementsRegtest] {
+            let (pubkey_hash, script_hash, blinded) = chain.prefixes();
+            if data[0] != blinded {
+                continue;
+            }
+            let ty = if data[1] == pubkey_hash {
+                address::Type::PubkeyHash
+            } else if data[1] == script_hash {
+                address::Type::ScriptHash
+            } else {
+                return Err(base58::Error::InvalidVersion(data[..2].to_owned()));
+            };
+            let blinding_key = try!(PublicKey::from_slice(secp, &data[2..35])
+                                        .map_err(|_| base58::Error::Other("bad blinding key".to_owned())));
+            return Ok(ConfidentialAddress {
+                chain: chain,
+                blinding_key: blinding_key,
+                address: Address {
+                    ty: ty,
+                    network: chain.network(),
+                    hash: Hash160::from(&data[35..])
+                }
+            });
+        }
+        Err(base58::Error::InvalidVersion(vec![data[0]]))
+    }
+
+    /// Encode the address as base58
+    pub fn to_base58check(&self, secp: &Secp256k1) -> String {
+        let (pubkey_hash, script_hash, blinded) = self.chain.prefixes();
+        let mut data = Vec::with_capacity(CONFIDENTIAL_LEN);
+        data.push(blinded);
+        data.push(match self.address.ty {
+            address::Type::PubkeyHash => pubkey_hash,
+            address::Type::ScriptHash => script_hash
+        });
+        data.extend(self.blinding_key.serialize_vec(secp, true).iter().cloned());
+        data.extend(self.address.hash[..].iter().cloned());
+        data.to_base58check()
+    }
+}
+
+/// The SLIP-0077 blinding private key of a scriptPubKey, derived from a
+/// wallet's master blinding key
+pub fn slip77_blinding_key(secp: &Secp256k1, master_blinding_key: &[u8], script_pubkey: &Script) -> Result<SecretKey, secp256k1::Error> {
+    let mut hmac = Hmac::new(Sha256::new(), master_blinding_key);
+    hmac.input(&script_pubkey[..]);
+    let mut result = [0; 32];
+    hmac.raw_result(&mut result);
+    SecretKey::from_slice(secp, &result)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::script::Script;
+    use bitcoin::network::constants::Network;
+    use bitcoin::util::address;
+    use bitcoin::util::base58;
+    use secp256k1::Secp256k1;
+    use secp256k1::key::PublicKey;
+    use serialize::hex::{FromHex, ToHex};
+
+    use super::*;
+
+    #[test]
+    fn confidential_addresses() {
+        let secp = Secp256k1::new();
+        // A Liquid P2PKH address, blinded to and paying the key whose secret
+        // key is 1, and an elementsregtest one
+        let liquid = "VTpvKKc1SNmLG4H8CnR1fGJdHdyWGEQEvdP9gfeneJR7n81S5kiwNtgF7vrZjC8mp63HvwxM81nEbTxU";
+        let regtest = "CTEo6VKG8xbe7HnfVW9mQoWTgtgeRSPktwTLbELzGw5tV8Ngzu53EBiasFMQKVbWmKWWTAdN5AUf4M6Y";
+
+        let address = ConfidentialAddress::from_base58check(&secp, liquid).unwrap();
+        assert_eq!(address.chain, ElementsNetwork::Liquid);
+        assert_eq!(address.blinding_key.serialize_vec(&secp, true)[..].to_hex(),
+                   "025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357");
+        assert_eq!(address.address.ty, address::Type::PubkeyHash);
+        assert_eq!(address.address.network, Network::Bitcoin);
+        assert_eq!(address.address.hash[..].to_hex(), "751e76e8199196d454941c45d1b3a323f1433bd6");
+        assert_eq!(address.to_base58check(&secp), liquid);
+
+        let address = ConfidentialAddress::from_base58check(&secp, regtest).unwrap();
+        assert_eq!(address.chain, ElementsNetwork::ElementsRegtest);
+        assert_eq!(address.address.ty, address::Type::PubkeyHash);
+        assert_eq!(address.address.network, Network::Testnet);
+        assert_eq!(address.to_base58check(&secp), regtest);
+
+        // Script hash addresses take the chain's other version byte
+        let mut p2sh = address.clone();
+        p2sh.address.ty = address::Type::ScriptHash;
+        let encoded = p2sh.to_base58check(&secp);
+        assert!(ConfidentialAddress::from_base58check(&secp, &encoded).unwrap() == p2sh);
+        let data: Vec<u8> = FromBase58::from_base58check(&encoded).unwrap();
+        assert_eq!(&data[..2], &[4, 75]);
+    }
+
+    #[test]
+    fn bad_confidential_addresses() {
+        let secp = Secp256k1::new();
+        // An unblinded address
+        match ConfidentialAddress::from_base58check(&secp, "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH") {
+            Err(base58::Error::InvalidLength(21)) => {}
+            other => panic!("unexpected result {:?}", other.map(|a| a.to_base58check(&secp)))
+        }
+        let mut data: Vec<u8> = FromBase58::from_base58check("VTpvKKc1SNmLG4H8CnR1fGJdHdyWGEQEvdP9gfeneJR7n81S5kiwNtgF7vrZjC8mp63HvwxM81nEbTxU").unwrap();
+        // The pubkey hash version of another chain
+        data[1] = 36;
+        match ConfidentialAddress::from_base58check(&secp, &data.to_base58check()) {
+            Err(base58::Error::InvalidVersion(ref v)) if *v == [12, 36] => {}
+            other => panic!("unexpected result {:?}", other.map(|a| a.to_base58check(&secp)))
+        }
+        // An unknown blinded prefix
+        data[0] = 13;
+        match ConfidentialAddress::from_base58check(&secp, &data.to_base58check()) {
+            Err(base58::Error::InvalidVersion(ref v)) if *v == [13] => {}
+            other => panic!("unexpected result {:?}", other.map(|a| a.to_base58check(&secp)))
+        }
+        // A blinding key off the curve
+        data[0] = 12;
+        data[1] = 57;
+        data[2] = 5;
+        match ConfidentialAddress::from_base58check(&secp, &data.to_base58check()) {
+            Err(base58::Error::Other(_)) => {}
+            other => panic!("unexpected result {:?}", other.map(|a| a.to_base58check(&secp)))
+        }
+    }
+
+    #[test]
+    fn slip77() {
+        let secp = Secp256k1::new();
+        // The vector of SLIP-0077, whose master blinding key is that of the
+        // mnemonic "all all all all all all all all all all all all"
+        let master = "6c2de18eabeff3f7822bc724ad482bef0557f3e1c1e1c75b7a393a5ced4de616".from_hex().unwrap();
+        let script_pubkey = Script::from("76a914a579388225827d9f2fe9014add644487808c695d88ac".from_hex().unwrap());
+        let secret = slip77_blinding_key(&secp, &master, &script_pubkey).unwrap();
+        assert_eq!(secret[..].to_hex(), "4e6e94df28448c7bb159271fe546da464ea863b3887d2eec6afd841184b70592");
+        let blinding_key = PublicKey::from_secret_key(&secp, &secret).unwrap();
+        assert_eq!(blinding_key.serialize_vec(&secp, true)[..].to_hex(),
+                   "0223ef5cf5d1185f86204b9386c8541061a24b6f72fa4a29e3a0b60e1c20ffaf5b");
+
+        // Its confidential address on elementsregtest
+        let address = ConfidentialAddress {
+            chain: ElementsNetwork::ElementsRegtest,
+            blinding_key: blinding_key,
+            address: Address {
+                ty: address::Type::PubkeyHash,
+                network: Network::Testnet,
+                hash: Hash160::from(&script_pubkey[3..23])
+            }
+        };
+        assert_eq!(address.to_base58check(&secp), "CTEkf75DFff5ReB7juTg2oehrj41aMj21kvvJaQdWsEAQohz1EDhu7Ayh6goxpz3GZRVKidTtaXaXYEJ");
+    }
+}
@@ -0,0 +1,129 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Esplora
+//! Looking up addresses on an Esplora server (the REST API behind
+//! blockstream.info and mempool.space), for users without a node of their
+//! own. Addresses are looked up by their Electrum scripthash, which covers
+//! every kind of scriptPubKey. As for RPC only plain HTTP is supported, so
+//! public HTTPS instances must be reached through a local tunnel.
+//!
+
+use bitcoin::blockdata::script::Script;
+use serialize::json::Json;
+
+use electrum;
+use rpc;
+
+/// Activity of a scriptPubKey, in blocks or in the mempool
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Stats {
+    /// Number of transactions touching it
+    pub tx_count: u64,
+    /// Number of outputs paying to it
+    pub funded_count: u64,
+    /// Total value of those outputs, in satoshis
+    pub funded_sum: u64,
+    /// Total value of those outputs which have been spent, in satoshis
+    pub spent_sum: u64
+}
+
+/// Activity of a scriptPubKey
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Status {
+    /// Activity in blocks
+    pub chain: Stats,
+    /// Activity in the mempool
+    pub mempool: Stats
+}
+
+impl Status {
+    /// Whether any transaction has touched the scriptPubKey
+    pub fn used(&self) -> bool {
+        self.chain.tx_count > 0 || self.mempool.tx_count > 0
+    }
+}
+
+/// Read the statistics of an address or scripthash reply
+fn stats(json: &Json) -> Option<Stats> {
+    let field = |name: &str| json.find(name).and_then(|v| v.as_u64());
+    match (field("tx_count"), field("funded_txo_count"), field("funded_txo_sum"), field("spent_txo_sum")) {
+        (Some(tx_count), Some(funded_count), Some(funded_sum), Some(spent_sum)) => Some(Stats {
+            tx_count: tx_count,
+            funded_count: funded_count,
+            funded_sum: funded_sum,
+            spent_sum: spent_sum
+        }),
+        _ => None
+    }
+}
+
+/// Look up a scriptPubKey on the Esplora server with some base URL, e.g.
+/// `http://127.0.0.1:3000` or `http://HOST/api`
+pub fn status(base_url: &str, script_pubkey: &Script) -> Result<Status, rpc::Error> {
+    let url = format!("{}/scripthash/{}", base_url.trim_right_matches('/'), electrum::scripthash(script_pubkey));
+    let json = try!(rpc::get_json(&url));
+    parse_status(&json)
+}
+
+/// Read a scripthash reply
+fn parse_status(json: &Json) -> Result<Status, rpc::Error> {
+    match (json.find("chain_stats").and_then(stats), json.find("mempool_stats").and_then(stats)) {
+        (Some(chain), Some(mempool)) => Ok(Status { chain: chain, mempool: mempool }),
+        _ => Err(rpc::Error::BadResponse(json.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::json::Json;
+
+    use rpc;
+
+    use super::*;
+
+    #[test]
+    fn statuses() {
+        let json = Json::from_str(r#"{
+            "scripthash": "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161",
+            "chain_stats": {"funded_txo_count": 52, "funded_txo_sum": 5007861364, "spent_txo_count": 0, "spent_txo_sum": 0, "tx_count": 52},
+            "mempool_stats": {"funded_txo_count": 1, "funded_txo_sum": 546, "spent_txo_count": 0, "spent_txo_sum": 0, "tx_count": 1}
+        }"#).unwrap();
+        let status = parse_status(&json).unwrap();
+        assert_eq!(status.chain, Stats { tx_count: 52, funded_count: 52, funded_sum: 5007861364, spent_sum: 0 });
+        assert_eq!(status.mempool, Stats { tx_count: 1, funded_count: 1, funded_sum: 546, spent_sum: 0 });
+        assert!(status.used());
+
+        let json = Json::from_str(r#"{
+            "chain_stats": {"funded_txo_count": 0, "funded_txo_sum": 0, "spent_txo_count": 0, "spent_txo_sum": 0, "tx_count": 0},
+            "mempool_stats": {"funded_txo_count": 0, "funded_txo_sum": 0, "spent_txo_count": 0, "spent_txo_sum": 0, "tx_count": 0}
+        }"#).unwrap();
+        assert!(!parse_status(&json).unwrap().used());
+    }
+
+    #[test]
+    fn bad_statuses() {
+        for bad in &[r#"{"chain_stats": {"funded_txo_count": 0, "funded_txo_sum": 0, "spent_txo_sum": 0, "tx_count": 0}}"#,
+                     r#"{"chain_stats": {"funded_txo_count": 0, "funded_txo_sum": 0, "spent_txo_sum": 0, "tx_count": 0},
+                         "mempool_stats": {"funded_txo_count": 0, "funded_txo_sum": 0, "spent_txo_sum": 0}}"#,
+                     r#"{"chain_stats": {"funded_txo_count": -1, "funded_txo_sum": 0, "spent_txo_sum": 0, "tx_count": 0},
+                         "mempool_stats": {"funded_txo_count": 0, "funded_txo_sum": 0, "spent_txo_sum": 0, "tx_count": 0}}"#,
+                     r#"["not", "an", "object"]"#] {
+            match parse_status(&Json::from_str(bad).unwrap()) {
+                Err(rpc::Error::BadResponse(_)) => {}
+                other => panic!("unexpected result {:?}", other)
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # C Interface
+//! The server's methods as `extern "C"` functions, for C and C++ wallets
+//! to link against. Arguments are NUL-terminated strings, in the forms the
+//! server's parameters take, and NULL for those not given. Results are
+//! JSON, as the server returns them, in strings which the caller frees with
+//! `pacthash_string_free`. On failure NULL (or -1) is returned, and
+//! `pacthash_last_error` describes what went wrong.
+//!
+//! Callers making many calls should create a context, which keeps the
+//! network between them; the functions without one make a context per
+//! call. Either way the secp context is built once, on the first call, and
+//! shared by every context. `pacthash_abi_version` gives the version of this
+//! interface, which changes only when an existing function does, so that
+//! callers loading the library at runtime can check it before anything
+//! else. It is also the soname's version on Linux.
+//!
+//! The header `include/pacthash.h` is generated from this module with
+//! `cbindgen --config cbindgen.toml -o include/pacthash.h`.
+//!
+
+use bitcoin::network::constants::Network;
+use serialize::json::Json;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+use chain::Chain;
+use server::Server;
+
+/// The version of this interface, bumped when a function changes or goes.
+/// build.rs gives it to the soname too.
+pub const PACTHASH_ABI_VERSION: u32 = 1;
+
+/// An opaque handle to what calls share: the network, and the server which
+/// answers them
+pub struct PacthashContext {
+    server: Server
+}
+
+thread_local! {
+    /// The error of the last call on this thread to fail
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None)
+}
+
+/// Note the error of a failed call
+fn set_error(message: String) {
+    // Messages are ours or the server's, so hold no NULs
+    let message = CString::new(message.replace('\0', "")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Gather some string arguments, as named parameters, skipping NULLs
+unsafe fn params(args: &[(&str, *const c_char)]) -> Result<Json, String> {
+    let mut ret = BTreeMap::new();
+    for &(name, arg) in args {
+        if arg.is_null() {
+            continue;
+        }
+        match CStr::from_ptr(arg).to_str() {
+            Ok(arg) => { ret.insert(name.to_owned(), Json::String(arg.to_owned())); }
+            Err(_) => { return Err(format!("{} is not UTF-8", name)); }
+        }
+    }
+    Ok(Json::Object(ret))
+}
+
+/// A context for one network
+fn context(testnet: c_int) -> PacthashContext {
+    let network = if testnet != 0 { Network::Testnet } else { Network::Bitcoin };
+    PacthashContext { server: Server::new(Chain::from_network(network), None) }
+}
+
+/// Call a method of a context's server, giving its result
+unsafe fn call(ctx: *const PacthashContext, method: &str, args: &[(&str, *const c_char)]) -> Option<Json> {
+    if ctx.is_null() {
+        set_error("context is NULL".to_owned());
+        return None;
+    }
+    let params = match params(args) {
+        Ok(params) => params,
+        Err(e) => {
+            set_error(e);
+            return None;
+        }
+    };
+    match (*ctx).server.call(method, &params) {
+        Ok(result) => Some(result),
+        Err((_, message)) => {
+            set_error(message);
+            None
+        }
+    }
+}
+
+/// Give a result to the caller as a string it must free
+fn to_c_string(result: Option<Json>) -> *mut c_char {
+    match result {
+        Some(result) => CString::new(result.to_string()).unwrap().into_raw(),
+        None => ptr::null_mut()
+    }
+}
+
+/// The version of this interface
+#[no_mangle]
+pub extern "C" fn pacthash_abi_version() -> u32 {
+    PACTHASH_ABI_VERSION
+}
+
+/// Create a context for mainnet, or testnet if `testnet` is nonzero. It is
+/// freed with `pacthash_context_free`, and may be shared between threads.
+#[no_mangle]
+pub extern "C" fn pacthash_context_new(testnet: c_int) -> *mut PacthashContext {
+    Box::into_raw(Box::new(context(testnet)))
+}
+
+/// Free a context
+#[no_mangle]
+pub unsafe extern "C" fn pacthash_context_free(ctx: *mut PacthashContext) {
+    if !ctx.is_null() {
+        drop(Box::from_raw(ctx));
+    }
+}
+
+/// The tweaked script and its addresses, given `script` (hex or asm), or
+/// the tweaked key and its P2PKH address, given `key`, as JSON
+#[no_mangle]
+pub unsafe extern "C" fn pacthash_ctx_gen_address(ctx: *const PacthashContext, contract: *const c_char,
+                                                  script: *const c_char, key: *const c_char) -> *mut c_char {
+    to_c_string(call(ctx, "gen_address", &[("contract", contract), ("script", script), ("key", key)]))
+}
+
+/// The tweaked private key of a WIF key, with its public key and P2PKH
+/// address, as JSON
+#[no_mangle]
+pub unsafe extern "C" fn pacthash_ctx_tweak_privkey(ctx: *const PacthashContext, contract: *const c_char,
+                                                    privkey: *const c_char) -> *mut c_char {
+    to_c_string(call(ctx, "tweak_privkey", &[("contract", contract), ("privkey", privkey)]))
+}
+
+/// Whether an address commits to a contract under `script` or `key`:
+/// 1 if it does, 0 if it does not, and -1 on error
+#[no_mangle]
+pub unsafe extern "C" fn pacthash_ctx_verify(ctx: *const PacthashContext, contract: *const c_char, script: *const c_char,
+                                             key: *const c_char, address: *const c_char) -> c_int {
+    let args = [("contract", contract), ("script", script), ("key", key), ("address", address)];
+    match call(ctx, "verify", &args) {
+        Some(result) => if result.find("valid").and_then(|v| v.as_boolean()) == Some(true) { 1 } else { 0 },
+        None => -1
+    }
+}
+
+/// The parts of a contract, as JSON
+#[no_mangle]
+pub unsafe extern "C" fn pacthash_ctx_decode_contract(ctx: *const PacthashContext, contract: *const c_char) -> *mut c_char {
+    to_c_string(call(ctx, "decode_contract", &[("contract", contract)]))
+}
+
+/// Build a contract from `ascii` text, or the P2SH or P2PH `address` it
+/// pays to, and a hex nonce, giving its parts (and hex) as JSON
+#[no_mangle]
+pub unsafe extern "C" fn pacthash_ctx_make_contract(ctx: *const PacthashContext, ascii: *const c_char,
+                                                    address: *const c_char, nonce: *const c_char) -> *mut c_char {
+    to_c_string(call(ctx, "decode_contract", &[("ascii", ascii), ("p2sh", address), ("nonce", nonce)]))
+}
+
+/// `pacthash_ctx_gen_address` with a context made for the call
+#[no_mangle]
+pub unsafe extern "C" fn pacthash_gen_address(contract: *const c_char, script: *const c_char,
+                                              key: *const c_char, testnet: c_int) -> *mut c_char {
+    pacthash_ctx_gen_address(&context(testnet), contract, script, key)
+}
+
+/// `pacthash_ctx_tweak_privkey` with a context made for the call
+#[no_mangle]
+pub unsafe extern "C" fn pacthash_tweak_privkey(contract: *const c_char, privkey: *const c_char,
+                                                testnet: c_int) -> *mut c_char {
+    pacthash_ctx_tweak_privkey(&context(testnet), contract, privkey)
+}
+
+/// `pacthash_ctx_verify` with a context made for the call
+#[no_mangle]
+pub unsafe extern "C" fn pacthash_verify(contract: *const c_char, script: *const c_char, key: *const c_char,
+                                         address: *const c_char, testnet: c_int) -> c_int {
+    pacthash_ctx_verify(&context(testnet), contract, script, key, address)
+}
+
+/// `pacthash_ctx_decode_contract` with a context made for the call
+#[no_mangle]
+pub unsafe extern "C" fn pacthash_decode_contract(contract: *const c_char) -> *mut c_char {
+    pacthash_ctx_decode_contract(&context(0), contract)
+}
+
+/// `pacthash_ctx_make_contract` with a context made for the call
+#[no_mangle]
+pub unsafe extern "C" fn pacthash_make_contract(ascii: *const c_char, address: *const c_char,
+                                                nonce: *const c_char, testnet: c_int) -> *mut c_char {
+    pacthash_ctx_make_contract(&context(testnet), ascii, address, nonce)
+}
+
+/// The error of the last call on this thread to fail, or NULL. The string
+/// belongs to the library and lasts until the next call fails.
+#[no_mangle]
+pub extern "C" fn pacthash_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Free a string returned by this library
+#[no_mangle]
+pub unsafe extern "C" fn pacthash_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
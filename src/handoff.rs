@@ -0,0 +1,293 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Air-gapped Handoff
+//! Files for moving a tweak between an online and an offline machine. A
+//! request carries a redemption script and contract to the offline
+//! machine; the response carries the tweaked keys and script back, along
+//! with a hash binding it to its request. Each file ends in a SHA256
+//! checksum of its other lines, which catches damage in transit but is
+//! not a signature.
+//!
+//! ```text
+//! pacthash-tweak-request 1
+//! network mainnet
+//! script <hex>
+//! contract <hex>
+//! checksum <hex>
+//! ```
+//!
+//! ```text
+//! pacthash-tweak-response 1
+//! request <hex>
+//! script <hex>
+//! key <original> <tweaked>
+//! checksum <hex>
+//! ```
+//!
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::network::constants::Network;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use secp256k1::Secp256k1;
+use secp256k1::key::PublicKey;
+use serialize::hex::{FromHex, ToHex};
+
+use contract::Contract;
+
+/// First line of a request file
+const REQUEST_MAGIC: &'static str = "pacthash-tweak-request 1";
+/// First line of a response file
+const RESPONSE_MAGIC: &'static str = "pacthash-tweak-response 1";
+
+/// Handoff file error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// File was not a handoff file of the expected kind and version
+    BadMagic,
+    /// Line could not be parsed (line number)
+    BadLine(usize),
+    /// A required field was missing
+    MissingField(&'static str),
+    /// Checksum did not match the file's contents
+    BadChecksum
+}
+
+/// Hex SHA256 of some text
+fn sha256_hex(text: &str) -> String {
+    let mut sha = Sha256::new();
+    sha.input_str(text);
+    sha.result_str()
+}
+
+/// Append the checksum line to the body of a file
+pub fn add_checksum(body: String) -> String {
+    let checksum = sha256_hex(&body);
+    format!("{}checksum {}\n", body, checksum)
+}
+
+/// Split a file into its numbered, whitespace-separated lines after
+/// checking its magic and checksum
+pub fn check_file<'a>(text: &'a str, magic: &str) -> Result<Vec<(usize, Vec<&'a str>)>, Error> {
+    let end = match text.trim_right().rfind('\n') {
+        Some(end) => end + 1,
+        None => { return Err(Error::BadMagic); }
+    };
+    let (body, last) = (&text[..end], text[end..].trim());
+    if !body.starts_with(magic) || body[magic.len()..].chars().next() != Some('\n') {
+        return Err(Error::BadMagic);
+    }
+    if !last.starts_with("checksum ") || last["checksum ".len()..] != sha256_hex(body)[..] {
+        return Err(Error::BadChecksum);
+    }
+    Ok(body.lines().enumerate().skip(1)
+           .map(|(n, line)| (n + 1, line.split_whitespace().collect()))
+           .filter(|&(_, ref fields): &(usize, Vec<&str>)| !fields.is_empty())
+           .collect())
+}
+
+/// Parse a hex-encoded script field
+fn parse_script(line: usize, hex: &str) -> Result<Script, Error> {
+    hex.from_hex().map(Script::from).map_err(|_| Error::BadLine(line))
+}
+
+/// A request to tweak the keys of a redemption script
+#[derive(Clone, PartialEq, Eq)]
+pub struct Request {
+    /// Network the addresses are for
+    pub network: Network,
+    /// Redemption script to tweak
+    pub script: Script,
+    /// Contract to tweak it for
+    pub contract: Contract
+}
+
+impl Request {
+    /// Serialize the request as a file
+    pub fn to_file(&self) -> String {
+        add_checksum(format!("{}\nnetwork {}\nscript {}\ncontract {:x}\n", REQUEST_MAGIC,
+                             match self.network { Network::Bitcoin => "mainnet", Network::Testnet => "testnet" },
+                             self.script[..].to_hex(), self.contract))
+    }
+
+    /// Parse a request file, checking its checksum
+    pub fn from_file(text: &str) -> Result<Request, Error> {
+        let (mut network, mut script, mut contract) = (None, None, None);
+        for (n, fields) in try!(check_file(text, REQUEST_MAGIC)) {
+            match (fields[0], fields.len()) {
+                ("network", 2) if fields[1] == "mainnet" => network = Some(Network::Bitcoin),
+                ("network", 2) if fields[1] == "testnet" => network = Some(Network::Testnet),
+                ("script", 2) => script = Some(try!(parse_script(n, fields[1]))),
+                ("contract", 2) => contract = Some(try!(Contract::from_hex(fields[1]).map_err(|_| Error::BadLine(n)))),
+                _ => { return Err(Error::BadLine(n)); }
+            }
+        }
+        Ok(Request {
+            network: try!(network.ok_or(Error::MissingField("network"))),
+            script: try!(script.ok_or(Error::MissingField("script"))),
+            contract: try!(contract.ok_or(Error::MissingField("contract")))
+        })
+    }
+}
+
+/// The result of tweaking the keys of a request
+#[derive(Clone, PartialEq, Eq)]
+pub struct Response {
+    /// SHA256 of the request file this answers
+    pub request_hash: String,
+    /// Modified redemption script
+    pub script: Script,
+    /// Each key which was tweaked, with what it was tweaked to
+    pub keys: Vec<(PublicKey, PublicKey)>
+}
+
+/// The hash of a request file which its response refers to it by
+pub fn request_hash(request_file: &str) -> String {
+    sha256_hex(request_file)
+}
+
+impl Response {
+    /// Serialize the response as a file
+    pub fn to_file(&self, secp: &Secp256k1) -> String {
+        let mut body = format!("{}\nrequest {}\nscript {}\n", RESPONSE_MAGIC, self.request_hash, self.script[..].to_hex());
+        for &(ref original, ref tweaked) in &self.keys {
+            body.push_str(&format!("key {} {}\n", original.serialize_vec(secp, true)[..].to_hex(),
+                                   tweaked.serialize_vec(secp, true)[..].to_hex()));
+        }
+        add_checksum(body)
+    }
+
+    /// Parse a response file, checking its checksum
+    pub fn from_file(secp: &Secp256k1, text: &str) -> Result<Response, Error> {
+        let (mut request_hash, mut script, mut keys) = (None, None, vec![]);
+        for (n, fields) in try!(check_file(text, RESPONSE_MAGIC)) {
+            match (fields[0], fields.len()) {
+                ("request", 2) => request_hash = Some(fields[1].to_owned()),
+                ("script", 2) => script = Some(try!(parse_script(n, fields[1]))),
+                ("key", 3) => {
+                    let parse = |hex: &str| hex.from_hex().ok().and_then(|data| PublicKey::from_slice(secp, &data).ok());
+                    match (parse(fields[1]), parse(fields[2])) {
+                        (Some(original), Some(tweaked)) => keys.push((original, tweaked)),
+                        _ => { return Err(Error::BadLine(n)); }
+                    }
+                }
+                _ => { return Err(Error::BadLine(n)); }
+            }
+        }
+        Ok(Response {
+            request_hash: try!(request_hash.ok_or(Error::MissingField("request"))),
+            script: try!(script.ok_or(Error::MissingField("script"))),
+            keys: keys
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::script::Script;
+    use bitcoin::network::constants::Network;
+    use secp256k1::Secp256k1;
+    use secp256k1::key::PublicKey;
+    use serialize::hex::FromHex;
+
+    use contract::Contract;
+
+    use super::*;
+
+    /// The public keys whose secret keys are 1 and 2
+    const KEY: &'static str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const TWEAKED: &'static str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+    /// A text contract
+    const CONTRACT: &'static str = "5445585400112233445566778899aabbccddeeff68656c6c6f2e2e2e2e2e2e2e2e2e2e2e2e2e2e00";
+
+    fn request() -> Request {
+        Request {
+            network: Network::Testnet,
+            script: Script::from(format!("5121{}51ae", KEY).from_hex().unwrap()),
+            contract: Contract::from_hex(CONTRACT).unwrap()
+        }
+    }
+
+    fn key(hex: &str) -> PublicKey {
+        PublicKey::from_slice(&Secp256k1::new(), &hex.from_hex().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn request_roundtrip() {
+        let file = request().to_file();
+        assert_eq!(file, format!("pacthash-tweak-request 1\n\
+                                  network testnet\n\
+                                  script 5121{}51ae\n\
+                                  contract {}\n\
+                                  checksum 8532beffa1158d06cd8c051f5f1f3c264e582367176086ba515eeb4254b387d8\n", KEY, CONTRACT));
+        assert!(Request::from_file(&file).unwrap() == request());
+        // Blank lines, and the checksum line's trailing newline, are not needed
+        assert!(Request::from_file(file.trim_right()).unwrap() == request());
+    }
+
+    #[test]
+    fn response_roundtrip() {
+        let secp = Secp256k1::new();
+        let request_file = request().to_file();
+        let response = Response {
+            request_hash: request_hash(&request_file),
+            script: Script::from(format!("5121{}51ae", TWEAKED).from_hex().unwrap()),
+            keys: vec![(key(KEY), key(TWEAKED))]
+        };
+        let file = response.to_file(&secp);
+        assert!(file.starts_with(&format!("pacthash-tweak-response 1\n\
+                                           request 5e709b1b2e2a07c692c9dd5870836c237ad06bc75c60f162e1e44fdc7a2f60bf\n\
+                                           script 5121{}51ae\n\
+                                           key {} {}\n\
+                                           checksum ", TWEAKED, KEY, TWEAKED)));
+        assert!(Response::from_file(&secp, &file).unwrap() == response);
+        // A response is not a request, nor the other way around
+        match Request::from_file(&file) {
+            Err(Error::BadMagic) => {}
+            other => panic!("unexpected result {:?}", other.map(|_| ()))
+        }
+        match Response::from_file(&secp, &request_file) {
+            Err(Error::BadMagic) => {}
+            other => panic!("unexpected result {:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn tampered_checksum() {
+        let secp = Secp256k1::new();
+        let file = request().to_file();
+        // A changed line, a changed checksum, or a missing one
+        let changed = file.replace("network testnet", "network mainnet");
+        let bad_checksum = file.replace("checksum 8", "checksum 9");
+        let no_checksum = file.replace("checksum", "checksun");
+        for text in &[changed, bad_checksum, no_checksum] {
+            match Request::from_file(text) {
+                Err(Error::BadChecksum) => {}
+                other => panic!("unexpected result {:?}", other.map(|_| ()))
+            }
+        }
+        // A response's key swapped for another
+        let response = Response {
+            request_hash: request_hash(&file),
+            script: Script::from(format!("5121{}51ae", TWEAKED).from_hex().unwrap()),
+            keys: vec![(key(KEY), key(TWEAKED))]
+        };
+        let swapped = response.to_file(&secp).replace(&format!("key {} {}", KEY, TWEAKED), &format!("key {} {}", TWEAKED, KEY));
+        match Response::from_file(&secp, &swapped) {
+            Err(Error::BadChecksum) => {}
+            other => panic!("unexpected result {:?}", other.map(|_| ()))
+        }
+    }
+}
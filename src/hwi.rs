@@ -0,0 +1,85 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Hardware Wallets
+//! Checking base keys against a hardware wallet through the `hwi` command
+//! line tool. Devices know nothing of contract tweaks, so they can only
+//! vouch for the untweaked keys; the tweak itself is computed here.
+//!
+
+use bitcoin::network::constants::Network;
+use bitcoin::util::base58::FromBase58;
+use secp256k1::key::PublicKey;
+use serialize::hex::ToHex;
+use serialize::json::Json;
+
+use std::process::{Command, Stdio};
+
+use bip32::{self, ExtendedPubKey, KeySource};
+
+/// Hardware wallet error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// `hwi` could not be run or failed
+    Hwi(String),
+    /// `hwi` reported an error from the device
+    Device(String),
+    /// `hwi` output could not be understood
+    BadOutput(String)
+}
+
+/// Run `hwi` for the device with a given fingerprint, returning the field
+/// `field` of its JSON output
+fn run(source: &KeySource, network: Network, args: &[&str], field: &str) -> Result<String, Error> {
+    let mut command = Command::new("hwi");
+    command.arg("--fingerprint").arg(source.fingerprint[..].to_hex());
+    if network == Network::Testnet {
+        command.arg("--chain").arg("test");
+    }
+    let output = try!(command.args(args)
+                             .stdin(Stdio::inherit())
+                             .stderr(Stdio::inherit())
+                             .output()
+                             .map_err(|e| Error::Hwi(format!("unable to run hwi: {}", e))));
+    let text = String::from_utf8_lossy(&output.stdout).into_owned();
+    let json = try!(Json::from_str(&text).map_err(|_| Error::BadOutput(text.clone())));
+    if let Some(error) = json.find("error").and_then(|e| e.as_string()) {
+        return Err(Error::Device(error.to_owned()));
+    }
+    if !output.status.success() {
+        return Err(Error::Hwi(format!("hwi failed ({})", output.status)));
+    }
+    match json.find(field).and_then(|f| f.as_string()) {
+        Some(value) => Ok(value.to_owned()),
+        None => Err(Error::BadOutput(text.clone()))
+    }
+}
+
+/// Fetch the public key at a key's origin from the device holding it
+pub fn get_pubkey(source: &KeySource, network: Network) -> Result<PublicKey, Error> {
+    let path = bip32::format_path(&source.path);
+    let xpub = try!(run(source, network, &["getxpub", &path], "xpub"));
+    let decode: Result<ExtendedPubKey, _> = FromBase58::from_base58check(&xpub);
+    match decode {
+        Ok(xpub) => Ok(xpub.public_key),
+        Err(_) => Err(Error::BadOutput(xpub))
+    }
+}
+
+/// Have the device display the (untweaked) P2PKH address of the key at
+/// a key's origin, returning the address it shows
+pub fn display_address(source: &KeySource, network: Network) -> Result<String, Error> {
+    let path = bip32::format_path(&source.path);
+    run(source, network, &["displayaddress", "--path", &path, "--addr-type", "legacy"], "address")
+}
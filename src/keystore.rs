@@ -0,0 +1,430 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Keystore
+//! A file of named private keys, each encrypted with ChaCha20-Poly1305
+//! under a key stretched from a passphrase with scrypt. Key names are
+//! stored in the clear so that they can be listed without the passphrase.
+//!
+//! The file is text, one record per line:
+//!
+//! ```text
+//! pacthash-keystore 1
+//! salt <hex>
+//! verifier <nonce> <ciphertext> <tag>
+//! default <name>
+//! key <name> <nonce> <ciphertext> <tag>
+//! ```
+//!
+
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::scrypt::{scrypt, ScryptParams};
+use rand::Rng;
+use serialize::hex::{FromHex, ToHex};
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str;
+
+use atomic;
+use memlock::{self, SecretText};
+use tty::{self, Stream};
+
+/// First line of a keystore file
+const MAGIC: &'static str = "pacthash-keystore 1";
+/// Additional data of the record which checks the passphrase
+const VERIFIER_AAD: &'static [u8] = b"pacthash keystore verifier";
+/// Environment variable which, if set, supplies the passphrase
+pub const PASSPHRASE_VAR: &'static str = "PACTHASH_KEYSTORE_PASSPHRASE";
+
+/// Keystore error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// File could not be read or written
+    Io(String),
+    /// File was not a keystore of a known version
+    BadFormat(usize),
+    /// Passphrase did not decrypt the keystore
+    WrongPassphrase,
+    /// A record failed authentication, i.e. the file was modified
+    Corrupt(String),
+    /// There was no key with the given name
+    NoSuchKey(String),
+    /// A key with the given name already exists
+    DuplicateKey(String),
+    /// Key names may not be empty or contain whitespace
+    BadName(String),
+    /// No name was given and the keystore has no default key
+    NoDefault
+}
+
+/// An encrypted record
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Sealed {
+    nonce: [u8; 8],
+    ciphertext: Vec<u8>,
+    tag: [u8; 16]
+}
+
+impl Sealed {
+    /// Encrypt some data, authenticating it along with `aad`
+    fn seal<R: Rng>(rng: &mut R, key: &[u8; 32], aad: &[u8], data: &[u8]) -> Sealed {
+        let mut nonce = [0; 8];
+        rng.fill_bytes(&mut nonce);
+        let mut ret = Sealed { nonce: nonce, ciphertext: vec![0; data.len()], tag: [0; 16] };
+        ChaCha20Poly1305::new(&key[..], &nonce, aad).encrypt(data, &mut ret.ciphertext, &mut ret.tag);
+        ret
+    }
+
+    /// Decrypt the record, if it authenticates along with `aad`
+    fn open(&self, key: &[u8; 32], aad: &[u8]) -> Option<Vec<u8>> {
+        let mut ret = vec![0; self.ciphertext.len()];
+        if ChaCha20Poly1305::new(&key[..], &self.nonce, aad).decrypt(&self.ciphertext, &mut ret, &self.tag) {
+            Some(ret)
+        } else {
+            None
+        }
+    }
+
+    /// Parse the three hex fields of a record
+    fn parse(fields: &[&str]) -> Option<Sealed> {
+        if fields.len() != 3 {
+            return None;
+        }
+        // Empty ciphertexts are written as `-` to keep the field count fixed
+        let ciphertext = if fields[1] == "-" { Ok(vec![]) } else { fields[1].from_hex() };
+        match (fields[0].from_hex(), ciphertext, fields[2].from_hex()) {
+            (Ok(ref nonce), Ok(ciphertext), Ok(ref tag)) if nonce.len() == 8 && tag.len() == 16 => {
+                let mut ret = Sealed { nonce: [0; 8], ciphertext: ciphertext, tag: [0; 16] };
+                ret.nonce.clone_from_slice(nonce);
+                ret.tag.clone_from_slice(tag);
+                Some(ret)
+            }
+            _ => None
+        }
+    }
+
+    /// Format the record as three hex fields
+    fn format(&self) -> String {
+        let ciphertext = if self.ciphertext.is_empty() { "-".to_owned() } else { self.ciphertext.to_hex() };
+        format!("{} {} {}", self.nonce[..].to_hex(), ciphertext, self.tag[..].to_hex())
+    }
+}
+
+/// An encrypted keystore
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Keystore {
+    salt: [u8; 16],
+    verifier: Sealed,
+    default: Option<String>,
+    keys: Vec<(String, Sealed)>
+}
+
+/// The keystore used when none is given, `~/.pacthash-keystore`
+pub fn default_path() -> PathBuf {
+    let mut ret = env::home_dir().unwrap_or(PathBuf::from("."));
+    ret.push(".pacthash-keystore");
+    ret
+}
+
+/// Read the keystore passphrase from `PACTHASH_KEYSTORE_PASSPHRASE`, or
+/// from the terminal if that is not set
+pub fn read_passphrase(prompt: &str) -> Result<String, Error> {
+    match env::var(PASSPHRASE_VAR) {
+        Ok(passphrase) => Ok(passphrase),
+        Err(_) => read_secret(prompt)
+    }
+}
+
+/// Read a line from the terminal without echoing it. From piped stdin the
+/// line is read without prompting for it.
+pub fn read_secret(prompt: &str) -> Result<String, Error> {
+    let interactive = tty::is_terminal(Stream::Stdin);
+    if interactive {
+        let _ = write!(io::stderr(), "{}", prompt);
+    }
+    let echo_off = interactive && Command::new("stty").arg("-echo").stdin(Stdio::inherit()).stderr(Stdio::null())
+                                                      .status().map(|s| s.success()).unwrap_or(false);
+    let mut line = String::new();
+    let result = io::stdin().read_line(&mut line);
+    if echo_off {
+        let _ = Command::new("stty").arg("echo").stdin(Stdio::inherit()).status();
+        let _ = writeln!(io::stderr(), "");
+    }
+    try!(result.map_err(|e| Error::Io(e.to_string())));
+    Ok(line.trim_right_matches(|c| c == '\r' || c == '\n').to_owned())
+}
+
+/// Check that a key name can be stored
+fn check_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        Err(Error::BadName(name.to_owned()))
+    } else {
+        Ok(())
+    }
+}
+
+impl Keystore {
+    /// Create an empty keystore encrypted with a passphrase
+    pub fn create<R: Rng>(rng: &mut R, passphrase: &str) -> Keystore {
+        let mut salt = [0; 16];
+        rng.fill_bytes(&mut salt);
+        let key = derive_key(passphrase, &salt);
+        Keystore {
+            salt: salt,
+            verifier: Sealed::seal(rng, &key, VERIFIER_AAD, &[]),
+            default: None,
+            keys: vec![]
+        }
+    }
+
+    /// Read a keystore from a file
+    pub fn load(path: &PathBuf) -> Result<Keystore, Error> {
+        let mut text = String::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_string(&mut text))
+                 .map_err(|e| Error::Io(format!("{}: {}", path.display(), e))));
+        Keystore::parse(&text)
+    }
+
+    /// Parse the text of a keystore file
+    pub fn parse(text: &str) -> Result<Keystore, Error> {
+        let mut have_salt = false;
+        let mut verifier = None;
+        let mut ret = Keystore { salt: [0; 16], verifier: Sealed { nonce: [0; 8], ciphertext: vec![], tag: [0; 16] }, default: None, keys: vec![] };
+        for (n, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if n == 0 {
+                if line != MAGIC {
+                    return Err(Error::BadFormat(1));
+                }
+                continue;
+            }
+            match fields.first() {
+                Some(&"salt") if fields.len() == 2 => {
+                    match fields[1].from_hex() {
+                        Ok(ref data) if data.len() == 16 => {
+                            ret.salt.clone_from_slice(data);
+                            have_salt = true;
+                        }
+                        _ => { return Err(Error::BadFormat(n + 1)); }
+                    }
+                }
+                Some(&"verifier") => { verifier = Sealed::parse(&fields[1..]); }
+                Some(&"default") if fields.len() == 2 => { ret.default = Some(fields[1].to_owned()); }
+                Some(&"key") if fields.len() == 5 => {
+                    match Sealed::parse(&fields[2..]) {
+                        Some(sealed) => ret.keys.push((fields[1].to_owned(), sealed)),
+                        None => { return Err(Error::BadFormat(n + 1)); }
+                    }
+                }
+                None => {}
+                _ => { return Err(Error::BadFormat(n + 1)); }
+            }
+        }
+        match verifier {
+            Some(verifier) if have_salt => { ret.verifier = verifier; Ok(ret) }
+            _ => Err(Error::BadFormat(0))
+        }
+    }
+
+    /// The text of the keystore file
+    pub fn format(&self) -> String {
+        let mut text = format!("{}\nsalt {}\nverifier {}\n", MAGIC, self.salt[..].to_hex(), self.verifier.format());
+        if let Some(ref name) = self.default {
+            text.push_str(&format!("default {}\n", name));
+        }
+        for &(ref name, ref sealed) in &self.keys {
+            text.push_str(&format!("key {} {}\n", name, sealed.format()));
+        }
+        text
+    }
+
+    /// Write the keystore to a file, readable only by its owner
+    pub fn save(&self, path: &PathBuf, create: bool) -> Result<(), Error> {
+        let text = self.format();
+        // Written atomically, so that a crash cannot lose the keys already stored
+        atomic::write(path, text.as_bytes(), !create, true).map_err(|e| Error::Io(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Derive the encryption key from the passphrase, checking it
+    pub fn unlock(&self, passphrase: &str) -> Result<[u8; 32], Error> {
+        let key = derive_key(passphrase, &self.salt);
+        match self.verifier.open(&key, VERIFIER_AAD) {
+            Some(_) => Ok(key),
+            None => Err(Error::WrongPassphrase)
+        }
+    }
+
+    /// The names of the stored keys, in the order they were added
+    pub fn names(&self) -> Vec<&str> {
+        self.keys.iter().map(|&(ref name, _)| &name[..]).collect()
+    }
+
+    /// The name of the key used when none is given
+    pub fn default_name(&self) -> Option<&str> {
+        self.default.as_ref().map(|s| &s[..])
+    }
+
+    /// Store a key under a new name
+    pub fn add<R: Rng>(&mut self, rng: &mut R, key: &[u8; 32], name: &str, secret: &str) -> Result<(), Error> {
+        try!(check_name(name));
+        if self.keys.iter().any(|&(ref existing, _)| existing == name) {
+            return Err(Error::DuplicateKey(name.to_owned()));
+        }
+        // The name is authenticated so records cannot be swapped between names
+        let sealed = Sealed::seal(rng, key, name.as_bytes(), secret.as_bytes());
+        self.keys.push((name.to_owned(), sealed));
+        Ok(())
+    }
+
+    /// Make a key the one used when none is given
+    pub fn set_default(&mut self, name: &str) -> Result<(), Error> {
+        if !self.keys.iter().any(|&(ref existing, _)| existing == name) {
+            return Err(Error::NoSuchKey(name.to_owned()));
+        }
+        self.default = Some(name.to_owned());
+        Ok(())
+    }
+
+    /// Decrypt the key with a given name, or the default key if the name
+    /// is empty
+    pub fn get(&self, key: &[u8; 32], name: &str) -> Result<SecretText, Error> {
+        let name = if name.is_empty() {
+            match self.default {
+                Some(ref name) => &name[..],
+                None => { return Err(Error::NoDefault); }
+            }
+        } else {
+            name
+        };
+        match self.keys.iter().find(|&&(ref existing, _)| existing == name) {
+            Some(&(_, ref sealed)) => {
+                let mut data = try!(sealed.open(key, name.as_bytes()).ok_or(Error::Corrupt(name.to_owned())));
+                let ret = str::from_utf8(&data).map(SecretText::from).map_err(|_| Error::Corrupt(name.to_owned()));
+                memlock::zero(&mut data);
+                ret
+            }
+            None => Err(Error::NoSuchKey(name.to_owned()))
+        }
+    }
+}
+
+/// Stretch a passphrase into an encryption key
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut ret = [0; 32];
+    scrypt(passphrase.as_bytes(), salt, &ScryptParams::new(15, 8, 1), &mut ret);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, StdRng};
+
+    use super::*;
+
+    const WIF: &'static str = "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn";
+
+    fn rng() -> StdRng {
+        let seed: &[_] = &[1, 2, 7];
+        SeedableRng::from_seed(seed)
+    }
+
+    #[test]
+    fn seal_open() {
+        let mut rng = rng();
+        let key = [7; 32];
+        let sealed = Sealed::seal(&mut rng, &key, b"alice", WIF.as_bytes());
+        assert_eq!(sealed.ciphertext.len(), WIF.len());
+        assert!(sealed.ciphertext[..] != WIF.as_bytes()[..]);
+        assert_eq!(sealed.open(&key, b"alice"), Some(WIF.as_bytes().to_owned()));
+        // The wrong key, other additional data or a flipped bit all fail
+        assert_eq!(sealed.open(&[8; 32], b"alice"), None);
+        assert_eq!(sealed.open(&key, b"bob"), None);
+        let mut tampered = sealed.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert_eq!(tampered.open(&key, b"alice"), None);
+
+        // Records format as three fields, with `-` for an empty ciphertext
+        let fields = sealed.format();
+        assert_eq!(Sealed::parse(&fields.split(' ').collect::<Vec<_>>()), Some(sealed));
+        let empty = Sealed::seal(&mut rng, &key, VERIFIER_AAD, &[]);
+        let fields = empty.format();
+        assert_eq!(fields.split(' ').nth(1), Some("-"));
+        assert_eq!(Sealed::parse(&fields.split(' ').collect::<Vec<_>>()), Some(empty));
+        assert_eq!(Sealed::parse(&["00", "-", "00"]), None);
+        assert_eq!(Sealed::parse(&[]), None);
+    }
+
+    #[test]
+    fn keystore() {
+        let mut rng = rng();
+        let mut keystore = Keystore::create(&mut rng, "correct horse");
+        let key = keystore.unlock("correct horse").unwrap();
+        match keystore.unlock("battery staple") {
+            Err(Error::WrongPassphrase) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+
+        keystore.add(&mut rng, &key, "alice", WIF).unwrap();
+        keystore.add(&mut rng, &key, "bob", "another key").unwrap();
+        match keystore.add(&mut rng, &key, "alice", WIF) {
+            Err(Error::DuplicateKey(ref name)) if name == "alice" => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+        match keystore.add(&mut rng, &key, "two words", WIF) {
+            Err(Error::BadName(_)) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+        match keystore.get(&key, "") {
+            Err(Error::NoDefault) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+        keystore.set_default("bob").unwrap();
+        assert_eq!(keystore.names(), vec!["alice", "bob"]);
+        assert_eq!(&keystore.get(&key, "alice").unwrap()[..], WIF);
+        assert_eq!(&keystore.get(&key, "").unwrap()[..], "another key");
+
+        // The file parses back to the same keystore, its verifier's empty
+        // ciphertext written as `-`
+        let text = keystore.format();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "pacthash-keystore 1");
+        assert_eq!(lines[2].split(' ').nth(2), Some("-"));
+        assert_eq!(lines[3], "default bob");
+        assert!(lines[4].starts_with("key alice "));
+        assert_eq!(Keystore::parse(&text).unwrap(), keystore);
+        match Keystore::parse(&text.replace("pacthash-keystore 1", "pacthash-keystore 2")) {
+            Err(Error::BadFormat(1)) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+        match Keystore::parse(&text.replace("key alice", "key alice extra")) {
+            Err(Error::BadFormat(5)) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+
+        // A record moved under another name fails to authenticate
+        let alice = lines[4]["key alice ".len()..].to_owned();
+        let moved = text.replace(&format!("key alice {}", alice), &format!("key carol {}", alice));
+        let moved = Keystore::parse(&moved).unwrap();
+        match moved.get(&key, "carol") {
+            Err(Error::Corrupt(ref name)) if name == "carol" => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+}
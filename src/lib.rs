@@ -0,0 +1,86 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PactHash
+//!
+//! The library behind the pacthash tool: contract hashing of keys and
+//! scripts, and the formats and services around it. It is also built as a
+//! C library, whose interface is the `ffi` module, and (with the `python`
+//! feature) a Python module, the `python` module.
+//!
+
+#![crate_name = "pacthash"]
+
+// Coding conventions
+#![deny(non_upper_case_globals)]
+#![deny(non_camel_case_types)]
+#![deny(non_snake_case)]
+#![deny(unused_mut)]
+#![deny(missing_docs)]
+
+extern crate bitcoin;
+extern crate crypto;
+extern crate num_cpus;
+extern crate rand;
+extern crate rustc_serialize as serialize;
+extern crate secp256k1;
+#[cfg(feature = "python")] extern crate pyo3;
+// The code PyO3 generates names `::core`, which is only the crate root's on
+// this edition
+#[cfg(feature = "python")] extern crate core;
+
+#[macro_use] pub mod macros;
+pub mod asm;
+pub mod atomic;
+pub mod bip32;
+pub mod bip39;
+pub mod bip329;
+pub mod chain;
+pub mod check;
+pub mod clipboard;
+pub mod context;
+pub mod contract;
+#[cfg(unix)] pub mod daemon;
+pub mod dedup;
+pub mod descriptor;
+pub mod electrum;
+pub mod elements;
+pub mod esplora;
+pub mod ffi;
+pub mod handoff;
+pub mod hwi;
+pub mod keystore;
+pub mod log;
+pub mod memlock;
+pub mod message;
+pub mod metrics;
+pub mod osrng;
+pub mod ots;
+pub mod parallel;
+pub mod plugin;
+pub mod privkey;
+pub mod psbt;
+#[cfg(feature = "python")] pub mod python;
+pub mod ratelimit;
+pub mod reserves;
+pub mod rpc;
+pub mod segwit;
+pub mod signing;
+pub mod slip39;
+pub mod server;
+pub mod sweep;
+pub mod template;
+pub mod tty;
+pub mod watch;
+pub mod zmq;
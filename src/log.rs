@@ -0,0 +1,206 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+
+//! # Logging
+//! Diagnostics of the long-running modes (`serve`, `daemon`, `watch`) and
+//! of batch runs, as one line per event: a level, an event name, and some
+//! `key=value` fields, so that journald and log shippers can pick them
+//! apart. Lines can instead be JSON objects, and go to stderr unless a log
+//! file is given. What a command prints as its result still goes to
+//! stdout; only diagnostics are logged.
+//!
+//! Fields whose names mark them as secret (keys, passphrases, tokens and
+//! the like) are redacted however they are passed in, so that no log line
+//! can carry one.
+//!
+
+use serialize::json::Json;
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Mutex, Once, ONCE_INIT};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What is logged in place of a secret
+const REDACTED: &'static str = "[redacted]";
+/// Field names which mark a secret, anywhere within them
+const SECRET_NAMES: &'static [&'static str] = &["privkey", "secret", "passphrase", "password", "token",
+                                                 "seed", "mnemonic", "xprv", "wif"];
+
+/// How important an event is
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    /// Something failed
+    Error,
+    /// Something went wrong which was recovered from
+    Warn,
+    /// The normal course of things
+    Info,
+    /// Detail, such as each request answered
+    Debug
+}
+
+impl Level {
+    /// Parse a level from its name
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            _ => None
+        }
+    }
+
+    /// The name of the level, as logged
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug"
+        }
+    }
+}
+
+/// How log lines are written
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Format {
+    /// `key=value` pairs
+    Text,
+    /// JSON objects
+    Json
+}
+
+/// Logging error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// The log file could not be opened
+    Io(String),
+    /// A level was not one of error, warn, info or debug
+    BadLevel(String),
+    /// A format was not text or json
+    BadFormat(String)
+}
+
+/// Where and how events are logged
+struct Sink {
+    level: Level,
+    format: Format,
+    file: Option<File>
+}
+
+static INIT: Once = ONCE_INIT;
+static mut SINK: *const Mutex<Sink> = 0 as *const Mutex<Sink>;
+
+/// The sink, which until `init` is called logs info and above to stderr
+fn sink() -> &'static Mutex<Sink> {
+    unsafe {
+        INIT.call_once(|| {
+            SINK = Box::into_raw(Box::new(Mutex::new(Sink { level: Level::Info, format: Format::Text, file: None })));
+        });
+        &*SINK
+    }
+}
+
+/// Set the least important level logged, the format of lines, and a file
+/// to append them to rather than writing them to stderr
+pub fn init(level: &str, format: &str, filename: Option<&str>) -> Result<(), Error> {
+    let level = try!(Level::from_name(level).ok_or(Error::BadLevel(level.to_owned())));
+    let format = match format {
+        "text" => Format::Text,
+        "json" => Format::Json,
+        _ => { return Err(Error::BadFormat(format.to_owned())); }
+    };
+    let file = match filename {
+        Some(filename) => Some(try!(OpenOptions::new().create(true).append(true).open(filename)
+                                        .map_err(|e| Error::Io(format!("{}: {}", filename, e))))),
+        None => None
+    };
+    let mut sink = sink().lock().unwrap();
+    sink.level = level;
+    sink.format = format;
+    sink.file = file;
+    Ok(())
+}
+
+/// Whether a field must not be logged
+fn is_secret(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SECRET_NAMES.iter().any(|name| key.contains(name))
+}
+
+/// Log an event, with some fields
+pub fn log(level: Level, event: &str, fields: &[(&str, &str)]) {
+    let mut sink = sink().lock().unwrap();
+    if level > sink.level {
+        return;
+    }
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9)
+                                                           .unwrap_or(0.0);
+    let fields: Vec<(&str, &str)> = fields.iter().map(|&(key, value)| {
+        (key, if is_secret(key) { REDACTED } else { value })
+    }).collect();
+
+    let line = match sink.format {
+        Format::Text => {
+            let mut line = format!("time={:.3} level={} event={}", time, level.name(), event);
+            for &(key, value) in &fields {
+                // Values are quoted when they would not survive splitting on spaces
+                if value.is_empty() || value.contains(|c: char| c == ' ' || c == '"' || c == '=' || c.is_control()) {
+                    line.push_str(&format!(" {}={:?}", key, value));
+                } else {
+                    line.push_str(&format!(" {}={}", key, value));
+                }
+            }
+            line
+        }
+        Format::Json => {
+            let mut obj = BTreeMap::new();
+            for &(key, value) in &fields {
+                obj.insert(key.to_owned(), Json::String(value.to_owned()));
+            }
+            obj.insert("time".to_owned(), Json::F64((time * 1000.0).round() / 1000.0));
+            obj.insert("level".to_owned(), Json::String(level.name().to_owned()));
+            obj.insert("event".to_owned(), Json::String(event.to_owned()));
+            Json::Object(obj).to_string()
+        }
+    };
+    let _ = match sink.file {
+        Some(ref mut file) => writeln!(file, "{}", line),
+        None => writeln!(io::stderr(), "{}", line)
+    };
+}
+
+/// Log an error
+pub fn error(event: &str, fields: &[(&str, &str)]) {
+    log(Level::Error, event, fields)
+}
+
+/// Log a warning
+pub fn warn(event: &str, fields: &[(&str, &str)]) {
+    log(Level::Warn, event, fields)
+}
+
+/// Log the normal course of things
+pub fn info(event: &str, fields: &[(&str, &str)]) {
+    log(Level::Info, event, fields)
+}
+
+/// Log detail
+pub fn debug(event: &str, fields: &[(&str, &str)]) {
+    log(Level::Debug, event, fields)
+}
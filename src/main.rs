@@ -27,28 +27,123 @@
 #![deny(unused_mut)]
 #![deny(missing_docs)]
 
+#[cfg(not(test))]
+use std::cmp;
+#[cfg(not(test))]
+use std::collections::BTreeMap;
 #[cfg(not(test))]
 use std::env;
+#[cfg(not(test))]
+use std::fs::File;
+#[cfg(not(test))]
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+#[cfg(not(test))]
+use std::iter;
+#[cfg(not(test))]
+use std::net::{TcpListener, TcpStream};
+#[cfg(all(unix, not(test)))] use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(not(test))]
+use std::path::{Path, PathBuf};
+#[cfg(not(test))]
+use std::sync::Arc;
+#[cfg(not(test))]
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+#[cfg(not(test))]
+use std::sync::mpsc;
+#[cfg(not(test))]
+use std::thread;
+#[cfg(not(test))]
+use std::time::Duration;
 
 extern crate bitcoin;
+extern crate crypto;
 extern crate getopts;
+extern crate pacthash;
 extern crate rand;
 extern crate rustc_serialize as serialize;
 extern crate secp256k1;
 
+#[cfg(not(test))]
 use bitcoin::blockdata::script::Script;
-use bitcoin::network::constants::Network;
+#[cfg(not(test))]
 use bitcoin::util::address::{Privkey, Address};
-use bitcoin::util::base58::{FromBase58, ToBase58};
-use bitcoin::util::contracthash::{tweak_keys, tweak_secret_key, untemplate};
-use rand::{Rng, OsRng};
+#[cfg(not(test))]
+use bitcoin::util::base58::{self, FromBase58, ToBase58};
+#[cfg(not(test))]
+use bitcoin::util::contracthash::{tweak_keys, untemplate};
+#[cfg(not(test))]
+use rand::Rng;
+#[cfg(not(test))]
 use secp256k1::Secp256k1;
-use serialize::hex::FromHex;
+#[cfg(not(test))]
+use secp256k1::key::PublicKey;
+#[cfg(not(test))]
+use serialize::hex::{FromHex, ToHex};
+#[cfg(not(test))]
+use serialize::json::Json;
 
+#[cfg(not(test))]
+use pacthash::{asm, atomic, bip32, bip39, bip329, chain, check, clipboard, context, contract, dedup, descriptor, electrum, elements,
+               esplora, handoff, hwi, keystore, log, memlock, message, metrics, osrng, ots, parallel, plugin, privkey, psbt, ratelimit,
+               reserves, rpc, segwit, server, signing, slip39, sweep, template, tty, watch, zmq};
+#[cfg(all(unix, not(test)))] use pacthash::daemon;
+#[cfg(not(test))]
+use bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey, KeySource, Wildcard, HARDENED};
+#[cfg(not(test))]
+use chain::Chain;
+#[cfg(not(test))]
+use check::MAX_PUBKEYS_PER_MULTISIG;
+#[cfg(not(test))]
 use contract::{Contract, Nonce};
+#[cfg(not(test))]
+use osrng::OsRng;
+#[cfg(not(test))]
+use tty::Stream;
+
+/// How many indices of a range are derived at once, bounding the keys held
+/// in memory however large the range
+#[cfg(not(test))]
+const JOB_CHUNK: usize = 4096;
+
+/// Size of the locked chunks tweaked keys bound for a file are gathered in
+#[cfg(not(test))]
+const SECRET_CHUNK: usize = 16384;
 
-#[macro_use] pub mod macros;
-pub mod contract;
+/// Set by `--format json`, to also report failures as JSON on stderr
+#[cfg(not(test))]
+static JSON_ERRORS: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Set by `fail`, so that the process exits non-zero once the command has
+/// returned
+#[cfg(not(test))]
+static FAILED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// When to sort the keys of a multisig given with `-k`, per BIP67
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Sorting {
+    /// Keep the keys in the order given
+    Unsorted,
+    /// Sort the untweaked keys, so the template is a BIP67 multisig and the
+    /// tweaked keys keep the positions of the keys they came from
+    BeforeTweak,
+    /// Sort the tweaked keys, so the modified redeem script is the BIP67
+    /// multisig a standard wallet would derive from the tweaked keys
+    AfterTweak
+}
+
+/// How to print the tweaked private key of `-c` mode
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PrivkeyFormat {
+    /// Base58check wallet import format
+    Wif,
+    /// The raw 32-byte secret, as hex
+    Hex,
+    /// BIP38-encrypted with the passphrase given with `--passphrase`
+    Bip38,
+    /// Split into SLIP-39 shares, encrypted with the passphrase given with
+    /// `--passphrase` if any, as (threshold, count)
+    Slip39(usize, usize)
+}
 
 /// Modes that the program can run in
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -59,124 +154,4751 @@ pub enum Mode {
     GenPrivkey
 }
 
+/// The `keystore` subcommand, managing the keys which `-p keystore:NAME` reads
 #[cfg(not(test))]
-fn main() {
-    let prog = env::args().next().unwrap();
-    let args: Vec<_> = env::args().skip(1).collect();
-    let mut rng = OsRng::new().unwrap(); // panic immediately if we can't get a RNG
+fn keystore_main(prog: &str, args: &[String]) {
+    // Only init and add need randomness, which not every platform has
+    let mut rng = OsRng::new();
 
-    // Parse options
     let mut opts = getopts::Options::new();
-    opts.optflag("c", "gen-address", "Generate a redemption script and corresponding address");
-    opts.optflag("g", "gen-privkey", "Generate a private key");
-    opts.optopt("r", "redeem-script", "Specify a hex-encode redemption script for -g mode.", "redemption script");
-    opts.optopt("p", "private-key", "Specify a base58-encoded private key for -c mode.", "redemption script");
-    opts.optopt("d", "p2sh-address", "Specify a contract as a P2SH address.", "P2SH address");
-    opts.optopt("a", "ascii-contract", "Specify a contract as an ASCII string.", "text");
-    opts.optopt("f", "hex-contract", "Specify a contract as an hexadecimal string.", "hex");
-    opts.optopt("n", "nonce", "Specify a hex-encoded nonce.", "nonce");
+    opts.optopt("", "keystore", "Specify the keystore file (defaults to ~/.pacthash-keystore).", "file");
     opts.optflag("h", "help", "Print this help message and exit.");
-    opts.optflag("t", "testnet", "Set the tool to testnet mode (defaults to main)");
+    let short_usage = format!("{} keystore [--keystore file] <init|add NAME|list|use NAME>\n\n\
+                               The passphrase is read from the terminal, or from {} if it is set.",
+                              prog, keystore::PASSPHRASE_VAR);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    memlock::disable_core_dumps();
+    let path = matches.opt_str("keystore").map(PathBuf::from).unwrap_or(keystore::default_path());
+    let command: Vec<&str> = matches.free.iter().map(|s| &s[..]).collect();
+
+    // Every command but init works on an existing keystore
+    let mut store = match command.first() {
+        Some(&"init") | None => None,
+        Some(_) => {
+            match keystore::Keystore::load(&path) {
+                Ok(store) => Some(store),
+                Err(e) => {
+                    fail("keystore", "--keystore", &format!("Unable to read keystore: {:?}", e));
+                    return;
+                }
+            }
+        }
+    };
+
+    let result = match (command.first(), command.len(), store.as_mut()) {
+        (Some(&"init"), 1, _) => {
+            if path.exists() {
+                fail("keystore", "--keystore", &format!("{} already exists.", path.display()));
+                return;
+            }
+            let rng = match rng.as_mut() {
+                Ok(rng) => rng,
+                Err(e) => {
+                    fail("no_rng", "", &format!("Unable to get a random number generator: {}", e));
+                    return;
+                }
+            };
+            keystore::read_passphrase("New keystore passphrase: ").and_then(|passphrase| {
+                if env::var(keystore::PASSPHRASE_VAR).is_err() &&
+                   try!(keystore::read_secret("Repeat passphrase: ")) != passphrase {
+                    fail("keystore", "", "Passphrases did not match.");
+                    return Ok(());
+                }
+                let store = keystore::Keystore::create(rng, &passphrase);
+                try!(store.save(&path, true));
+                println!("Created keystore {}.", path.display());
+                Ok(())
+            })
+        }
+        (Some(&"add"), 2, Some(store)) => {
+            let rng = match rng.as_mut() {
+                Ok(rng) => rng,
+                Err(e) => {
+                    fail("no_rng", "", &format!("Unable to get a random number generator: {}", e));
+                    return;
+                }
+            };
+            keystore::read_passphrase("Keystore passphrase: ").and_then(|passphrase| {
+                let key = try!(store.unlock(&passphrase));
+                let secret = try!(keystore::read_secret(&format!("Private key for {}: ", command[1])));
+                try!(store.add(rng, &key, command[1], secret.trim()));
+                try!(store.save(&path, false));
+                println!("Added key {}.", command[1]);
+                Ok(())
+            })
+        }
+        (Some(&"list"), 1, Some(store)) => {
+            for name in store.names() {
+                if store.default_name() == Some(name) {
+                    println!("{} (default)", name);
+                } else {
+                    println!("{}", name);
+                }
+            }
+            Ok(())
+        }
+        (Some(&"use"), 2, Some(store)) => {
+            store.set_default(command[1]).and_then(|_| store.save(&path, false)).map(|_| {
+                println!("Key {} is now the default for -p keystore:.", command[1]);
+            })
+        }
+        _ => {
+            fail("usage", "", "keystore takes one of init, add NAME, list or use NAME.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if let Err(e) = result {
+        fail("keystore", "", &format!("Keystore error: {:?}", e));
+    }
+}
+
+/// The kinds of file `registry fsck` checks, each with the test of a
+/// complete, well-formed file of that kind
+#[cfg(not(test))]
+fn registry_kinds() -> Vec<(&'static str, &'static str, fn(&str) -> bool)> {
+    fn keystore(text: &str) -> bool {
+        keystore::Keystore::parse(text).is_ok()
+    }
+    fn electrum_wallet(text: &str) -> bool {
+        Json::from_str(text).is_ok()
+    }
+    fn bip329(text: &str) -> bool {
+        text.lines().all(|line| line.trim().is_empty() || Json::from_str(line).is_ok())
+    }
+    fn watch_file(text: &str) -> bool {
+        text.lines().all(|line| line.trim().is_empty() || line.split_whitespace().count() >= 2)
+    }
+    vec![("keystore", "Check a keystore file (defaults to ~/.pacthash-keystore if nothing else is given).", keystore),
+         ("electrum-wallet", "Check an Electrum wallet file written by --electrum-wallet.", electrum_wallet),
+         ("bip329", "Check a BIP329 label file written by --bip329.", bip329),
+         ("watch-file", "Check an addresses file written by rotate --watch-file.", watch_file)]
+}
 
-    let short_usage = format!("{} [-t] <-c|-g> <-f contract|-d p2sh -n nonce|-a ascii -n nonce>", prog);
+/// The `registry fsck` subcommand, finding and repairing files that a
+/// crash left partly written
+#[cfg(not(test))]
+fn registry_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    for &(name, desc, _) in &registry_kinds() {
+        opts.optmulti("", name, desc, "file");
+    }
+    opts.optflag("n", "dry-run", "Report what is wrong without repairing it.");
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} registry fsck [-n] [--keystore file] [--electrum-wallet file] [--bip329 file] [--watch-file file]\n\n\
+                               A temporary file left by an interrupted write is moved into place if it is whole, and\n\
+                               removed otherwise. A file ending in a partial record has that record cut off, and is\n\
+                               kept as it was beside itself with .torn appended to its name.", prog);
     let full_usage = opts.usage(&short_usage);
 
-    let matches = match opts.parse(&args[..]) {
+    let matches = match opts.parse(args) {
         Ok(m) => m,
         Err(e) => {
-            println!("Argument error: {}", e);
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.free.len() != 1 || matches.free[0] != "fsck" {
+        fail("usage", "", "registry takes the one command fsck.");
+        println!("{}", full_usage);
+        return;
+    }
+    let dry_run = matches.opt_present("n");
+    let mut files = vec![];
+    for &(name, _, valid) in &registry_kinds() {
+        for filename in matches.opt_strs(name) {
+            files.push((PathBuf::from(filename), valid));
+        }
+    }
+    if files.is_empty() {
+        files.push((keystore::default_path(), registry_kinds()[0].2));
+    }
+
+    let mut damaged = 0;
+    for &(ref path, valid) in &files {
+        let findings = match atomic::fsck(path, valid, dry_run) {
+            Ok(findings) => findings,
+            Err(e) => {
+                fail("io", "", &format!("{}: unable to check: {}", path.display(), e));
+                damaged += 1;
+                continue;
+            }
+        };
+        if findings.is_empty() {
+            println!("{}: ok", path.display());
+        }
+        for finding in &findings {
+            match *finding {
+                atomic::Finding::Missing => println!("{}: does not exist", path.display()),
+                atomic::Finding::Restored => println!("{}: restored from the whole temporary file {}", path.display(),
+                                                      atomic::temp_path(path).display()),
+                atomic::Finding::StaleTemp => println!("{}: removed the leftover temporary file {}", path.display(),
+                                                       atomic::temp_path(path).display()),
+                atomic::Finding::Torn(ref record) => println!("{}: cut off the partial last record {:?}", path.display(), record),
+                atomic::Finding::Unrepairable => fail("damaged", "", &format!("{}: damaged, and cannot be repaired", path.display()))
+            }
+            if *finding != atomic::Finding::Missing {
+                damaged += 1;
+            }
+        }
+    }
+    if dry_run && damaged > 0 {
+        println!("Nothing was repaired, since -n was given.");
+    }
+}
+
+/// The key given to `-p`, read from the environment for `env:VAR`. A key
+/// given on the command line itself is left in shell history and can be
+/// seen by every user in `ps`, so unless `allow_literal` is set only a
+/// BIP38-encrypted one is taken; otherwise the command fails, naming the
+/// other ways `elsewhere` it can be given a key.
+#[cfg(not(test))]
+fn cli_key(p: &str, allow_literal: bool, elsewhere: &str) -> memlock::SecretText {
+    if p.starts_with("env:") {
+        match env::var(&p["env:".len()..]) {
+            Ok(mut key) => {
+                let ret = memlock::SecretText::from(key.trim());
+                memlock::zero_str(&mut key);
+                ret
+            }
+            Err(e) => fail_exit("bad_privkey", "-p", &format!("option to -p could not be read from the environment variable {}: {}.", &p["env:".len()..], e))
+        }
+    } else if allow_literal || privkey::is_bip38(p) {
+        memlock::SecretText::from(p)
+    } else {
+        fail_exit("cli_key_refused", "-p", &format!("Refusing a private key given on the command line, where it is left in shell history and visible in ps. \
+                                                     Give -p env:VAR to read it from an environment variable{}, or --insecure-allow-cli-key to accept the risk.", elsewhere))
+    }
+}
+
+/// The private key given to `-p`, or read from the file of
+/// `--privkey-file` (decrypting it if need be), the keystore or keychain
+/// `-p` names, or the environment. `-p -` is left for the caller to read
+/// from stdin. None if the key could not be had, which has been reported.
+#[cfg(not(test))]
+fn read_privkey(matches: &getopts::Matches, full_usage: &str) -> Option<Option<memlock::SecretText>> {
+    let privkey_str = match (matches.opt_str("p"), matches.opt_str("privkey-file")) {
+        (Some(_), Some(_)) => {
+            fail("usage", "-p", "At most one of -p or --privkey-file may be specified.");
+            println!("{}", full_usage);
+            return None;
+        }
+        (None, Some(filename)) => {
+            match privkey::read_key_file(&filename) {
+                Ok(key) => Some(key),
+                Err(e) => {
+                    fail("io", "--privkey-file", &format!("option to --privkey-file could not be read: {:?}.", e));
+                    return None;
+                }
+            }
+        }
+        (Some(ref p), None) if p.starts_with("keystore:") => {
+            let path = matches.opt_str("keystore").map(PathBuf::from).unwrap_or(keystore::default_path());
+            let result = keystore::Keystore::load(&path).and_then(|store| {
+                let passphrase = try!(keystore::read_passphrase("Keystore passphrase: "));
+                let key = try!(store.unlock(&passphrase));
+                store.get(&key, &p["keystore:".len()..])
+            });
+            match result {
+                Ok(key) => Some(key),
+                Err(e) => {
+                    fail("bad_privkey", "-p", &format!("option to -p could not be read from the keystore: {:?}.", e));
+                    return None;
+                }
+            }
+        }
+        (Some(ref p), None) if p.starts_with("keychain:") => {
+            match privkey::read_keychain(&p["keychain:".len()..]) {
+                Ok(key) => Some(key),
+                Err(e) => {
+                    fail("bad_privkey", "-p", &format!("option to -p could not be found in the keychain: {:?}.", e));
+                    return None;
+                }
+            }
+        }
+        // Left for the caller, which reads stdin once everything else is parsed
+        (Some(ref p), None) if p == "-" => Some(memlock::SecretText::from(&p[..])),
+        (Some(p), None) => Some(cli_key(&p, matches.opt_present("insecure-allow-cli-key"),
+                                        "; or use --privkey-file, -p - (stdin), -p keystore:NAME or -p keychain:NAME")),
+        (None, None) => None
+    };
+    Some(privkey_str)
+}
+
+/// Print what this binary was built from, for pinning exactly which one
+/// produced an address
+#[cfg(not(test))]
+fn print_version() {
+    println!("pacthash {}", env!("CARGO_PKG_VERSION"));
+    println!("commit: {}", env!("PACTHASH_GIT_COMMIT"));
+    println!("build date: {}", env!("PACTHASH_BUILD_DATE"));
+    println!("secp256k1: libsecp256k1, bundled with the secp256k1 crate {}", env!("PACTHASH_SECP256K1_VERSION"));
+    println!("features: {}", env!("PACTHASH_FEATURES"));
+}
+
+/// Print which chain is in use, for people reading the output. Piped
+/// output is left to the lines a program would parse.
+#[cfg(not(test))]
+fn print_banner(chain: Chain) {
+    if tty::is_terminal(Stream::Stdout) {
+        match chain {
+            Chain::Bitcoin => println!("Using mainnet!"),
+            Chain::Testnet => println!("Using testnet!"),
+            Chain::Signet => println!("Using signet!"),
+            Chain::Regtest => println!("Using regtest!"),
+            Chain::Litecoin => println!("Using Litecoin mainnet!"),
+            Chain::LitecoinTestnet => println!("Using Litecoin testnet!"),
+            Chain::Custom(params) => println!("Using {}!", params.name)
+        }
+    }
+}
+
+/// Print a sanity check warning, which goes to stderr instead when stdout
+/// is piped, so as not to be taken for data
+#[cfg(not(test))]
+fn print_warning(warning: &check::Warning) {
+    if tty::is_terminal(Stream::Stdout) {
+        println!("Warning: {}.", warning);
+    } else {
+        let _ = writeln!(io::stderr(), "Warning: {}.", warning);
+    }
+}
+
+/// Report a failure, as text on stdout. With `--format json` it is also
+/// written to stderr as a JSON object, giving a kind to branch on and the
+/// flag at fault (empty when no one flag is), e.g.
+/// `{"error":{"flag":"-n","kind":"bad_nonce","message":"..."}}`. The
+/// caller goes on to return, after which the process exits with status 1.
+#[cfg(not(test))]
+fn fail(kind: &str, flag: &str, message: &str) {
+    println!("{}", message);
+    fail_json(kind, flag, message);
+    FAILED.store(true, Ordering::SeqCst);
+}
+
+/// Report a failure of the `-p -` stream, whose stdout holds the tweaked
+/// keys, as text on stderr instead, and exit non-zero so that a script
+/// reading the keys can tell they are incomplete
+#[cfg(not(test))]
+fn fail_stream(kind: &str, message: &str) -> ! {
+    fail_exit(kind, "-p", message)
+}
+
+/// Report a failure which must not be mistaken for success, as text on
+/// stderr (and as JSON, if `--format json` asks), and exit non-zero
+#[cfg(not(test))]
+fn fail_exit(kind: &str, flag: &str, message: &str) -> ! {
+    let _ = writeln!(io::stderr(), "{}", message);
+    fail_json(kind, flag, message);
+    std::process::exit(1);
+}
+
+/// Write a failure to stderr as a JSON object, if `--format json` asks
+#[cfg(not(test))]
+fn fail_json(kind: &str, flag: &str, message: &str) {
+    if JSON_ERRORS.load(Ordering::SeqCst) {
+        let mut error = BTreeMap::new();
+        error.insert("kind".to_owned(), Json::String(kind.to_owned()));
+        error.insert("flag".to_owned(), Json::String(flag.to_owned()));
+        error.insert("message".to_owned(), Json::String(message.to_owned()));
+        let mut obj = BTreeMap::new();
+        obj.insert("error".to_owned(), Json::Object(error));
+        let _ = writeln!(io::stderr(), "{}", Json::Object(obj));
+    }
+}
+
+/// What to do with private keys about to be printed
+#[cfg(not(test))]
+enum Reveal {
+    /// Print them
+    Print,
+    /// Write them to a new file, which only its owner can read, instead
+    File(String),
+    /// Put them on the clipboard instead, as `--copy` asks
+    Clipboard,
+    /// Print nothing
+    Cancel
+}
+
+/// Ask before private keys are printed to a terminal, where a shared or
+/// recorded screen shows them to whoever is watching, offering to write
+/// them to a file instead. Output which is not a terminal, or `yes`, is
+/// printed without asking. The answer is read from the terminal itself,
+/// since stdin may be carrying keys.
+#[cfg(not(test))]
+fn confirm_reveal(yes: bool) -> Reveal {
+    if yes || !tty::is_terminal(Stream::Stdout) {
+        return Reveal::Print;
+    }
+    let ask = |prompt: &str| -> Option<String> {
+        let _ = write!(io::stderr(), "{}", prompt);
+        let mut line = String::new();
+        let read = match File::open("/dev/tty") {
+            Ok(tty) => io::BufReader::new(tty).read_line(&mut line),
+            Err(_) => io::stdin().read_line(&mut line)
+        };
+        read.ok().map(|_| line.trim().to_owned())
+    };
+    match ask("About to print a private key to the terminal. Print it (y), write it to a file instead (f), or stop (N)? ") {
+        Some(ref answer) if answer == "y" || answer == "Y" => Reveal::Print,
+        Some(ref answer) if answer == "f" || answer == "F" => {
+            match ask("File to write it to: ") {
+                Some(ref filename) if !filename.is_empty() => Reveal::File(filename.clone()),
+                _ => Reveal::Cancel
+            }
+        }
+        _ => Reveal::Cancel
+    }
+}
+
+/// Write private keys to a new file only its owner can read, for
+/// `Reveal::File`, reporting the outcome
+#[cfg(not(test))]
+fn write_secret_file(filename: &str, text: &memlock::SecretText) {
+    match atomic::write(Path::new(filename), text.as_bytes(), false, true) {
+        Ok(_) => println!("Wrote the private key to {}.", filename),
+        Err(e) => fail("io", "", &format!("Unable to write {}: {}", filename, e))
+    }
+}
+
+/// A line of key text, with its newline, for `write_secret_file`
+#[cfg(not(test))]
+fn secret_line(text: &str) -> memlock::SecretText {
+    let mut line = memlock::SecretText::with_capacity(text.len() + 1);
+    line.push_str(text);
+    line.push_str("\n");
+    line
+}
+
+/// Read one line from stdin, as the key of `-p -`
+#[cfg(not(test))]
+fn stdin_key() -> Option<memlock::SecretText> {
+    let stdin = io::stdin();
+    let line = memlock::read_line(&mut stdin.lock(), privkey::MAX_KEY_TEXT);
+    match line {
+        Ok(Some(line)) => Some(memlock::SecretText::from(line.trim())),
+        Ok(None) => Some(memlock::SecretText::with_capacity(0)),
+        Err(e) => {
+            fail("io", "-p", &format!("Unable to read the private key from stdin: {}", e));
+            None
+        }
+    }
+}
+
+/// Read a whole file as text, reporting any failure
+#[cfg(not(test))]
+fn read_text_file(filename: &str) -> Option<String> {
+    let mut text = String::new();
+    match File::open(filename).and_then(|mut f| f.read_to_string(&mut text)) {
+        Ok(_) => Some(text),
+        Err(e) => {
+            fail("io", "", &format!("Unable to read {}: {}", filename, e));
+            None
+        }
+    }
+}
+
+/// Write an Electrum wallet file, reporting the outcome
+#[cfg(not(test))]
+fn write_electrum_wallet(filename: &str, wallet: &Json, signer: Option<&signing::Key>) {
+    match atomic::write(Path::new(filename), format!("{}\n", wallet.pretty()).as_bytes(), true, false) {
+        Ok(_) => {
+            println!("Wrote Electrum wallet file {}.", filename);
+            sign_file(signer, filename);
+        }
+        Err(e) => fail("io", "--electrum-wallet", &format!("Unable to write {}: {}", filename, e))
+    }
+}
+
+/// Write a BIP329 label file naming the contract, nonce and type of each
+/// address, reporting the outcome
+#[cfg(not(test))]
+fn write_bip329_labels(filename: &str, label: &str, addresses: &[(String, &str, Contract)], signer: Option<&signing::Key>) {
+    let records: Vec<Json> = addresses.iter().map(|&(ref address, address_type, ref contract)| {
+        bip329::address_label(address, &format!("{} ({}, nonce {:x}, contract {:x})", label, address_type,
+                                                Nonce::from_contract(contract), contract))
+    }).collect();
+    match atomic::write(Path::new(filename), bip329::to_jsonl(&records).as_bytes(), true, false) {
+        Ok(_) => {
+            println!("Wrote {} BIP329 labels to {}.", records.len(), filename);
+            sign_file(signer, filename);
+        }
+        Err(e) => fail("io", "--bip329", &format!("Unable to write {}: {}", filename, e))
+    }
+}
+
+/// Sign a file just written with the key of `--sign-with`, if one was
+/// given, reporting the outcome
+#[cfg(not(test))]
+fn sign_file(signer: Option<&signing::Key>, filename: &str) {
+    if let Some(key) = signer {
+        match key.sign(filename) {
+            Ok(signature) => println!("Signed {}: wrote {}.", filename, signature),
+            Err(e) => fail("sign_failed", "--sign-with", &format!("Unable to sign {}: {:?}", filename, e))
+        }
+    }
+}
+
+/// Parse the key of `--sign-with`, reporting any failure
+#[cfg(not(test))]
+fn parse_signer(matches: &getopts::Matches) -> Result<Option<signing::Key>, ()> {
+    match matches.opt_str("sign-with").map(|s| signing::Key::parse(&s)) {
+        None => Ok(None),
+        Some(Ok(key)) => Ok(Some(key)),
+        Some(Err(_)) => {
+            fail("bad_value", "--sign-with", "option to --sign-with must be minisign:SECRET-KEY-FILE, gpg:KEY-ID or gpg.");
+            Err(())
+        }
+    }
+}
+
+/// Parse a base58 or bech32 address of a chain into its scriptPubKey,
+/// reporting any failure as being of `what` (e.g. `option to --to`)
+#[cfg(not(test))]
+fn parse_address(chain: Chain, what: &str, s: &str) -> Option<Script> {
+    match decode_address(chain, s) {
+        Ok(script_pubkey) => Some(script_pubkey),
+        Err(e) => {
+            fail("bad_address", "", &format!("{} {}.", what, e));
+            None
+        }
+    }
+}
+
+/// The scriptPubKey of a base58 or segwit address, or why there is none
+#[cfg(not(test))]
+fn decode_address(chain: Chain, s: &str) -> Result<Script, &'static str> {
+    match (chain.decode_address(s), segwit::decode_address(chain.hrp(), s)) {
+        (Ok(address), _) => Ok(address.script_pubkey()),
+        (Err(_), Some((version, program))) => Ok(segwit::witness_script_pubkey(version, &program)),
+        (Err(base58::Error::InvalidVersion(_)), None) => Err("did not match tool mode (did you forget -t?)"),
+        (Err(_), None) => Err("could not be parsed as an address")
+    }
+}
+
+/// Read a file of outputs, one `txid:vout amount` per line, reporting any
+/// failure
+#[cfg(not(test))]
+fn read_utxos_file(filename: &str) -> Option<Vec<sweep::Utxo>> {
+    let text = match read_text_file(filename) {
+        Some(text) => text,
+        None => { return None; }
+    };
+    let mut utxos = vec![];
+    for (n, line) in text.lines().enumerate().filter(|&(_, l)| !l.trim().is_empty()) {
+        match sweep::Utxo::parse(line) {
+            Some(utxo) => utxos.push(utxo),
+            None => {
+                fail("bad_value", "", &format!("line {} of {} is not of the form txid:vout amount.", n + 1, filename));
+                return None;
+            }
+        }
+    }
+    Some(utxos)
+}
+
+/// Read a file of addresses, one address and an optional label per line,
+/// giving their scriptPubKeys and labels (the address itself if none is
+/// given), and reporting any failure
+#[cfg(not(test))]
+fn read_addresses_file(chain: Chain, filename: &str) -> Option<Vec<(Script, String)>> {
+    let text = match read_text_file(filename) {
+        Some(text) => text,
+        None => { return None; }
+    };
+    let mut scripts = vec![];
+    for (n, line) in text.lines().enumerate().filter(|&(_, line)| !line.trim().is_empty()) {
+        let mut fields = line.trim().splitn(2, char::is_whitespace);
+        let address = fields.next().unwrap();
+        let label = fields.next().map(|label| label.trim().to_owned()).unwrap_or(address.to_owned());
+        match parse_address(chain, &format!("line {} of {}", n + 1, filename), address) {
+            Some(script_pubkey) => scripts.push((script_pubkey, label)),
+            None => { return None; }
+        }
+    }
+    Some(scripts)
+}
+
+/// Read a file of contracts, one hex contract and an optional label per
+/// line, giving the line of each contract, the contract and its label (the
+/// contract itself if none is given), and reporting any failure
+#[cfg(not(test))]
+fn read_contracts_file(filename: &str, tags: &[[u8; 4]]) -> Option<Vec<(usize, Contract, String)>> {
+    let text = match read_text_file(filename) {
+        Some(text) => text,
+        None => { return None; }
+    };
+    let mut contracts = vec![];
+    for (n, line) in text.lines().enumerate().filter(|&(_, line)| !line.trim().is_empty()) {
+        let mut fields = line.trim().splitn(2, char::is_whitespace);
+        let hex = fields.next().unwrap();
+        match Contract::from_hex_custom(hex, tags) {
+            Ok(contract) => contracts.push((n + 1, contract, fields.next().map(|label| label.trim().to_owned()).unwrap_or(hex.to_owned()))),
+            Err(e) => {
+                fail("bad_contract", "", &format!("line {} of {} could not be parsed as a contract: {:?}.", n + 1, filename, e));
+                return None;
+            }
+        }
+    }
+    Some(contracts)
+}
+
+/// The numbered lines of a --contracts-file, from its start, so that it can
+/// be read again through the same handle however many times it is gone over
+#[cfg(not(test))]
+fn contracts_file_lines(file: &File) -> io::Result<iter::Enumerate<io::Lines<io::BufReader<&File>>>> {
+    let mut file = file;
+    try!(file.seek(SeekFrom::Start(0)));
+    Ok(io::BufReader::new(file).lines().enumerate())
+}
+
+/// Read the next contracts of a --contracts-file, at most `max` of them and
+/// of the built-in types or those with these tags, giving the line of each
+/// contract and the contract, and reporting any failure
+#[cfg(not(test))]
+fn read_contracts_chunk<I>(lines: &mut I, filename: &str, tags: &[[u8; 4]], max: usize) -> Option<Vec<(usize, Contract)>>
+    where I: Iterator<Item=(usize, io::Result<String>)>
+{
+    let mut chunk = Vec::with_capacity(cmp::min(max, JOB_CHUNK));
+    while chunk.len() < max {
+        let (n, line) = match lines.next() {
+            Some((n, Ok(line))) => (n, line),
+            Some((_, Err(e))) => {
+                fail("io", "--contracts-file", &format!("Unable to read {}: {}", filename, e));
+                return None;
+            }
+            None => break
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match Contract::from_hex_custom(line.trim(), tags) {
+            Ok(contract) => chunk.push((n + 1, contract)),
+            Err(e) => {
+                fail("bad_contract", "--contracts-file", &format!("line {} of {} could not be parsed as a contract: {:?}.", n + 1, filename, e));
+                return None;
+            }
+        }
+    }
+    Some(chunk)
+}
+
+/// Warn if the nonce of a contract about to be committed to is weak,
+/// returning whether it is
+#[cfg(not(test))]
+fn warn_weak_nonce(contract: &Contract) -> bool {
+    let nonce = Nonce::from_contract(contract);
+    match nonce.weakness() {
+        Some(weakness) => {
+            let _ = writeln!(io::stderr(), "Warning: the nonce {:x} is weak: {}.", nonce, weakness);
+            true
+        }
+        None => false
+    }
+}
+
+/// Describe a value a batch has more than once, whose items are named as
+/// `item` (e.g. row) in the description
+#[cfg(not(test))]
+fn describe_duplicate(duplicate: &dedup::Duplicate, item: &str) -> String {
+    format!("Duplicate {} {}, at {} {} and again at {}.", duplicate.kind.name(), duplicate.value, item, duplicate.first, duplicate.again)
+}
+
+/// Print an unsigned transaction committing to a contract in an OP_RETURN
+/// output, spending some outputs, as a PSBT; reporting any failure
+#[cfg(not(test))]
+fn print_op_return_tx(contract: &Contract, utxos: &[sweep::Utxo], change: Option<Script>, fee: u64) {
+    println!("Contract commitment hash: {}", contract.commitment_hash()[..].to_hex());
+    match sweep::funding(utxos, contract.op_return_script(), 0, change, fee) {
+        Ok(tx) => {
+            if !utxos.is_empty() {
+                println!("Unsigned commitment transaction: {}", tx.serialize(&vec![vec![]; tx.inputs.len()], &[]).to_hex());
+            }
+            println!("Commitment PSBT ({} inputs): {}", tx.inputs.len(), psbt::Psbt::from_unsigned(&tx).to_base64());
+        }
+        Err(e) => fail("funding_failed", "--op-return", &format!("Unable to build commitment transaction: {:?}", e))
+    }
+}
+
+/// Print what a plugin makes of a contract of the type it defines
+#[cfg(not(test))]
+fn print_plugin_payload(plugins: &plugin::Plugins, contract: &Contract) {
+    if let Some(plugin) = contract.custom_tag().and_then(|tag| plugins.get(&tag)) {
+        match plugin.decode(contract.data()) {
+            Ok(payload) => println!("Contract payload ({}): {}", contract.type_name(), payload),
+            Err(e) => println!("Warning: the plugin for {} could not decode the contract: {:?}", contract.type_name(), e)
+        }
+    }
+}
+
+/// The address paying to a script in one of the ways it can be paid
+#[cfg(not(test))]
+fn script_address(chain: Chain, script: &Script, spend_type: sweep::SpendType) -> String {
+    match spend_type {
+        sweep::SpendType::P2wsh => chain.p2wsh_address(script),
+        sweep::SpendType::P2shP2wsh => chain.p2sh_p2wsh_address(script),
+        _ => chain.p2sh_address(script)
+    }
+}
+
+/// Import watch-only addresses into a node's wallet with importdescriptors,
+/// falling back to importmulti for wallets from before descriptors
+#[cfg(not(test))]
+fn import_to_core(client: &rpc::Client, descriptors: Vec<Json>, multi: Vec<Json>) {
+    let (method, result) = match client.call("importdescriptors", vec![Json::Array(descriptors)]) {
+        // Method not found, or a legacy wallet
+        Err(rpc::Error::Rpc(code, _)) if code == -32601 || code == -4 => ("importmulti", client.call("importmulti", vec![Json::Array(multi)])),
+        result => ("importdescriptors", result)
+    };
+    let results = match result {
+        Ok(Json::Array(results)) => results,
+        Ok(result) => {
+            fail("rpc_failed", "--import-to-core", &format!("bitcoind gave an unexpected reply to {}: {}", method, result));
+            return;
+        }
+        Err(e) => {
+            fail("rpc_failed", "--import-to-core", &format!("Unable to import into bitcoind: {:?}", e));
+            return;
+        }
+    };
+    let mut n_imported = 0;
+    for result in &results {
+        if result.find("success").and_then(|s| s.as_boolean()) == Some(true) {
+            n_imported += 1;
+        } else {
+            fail("rpc_failed", "--import-to-core", &format!("bitcoind failed to import an address: {}", result));
+        }
+    }
+    println!("Imported {} of {} addresses into bitcoind with {}.", n_imported, results.len(), method);
+}
+
+/// Ask an Electrum server for the balance and history of some labelled
+/// scriptPubKeys, and report them with their total
+#[cfg(not(test))]
+fn electrum_balances(server: &str, scripts: &[(Script, String)]) {
+    let mut client = match electrum::Client::connect(server) {
+        Ok(client) => client,
+        Err(e) => {
+            fail("electrum_failed", "--electrum", &format!("Unable to connect to Electrum server {}: {:?}", server, e));
+            return;
+        }
+    };
+    let mut total = electrum::Balance { confirmed: 0, unconfirmed: 0 };
+    for &(ref script_pubkey, ref label) in scripts {
+        let (balance, history) = match (client.balance(script_pubkey), client.history(script_pubkey)) {
+            (Ok(balance), Ok(history)) => (balance, history),
+            (Err(e), _) | (_, Err(e)) => {
+                fail("electrum_failed", "--electrum", &format!("Unable to look up {} on the Electrum server: {:?}", label, e));
+                return;
+            }
+        };
+        println!("{}: {} satoshis confirmed, {} unconfirmed, in {} transactions",
+                 label, balance.confirmed, balance.unconfirmed, history.len());
+        for item in &history {
+            if item.height > 0 {
+                println!("    {} at height {}", item.txid, item.height);
+            } else {
+                println!("    {} in the mempool", item.txid);
+            }
+        }
+        total.confirmed += balance.confirmed;
+        total.unconfirmed += balance.unconfirmed;
+    }
+    println!("Total: {} satoshis confirmed, {} unconfirmed", total.confirmed, total.unconfirmed);
+}
+
+/// Ask an Esplora server whether some labelled scriptPubKeys have been used
+/// or funded, and report their balances with their total
+#[cfg(not(test))]
+fn esplora_balances(base_url: &str, scripts: &[(Script, String)]) {
+    let (mut confirmed, mut unconfirmed) = (0i64, 0i64);
+    for &(ref script_pubkey, ref label) in scripts {
+        let status = match esplora::status(base_url, script_pubkey) {
+            Ok(status) => status,
+            Err(e) => {
+                fail("esplora_failed", "--esplora-url", &format!("Unable to look up {} on the Esplora server: {:?}", label, e));
+                return;
+            }
+        };
+        if !status.used() {
+            println!("{}: unused", label);
+            continue;
+        }
+        let chain_balance = status.chain.funded_sum as i64 - status.chain.spent_sum as i64;
+        let mempool_balance = status.mempool.funded_sum as i64 - status.mempool.spent_sum as i64;
+        println!("{}: used in {} transactions ({} unconfirmed), funded by {} outputs of {} satoshis; \
+                  {} satoshis confirmed, {} unconfirmed",
+                 label, status.chain.tx_count + status.mempool.tx_count, status.mempool.tx_count,
+                 status.chain.funded_count + status.mempool.funded_count,
+                 status.chain.funded_sum + status.mempool.funded_sum, chain_balance, mempool_balance);
+        confirmed += chain_balance;
+        unconfirmed += mempool_balance;
+    }
+    println!("Total: {} satoshis confirmed, {} unconfirmed", confirmed, unconfirmed);
+}
+
+/// The `balance` subcommand
+#[cfg(not(test))]
+fn balance_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optopt("", "electrum", "Specify an Electrum server to ask, as host:port or tcp://host:port.", "server");
+    opts.optopt("", "esplora-url", "Specify the http:// base URL of an Esplora server to ask instead.", "url");
+    add_chain_options(&mut opts);
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} balance [-t|--chain name] <--electrum server|--esplora-url url> ADDRESSES-FILE\n\n\
+                               Each line of the addresses file is an address and an optional label.", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    let (electrum_server, esplora_url) = (matches.opt_str("electrum"), matches.opt_str("esplora-url"));
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.free.len() != 1 || electrum_server.is_some() == esplora_url.is_some() {
+        fail("usage", "", "balance takes one of --electrum or --esplora-url, and an addresses file.");
+        println!("{}", full_usage);
+        return;
+    }
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+    let scripts = match read_addresses_file(chain, &matches.free[0]) {
+        Some(scripts) => scripts,
+        None => { return; }
+    };
+    match (electrum_server, esplora_url) {
+        (Some(server), _) => electrum_balances(&server, &scripts),
+        (_, Some(url)) => esplora_balances(&url, &scripts),
+        (None, None) => unreachable!()
+    }
+}
+
+/// Look up the coins held by some descriptors with scantxoutset, and
+/// report the balance of each contract they were made for. bitcoind
+/// describes the coins it finds in its own way, so they are matched up by
+/// scriptPubKey.
+#[cfg(not(test))]
+fn scan_balances(client: &rpc::Client, descriptors: &[(String, Script, Contract)]) {
+    let scan_objects = descriptors.iter().map(|&(ref desc, _, _)| Json::String(descriptor::with_checksum(desc).unwrap())).collect();
+    let result = match client.call("scantxoutset", vec![Json::String("start".to_owned()), Json::Array(scan_objects)]) {
+        Ok(result) => result,
+        Err(e) => {
+            fail("rpc_failed", "--scan-balance", &format!("Unable to scan the UTXO set: {:?}", e));
+            return;
+        }
+    };
+    let unspents = match result.find("unspents").and_then(|u| u.as_array()) {
+        Some(unspents) => unspents.clone(),
+        None => {
+            fail("rpc_failed", "--scan-balance", &format!("bitcoind gave an unexpected reply to scantxoutset: {}", result));
+            return;
+        }
+    };
+    // Contracts in the order they were first given, with their totals
+    let mut balances: Vec<(Contract, u64, usize)> = vec![];
+    for &(_, _, ref contract) in descriptors {
+        if !balances.iter().any(|&(ref c, _, _)| c == contract) {
+            balances.push((contract.clone(), 0, 0));
+        }
+    }
+    for unspent in &unspents {
+        let amount = unspent.find("amount").and_then(|a| a.as_f64()).map_or(0, |btc| (btc * 1e8).round() as u64);
+        let script_pubkey = unspent.find("scriptPubKey").and_then(|s| s.as_string()).unwrap_or("");
+        let contract = descriptors.iter().find(|&&(_, ref spk, _)| spk[..].to_hex() == script_pubkey).map(|&(_, _, ref contract)| contract);
+        println!("Unspent {}:{} with scriptPubKey {}: {} satoshis",
+                 unspent.find("txid").and_then(|t| t.as_string()).unwrap_or("?"),
+                 unspent.find("vout").and_then(|v| v.as_u64()).unwrap_or(0), script_pubkey, amount);
+        if let Some(contract) = contract {
+            let entry = balances.iter_mut().find(|&&mut (ref c, _, _)| c == contract).unwrap();
+            entry.1 += amount;
+            entry.2 += 1;
+        }
+    }
+    for &(ref contract, balance, count) in &balances {
+        println!("Balance of contract {:x}: {} satoshis in {} outputs", contract, balance, count);
+    }
+}
+
+/// The `process-request` subcommand, run on the offline machine to answer
+/// a request made with `create-request`. The response is printed to stdout
+/// and any warnings about the modified script to stderr.
+#[cfg(not(test))]
+fn process_request_main(prog: &str, args: &[String]) {
+    let usage = format!("Usage: {} process-request REQUEST-FILE > RESPONSE-FILE", prog);
+    if args.len() == 1 && (args[0] == "-h" || args[0] == "--help") {
+        println!("{}", usage);
+        return;
+    }
+    if args.len() != 1 {
+        fail("usage", "", "process-request takes one request file.");
+        println!("{}", usage);
+        return;
+    }
+    let secp = context::secp();
+    let text = match read_text_file(&args[0]) {
+        Some(text) => text,
+        None => { return; }
+    };
+    let request = match handoff::Request::from_file(&text) {
+        Ok(request) => request,
+        Err(e) => {
+            fail("bad_request", "", &format!("Unable to parse tweak request: {:?}", e));
+            return;
+        }
+    };
+    let (new_script, locations, tweaked_keys) = match template::tweak_script(&secp, &request.script, &request.contract.to_bytes()[..]) {
+        Ok(result) => result,
+        Err(e) => {
+            fail("tweak_failed", "", &format!("Unable to tweak keys: {:?}", e));
+            return;
+        }
+    };
+    for warning in &check::check_script(&new_script) {
+        let _ = writeln!(io::stderr(), "Warning: {}.", warning);
+    }
+    let response = handoff::Response {
+        request_hash: handoff::request_hash(&text),
+        script: new_script,
+        keys: locations.into_iter().map(|location| location.key).zip(tweaked_keys.into_iter()).collect()
+    };
+    print!("{}", response.to_file(&secp));
+}
+
+/// The `import-response` subcommand, run back on the online machine to
+/// check a response against its request and print the resulting addresses.
+/// The tweak is recomputed here, so a response which does not match its
+/// request, however it came about, is rejected.
+#[cfg(not(test))]
+fn import_response_main(prog: &str, args: &[String]) {
+    let usage = format!("Usage: {} import-response REQUEST-FILE RESPONSE-FILE", prog);
+    if args.len() == 1 && (args[0] == "-h" || args[0] == "--help") {
+        println!("{}", usage);
+        return;
+    }
+    if args.len() != 2 {
+        fail("usage", "", "import-response takes a request file and its response file.");
+        println!("{}", usage);
+        return;
+    }
+    let secp = context::secp();
+    let (request_text, response_text) = match (read_text_file(&args[0]), read_text_file(&args[1])) {
+        (Some(request), Some(response)) => (request, response),
+        _ => { return; }
+    };
+    let request = match handoff::Request::from_file(&request_text) {
+        Ok(request) => request,
+        Err(e) => {
+            fail("bad_request", "", &format!("Unable to parse tweak request: {:?}", e));
+            return;
+        }
+    };
+    let response = match handoff::Response::from_file(&secp, &response_text) {
+        Ok(response) => response,
+        Err(e) => {
+            fail("bad_request", "", &format!("Unable to parse tweak response: {:?}", e));
+            return;
+        }
+    };
+    if response.request_hash != handoff::request_hash(&request_text) {
+        fail("bad_request", "", "Tweak response is for a different request.");
+        return;
+    }
+    let (new_script, locations, tweaked_keys) = match template::tweak_script(&secp, &request.script, &request.contract.to_bytes()[..]) {
+        Ok(result) => result,
+        Err(e) => {
+            fail("tweak_failed", "", &format!("Unable to tweak keys: {:?}", e));
+            return;
+        }
+    };
+    let keys: Vec<_> = locations.into_iter().map(|location| location.key).zip(tweaked_keys.into_iter()).collect();
+    if new_script != response.script || keys != response.keys {
+        fail("bad_request", "", "Tweak response does not match the tweak of its request; refusing to use it.");
+        return;
+    }
+
+    for warning in &check::check_script(&new_script) {
+        print_warning(warning);
+    }
+    print_banner(Chain::from_network(request.network));
+    println!("Full serialized contract: {:x}", request.contract);
+    println!("Modified redeem script: {}", new_script[..].to_hex());
+    println!("Modified redeem script (asm): {}", asm::disassemble(&new_script));
+    println!("Modified redeem script as P2SH address: {}", Address::from_script(request.network, &new_script).to_base58check());
+    println!("Modified redeem script as P2WSH address: {}", segwit::p2wsh_address(request.network, &new_script));
+    println!("Modified redeem script as P2SH-P2WSH address: {}", segwit::p2sh_p2wsh_address(request.network, &new_script).to_base58check());
+    for (n, &(ref original, ref tweaked)) in keys.iter().enumerate() {
+        println!("    Key {}: {} tweaked to {}", n, original.serialize_vec(&secp, true)[..].to_hex(),
+                 tweaked.serialize_vec(&secp, true)[..].to_hex());
+    }
+}
+
+/// The `verify-message` subcommand, checking a signed message against an
+/// address and, given the base key and contract, that the key which signed
+/// it is the base key tweaked for the contract
+#[cfg(not(test))]
+fn verify_message_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optopt("", "pubkey", "Specify the hex public key which the signing key should be a tweak of.", "key");
+    opts.optopt("f", "hex-contract", "Specify the contract of the tweak as an hexadecimal string.", "hex");
+    opts.optopt("a", "ascii-contract", "Specify the contract of the tweak as an ASCII string.", "text");
+    opts.optopt("d", "p2sh-address", "Specify the contract of the tweak as a P2SH address.", "P2SH address");
+    opts.optopt("n", "nonce", "Specify the hex-encoded nonce of a contract given with -a or -d.", "nonce");
+    add_chain_options(&mut opts);
+    add_plugin_options(&mut opts);
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} verify-message [-t|--chain name] [--pubkey key <-f contract|-d p2sh -n nonce|-a ascii -n nonce>] ADDRESS SIGNATURE MESSAGE", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.free.len() != 3 {
+        fail("usage", "", "verify-message takes an address, a signature and a message.");
+        println!("{}", full_usage);
+        return;
+    }
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+    let plugins = match parse_plugins(&matches) {
+        Some(plugins) => plugins,
+        None => { return; }
+    };
+    // Signed messages are hashed with Bitcoin's prefix
+    if !chain.is_bitcoin() {
+        fail("usage", "--chain", "verify-message may only be used on the bitcoin, testnet, signet and regtest chains.");
+        println!("{}", full_usage);
+        return;
+    }
+    let secp = context::secp();
+    let (address, signature, message) = (&matches.free[0], &matches.free[1], &matches.free[2]);
+
+    // The base key and contract, if the commitment is to be checked
+    let nonce = match matches.opt_str("n").map(|hex| Nonce::from_hex(&hex)) {
+        Some(Ok(nonce)) => Some(nonce),
+        Some(Err(e)) => {
+            fail("bad_nonce", "-n", &format!("option to -n could not be parsed as a nonce: {:?}.", e));
+            return;
+        }
+        None => None
+    };
+    let contract = match (matches.opt_str("f"), nonce, matches.opt_str("d"), matches.opt_str("a")) {
+        (None, None, None, None) => None,
+        (Some(hex), None, None, None) => Some(Contract::from_hex_custom(&hex, &plugins.tags()).map_err(|e| format!("-f could not be parsed as a contract: {:?}", e))),
+        (None, Some(nonce), Some(p2sh), None) => Some(Contract::from_p2sh_base58_str(&p2sh, nonce, chain).map_err(|e| format!("-d could not be parsed as a P2SH contract: {:?}", e))),
+        (None, Some(nonce), None, Some(ascii)) => Some(Contract::from_ascii_str(&ascii, nonce).map_err(|e| format!("-a could not be parsed as a contract: {:?}", e))),
+        _ => {
+            fail("usage", "", "Must specify at most one of: -f; -a -n; or -d -n");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    let commitment = match (matches.opt_str("pubkey"), contract) {
+        (None, None) => None,
+        (Some(hex), Some(Ok(contract))) => {
+            match hex.from_hex().ok().and_then(|data| PublicKey::from_slice(&secp, &data).ok()) {
+                Some(key) => Some((key, contract)),
+                None => {
+                    fail("bad_pubkey", "--pubkey", "option to --pubkey could not be parsed as a public key.");
+                    return;
+                }
+            }
+        }
+        (_, Some(Err(e))) => {
+            fail("bad_contract", "", &format!("option to {}.", e));
+            return;
+        }
+        _ => {
+            fail("usage", "--pubkey", "--pubkey and a contract must be given together.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+
+    if parse_address(chain, "ADDRESS", address).is_none() {
+        return;
+    }
+    let (key, compressed) = match message::recover(&secp, signature, message) {
+        Ok(result) => result,
+        Err(e) => {
+            fail("bad_signature", "", &format!("Invalid signature: {:?}", e));
+            return;
+        }
+    };
+    let signer = chain.p2pkh_address(&key, compressed);
+    if &signer != address {
+        fail("bad_signature", "", &format!("Signature is not valid for {} (it was made by {}).", address, signer));
+        return;
+    }
+    println!("Signature is valid for {}.", address);
+    println!("Signing key: {}", key.serialize_vec(&secp, true)[..].to_hex());
+    if let Some((base_key, contract)) = commitment {
+        match tweak_keys(&secp, &[base_key], &contract.to_bytes()[..]) {
+            Ok(ref tweaked) if tweaked[0] == key => {
+                println!("Signing key is {} tweaked for contract {:x}.", base_key.serialize_vec(&secp, true)[..].to_hex(), contract);
+            }
+            Ok(_) => fail("not_tweaked", "", &format!("Signing key is NOT {} tweaked for contract {:x}.", base_key.serialize_vec(&secp, true)[..].to_hex(), contract)),
+            Err(e) => fail("tweak_failed", "", &format!("Unable to tweak key: {:?}", e))
+        }
+    }
+}
+
+/// The `prove-reserves` subcommand, signing a message with the tweaked key
+/// of every line of a keys file to make a proof of reserves
+#[cfg(not(test))]
+fn prove_reserves_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    add_chain_options(&mut opts);
+    add_plugin_options(&mut opts);
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} prove-reserves [-t|--chain name] KEYS-FILE MESSAGE > PROOF-FILE\n\n\
+                               Each line of the keys file is an untweaked private key and the hex\n\
+                               contract it was tweaked for, separated by a space.", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.free.len() != 2 {
+        fail("usage", "", "prove-reserves takes a keys file and a message.");
+        println!("{}", full_usage);
+        return;
+    }
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+    let plugins = match parse_plugins(&matches) {
+        Some(plugins) => plugins,
+        None => { return; }
+    };
+    // Signed messages are hashed with Bitcoin's prefix
+    if !chain.is_bitcoin() {
+        fail("usage", "--chain", "prove-reserves may only be used on the bitcoin, testnet, signet and regtest chains.");
+        println!("{}", full_usage);
+        return;
+    }
+    let args = &matches.free;
+    let secp = context::secp();
+    // Private keys are about to be read, and should not end up in a core dump
+    memlock::disable_core_dumps();
+    let text = match read_text_file(&args[0]) {
+        Some(text) => text,
+        None => { return; }
+    };
+    let mut keys = vec![];
+    for (n, line) in text.lines().enumerate().filter(|&(_, l)| !l.trim().is_empty()) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 2 {
+            fail("bad_value", "", &format!("line {} of {} is not a private key and a contract.", n + 1, args[0]));
+            return;
+        }
+        let key = match privkey::decode(&secp, fields[0], chain, None) {
+            Ok(key) => key,
+            // A WIF key of another chain
+            Err(privkey::Error::Base58(base58::Error::InvalidVersion(_))) => {
+                fail("wrong_network", "", &format!("Private key on line {} of {} did not match tool mode (did you forget -t?).", n + 1, args[0]));
+                return;
+            }
+            Err(e) => {
+                fail("bad_privkey", "", &format!("line {} of {} could not be parsed as a private key: {:?}.", n + 1, args[0], e));
+                return;
+            }
+        };
+        match Contract::from_hex_custom(fields[1], &plugins.tags()) {
+            Ok(contract) => keys.push((key, contract)),
+            Err(e) => {
+                fail("bad_contract", "", &format!("line {} of {} could not be parsed as a contract: {:?}.", n + 1, args[0], e));
+                return;
+            }
+        }
+    }
+    match reserves::Proof::create(&secp, chain.network(), &args[1], &keys) {
+        Ok(proof) => print!("{}", proof.to_file(&secp)),
+        Err(e) => fail("sign_failed", "", &format!("Unable to make proof: {:?}", e))
+    }
+}
+
+/// The `verify-reserves` subcommand, checking every address of a proof of
+/// reserves
+#[cfg(not(test))]
+fn verify_reserves_main(prog: &str, args: &[String]) {
+    let usage = format!("Usage: {} verify-reserves PROOF-FILE", prog);
+    if args.len() == 1 && (args[0] == "-h" || args[0] == "--help") {
+        println!("{}", usage);
+        return;
+    }
+    if args.len() != 1 {
+        fail("usage", "", "verify-reserves takes one proof file.");
+        println!("{}", usage);
+        return;
+    }
+    let secp = context::secp();
+    let text = match read_text_file(&args[0]) {
+        Some(text) => text,
+        None => { return; }
+    };
+    let proof = match reserves::Proof::from_file(&secp, &text) {
+        Ok(proof) => proof,
+        Err(e) => {
+            fail("bad_proof", "", &format!("Unable to parse proof: {:?}", e));
+            return;
+        }
+    };
+    print_banner(Chain::from_network(proof.network));
+    println!("Message: {}", proof.message);
+    let statuses = proof.verify(&secp);
+    for (entry, status) in proof.entries.iter().zip(statuses.iter()) {
+        let status = match *status {
+            reserves::Status::Valid => "valid",
+            reserves::Status::BadSignature => "INVALID SIGNATURE",
+            reserves::Status::BadCommitment => "DOES NOT COMMIT TO CONTRACT"
+        };
+        println!("{}: {} (key {}, nonce {:x}, contract {:x})", entry.address, status,
+                 entry.base_key.serialize_vec(&secp, true)[..].to_hex(), Nonce::from_contract(&entry.contract), entry.contract);
+    }
+    let valid = statuses.iter().filter(|&&status| status == reserves::Status::Valid).count();
+    if valid == statuses.len() {
+        println!("{} of {} addresses proven.", valid, statuses.len());
+    } else {
+        fail("bad_proof", "", &format!("{} of {} addresses proven.", valid, statuses.len()));
+    }
+}
+
+/// The `convert-key` subcommand, re-encoding a private key for another
+/// network or compression without changing the secret
+#[cfg(not(test))]
+fn convert_key_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optopt("p", "private-key", "Specify the key to convert, as WIF of any built-in chain or of --chain, raw hex or a mini private key, keychain:NAME for a key in the platform secret store, keystore:NAME for a key added with the keystore subcommand, - to read it from stdin, or env:VAR to read it from an environment variable. A key given on the command line itself needs --insecure-allow-cli-key.", "private key");
+    opts.optflag("", "insecure-allow-cli-key", "Accept a private key given to -p on the command line itself, despite its being left in shell history and visible in ps.");
+    opts.optopt("", "privkey-file", "Read the key to convert from a file instead. Files ending in .age, .gpg or .asc are decrypted with age or gpg without writing the plaintext to disk.", "file");
+    opts.optopt("", "keystore", "Specify the keystore file which -p keystore:NAME reads (defaults to ~/.pacthash-keystore).", "file");
+    add_chain_options(&mut opts);
+    opts.optflag("", "compressed", "Mark the key as used compressed.");
+    opts.optflag("", "uncompressed", "Mark the key as used uncompressed.");
+    opts.optflag("", "yes", "Print the converted private key to a terminal without asking first.");
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} convert-key [-t|--chain name] [--compressed|--uncompressed] <-p key|--privkey-file file>", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if !matches.free.is_empty() {
+        fail("usage", "", "convert-key takes its key from -p or --privkey-file, not as an argument.");
+        println!("{}", full_usage);
+        return;
+    }
+    // Private keys are about to be read, and should not end up in a core dump
+    memlock::disable_core_dumps();
+    let key = match read_privkey(&matches, &full_usage) {
+        Some(Some(ref key)) if &key[..] == "-" => stdin_key(),
+        Some(Some(key)) => Some(key),
+        Some(None) => {
+            fail("usage", "-p", "Exactly one of -p or --privkey-file must be specified.");
+            println!("{}", full_usage);
+            return;
+        }
+        None => { return; }
+    };
+    let key = match key {
+        Some(key) => key,
+        None => { return; }
+    };
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+    let secp = context::secp();
+    // A WIF key may be of any chain, which is found by its version byte
+    let decoded = iter::once(chain).chain(chain::ALL.iter().cloned())
+                      .map(|from| privkey::decode(&secp, &key, from, None))
+                      .find(|result| match *result {
+                          Err(privkey::Error::Base58(base58::Error::InvalidVersion(_))) => false,
+                          _ => true
+                      });
+    let mut key = match decoded.unwrap_or_else(|| privkey::decode(&secp, &key, chain, None)) {
+        Ok(key) => memlock::Secret::new(key),
+        Err(e) => {
+            fail("bad_privkey", "-p", &format!("option to -p could not be parsed as a private key: {:?}.", e));
+            return;
+        }
+    };
+    key.network = chain.network();
+    match (matches.opt_present("compressed"), matches.opt_present("uncompressed")) {
+        (true, true) => {
+            fail("usage", "--compressed", "At most one of --compressed or --uncompressed may be specified.");
+            return;
+        }
+        (true, false) => key.compressed = true,
+        (false, true) => key.compressed = false,
+        (false, false) => {}
+    }
+    let encoded = memlock::SecretText::take(chain.encode_privkey(&key));
+    match confirm_reveal(matches.opt_present("yes")) {
+        Reveal::Print => println!("{}", &encoded[..]),
+        Reveal::File(ref filename) => write_secret_file(filename, &secret_line(&encoded)),
+        Reveal::Clipboard | Reveal::Cancel => fail("cancelled", "", "Not printing the converted secret key.")
+    }
+}
+
+/// The `recover` subcommand, combining the SLIP-39 shares that
+/// `--privkey-format slip39` split a private key into
+#[cfg(not(test))]
+fn recover_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    add_chain_options(&mut opts);
+    opts.optflag("", "uncompressed", "Mark the recovered key as used uncompressed.");
+    opts.optopt("", "passphrase", "Specify the SLIP-39 passphrase the shares were made with (defaults to empty).", "passphrase");
+    opts.optflag("", "yes", "Print the recovered private key to a terminal without asking first.");
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} recover [-t|--chain name] [--uncompressed] [--passphrase passphrase] < SHARES\n\n\
+                               SHARES has one SLIP-39 share per line; exactly as many as the threshold are needed.", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if !matches.free.is_empty() {
+        fail("usage", "", "recover reads the shares from stdin, and takes no arguments.");
+        println!("{}", full_usage);
+        return;
+    }
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+
+    // Private keys are about to be read, and should not end up in a core dump
+    memlock::disable_core_dumps();
+    let mut shares = vec![];
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    loop {
+        match memlock::read_line(&mut stdin, privkey::MAX_KEY_TEXT) {
+            Ok(Some(ref line)) if line.trim().is_empty() => {}
+            Ok(Some(line)) => shares.push(line),
+            Ok(None) => break,
+            Err(e) => {
+                fail("io", "", &format!("Unable to read stdin: {}", e));
+                return;
+            }
+        }
+    }
+    let shares: Vec<&str> = shares.iter().map(|share| share.trim()).collect();
+    let mut secret = match slip39::combine(&shares, &matches.opt_str("passphrase").unwrap_or(String::new())) {
+        Ok(secret) => secret,
+        Err(e) => {
+            fail("bad_shares", "", &format!("Unable to combine the shares: {:?}", e));
+            return;
+        }
+    };
+    let secp = context::secp();
+    let key = secp256k1::key::SecretKey::from_slice(&secp, &secret[..]);
+    memlock::zero(&mut secret);
+    let key = match key {
+        Ok(key) => memlock::Secret::new(Privkey { compressed: !matches.opt_present("uncompressed"), network: chain.network(), key: key }),
+        Err(e) => {
+            fail("bad_shares", "", &format!("The shares do not hold a private key ({} bytes): {:?}", secret.len(), e));
+            return;
+        }
+    };
+    let encoded = memlock::SecretText::take(chain.encode_privkey(&key));
+    match confirm_reveal(matches.opt_present("yes")) {
+        Reveal::Print => println!("Recovered secret key: {}", &encoded[..]),
+        Reveal::File(ref filename) => write_secret_file(filename, &secret_line(&encoded)),
+        Reveal::Clipboard | Reveal::Cancel => fail("cancelled", "", "Not printing the recovered secret key.")
+    }
+}
+
+/// The `sign` subcommand, signing files with an operator key, e.g. batch
+/// output redirected to a file
+#[cfg(not(test))]
+fn sign_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optopt("", "sign-with", "Specify the operator key: minisign:SECRET-KEY-FILE, gpg:KEY-ID, or gpg for gpg's default key.", "key");
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} sign --sign-with key FILE...\n\n\
+                               Each signature is written beside its file, as FILE.minisig or FILE.asc.", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.free.is_empty() || !matches.opt_present("sign-with") {
+        fail("usage", "", "sign takes --sign-with and the files to sign.");
+        println!("{}", full_usage);
+        return;
+    }
+    let signer = match parse_signer(&matches) {
+        Ok(signer) => signer,
+        Err(_) => { return; }
+    };
+    for filename in &matches.free {
+        sign_file(signer.as_ref(), filename);
+    }
+}
+
+/// The `verify-signature` subcommand, checking that a file was signed by
+/// an operator key
+#[cfg(not(test))]
+fn verify_signature_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optopt("", "key", "Specify the key the file must be signed by: minisign:PUBLIC-KEY-FILE, gpg:FINGERPRINT, or gpg for any key of the keyring.", "key");
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} verify-signature --key key FILE [SIGNATURE]\n\n\
+                               SIGNATURE defaults to FILE.minisig or FILE.asc.", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.free.is_empty() || matches.free.len() > 2 || !matches.opt_present("key") {
+        fail("usage", "", "verify-signature takes --key, a file and optionally its signature.");
+        println!("{}", full_usage);
+        return;
+    }
+    let key = match signing::Key::parse(&matches.opt_str("key").unwrap()) {
+        Ok(key) => key,
+        Err(_) => {
+            fail("bad_value", "--key", "option to --key must be minisign:PUBLIC-KEY-FILE, gpg:FINGERPRINT or gpg.");
+            return;
+        }
+    };
+    let filename = &matches.free[0];
+    let signature = matches.free.get(1).cloned().unwrap_or(key.signature_path(filename));
+    match key.verify(filename, &signature) {
+        Ok(details) => println!("Signature of {} is valid: {}", filename, details),
+        Err(signing::Error::WrongKey(fingerprint)) => fail("bad_signature", "", &format!("INVALID: {} is signed by {}, not the key given.", filename, fingerprint)),
+        Err(e) => fail("bad_signature", "", &format!("INVALID: the signature of {} could not be verified: {:?}", filename, e))
+    }
+}
+
+/// The `ots` subcommand, completing and checking OpenTimestamps proofs made
+/// with `--ots`
+#[cfg(not(test))]
+fn ots_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} ots <upgrade|verify> PROOF-FILE\n\n\
+                               upgrade completes a proof once its calendars' commitments are in a block, and\n\
+                               verify checks a completed proof against the chain. The timestamped file must be\n\
+                               beside its proof.", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    let command = matches.free.first().map_or("", |command| &command[..]);
+    if matches.free.len() != 2 || (command != "upgrade" && command != "verify") {
+        fail("usage", "", "ots takes upgrade or verify, and a proof file.");
+        println!("{}", full_usage);
+        return;
+    }
+    let proof = &matches.free[1];
+    let result = match command {
+        "upgrade" => ots::upgrade(proof),
+        _ => ots::verify(proof)
+    };
+    match result {
+        Ok(said) => println!("{}", said),
+        Err(ots::Error::Failed(said)) => fail("ots_failed", "", &format!("Unable to {} {}:\n{}", command, proof, said)),
+        Err(e) => fail("ots_failed", "", &format!("Unable to {} {}: {:?}", command, proof, e))
+    }
+}
+
+/// The `sweep` subcommand, moving every coin paid to a tweaked key or to a
+/// modified script it alone can satisfy to one address
+#[cfg(not(test))]
+fn sweep_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optopt("p", "private-key", "Specify the tweaked private key, as -c mode prints it: - to read it from stdin, or env:VAR to read it from an environment variable.", "private key");
+    opts.optflag("", "insecure-allow-cli-key", "Accept a private key given on the command line itself, despite its being left in shell history and visible in ps.");
+    opts.optopt("r", "redeem-script", "Specify the modified redeem script the coins were paid to, as hex or asm, if they were not paid to the key's P2PKH address.", "redemption script");
+    opts.optopt("", "address-type", "How the coins were paid: p2pkh (the default without -r), p2sh (the default with -r), p2wsh or p2sh-p2wsh.", "type");
+    opts.optopt("", "utxos", "Specify a file of the outputs to sweep, one txid:vout amount per line, with amounts in satoshis.", "file");
+    opts.optopt("", "to", "Specify the address to sweep to.", "address");
+    opts.optopt("", "fee", "Specify the fee to pay, in satoshis.", "satoshis");
+    opts.optopt("", "fee-rate", "Specify the fee rate to pay, in satoshis per virtual byte, instead of --fee.", "sat/vB");
+    opts.optopt("", "amount", "Specify the amount to send to --to, in satoshis, instead of everything; the rest goes to --change.", "satoshis");
+    opts.optopt("", "change", "Specify the address to send change to with --amount.", "address");
+    opts.optflag("", "rbf", "Signal BIP125 replaceability, so the fee can be bumped.");
+    add_chain_options(&mut opts);
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} sweep [-t|--chain name] -p key [-r script] --utxos file --to address <--fee satoshis|--fee-rate sat/vB> [--amount satoshis --change address] [--rbf]", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    let (key, filename, to) = match (matches.opt_str("p"), matches.opt_str("utxos"), matches.opt_str("to")) {
+        (Some(key), Some(filename), Some(to)) => (key, filename, to),
+        _ => {
+            fail("usage", "", "-p, --utxos and --to must all be specified.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+    let secp = context::secp();
+
+    // Private keys are about to be read, and should not end up in a core dump
+    memlock::disable_core_dumps();
+    let key = match if key == "-" { stdin_key() } else { Some(cli_key(&key, matches.opt_present("insecure-allow-cli-key"), ", - to read it from stdin")) } {
+        Some(key) => key,
+        None => { return; }
+    };
+    let key = match privkey::decode(&secp, &key, chain, None) {
+        Ok(key) => memlock::Secret::new(key),
+        // A WIF key of another chain
+        Err(privkey::Error::Base58(base58::Error::InvalidVersion(_))) => {
+            fail("wrong_network", "-p", "Private key network did not match tool mode (did you forget -t?).");
+            return;
+        }
+        Err(e) => {
+            fail("bad_privkey", "-p", &format!("option to -p could not be parsed as a private key: {:?}.", e));
+            return;
+        }
+    };
+    let script = match matches.opt_str("r") {
+        Some(x) => {
+            match asm::Encoding::Hex.decode(&x).or_else(|_| asm::assemble(&x)) {
+                Ok(script) => Some(script),
+                Err(e) => {
+                    fail("bad_script", "-r", &format!("option to -r could not be parsed as hex or asm: {:?}.", e));
+                    return;
+                }
+            }
+        }
+        None => None
+    };
+    let spend_type = match (matches.opt_str("address-type"), script.is_some()) {
+        (None, false) => sweep::SpendType::P2pkh,
+        (None, true) => sweep::SpendType::P2sh,
+        (Some(ref ty), _) if ty == "p2pkh" => sweep::SpendType::P2pkh,
+        (Some(ref ty), _) if ty == "p2sh" => sweep::SpendType::P2sh,
+        (Some(ref ty), _) if ty == "p2wsh" => sweep::SpendType::P2wsh,
+        (Some(ref ty), _) if ty == "p2sh-p2wsh" => sweep::SpendType::P2shP2wsh,
+        (Some(_), _) => {
+            fail("bad_value", "--address-type", "option to --address-type must be p2pkh, p2sh, p2wsh or p2sh-p2wsh.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    let destination = match parse_address(chain, "option to --to", &to) {
+        Some(script_pubkey) => script_pubkey,
+        None => { return; }
+    };
+    let change = match (matches.opt_str("amount"), matches.opt_str("change")) {
+        (Some(_), Some(change)) => {
+            match parse_address(chain, "option to --change", &change) {
+                Some(script_pubkey) => Some(script_pubkey),
+                None => { return; }
+            }
+        }
+        (None, None) => None,
+        _ => {
+            fail("usage", "--amount", "--amount and --change must be used together.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    // Amounts and fees are whole numbers of satoshis
+    let parse_number = |option: &str, s: &str| -> Option<u64> {
+        match s.parse::<u64>() {
+            Ok(n) => Some(n),
+            Err(e) => {
+                fail("bad_number", option, &format!("option to {} could not be parsed as a number: {}.", option, e));
+                None
+            }
+        }
+    };
+    let amount = match matches.opt_str("amount") {
+        Some(amount) => {
+            match parse_number("--amount", &amount) {
+                Some(amount) => Some(amount),
+                None => { return; }
+            }
+        }
+        None => None
+    };
+    let fee = match (matches.opt_str("fee"), matches.opt_str("fee-rate")) {
+        (Some(fee), None) => {
+            match parse_number("--fee", &fee) {
+                Some(fee) => sweep::Fee::Absolute(fee),
+                None => { return; }
+            }
+        }
+        (None, Some(rate)) => {
+            match parse_number("--fee-rate", &rate) {
+                Some(rate) => sweep::Fee::Rate(rate),
+                None => { return; }
+            }
+        }
+        _ => {
+            fail("usage", "--fee", "Exactly one of --fee or --fee-rate must be specified.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    let options = sweep::Options {
+        destination: destination,
+        amount: amount,
+        change: change,
+        fee: fee,
+        rbf: matches.opt_present("rbf")
+    };
+    let utxos = match read_utxos_file(&filename) {
+        Some(utxos) => utxos,
+        None => { return; }
+    };
+
+    match sweep::sweep(&secp, &key, script.as_ref(), spend_type, &utxos, &options) {
+        Ok(signed) => {
+            let total = utxos.iter().fold(0, |acc, utxo| acc + utxo.amount);
+            println!("Sweeping {} outputs worth {} satoshis, paying {} in fees for {} vbytes.", utxos.len(), total, signed.fee, signed.vsize);
+            if let Some(change) = signed.change {
+                println!("Sending {} satoshis to change.", change);
+            }
+            if options.rbf {
+                println!("Transaction signals replaceability (BIP125).");
+            }
+            println!("Signed transaction: {}", signed.tx[..].to_hex());
+        }
+        Err(e) => fail("sweep_failed", "", &format!("Unable to sweep: {:?}", e))
+    }
+}
+
+/// The `sign-tx` subcommand, signing one input of a transaction with the
+/// tweak of a base key
+#[cfg(not(test))]
+fn sign_tx_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optopt("p", "private-key", "Specify the untweaked private key, as WIF, raw hex or a mini private key: - to read it from stdin, or env:VAR to read it from an environment variable.", "private key");
+    opts.optflag("", "insecure-allow-cli-key", "Accept a private key given on the command line itself, despite its being left in shell history and visible in ps.");
+    opts.optopt("f", "hex-contract", "Specify the contract of the tweak as an hexadecimal string.", "hex");
+    opts.optopt("a", "ascii-contract", "Specify the contract of the tweak as an ASCII string.", "text");
+    opts.optopt("d", "p2sh-address", "Specify the contract of the tweak as a P2SH address.", "P2SH address");
+    opts.optopt("n", "nonce", "Specify the hex-encoded nonce of a contract given with -a or -d.", "nonce");
+    opts.optopt("r", "redeem-script", "Specify the redeem (or witness) script the input spends, as hex or asm, if it was not paid to the tweaked key's P2PKH address. The original template is tweaked first.", "redemption script");
+    opts.optopt("", "address-type", "How the input was paid: p2pkh (the default without -r), p2sh (the default with -r), p2wsh or p2sh-p2wsh.", "type");
+    opts.optopt("", "input", "Specify the index of the input to sign (defaults to 0).", "n");
+    opts.optopt("", "amount", "Specify the amount in satoshis of the output the input spends, which p2wsh and p2sh-p2wsh signatures commit to.", "satoshis");
+    add_chain_options(&mut opts);
+    add_plugin_options(&mut opts);
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} sign-tx [-t|--chain name] -p key <-f contract|-d p2sh -n nonce|-a ascii -n nonce> [-r script] [--input n] [--amount satoshis] TRANSACTION", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.free.len() != 1 || !matches.opt_present("p") {
+        fail("usage", "", "sign-tx takes one TRANSACTION and requires -p.");
+        println!("{}", full_usage);
+        return;
+    }
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+    let plugins = match parse_plugins(&matches) {
+        Some(plugins) => plugins,
+        None => { return; }
+    };
+    let secp = context::secp();
+
+    let nonce = match matches.opt_str("n").map(|hex| Nonce::from_hex(&hex)) {
+        Some(Ok(nonce)) => Some(nonce),
+        Some(Err(e)) => {
+            fail("bad_nonce", "-n", &format!("option to -n could not be parsed as a nonce: {:?}.", e));
+            return;
+        }
+        None => None
+    };
+    let contract = match (matches.opt_str("f"), nonce, matches.opt_str("d"), matches.opt_str("a")) {
+        (Some(hex), None, None, None) => Contract::from_hex_custom(&hex, &plugins.tags()).map_err(|e| format!("-f could not be parsed as a contract: {:?}", e)),
+        (None, Some(nonce), Some(p2sh), None) => Contract::from_p2sh_base58_str(&p2sh, nonce, chain).map_err(|e| format!("-d could not be parsed as a P2SH contract: {:?}", e)),
+        (None, Some(nonce), None, Some(ascii)) => Contract::from_ascii_str(&ascii, nonce).map_err(|e| format!("-a could not be parsed as a contract: {:?}", e)),
+        _ => {
+            fail("usage", "", "Must specify exactly one of: -f; -a -n; or -d -n");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    let contract = match contract {
+        Ok(contract) => contract,
+        Err(e) => {
+            fail("bad_contract", "", &format!("option to {}.", e));
+            return;
+        }
+    };
+    // Private keys are about to be read, and should not end up in a core dump
+    memlock::disable_core_dumps();
+    let p = matches.opt_str("p").unwrap();
+    let base_key = match if p == "-" { stdin_key() } else { Some(cli_key(&p, matches.opt_present("insecure-allow-cli-key"), ", - to read it from stdin")) } {
+        Some(key) => key,
+        None => { return; }
+    };
+    let base_key = match privkey::decode(&secp, &base_key, chain, None) {
+        Ok(key) => memlock::Secret::new(key),
+        // A WIF key of another chain
+        Err(privkey::Error::Base58(base58::Error::InvalidVersion(_))) => {
+            fail("wrong_network", "-p", "Private key network did not match tool mode (did you forget -t?).");
+            return;
+        }
+        Err(e) => {
+            fail("bad_privkey", "-p", &format!("option to -p could not be parsed as a private key: {:?}.", e));
+            return;
+        }
+    };
+    let key = match context::tweak_secret_key(&base_key.key, &contract.to_bytes()[..]) {
+        Ok(tweaked) => memlock::Secret::new(Privkey { compressed: base_key.compressed, network: chain.network(), key: tweaked }),
+        Err(e) => {
+            fail("tweak_failed", "", &format!("Failed to tweak private key: {:?}", e));
+            return;
+        }
+    };
+
+    let script = match matches.opt_str("r") {
+        Some(x) => {
+            match asm::Encoding::Hex.decode(&x).or_else(|_| asm::assemble(&x)) {
+                Ok(script) => Some(script),
+                Err(e) => {
+                    fail("bad_script", "-r", &format!("option to -r could not be parsed as hex or asm: {:?}.", e));
+                    return;
+                }
+            }
+        }
+        None => None
+    };
+    let spend_type = match (matches.opt_str("address-type"), script.is_some()) {
+        (None, false) => sweep::SpendType::P2pkh,
+        (None, true) => sweep::SpendType::P2sh,
+        (Some(ref ty), _) if ty == "p2pkh" => sweep::SpendType::P2pkh,
+        (Some(ref ty), _) if ty == "p2sh" => sweep::SpendType::P2sh,
+        (Some(ref ty), _) if ty == "p2wsh" => sweep::SpendType::P2wsh,
+        (Some(ref ty), _) if ty == "p2sh-p2wsh" => sweep::SpendType::P2shP2wsh,
+        (Some(_), _) => {
+            fail("bad_value", "--address-type", "option to --address-type must be p2pkh, p2sh, p2wsh or p2sh-p2wsh.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    let tx = match matches.free[0].from_hex().map_err(|_| sweep::Error::BadTransaction).and_then(|data| sweep::UnsignedTx::parse(&data)) {
+        Ok(tx) => tx,
+        Err(e) => {
+            fail("bad_transaction", "", &format!("TRANSACTION could not be parsed: {:?}.", e));
+            return;
+        }
+    };
+    let input = match matches.opt_str("input").map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n < tx.inputs.len() => n,
+        None => 0,
+        Some(_) => {
+            fail("bad_value", "--input", &format!("option to --input must be the index of one of the {} inputs.", tx.inputs.len()));
+            return;
+        }
+    };
+    let amount = match (spend_type, matches.opt_str("amount").map(|n| n.parse::<u64>())) {
+        (_, Some(Ok(amount))) => amount,
+        (_, Some(Err(e))) => {
+            fail("bad_number", "--amount", &format!("option to --amount could not be parsed as a number: {}.", e));
+            return;
+        }
+        (sweep::SpendType::P2wsh, None) | (sweep::SpendType::P2shP2wsh, None) => {
+            fail("usage", "--amount", "--amount must be specified for p2wsh and p2sh-p2wsh inputs.");
+            return;
+        }
+        (_, None) => 0
+    };
+
+    // A script without the tweaked key may be the original template
+    let signer = match sweep::Signer::new(&secp, &key, script.as_ref(), spend_type) {
+        Err(sweep::Error::KeyNotInScript) => {
+            let tweaked = template::tweak_script(&secp, script.as_ref().unwrap(), &contract.to_bytes()[..]);
+            match tweaked {
+                Ok((new_script, _, _)) => {
+                    println!("Tweaked the template given with -r to: {}", asm::disassemble(&new_script));
+                    sweep::Signer::new(&secp, &key, Some(&new_script), spend_type)
+                }
+                Err(_) => Err(sweep::Error::KeyNotInScript)
+            }
+        }
+        result => result
+    };
+    let signer = match signer {
+        Ok(signer) => signer,
+        Err(e) => {
+            fail("sign_failed", "", &format!("Unable to sign for the script: {:?}", e));
+            return;
+        }
+    };
+    let sig = match signer.sign(&tx, input, amount) {
+        Ok(sig) => sig,
+        Err(e) => {
+            fail("sign_failed", "", &format!("Unable to sign: {:?}", e));
+            return;
+        }
+    };
+    let public_key = PublicKey::from_secret_key(&secp, &key.key).unwrap();
+    println!("Tweaked key: {}", public_key.serialize_vec(&secp, key.compressed)[..].to_hex());
+    println!("Signature for input {}: {}", input, sig.to_hex());
+    // The key alone may satisfy the script, completing the input
+    if let Some((script_sig, witness)) = signer.satisfy(sig) {
+        if !script_sig.is_empty() {
+            println!("Input {} scriptSig: {}", input, script_sig.to_hex());
+        }
+        if !witness.is_empty() {
+            println!("Input {} witness:", input);
+            for item in &witness {
+                println!("    {}", if item.is_empty() { "(empty)".to_owned() } else { item.to_hex() });
+            }
+        }
+        if tx.inputs.len() == 1 {
+            println!("Signed transaction: {}", tx.serialize(&[script_sig], &[witness]).to_hex());
+        }
+    }
+}
+
+/// The `watch` subcommand, following bitcoind's ZMQ notifications for
+/// transactions touching a set of addresses
+#[cfg(not(test))]
+fn watch_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optmulti("", "zmq", "Specify a ZMQ endpoint of bitcoind's zmqpubrawtx or zmqpubrawblock, e.g. tcp://127.0.0.1:28332; give it twice if they differ.", "endpoint");
+    opts.optopt("", "utxos", "Specify a file of coins the addresses already hold, one txid:vout amount per line, so that spending them is noticed too.", "file");
+    opts.optflag("", "json", "Print each event as a line of JSON rather than text.");
+    opts.optopt("", "webhook", "Also POST each event as JSON to an http:// URL.", "url");
+    opts.optopt("", "metrics", "Serve Prometheus metrics at /metrics on an address like 127.0.0.1:9100.", "address");
+    add_log_options(&mut opts);
+    add_chain_options(&mut opts);
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} watch [-t|--chain name] --zmq endpoint [--utxos file] [--json] [--webhook url] [--metrics address] ADDRESSES-FILE\n\n\
+                               Each line of the addresses file is an address and an optional label.", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    let endpoints = matches.opt_strs("zmq");
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.free.len() != 1 || endpoints.is_empty() {
+        fail("usage", "", "watch takes one WATCH-FILE and requires --zmq.");
+        println!("{}", full_usage);
+        return;
+    }
+    if !init_log(&matches) {
+        return;
+    }
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+    let json = matches.opt_present("json");
+    let webhook = matches.opt_str("webhook");
+
+    let scripts = match read_addresses_file(chain, &matches.free[0]) {
+        Some(scripts) => scripts,
+        None => { return; }
+    };
+    let metrics = Arc::new(metrics::Metrics::new());
+    let n_scripts = scripts.len();
+    metrics.set("pacthash_watched_scripts", "", n_scripts as f64);
+    if let Some(address) = matches.opt_str("metrics") {
+        let listener = match TcpListener::bind(&address[..]) {
+            Ok(listener) => listener,
+            Err(e) => {
+                fail("io", "--metrics", &format!("Unable to listen on {}: {}", address, e));
+                return;
+            }
+        };
+        let metrics = metrics.clone();
+        thread::spawn(move || serve_metrics(listener, metrics));
+    }
+    let mut watcher = watch::Watcher::new(scripts);
+    if let Some(filename) = matches.opt_str("utxos") {
+        let utxos = match read_utxos_file(&filename) {
+            Some(utxos) => utxos,
+            None => { return; }
+        };
+        for utxo in &utxos {
+            watcher.add_coin(utxo, &format!("held coin {}:{}", utxo.txid[..].to_hex(), utxo.vout));
+        }
+    }
+
+    // Each endpoint is followed on its own thread
+    let (sender, receiver) = mpsc::channel();
+    for endpoint in endpoints {
+        let mut subscriber = match zmq::Subscriber::connect(&endpoint, &["rawtx", "rawblock"]) {
+            Ok(subscriber) => subscriber,
+            Err(e) => {
+                fail("zmq_failed", "--zmq", &format!("Unable to subscribe to {}: {:?}", endpoint, e));
+                return;
+            }
+        };
+        let sender = sender.clone();
+        thread::spawn(move || {
+            loop {
+                let message = subscriber.recv().map_err(|e| (endpoint.clone(), e));
+                let failed = message.is_err();
+                if sender.send(message).is_err() || failed {
+                    return;
+                }
+            }
+        });
+    }
+    drop(sender);
+
+    log::info("watching", &[("scripts", &n_scripts.to_string())]);
+    for message in receiver {
+        let frames = match message {
+            Ok(frames) => frames,
+            Err((endpoint, e)) => {
+                log::error("connection_lost", &[("endpoint", &endpoint), ("error", &format!("{:?}", e))]);
+                return;
+            }
+        };
+        let events = match frames.first().map(|topic| &topic[..]) {
+            Some(b"rawtx") if frames.len() >= 2 => watcher.check_tx(&frames[1]),
+            Some(b"rawblock") if frames.len() >= 2 => watcher.check_block(&frames[1]),
+            _ => continue
+        };
+        let events = match events {
+            Ok(events) => events,
+            Err(e) => {
+                log::warn("notification_invalid", &[("error", &format!("{:?}", e))]);
+                continue;
+            }
+        };
+        for event in events {
+            let kind = match event.kind { watch::Kind::Receive { .. } => "receive", watch::Kind::Spend { .. } => "spend" };
+            let status = if event.confirmed { "confirmed" } else { "seen" };
+            metrics.increment("pacthash_watch_events_total", &format!("event=\"{}\",status=\"{}\"", kind, status));
+            if json {
+                println!("{}", event.to_json());
+            } else {
+                println!("{}", event);
+            }
+            if let Some(ref url) = webhook {
+                if let Err(e) = rpc::post_json(url, &event.to_json()) {
+                    log::warn("webhook_failed", &[("url", url), ("error", &format!("{:?}", e))]);
+                }
+            }
+        }
+    }
+}
+
+/// Add the options which select a chain
+#[cfg(not(test))]
+fn add_chain_options(opts: &mut getopts::Options) {
+    opts.optflag("t", "testnet", "Set the tool to testnet mode (defaults to main)");
+    opts.optopt("", "chain", "Set the chain whose addresses and private keys are read and written: bitcoin, testnet, signet (the default signet or a custom one, which share addresses), regtest, litecoin, litecoin-testnet or one declared with --chains (defaults to bitcoin, or testnet with -t). Extended keys are those of Bitcoin mainnet or testnet, as the chain's mode is.", "name");
+    opts.optopt("", "chains", "Specify the file declaring chains for --chain beyond the built-in ones, one per line as name, mainnet or testnet, the pubkey hash, script hash and WIF version bytes, and the segwit prefix (defaults to ~/.pacthash-chains, if it exists).", "file");
+}
+
+/// The chain the options of `add_chain_options` select, reporting any
+/// failure
+#[cfg(not(test))]
+fn parse_chain(matches: &getopts::Matches) -> Option<Chain> {
+    // Chains declared beyond the built-in ones
+    let filename = match matches.opt_str("chains") {
+        Some(filename) => Some(filename),
+        None => {
+            let path = chain::default_path();
+            if path.exists() { Some(path.to_string_lossy().into_owned()) } else { None }
+        }
+    };
+    let chains = match filename.map(|filename| chain::Registry::read(&filename)) {
+        Some(Ok(chains)) => chains,
+        Some(Err(e)) => {
+            fail("bad_chains", "--chains", &format!("Unable to read chains file: {:?}", e));
+            return None;
+        }
+        None => chain::Registry::default()
+    };
+    match (matches.opt_present("t"), matches.opt_str("chain")) {
+        (false, None) => Some(Chain::Bitcoin),
+        (true, None) => Some(Chain::Testnet),
+        (false, Some(name)) => {
+            let chain = chains.get(&name);
+            if chain.is_none() {
+                fail("bad_value", "--chain", &format!("option to --chain must be one of {}.", chains.names().join(", ")));
+            }
+            chain
+        }
+        (true, Some(_)) => {
+            fail("usage", "--chain", "-t may not be used with --chain, which names testnets itself.");
+            None
+        }
+    }
+}
+
+/// Add the option which declares contract types beyond the built-in ones
+#[cfg(not(test))]
+fn add_plugin_options(opts: &mut getopts::Options) {
+    opts.optopt("", "plugins", "Specify the file declaring the plugins which define contract types beyond the built-in ones (defaults to ~/.pacthash-plugins, if it exists).", "file");
+}
+
+/// The plugins the option of `add_plugin_options` declares, reporting any
+/// failure
+#[cfg(not(test))]
+fn parse_plugins(matches: &getopts::Matches) -> Option<plugin::Plugins> {
+    let filename = match matches.opt_str("plugins") {
+        Some(filename) => filename,
+        None => {
+            let path = plugin::default_path();
+            if !path.exists() {
+                return Some(plugin::Plugins::default());
+            }
+            path.to_string_lossy().into_owned()
+        }
+    };
+    match plugin::Plugins::read(&filename) {
+        Ok(plugins) => Some(plugins),
+        Err(e) => {
+            fail("bad_plugins", "--plugins", &format!("Unable to read plugins file: {:?}", e));
+            None
+        }
+    }
+}
+
+/// Add the options which configure logging
+#[cfg(not(test))]
+fn add_log_options(opts: &mut getopts::Options) {
+    opts.optopt("", "log-level", "Log only events at least this important: error, warn, info (the default) or debug.", "level");
+    opts.optopt("", "log-format", "Log lines as text (the default) or json.", "format");
+    opts.optopt("", "log-file", "Append log lines to a file rather than writing them to stderr.", "file");
+}
+
+/// Set up logging as the options of `add_log_options` ask, reporting any
+/// failure
+#[cfg(not(test))]
+fn init_log(matches: &getopts::Matches) -> bool {
+    let level = matches.opt_str("log-level").unwrap_or("info".to_owned());
+    let format = matches.opt_str("log-format").unwrap_or("text".to_owned());
+    match log::init(&level, &format, matches.opt_str("log-file").as_ref().map(|f| &f[..])) {
+        Ok(()) => true,
+        Err(e) => {
+            let (kind, flag) = match e {
+                log::Error::Io(_) => ("io", "--log-file"),
+                log::Error::BadLevel(_) => ("bad_value", "--log-level"),
+                log::Error::BadFormat(_) => ("bad_value", "--log-format")
+            };
+            fail(kind, flag, &format!("Unable to set up logging: {:?}", e));
+            false
+        }
+    }
+}
+
+/// The most bytes of an HTTP request line and headers, together
+#[cfg(not(test))]
+const MAX_HTTP_HEADER: u64 = 8192;
+
+/// The most bytes of an HTTP request body; requests are a few small
+/// parameters
+#[cfg(not(test))]
+const MAX_HTTP_BODY: usize = 65536;
+
+/// The most bytes of a request line on the unix socket, as many as an
+/// HTTP request body
+#[cfg(not(test))]
+const MAX_SOCKET_LINE: u64 = 65536;
+
+/// Seconds a client may leave a connection idle before it is closed
+#[cfg(not(test))]
+const CONNECTION_TIMEOUT: u64 = 30;
+
+/// The most connections a server keeps open at once, each in a thread of
+/// its own
+#[cfg(not(test))]
+const MAX_CONNECTIONS: usize = 64;
+
+/// Serve the methods of a server over HTTP. Each connection carries one
+/// request, and is closed after the reply.
+#[cfg(not(test))]
+fn serve_http(server: Arc<server::Server>, address: &str, allow_privkeys: bool, tokens: Option<Arc<server::Tokens>>,
+              limiter: Arc<ratelimit::Limiter>) {
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error("listen_failed", &[("http", address), ("error", &e.to_string())]);
+            return;
+        }
+    };
+    log::info("listening", &[("http", address)]);
+    let connections = ratelimit::Connections::new(MAX_CONNECTIONS);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn("accept_failed", &[("error", &e.to_string())]);
+                continue;
+            }
+        };
+        let connection = match open_http_connection(&connections, stream) {
+            Some(connection) => connection,
+            None => continue
+        };
+        let server = server.clone();
+        let tokens = tokens.clone();
+        let limiter = limiter.clone();
+        thread::spawn(move || {
+            let (stream, _connection) = connection;
+            answer_http(&server, stream, allow_privkeys, tokens.as_ref().map(|t| &**t), &limiter)
+        });
+    }
+}
+
+/// Count a newly accepted HTTP connection and set its timeouts, or refuse
+/// it with a 503 if as many as allowed are already open
+#[cfg(not(test))]
+fn open_http_connection(connections: &ratelimit::Connections, stream: TcpStream) -> Option<(TcpStream, ratelimit::Connection)> {
+    set_timeouts(&stream);
+    match connections.open() {
+        Some(connection) => Some((stream, connection)),
+        None => {
+            log::warn("too_many_connections", &[("max", &MAX_CONNECTIONS.to_string())]);
+            write_http_error(stream, 503, "too many connections");
+            None
+        }
+    }
+}
+
+/// Close a connection whose client sends or reads nothing for
+/// `CONNECTION_TIMEOUT` seconds
+#[cfg(not(test))]
+fn set_timeouts(stream: &TcpStream) {
+    let timeout = Some(Duration::from_secs(CONNECTION_TIMEOUT));
+    let _ = stream.set_read_timeout(timeout);
+    let _ = stream.set_write_timeout(timeout);
+}
+
+/// Read a line of at most `limit` bytes. It has no newline at its end if
+/// it was longer, or if the stream ended first.
+#[cfg(not(test))]
+fn read_line_limited<R: BufRead>(reader: &mut R, limit: u64) -> io::Result<String> {
+    let mut line = String::new();
+    try!(reader.by_ref().take(limit).read_line(&mut line));
+    Ok(line)
+}
+
+/// Read an HTTP request, giving its method, path, `Authorization` header
+/// and body. A request which should be answered with an error gives the
+/// status of it, and one which cannot be answered at all gives `None`.
+#[cfg(not(test))]
+fn read_http_request(stream: &TcpStream) -> Result<(String, String, Option<String>, String), Option<u16>> {
+    let mut reader = match stream.try_clone() {
+        Ok(stream) => io::BufReader::new(stream),
+        Err(_) => { return Err(None); }
+    };
+    // The request line and headers share one budget, so that neither many
+    // lines nor one long one can grow without limit
+    let mut header_left = MAX_HTTP_HEADER;
+    let mut next_line = |reader: &mut io::BufReader<TcpStream>| -> Result<String, Option<u16>> {
+        match read_line_limited(reader, header_left) {
+            Ok(line) => {
+                if line.ends_with('\n') {
+                    header_left -= line.len() as u64;
+                    Ok(line)
+                } else if line.len() as u64 == header_left {
+                    Err(Some(413))
+                } else {
+                    Err(None)
+                }
+            }
+            Err(_) => Err(None)
+        }
+    };
+    let request_line = try!(next_line(&mut reader));
+    let mut content_length = 0;
+    let mut authorization = None;
+    loop {
+        let header = try!(next_line(&mut reader));
+        if header.trim().is_empty() {
+            break;
+        }
+        let mut fields = header.splitn(2, ':');
+        let name = fields.next().unwrap_or("").trim().to_ascii_lowercase();
+        let value = fields.next().unwrap_or("").trim();
+        if name == "content-length" {
+            content_length = match value.parse() {
+                Ok(n) if n <= MAX_HTTP_BODY => n,
+                Ok(_) => { return Err(Some(413)); }
+                Err(_) => { return Err(Some(400)); }
+            };
+        } else if name == "authorization" {
+            authorization = Some(value.to_owned());
+        }
+    }
+    let mut body = vec![0; content_length];
+    if reader.read_exact(&mut body).is_err() {
+        return Err(None);
+    }
+    let mut request = request_line.split_whitespace();
+    let (method, path) = (request.next().unwrap_or(""), request.next().unwrap_or(""));
+    Ok((method.to_owned(), path.to_owned(), authorization, String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// Write an HTTP reply, closing the connection after it
+#[cfg(not(test))]
+fn write_http_response(stream: TcpStream, status: u16, content_type: &str, body: &str) {
+    let challenge = if status == 401 { "WWW-Authenticate: Bearer\r\n" } else { "" };
+    write_http_response_with(stream, status, challenge, content_type, body);
+}
+
+/// Write an HTTP reply of a JSON error, closing the connection after it
+#[cfg(not(test))]
+fn write_http_error(stream: TcpStream, status: u16, message: &str) {
+    let mut error = BTreeMap::new();
+    error.insert("message".to_owned(), Json::String(message.to_owned()));
+    let mut reply = BTreeMap::new();
+    reply.insert("error".to_owned(), Json::Object(error));
+    write_http_response(stream, status, "application/json", &Json::Object(reply).to_string());
+}
+
+/// Write an HTTP reply with some extra headers, each ending in `\r\n`
+#[cfg(not(test))]
+fn write_http_response_with(mut stream: TcpStream, status: u16, headers: &str, content_type: &str, body: &str) {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        _ => "Unprocessable Entity"
+    };
+    let _ = write!(stream, "HTTP/1.1 {} {}\r\n{}Content-Type: {}\r\nContent-Length: {}\r\n\
+                            Connection: close\r\n\r\n{}", status, reason, headers, content_type, body.len(), body);
+}
+
+/// Prometheus' content type for its text format
+#[cfg(not(test))]
+const METRICS_CONTENT_TYPE: &'static str = "text/plain; version=0.0.4";
+
+/// Answer the one HTTP request of a connection
+#[cfg(not(test))]
+fn answer_http(server: &server::Server, stream: TcpStream, allow_privkeys: bool, tokens: Option<&server::Tokens>,
+               limiter: &ratelimit::Limiter) {
+    let (method, path, authorization, body) = match read_http_request(&stream) {
+        Ok(request) => request,
+        Err(Some(status)) => {
+            log::warn("request_refused", &[("status", &status.to_string())]);
+            let message = if status == 413 { "the request is too large" } else { "the request is malformed" };
+            write_http_error(stream, status, message);
+            return;
+        }
+        Err(None) => { return; }
+    };
+    // Clients are told apart by their token, or by their address if there
+    // are no tokens
+    let mut client = match stream.peer_addr() {
+        Ok(address) => address.ip().to_string(),
+        Err(_) => "unknown".to_owned()
+    };
+    if let Some(tokens) = tokens {
+        match tokens.check(authorization.as_ref().map(|a| &a[..]), path.trim_left_matches('/')) {
+            Ok(n) => client = format!("token {}", n + 1),
+            Err(status) => {
+                log::warn("request_refused", &[("method", &method), ("path", &path), ("status", &status.to_string())]);
+                let message = if status == 401 { "a valid bearer token is required" } else { "the token may not call this" };
+                write_http_error(stream, status, message);
+                return;
+            }
+        }
+    }
+    if path == "/metrics" && method == "GET" {
+        write_http_response(stream, 200, METRICS_CONTENT_TYPE, &server.metrics().render());
+        return;
+    }
+    // Scrapes of the metrics are not limited, so that they still show a
+    // server which is refusing requests
+    if let Err((limit, retry_after)) = limiter.check(&client) {
+        server.metrics().increment("pacthash_rate_limited_total", &format!("limit=\"{}\"", limit.name()));
+        log::warn("rate_limited", &[("client", &client), ("limit", limit.name()), ("path", &path)]);
+        let mut error = BTreeMap::new();
+        error.insert("code".to_owned(), Json::I64(server::RATE_LIMITED));
+        error.insert("message".to_owned(), Json::String("too many requests".to_owned()));
+        let mut reply = BTreeMap::new();
+        reply.insert("error".to_owned(), Json::Object(error));
+        write_http_response_with(stream, 429, &format!("Retry-After: {}\r\n", retry_after), "application/json",
+                                 &Json::Object(reply).to_string());
+        return;
+    }
+    let (status, reply) = server.handle_http(&method, &path, &body, allow_privkeys);
+    // The body may hold a private key, so only the method and path are logged
+    log::debug("request", &[("method", &method), ("path", &path), ("status", &status.to_string())]);
+    write_http_response(stream, status, "application/json", &reply.to_string());
+}
+
+/// Serve only `/metrics` over HTTP, for processes which answer no requests
+#[cfg(not(test))]
+fn serve_metrics(listener: TcpListener, metrics: Arc<metrics::Metrics>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue
+        };
+        // Connections are answered one at a time, so a slow one holds up the rest
+        set_timeouts(&stream);
+        match read_http_request(&stream) {
+            Ok((ref method, ref path, _, _)) if method == "GET" && path == "/metrics" => {
+                write_http_response(stream, 200, METRICS_CONTENT_TYPE, &metrics.render());
+            }
+            Ok(_) => write_http_response(stream, 404, "text/plain", "Not Found\n"),
+            Err(Some(413)) => write_http_response(stream, 413, "text/plain", "Payload Too Large\n"),
+            Err(Some(status)) => write_http_response(stream, status, "text/plain", "Bad Request\n"),
+            Err(None) => {}
+        }
+    }
+}
+
+/// The `serve` subcommand, answering JSON-RPC requests on a unix socket
+#[cfg(not(test))]
+fn serve_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optopt("", "socket", "Specify the path of the unix socket to listen on.", "path");
+    opts.optopt("", "http", "Serve HTTP instead, on an address like 127.0.0.1:8080.", "address");
+    opts.optflag("", "allow-privkeys", "Serve tweak_privkey over HTTP too.");
+    opts.optopt("", "tokens", "Require HTTP requests to carry a bearer token from this file, of lines TOKEN METHOD,METHOD,...", "file");
+    opts.optflag("", "jsonl", "Answer JSON lines from stdin on stdout instead.");
+    opts.optopt("", "rate-limit", "Limit each client to RATE requests a second, in bursts of up to BURST.", "RATE[/BURST]");
+    opts.optopt("", "global-rate-limit", "Limit all clients together to RATE requests a second, in bursts of up to BURST.", "RATE[/BURST]");
+    opts.optopt("r", "redeem-script", "Specify a redeem script, as hex or asm, for requests which give none.", "redemption script");
+    add_log_options(&mut opts);
+    add_chain_options(&mut opts);
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} serve [-t|--chain name] <--socket path|--http address [--allow-privkeys] [--tokens file]|--jsonl> [-r script]\n\
+                               [--rate-limit RATE[/BURST]] [--global-rate-limit RATE[/BURST]]\n\n\
+                               Requests are JSON-RPC 2.0, one per line, with the methods gen_address,\n\
+                               tweak_privkey, verify and decode_contract. Over HTTP, the parameters\n\
+                               are POSTed to /METHOD, and /metrics gives Prometheus metrics; as JSON\n\
+                               lines, the method is given as \"op\".\n\n\
+                               HTTP is served without TLS, so tokens and private keys would cross the\n\
+                               network in plaintext: with --allow-privkeys or --tokens the address\n\
+                               must be a loopback one, such as 127.0.0.1:8080. To reach the server\n\
+                               from elsewhere, put a proxy which terminates TLS in front of it.", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    let (socket, http, jsonl) = (matches.opt_str("socket"), matches.opt_str("http"), matches.opt_present("jsonl"));
+    let n_transports = [socket.is_some(), http.is_some(), jsonl].iter().filter(|&&t| t).count();
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if !matches.free.is_empty() || n_transports != 1 ||
+       (http.is_none() && (matches.opt_present("allow-privkeys") || matches.opt_present("tokens"))) ||
+       (jsonl && (matches.opt_present("rate-limit") || matches.opt_present("global-rate-limit"))) {
+        fail("usage", "", "serve takes exactly one of --socket, --http or --jsonl, and no other arguments.");
+        println!("{}", full_usage);
+        return;
+    }
+    if !init_log(&matches) {
+        return;
+    }
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+    let script = match matches.opt_str("r").map(|r| asm::Encoding::Hex.decode(&r).or_else(|_| asm::assemble(&r))) {
+        Some(Ok(script)) => Some(script),
+        Some(Err(e)) => {
+            fail("bad_script", "-r", &format!("option to -r could not be parsed as hex or asm: {:?}.", e));
+            return;
+        }
+        None => None
+    };
+    let mut rates = [None, None];
+    for (rate, name) in rates.iter_mut().zip(["rate-limit", "global-rate-limit"].iter()) {
+        if let Some(s) = matches.opt_str(name) {
+            match ratelimit::Rate::parse(&s) {
+                Ok(parsed) => *rate = Some(parsed),
+                Err(e) => {
+                    fail("bad_value", &format!("--{}", name), &format!("option to --{} could not be parsed: {:?}.", name, e));
+                    return;
+                }
+            }
+        }
+    }
+    let limiter = Arc::new(ratelimit::Limiter::new(rates[0], rates[1]));
+    // Clients may send private keys to tweak
+    memlock::disable_core_dumps();
+    let server = Arc::new(server::Server::new(chain, script));
+    if let Some(address) = http {
+        if (matches.opt_present("allow-privkeys") || matches.opt_present("tokens")) && !server::is_loopback(&address) {
+            fail("not_loopback", "--http", &format!("Refusing to serve private keys or tokens over plaintext HTTP on {}, which is not a \
+                                                     loopback address. Listen on one such as 127.0.0.1, behind a proxy which terminates TLS.",
+                                                    address));
+            return;
+        }
+        let tokens = match matches.opt_str("tokens").map(|filename| server::Tokens::read(&filename)) {
+            Some(Ok(tokens)) => Some(Arc::new(tokens)),
+            Some(Err(e)) => {
+                fail("bad_tokens", "--tokens", &format!("Unable to read the token file: {:?}", e));
+                return;
+            }
+            None => None
+        };
+        serve_http(server, &address, matches.opt_present("allow-privkeys"), tokens, limiter);
+        return;
+    }
+    if jsonl {
+        let stdout = io::stdout();
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => { return; }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            // Flushed per line, since the parent waits for each answer
+            let mut stdout = stdout.lock();
+            if writeln!(stdout, "{}", server.handle_jsonl(&line)).and_then(|_| stdout.flush()).is_err() {
+                return;
+            }
+        }
+        return;
+    }
+    serve_socket(server, &socket.unwrap(), limiter);
+}
+
+/// Serve JSON-RPC on a unix socket
+#[cfg(all(unix, not(test)))]
+fn serve_socket(server: Arc<server::Server>, socket: &str, limiter: Arc<ratelimit::Limiter>) {
+    // A socket left behind by a previous run is not removed, in case it
+    // belongs to a server which is still running
+    if Path::new(socket).exists() {
+        log::error("listen_failed", &[("socket", socket), ("error", "already exists; remove it if no server is using it")]);
+        return;
+    }
+    let listener = match UnixListener::bind(socket) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error("listen_failed", &[("socket", socket), ("error", &e.to_string())]);
+            return;
+        }
+    };
+    log::info("listening", &[("socket", socket)]);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn("accept_failed", &[("error", &e.to_string())]);
+                continue;
+            }
+        };
+        let server = server.clone();
+        let limiter = limiter.clone();
+        thread::spawn(move || answer_socket(&server, stream, &limiter));
+    }
+}
+
+/// Unix sockets exist only on Unix
+#[cfg(all(not(unix), not(test)))]
+fn serve_socket(_: Arc<server::Server>, _: &str, _: Arc<ratelimit::Limiter>) {
+    fail("usage", "--socket", "Unix sockets are not supported on this platform; use --http or --jsonl.");
+}
+
+/// Answer the JSON-RPC requests of a unix socket connection, one per line
+#[cfg(all(unix, not(test)))]
+fn answer_socket(server: &server::Server, stream: UnixStream, limiter: &ratelimit::Limiter) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => { return; }
+    };
+    let mut reader = io::BufReader::new(stream);
+    loop {
+        let line = match read_line_limited(&mut reader, MAX_SOCKET_LINE) {
+            Ok(ref line) if line.is_empty() => { return; }
+            Ok(line) => line,
+            Err(_) => { return; }
+        };
+        // The rest of a line cut off at the limit cannot be told apart from
+        // the next request, so the connection is closed
+        if !line.ends_with('\n') && line.len() as u64 == MAX_SOCKET_LINE {
+            let message = format!("request is longer than {} bytes", MAX_SOCKET_LINE);
+            let _ = writeln!(writer, "{}", server::response(Json::Null, Err((server::INVALID_REQUEST, message))));
+            return;
+        }
+        let line = line.trim_right_matches(|c| c == '\n' || c == '\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+        // Connections to the socket cannot be told apart, so count as one client
+        let reply = match limiter.check("socket") {
+            Ok(()) => server.handle_line(&line),
+            Err((limit, retry_after)) => {
+                server.metrics().increment("pacthash_rate_limited_total", &format!("limit=\"{}\"", limit.name()));
+                log::warn("rate_limited", &[("client", "socket"), ("limit", limit.name())]);
+                let id = Json::from_str(&line).ok().and_then(|request| request.find("id").cloned()).unwrap_or(Json::Null);
+                let message = format!("too many requests; retry after {} seconds", retry_after);
+                server::response(id, Err((server::RATE_LIMITED, message))).to_string()
+            }
+        };
+        if writeln!(writer, "{}", reply).is_err() {
+            return;
+        }
+    }
+}
+
+/// The `daemon` subcommand, serving as `serve` does but configured by a
+/// file, for running under a service manager
+#[cfg(all(unix, not(test)))]
+fn daemon_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optflag("h", "help", "Print this help");
+    opts.optopt("", "config", "Specify the config file, which is read again on SIGHUP.", "file");
+    opts.optopt("", "pidfile", "Write the daemon's pid to this file while it runs.", "file");
+
+    let short_usage = format!("{} daemon --config file [--pidfile file]\n\n\
+                               The config file sets network, socket, http, allow-privkeys and\n\
+                               redeem-script, one `key = value` per line. SIGTERM stops the daemon;\n\
+                               SIGHUP reloads the config, though changes to socket and http need a\n\
+                               restart. As for serve, with allow-privkeys or tokens the http address\n\
+                               must be a loopback one, since HTTP is served without TLS.", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    let config_file = match matches.opt_str("config") {
+        Some(ref config_file) if matches.free.is_empty() => config_file.clone(),
+        _ => {
+            fail("usage", "", "daemon requires --config and takes no other arguments.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    memlock::disable_core_dumps();
+    let mut config = match daemon::read_config(&config_file) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error("config_invalid", &[("config", &config_file), ("error", &format!("{:?}", e))]);
+            return;
+        }
+    };
+    if let Err(e) = log::init(&config.log_level, &config.log_format, config.log_file.as_ref().map(|f| &f[..])) {
+        log::error("log_failed", &[("error", &format!("{:?}", e))]);
+        return;
+    }
+    let pidfile = matches.opt_str("pidfile");
+    if let Some(ref pidfile) = pidfile {
+        if let Err(e) = daemon::write_pidfile(pidfile) {
+            log::error("pidfile_failed", &[("pidfile", pidfile), ("error", &format!("{:?}", e))]);
+            return;
+        }
+    }
+    daemon::install_handlers();
+
+    // The listeners are polled, so that signals are noticed between
+    // connections rather than only when one arrives
+    let mut ok = true;
+    let unix_listener = match config.socket {
+        Some(ref socket) if Path::new(socket).exists() => {
+            log::error("listen_failed", &[("socket", socket), ("error", "already exists")]);
+            ok = false;
+            None
+        }
+        Some(ref socket) => match UnixListener::bind(socket).and_then(|l| l.set_nonblocking(true).map(|_| l)) {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                log::error("listen_failed", &[("socket", socket), ("error", &e.to_string())]);
+                ok = false;
+                None
+            }
+        },
+        None => None
+    };
+    let tcp_listener = match config.http {
+        Some(ref address) => match TcpListener::bind(&address[..]).and_then(|l| l.set_nonblocking(true).map(|_| l)) {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                log::error("listen_failed", &[("http", address), ("error", &e.to_string())]);
+                ok = false;
+                None
+            }
+        },
+        None => None
+    };
+
+    // Token files are read again on reload too, so tokens can be revoked
+    let read_tokens = |config: &daemon::Config| match config.tokens {
+        Some(ref filename) => server::Tokens::read(filename).map(|tokens| Some(Arc::new(tokens))),
+        None => Ok(None)
+    };
+    let mut tokens = match read_tokens(&config) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            log::error("tokens_invalid", &[("error", &format!("{:?}", e))]);
+            ok = false;
+            None
+        }
+    };
+
+    let socket = if unix_listener.is_some() { config.socket.clone() } else { None };
+    let http = config.http.clone();
+    if ok {
+        let pid = std::process::id().to_string();
+        log::info("started", &[("pid", &pid), ("socket", socket.as_ref().map_or("", |s| &s[..])),
+                                ("http", http.as_ref().map_or("", |s| &s[..]))]);
+    }
+    // Metrics are kept across reloads, but limits start afresh
+    let metrics = Arc::new(metrics::Metrics::new());
+    let mut limiter = Arc::new(ratelimit::Limiter::new(config.rate_limit, config.global_rate_limit));
+    let mut server = Arc::new(server::Server::with_metrics(Chain::from_network(config.network), config.script.clone(), metrics.clone()));
+    let connections = ratelimit::Connections::new(MAX_CONNECTIONS);
+    while ok && !daemon::stopping() {
+        if daemon::take_reload() {
+            let reloaded = daemon::read_config(&config_file).map_err(|e| format!("{:?}", e)).and_then(|new_config| {
+                read_tokens(&new_config).map(|new_tokens| (new_config, new_tokens)).map_err(|e| format!("{:?}", e))
+            });
+            match reloaded {
+                Ok((new_config, new_tokens)) => {
+                    if new_config.socket != config.socket || new_config.http != config.http {
+                        log::warn("restart_needed", &[("reason", "socket or http changed")]);
+                    }
+                    // Connections already open keep the server they started with
+                    server = Arc::new(server::Server::with_metrics(Chain::from_network(new_config.network), new_config.script.clone(),
+                                                                  metrics.clone()));
+                    limiter = Arc::new(ratelimit::Limiter::new(new_config.rate_limit, new_config.global_rate_limit));
+                    config = new_config;
+                    tokens = new_tokens;
+                    // The log file is reopened, so that it can be rotated
+                    if let Err(e) = log::init(&config.log_level, &config.log_format, config.log_file.as_ref().map(|f| &f[..])) {
+                        log::error("log_failed", &[("error", &format!("{:?}", e))]);
+                    }
+                    log::info("reloaded", &[("config", &config_file)]);
+                }
+                Err(e) => log::error("reload_failed", &[("config", &config_file), ("error", &e)])
+            }
+        }
+
+        let mut idle = true;
+        if let Some(ref listener) = unix_listener {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    idle = false;
+                    let _ = stream.set_nonblocking(false);
+                    let server = server.clone();
+                    let limiter = limiter.clone();
+                    thread::spawn(move || answer_socket(&server, stream, &limiter));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => log::warn("accept_failed", &[("error", &e.to_string())])
+            }
+        }
+        if let Some(ref listener) = tcp_listener {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    idle = false;
+                    let _ = stream.set_nonblocking(false);
+                    if let Some(connection) = open_http_connection(&connections, stream) {
+                        let server = server.clone();
+                        let allow_privkeys = config.allow_privkeys;
+                        let tokens = tokens.clone();
+                        let limiter = limiter.clone();
+                        thread::spawn(move || {
+                            let (stream, _connection) = connection;
+                            answer_http(&server, stream, allow_privkeys, tokens.as_ref().map(|t| &**t), &limiter)
+                        });
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => log::warn("accept_failed", &[("error", &e.to_string())])
+            }
+        }
+        if idle {
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    if ok {
+        log::info("stopping", &[]);
+    }
+    if let Some(ref socket) = socket {
+        let _ = std::fs::remove_file(socket);
+    }
+    if let Some(ref pidfile) = pidfile {
+        let _ = std::fs::remove_file(pidfile);
+    }
+    if ok {
+        log::info("stopped", &[]);
+    }
+}
+
+/// The daemon needs signals and unix sockets, so exists only on Unix
+#[cfg(all(not(unix), not(test)))]
+fn daemon_main(_: &str, _: &[String]) {
+    fail("usage", "", "The daemon is not supported on this platform; use serve --http or --jsonl.");
+}
+
+/// The `audit` subcommand, re-deriving recorded addresses from their
+/// contracts and untweaked keys or scripts
+#[cfg(not(test))]
+fn audit_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    add_chain_options(&mut opts);
+    add_plugin_options(&mut opts);
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} audit [-t|--chain name] RECORDS-FILE\n\n\
+                               Each line of the records file is a hex contract, the untweaked public key or\n\
+                               redeem script (as hex) it was applied to, the address recorded for them, and\n\
+                               an optional label. Lines starting with # are ignored.", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.free.len() != 1 {
+        fail("usage", "", "audit takes exactly one FILE.");
+        println!("{}", full_usage);
+        return;
+    }
+    let secp = context::secp();
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+    let plugins = match parse_plugins(&matches) {
+        Some(plugins) => plugins,
+        None => { return; }
+    };
+    let text = match read_text_file(&matches.free[0]) {
+        Some(text) => text,
+        None => { return; }
+    };
+
+    let (mut records, mut mismatches) = (0, 0);
+    for (n, line) in text.lines().enumerate().filter(|&(_, l)| !l.trim().is_empty() && !l.trim().starts_with('#')) {
+        let fields: Vec<&str> = line.splitn(4, char::is_whitespace).collect();
+        let what = match fields.get(3) {
+            Some(label) => format!("line {} ({})", n + 1, label.trim()),
+            None => format!("line {}", n + 1)
+        };
+        records += 1;
+        if fields.len() < 3 {
+            fail("bad_record", "", &format!("{}: not a contract, a key or script, and an address.", what));
+            mismatches += 1;
+            continue;
+        }
+        let contract = match Contract::from_hex_custom(fields[0], &plugins.tags()) {
+            Ok(contract) => contract,
+            Err(e) => {
+                fail("bad_contract", "", &format!("{}: contract could not be parsed: {:?}.", what, e));
+                mismatches += 1;
+                continue;
+            }
+        };
+        let base = match fields[1].from_hex() {
+            Ok(data) => data,
+            Err(_) => {
+                fail("bad_record", "", &format!("{}: key or script is not hex.", what));
+                mismatches += 1;
+                continue;
+            }
+        };
+        let recorded = match parse_address(chain, &format!("address on {}", what), fields[2]) {
+            Some(script_pubkey) => script_pubkey,
+            None => {
+                mismatches += 1;
+                continue;
+            }
+        };
+        let derived = match tweaked_outputs(&secp, chain, base, &contract) {
+            Ok(derived) => derived,
+            Err(e) => {
+                fail("tweak_failed", "", &format!("{}: unable to tweak: {}", what, e));
+                mismatches += 1;
+                continue;
+            }
+        };
+        if !derived.iter().any(|&(_, _, ref script_pubkey)| *script_pubkey == recorded) {
+            let expected: Vec<String> = derived.iter().map(|&(ty, ref address, _)| format!("{} {}", ty, address)).collect();
+            fail("mismatch", "", &format!("{}: MISMATCH: recorded {}, but the contract gives {}", what, fields[2], expected.join(", ")));
+            mismatches += 1;
+        }
+    }
+    println!("Audited {} records: {} mismatches.", records, mismatches);
+}
+
+/// The outputs a tweaked key or redeem script could be paid to, with their
+/// types and addresses: the P2PKH of a key, or any wrapping of a script
+#[cfg(not(test))]
+fn tweaked_outputs(secp: &Secp256k1, chain: Chain, base: Vec<u8>, contract: &Contract) -> Result<Vec<(&'static str, String, Script)>, String> {
+    let network = chain.network();
+    match PublicKey::from_slice(secp, &base) {
+        Ok(key) if base.len() == 33 || base.len() == 65 => {
+            tweak_keys(secp, &[key], &contract.to_bytes()[..]).map_err(|e| format!("{:?}", e)).map(|keys| {
+                let address = Address::from_key(network, &keys[0], base.len() == 33);
+                vec![("P2PKH", chain.encode_address(&address), address.script_pubkey())]
+            })
+        }
+        _ => {
+            template::tweak_script(secp, &Script::from(base), &contract.to_bytes()[..]).map_err(|e| format!("{:?}", e)).map(|(new_script, _, _)| {
+                let p2sh_p2wsh = segwit::p2sh_p2wsh_address(network, &new_script);
+                vec![("P2SH", script_address(chain, &new_script, sweep::SpendType::P2sh), Address::from_script(network, &new_script).script_pubkey()),
+                     ("P2WSH", chain.p2wsh_address(&new_script), segwit::p2wsh_script_pubkey(&new_script)),
+                     ("P2SH-P2WSH", chain.encode_address(&p2sh_p2wsh), p2sh_p2wsh.script_pubkey())]
+            })
+        }
+    }
+}
+
+/// Check one row of a `verify-batch` file, `script,contract,nonce,address`,
+/// giving its contract if it passed and why it failed if it did not
+#[cfg(not(test))]
+fn verify_row(secp: &Secp256k1, chain: Chain, tags: &[[u8; 4]], row: &str) -> Result<Contract, String> {
+    let fields: Vec<&str> = row.split(',').map(|f| f.trim()).collect();
+    if fields.len() != 4 {
+        return Err(format!("has {} fields, not 4", fields.len()));
+    }
+    let base = match fields[0].from_hex() {
+        Ok(data) => data,
+        Err(_) => match asm::assemble(fields[0]) {
+            Ok(script) => script[..].to_owned(),
+            Err(e) => { return Err(format!("script could not be parsed as hex or asm: {:?}", e)); }
+        }
+    };
+    // A full contract has its nonce in it; text and addresses are given one
+    let contract = if fields[2].is_empty() {
+        try!(Contract::from_hex_custom(fields[1], tags).map_err(|e| format!("contract could not be parsed: {:?}", e)))
+    } else {
+        let nonce = try!(Nonce::from_hex(fields[2]).map_err(|e| format!("nonce could not be parsed: {:?}", e)));
+        try!(Contract::from_p2sh_base58_str(fields[1], nonce, chain)
+                 .or_else(|_| Contract::from_ascii_str(fields[1], nonce))
+                 .map_err(|e| format!("contract is neither an address nor 20 characters of text: {:?}", e)))
+    };
+    let recorded = try!(decode_address(chain, fields[3]).map_err(|e| format!("address {}", e)));
+    let derived = try!(tweaked_outputs(secp, chain, base, &contract).map_err(|e| format!("unable to tweak: {}", e)));
+    if derived.iter().any(|&(_, _, ref script_pubkey)| *script_pubkey == recorded) {
+        Ok(contract)
+    } else {
+        let expected: Vec<String> = derived.iter().map(|&(ty, ref address, _)| format!("{} {}", ty, address)).collect();
+        Err(format!("MISMATCH: recorded {}, but the contract gives {}", fields[3], expected.join(", ")))
+    }
+}
+
+/// The `verify-batch` subcommand, checking a CSV of commitments on every core
+/// and printing only the rows which fail
+#[cfg(not(test))]
+fn verify_batch_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    add_chain_options(&mut opts);
+    add_plugin_options(&mut opts);
+    opts.optopt("", "threads", "Specify how many threads check rows (defaults to one per core). Output is the same, in the order of the rows, however many there are.", "n");
+    opts.optflag("", "strict", "Count rows repeating the contract, nonce or address of an earlier row as failures, rather than only summarising them.");
+    add_log_options(&mut opts);
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} verify-batch [-t|--chain name] [--threads n] [--strict] FILE.csv\n\n\
+                               Each row is script,contract,nonce,address: the untweaked redeem script (hex or\n\
+                               asm) or public key; the contract as hex, with an empty nonce, or as an address\n\
+                               or 20 characters of text, with its hex nonce; and the address recorded for them.\n\
+                               A first row starting with \"script,\" is taken as a header.", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.free.len() != 1 {
+        fail("usage", "", "verify-batch takes exactly one FILE.");
+        println!("{}", full_usage);
+        return;
+    }
+    if !init_log(&matches) {
+        return;
+    }
+    let secp = context::secp();
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+    let plugins = match parse_plugins(&matches) {
+        Some(plugins) => plugins,
+        None => { return; }
+    };
+    let threads = match matches.opt_str("threads") {
+        None => parallel::default_threads(),
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                fail("bad_number", "--threads", "option to --threads must be a positive number.");
+                return;
+            }
+        }
+    };
+    let file = match File::open(&matches.free[0]) {
+        Ok(file) => file,
+        Err(e) => {
+            fail("io", "", &format!("Unable to read {}: {}", matches.free[0], e));
+            return;
+        }
+    };
+
+    // Rows are read and checked a chunk at a time, so the file may be any size
+    let mut lines = io::BufReader::new(file).lines().enumerate();
+    let (mut rows, mut failures) = (0, 0);
+    let strict = matches.opt_present("strict");
+    let mut duplicates = dedup::Tracker::new();
+    log::info("batch_started", &[("file", &matches.free[0]), ("threads", &threads.to_string())]);
+    loop {
+        let mut chunk = Vec::with_capacity(JOB_CHUNK);
+        for (n, line) in lines.by_ref() {
+            match line {
+                Ok(ref line) if line.trim().is_empty() || (n == 0 && line.starts_with("script,")) => {}
+                Ok(line) => chunk.push((n + 1, line)),
+                Err(e) => {
+                    fail("io", "", &format!("Unable to read {}: {}", matches.free[0], e));
+                    return;
+                }
+            }
+            if chunk.len() == JOB_CHUNK {
+                break;
+            }
+        }
+        if chunk.is_empty() {
+            break;
+        }
+        let tags = plugins.tags();
+        let results = parallel::map(&chunk, threads, move |&(_, ref line)| verify_row(secp, chain, &tags, line));
+        for (&(n, ref line), result) in chunk.iter().zip(results) {
+            // Rows are only compared with earlier ones which passed
+            let result = result.and_then(|contract| {
+                let old = duplicates.duplicates().len();
+                duplicates.add_contract(&contract, n);
+                duplicates.add(dedup::Kind::Address, line.split(',').nth(3).unwrap().trim().to_owned(), n);
+                match duplicates.duplicates()[old..].first() {
+                    Some(duplicate) if strict => Err(format!("repeats the {} of row {}", duplicate.kind.name(), duplicate.first)),
+                    _ => Ok(())
+                }
+            });
+            if let Err(e) = result {
+                fail("verify_failed", "", &format!("row {}: {}", n, e));
+                log::warn("row_failed", &[("file", &matches.free[0]), ("row", &n.to_string()), ("error", &e)]);
+                failures += 1;
+            }
+        }
+        rows += chunk.len();
+        log::debug("chunk_done", &[("rows", &rows.to_string()), ("failures", &failures.to_string())]);
+    }
+    println!("Verified {} rows: {} failures.", rows, failures);
+    if !strict && !duplicates.duplicates().is_empty() {
+        println!("Found {} duplicates:", duplicates.duplicates().len());
+        for duplicate in duplicates.duplicates() {
+            println!("    {}", describe_duplicate(duplicate, "row"));
+        }
+    }
+    log::info("batch_done", &[("file", &matches.free[0]), ("rows", &rows.to_string()), ("failures", &failures.to_string())]);
+}
+
+/// The `rotate` subcommand, mapping the addresses of outstanding contracts
+/// under an old script to those under its replacement
+#[cfg(not(test))]
+fn rotate_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optopt("", "old", "Specify the old (untweaked) redeem script, as hex or asm.", "script");
+    opts.optopt("", "new", "Specify the new (untweaked) redeem script, as hex or asm.", "script");
+    opts.optopt("", "address-type", "How the contracts are paid: p2sh (the default), p2wsh or p2sh-p2wsh.", "type");
+    opts.optopt("", "watch-file", "Also write the new addresses with their labels as an addresses file for the watch subcommand.", "file");
+    opts.optopt("", "sign-with", "Sign the --watch-file with an operator key, minisign:SECRET-KEY-FILE or gpg:KEY-ID (or gpg, for gpg's default key).", "key");
+    add_chain_options(&mut opts);
+    add_plugin_options(&mut opts);
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} rotate [-t|--chain name] --old script --new script [--address-type type] [--watch-file file] CONTRACTS-FILE\n\n\
+                               Each line of the contracts file is a hex contract and an optional label.", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.free.len() != 1 || !matches.opt_present("old") || !matches.opt_present("new") {
+        fail("usage", "", "rotate takes one FILE and requires --old and --new.");
+        println!("{}", full_usage);
+        return;
+    }
+    let secp = context::secp();
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+    let plugins = match parse_plugins(&matches) {
+        Some(plugins) => plugins,
+        None => { return; }
+    };
+    let mut scripts = vec![];
+    for opt in &["old", "new"] {
+        match asm::Encoding::Hex.decode(&matches.opt_str(opt).unwrap()).or_else(|_| asm::assemble(&matches.opt_str(opt).unwrap())) {
+            Ok(script) => scripts.push(script),
+            Err(e) => {
+                fail("bad_script", &format!("--{}", opt), &format!("option to --{} could not be parsed as hex or asm: {:?}.", opt, e));
+                return;
+            }
+        }
+    }
+    let spend_type = match matches.opt_str("address-type") {
+        None => sweep::SpendType::P2sh,
+        Some(ref ty) if ty == "p2sh" => sweep::SpendType::P2sh,
+        Some(ref ty) if ty == "p2wsh" => sweep::SpendType::P2wsh,
+        Some(ref ty) if ty == "p2sh-p2wsh" => sweep::SpendType::P2shP2wsh,
+        Some(_) => {
+            fail("bad_value", "--address-type", "option to --address-type must be p2sh, p2wsh or p2sh-p2wsh.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    let signer = match parse_signer(&matches) {
+        Ok(signer) => signer,
+        Err(_) => { return; }
+    };
+    let contracts = match read_contracts_file(&matches.free[0], &plugins.tags()) {
+        Some(contracts) => contracts,
+        None => { return; }
+    };
+
+    let mut watch_lines = String::new();
+    for &(line, ref contract, ref label) in &contracts {
+        let mut addresses = vec![];
+        for (script, name) in scripts.iter().zip(["old", "new"].iter()) {
+            match template::tweak_script(&secp, script, &contract.to_bytes()[..]) {
+                Ok((new_script, _, _)) => addresses.push(script_address(chain, &new_script, spend_type)),
+                Err(e) => {
+                    fail("tweak_failed", "", &format!("Unable to tweak the {} script for {}: {:?}", name, label, e));
+                    return;
+                }
+            }
+        }
+        println!("{} from line {}: {} -> {}", label, line, addresses[0], addresses[1]);
+        watch_lines.push_str(&format!("{} {}\n", addresses[1], label));
+    }
+    if let Some(filename) = matches.opt_str("watch-file") {
+        match atomic::write(Path::new(&filename), watch_lines.as_bytes(), true, false) {
+            Ok(_) => {
+                println!("Wrote {} new addresses to {}.", contracts.len(), filename);
+                sign_file(signer.as_ref(), &filename);
+            }
+            Err(e) => fail("io", "--watch-file", &format!("Unable to write {}: {}", filename, e))
+        }
+    }
+}
+
+/// The `pegin-claim` subcommand, gathering what claiming a peg-in on the
+/// sidechain needs from the federation script, the contract and the
+/// mainchain transaction which paid the peg address
+#[cfg(not(test))]
+fn pegin_claim_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optopt("r", "redeem-script", "Specify the federation's (untweaked) peg script, as hex or asm.", "redemption script");
+    opts.optopt("f", "hex-contract", "Specify the contract of the peg-in as an hexadecimal string.", "hex");
+    opts.optopt("d", "p2sh-address", "Specify the contract of the peg-in as the sidechain address it pays.", "address");
+    opts.optopt("n", "nonce", "Specify the hex-encoded nonce of a contract given with -d.", "nonce");
+    add_chain_options(&mut opts);
+    add_plugin_options(&mut opts);
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} pegin-claim [-t|--chain name] -r script <-f contract|-d address -n nonce> FUNDING-TRANSACTION", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.free.len() != 1 || !matches.opt_present("r") {
+        fail("usage", "", "pegin-claim takes one FUNDING-TRANSACTION and requires -r.");
+        println!("{}", full_usage);
+        return;
+    }
+    let secp = context::secp();
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+    let plugins = match parse_plugins(&matches) {
+        Some(plugins) => plugins,
+        None => { return; }
+    };
+    // Liquid only pegs into Bitcoin itself
+    if !chain.is_bitcoin() {
+        fail("usage", "--chain", "pegin-claim may only be used on the bitcoin, testnet, signet and regtest chains.");
+        println!("{}", full_usage);
+        return;
+    }
+    let network = chain.network();
+
+    let script = match asm::Encoding::Hex.decode(&matches.opt_str("r").unwrap()).or_else(|_| asm::assemble(&matches.opt_str("r").unwrap())) {
+        Ok(script) => script,
+        Err(e) => {
+            fail("bad_script", "-r", &format!("option to -r could not be parsed as hex or asm: {:?}.", e));
+            return;
+        }
+    };
+    let nonce = match matches.opt_str("n").map(|hex| Nonce::from_hex(&hex)) {
+        Some(Ok(nonce)) => Some(nonce),
+        Some(Err(e)) => {
+            fail("bad_nonce", "-n", &format!("option to -n could not be parsed as a nonce: {:?}.", e));
+            return;
+        }
+        None => None
+    };
+    let contract = match (matches.opt_str("f"), nonce, matches.opt_str("d")) {
+        (Some(hex), None, None) => Contract::from_hex_custom(&hex, &plugins.tags()).map_err(|e| format!("-f could not be parsed as a contract: {:?}", e)),
+        (None, Some(nonce), Some(address)) => Contract::from_p2sh_base58_str(&address, nonce, chain).map_err(|e| format!("-d could not be parsed as an address: {:?}", e)),
+        _ => {
+            fail("usage", "", "Must specify exactly one of: -f; or -d -n");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    let contract = match contract {
+        Ok(contract) => contract,
+        Err(e) => {
+            fail("bad_contract", "", &format!("option to {}.", e));
+            return;
+        }
+    };
+    let claim_script = match contract.destination_script() {
+        Some(script) => script,
+        None => {
+            fail("bad_contract", "", "The contract is a text contract, which pays to nothing on the sidechain and cannot be claimed.");
+            return;
+        }
+    };
+    let (new_script, _, _) = match template::tweak_script(&secp, &script, &contract.to_bytes()[..]) {
+        Ok(tweaked) => tweaked,
+        Err(e) => {
+            fail("tweak_failed", "", &format!("Unable to tweak the peg script: {:?}", e));
+            return;
+        }
+    };
+    let (tx, txid, _) = match matches.free[0].from_hex().map_err(|_| sweep::Error::BadTransaction).and_then(|data| sweep::UnsignedTx::parse_prefix(&data)) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            fail("bad_transaction", "", &format!("FUNDING-TRANSACTION could not be parsed: {:?}.", e));
+            return;
+        }
+    };
+
+    // The peg may have been paid in any of the ways the script can be
+    let peg_outputs = [("P2SH", Address::from_script(network, &new_script).script_pubkey()),
+                       ("P2WSH", segwit::p2wsh_script_pubkey(&new_script)),
+                       ("P2SH-P2WSH", segwit::p2sh_p2wsh_address(network, &new_script).script_pubkey())];
+    let display_txid: Vec<u8> = txid[..].iter().rev().cloned().collect();
+    let display_txid = display_txid.to_hex();
+    let mut found = None;
+    for (vout, &(amount, ref script_pubkey)) in tx.outputs.iter().enumerate() {
+        if let Some(&(address_type, _)) = peg_outputs.iter().find(|&&(_, ref spk)| spk == script_pubkey) {
+            found = Some((vout, amount, address_type));
+            break;
+        }
+    }
+    let (vout, amount, address_type) = match found {
+        Some(found) => found,
+        None => {
+            fail("bad_transaction", "", &format!("Transaction {} does not pay to the peg script tweaked with this contract.", display_txid));
+            return;
+        }
+    };
+
+    println!("Peg-in output: {}:{} pays {} satoshis to the {} peg address", display_txid, vout, amount, address_type);
+    println!("Tweaked peg script: {}", new_script[..].to_hex());
+    println!("Claim script (sidechain destination): {}", claim_script[..].to_hex());
+    println!("Claim script (asm): {}", asm::disassemble(&claim_script));
+    println!("Once the transaction is confirmed, ask bitcoind for its proof with:");
+    println!("    bitcoin-cli gettxoutproof '[\"{}\"]'", display_txid);
+    println!("and claim it on the sidechain with:");
+    println!("    elements-cli claimpegin {} TXOUTPROOF {}", matches.free[0], claim_script[..].to_hex());
+
+    let mut claim = BTreeMap::new();
+    claim.insert("mainchain_txid".to_owned(), Json::String(display_txid));
+    claim.insert("vout".to_owned(), Json::U64(vout as u64));
+    claim.insert("amount".to_owned(), Json::U64(amount));
+    claim.insert("address_type".to_owned(), Json::String(address_type.to_owned()));
+    claim.insert("contract".to_owned(), Json::String(format!("{:x}", contract)));
+    claim.insert("nonce".to_owned(), Json::String(format!("{:x}", Nonce::from_contract(&contract))));
+    claim.insert("peg_script".to_owned(), Json::String(new_script[..].to_hex()));
+    claim.insert("claim_script".to_owned(), Json::String(claim_script[..].to_hex()));
+    println!("Claim data for the functionaries:\n{}", Json::Object(claim).pretty());
+}
+
+/// The `sighash` subcommand, printing what each input of a transaction
+/// spending a modified script signs, for cosigners signing elsewhere
+#[cfg(not(test))]
+fn sighash_main(prog: &str, args: &[String]) {
+    let mut opts = getopts::Options::new();
+    opts.optopt("r", "redeem-script", "Specify the modified redeem (or witness) script the inputs spend, as hex or asm.", "redemption script");
+    opts.optopt("", "amounts", "Specify the comma-separated amounts in satoshis of the outputs spent by each input, which BIP143 sighashes commit to.", "satoshis");
+    opts.optopt("", "signatures", "Specify a file of cosigners' signatures, one input-number signature-hex per line in the order the script checks them, to assemble into the signed transaction.", "file");
+    opts.optopt("", "address-type", "How the coins were paid, for --signatures: p2sh (the default), p2wsh or p2sh-p2wsh.", "type");
+    opts.optflag("h", "help", "Print this help message and exit.");
+    let short_usage = format!("{} sighash -r script [--amounts a,b,...] [--signatures file [--address-type type]] TRANSACTION", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.free.len() != 1 || !matches.opt_present("r") {
+        fail("usage", "", "sighash takes one TRANSACTION and requires -r.");
+        println!("{}", full_usage);
+        return;
+    }
+    let script = match asm::Encoding::Hex.decode(&matches.opt_str("r").unwrap()).or_else(|_| asm::assemble(&matches.opt_str("r").unwrap())) {
+        Ok(script) => script,
+        Err(e) => {
+            fail("bad_script", "-r", &format!("option to -r could not be parsed as hex or asm: {:?}.", e));
+            return;
+        }
+    };
+    let tx = match matches.free[0].from_hex().map_err(|_| sweep::Error::BadTransaction).and_then(|data| sweep::UnsignedTx::parse(&data)) {
+        Ok(tx) => tx,
+        Err(e) => {
+            fail("bad_transaction", "", &format!("TRANSACTION could not be parsed: {:?}.", e));
+            return;
+        }
+    };
+    let amounts = match matches.opt_str("amounts") {
+        Some(list) => {
+            match list.split(',').map(|a| a.parse::<u64>()).collect::<Result<Vec<u64>, _>>() {
+                Ok(ref amounts) if amounts.len() == tx.inputs.len() => Some(amounts.clone()),
+                Ok(amounts) => {
+                    fail("bad_value", "--amounts", &format!("--amounts gave {} amounts for {} inputs.", amounts.len(), tx.inputs.len()));
+                    return;
+                }
+                Err(e) => {
+                    fail("bad_number", "--amounts", &format!("option to --amounts could not be parsed as a list of numbers: {}.", e));
+                    return;
+                }
+            }
+        }
+        None => None
+    };
+
+    if let Some(filename) = matches.opt_str("signatures") {
+        let spend_type = match matches.opt_str("address-type") {
+            None => sweep::SpendType::P2sh,
+            Some(ref ty) if ty == "p2sh" => sweep::SpendType::P2sh,
+            Some(ref ty) if ty == "p2wsh" => sweep::SpendType::P2wsh,
+            Some(ref ty) if ty == "p2sh-p2wsh" => sweep::SpendType::P2shP2wsh,
+            Some(_) => {
+                fail("bad_value", "--address-type", "option to --address-type must be p2sh, p2wsh or p2sh-p2wsh.");
+                println!("{}", full_usage);
+                return;
+            }
+        };
+        let text = match read_text_file(&filename) {
+            Some(text) => text,
+            None => { return; }
+        };
+        let mut signatures = vec![vec![]; tx.inputs.len()];
+        for (n, line) in text.lines().enumerate().filter(|&(_, line)| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let input = if fields.len() == 2 { fields[0].parse::<usize>().ok() } else { None };
+            match (input, fields.last().and_then(|sig| sig.from_hex().ok())) {
+                (Some(input), Some(sig)) if input < tx.inputs.len() => signatures[input].push(sig),
+                _ => {
+                    fail("bad_value", "--signatures", &format!("{} line {}: expected an input number less than {} and a hex signature.", filename, n + 1, tx.inputs.len()));
+                    return;
+                }
+            }
+        }
+        match sweep::assemble(&tx, &script, spend_type, &signatures) {
+            Ok(signed) => println!("Signed transaction: {}", signed.to_hex()),
+            Err(e) => fail("sign_failed", "", &format!("Could not assemble transaction: {:?}.", e))
+        }
+        return;
+    }
+
+    println!("Script: {}", asm::disassemble(&script));
+    for (n, input) in tx.inputs.iter().enumerate() {
+        let txid: Vec<u8> = input.outpoint[..32].iter().rev().cloned().collect();
+        let vout = input.outpoint[32..].iter().rev().fold(0u32, |acc, &b| (acc << 8) | b as u32);
+        println!("Input {} spending {}:{}:", n, txid.to_hex(), vout);
+        println!("    Legacy (P2SH) sighash: {}", tx.legacy_sighash(n, &script)[..].to_hex());
+        match amounts {
+            Some(ref amounts) => println!("    BIP143 (P2WSH, P2SH-P2WSH) sighash: {}", tx.segwit_sighash(n, &script, amounts[n])[..].to_hex()),
+            None => println!("    BIP143 (P2WSH, P2SH-P2WSH) sighash: needs --amounts")
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn main() {
+    run();
+    if FAILED.load(Ordering::SeqCst) {
+        std::process::exit(1);
+    }
+}
+
+/// Run the command the arguments name
+/// The subcommands with options of their own, which take `--format` too
+#[cfg(not(test))]
+const SUBCOMMANDS: &'static [&'static str] = &[
+    "keystore", "registry", "process-request", "verify-message", "prove-reserves", "verify-reserves",
+    "sign", "verify-signature", "ots", "convert-key", "recover", "sweep", "sign-tx", "watch", "balance",
+    "serve", "daemon", "audit", "verify-batch", "rotate", "pegin-claim", "sighash", "import-response"
+];
+
+/// Take `--format` out of the arguments of a subcommand, which reports its
+/// failures as the main mode does, returning false if its value is bad
+#[cfg(not(test))]
+fn take_format(args: &mut Vec<String>) -> bool {
+    let mut n = 1;
+    while n < args.len() {
+        let format = if args[n] == "--format" && n + 1 < args.len() {
+            args.remove(n);
+            args.remove(n)
+        } else if args[n].starts_with("--format=") {
+            args.remove(n)["--format=".len()..].to_owned()
+        } else {
+            n += 1;
+            continue;
+        };
+        if format != "text" && format != "json" {
+            fail("bad_value", "--format", "option to --format must be text or json.");
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(not(test))]
+fn run() {
+    let prog = env::args().next().unwrap();
+    let mut args: Vec<_> = env::args().skip(1).collect();
+    // Before parsing, so that argument errors are reported as JSON too
+    if args.windows(2).any(|w| w[0] == "--format" && w[1] == "json") || args.iter().any(|a| a == "--format=json") {
+        JSON_ERRORS.store(true, Ordering::SeqCst);
+    }
+    if args.first().map(|s| SUBCOMMANDS.contains(&&s[..])) == Some(true) && !take_format(&mut args) {
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("keystore") {
+        keystore_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("registry") {
+        registry_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("process-request") {
+        process_request_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("verify-message") {
+        verify_message_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("prove-reserves") {
+        prove_reserves_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("verify-reserves") {
+        verify_reserves_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("sign") {
+        sign_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("verify-signature") {
+        verify_signature_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("ots") {
+        ots_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("convert-key") {
+        convert_key_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("recover") {
+        recover_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("sweep") {
+        sweep_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("sign-tx") {
+        sign_tx_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("watch") {
+        watch_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("balance") {
+        balance_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("serve") {
+        serve_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("daemon") {
+        daemon_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("audit") {
+        audit_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("verify-batch") {
+        verify_batch_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("rotate") {
+        rotate_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("pegin-claim") {
+        pegin_claim_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("sighash") {
+        sighash_main(&prog, &args[1..]);
+        return;
+    }
+    if args.first().map(|s| &s[..]) == Some("import-response") {
+        import_response_main(&prog, &args[1..]);
+        return;
+    }
+    // `create-request` takes the options of -g mode, but writes the script
+    // and contract to a request file for `process-request` instead
+    let create_request = args.first().map(|s| &s[..]) == Some("create-request");
+    if create_request {
+        args.remove(0);
+    }
+    // `sign-message` takes the options of -c mode and a message, which it
+    // signs with the tweaked key instead of printing the key
+    let sign_message = args.first().map(|s| &s[..]) == Some("sign-message");
+    if sign_message {
+        args.remove(0);
+    }
+    // Not every platform (e.g. WASI) has a system RNG, so its absence only
+    // matters once a nonce must be made
+    let mut rng = OsRng::new();
+
+    // Parse options
+    let mut opts = getopts::Options::new();
+    opts.optflag("c", "gen-address", "Generate a redemption script and corresponding address");
+    opts.optflag("g", "gen-privkey", "Generate a private key");
+    opts.optopt("r", "redeem-script", "Specify a redemption script for -g mode, as hex or asm.", "redemption script");
+    opts.optopt("k", "pubkeys", "Specify a comma-separated list of hex compressed public keys to make a multisig redemption script from for -g mode.", "keys");
+    opts.optopt("m", "threshold", "Specify the number of signatures required by the multisig given with -k.", "n");
+    opts.optopt("", "sorted", "Sort the keys given with -k per BIP67, either before tweaking (the template is sorted) or after (the modified script is sorted).", "before|after");
+    opts.optopt("", "emergency-keys", "Specify a comma-separated list of emergency public keys, which make the multisig of -k or -x (with -m) the normal branch of a Liquid watchman (fedpeg) script. Emergency keys are not tweaked.", "keys");
+    opts.optopt("", "emergency-threshold", "Specify the number of signatures required from the --emergency-keys (defaults to all of them).", "n");
+    opts.optopt("", "emergency-csv", "Specify the relative timelock in blocks after which the --emergency-keys can spend (defaults to 4032, as on Liquid).", "blocks");
+    opts.optopt("x", "xpub", "Specify a comma-separated list of extended public keys whose children make the keys for -g mode, as a single key or (with -m) a multisig. Keys may be prefixed with their origin, e.g. [d34db33f/45h]xpub..., which is extended by the derivation path for each key printed.", "xpubs");
+    opts.optopt("", "range", "Specify the child index, or inclusive range first-last of indices, to substitute for the * in the derivation path of the keys given with -x (defaults to 0).", "range");
+    opts.optopt("", "threads", "Specify how many threads derive and tweak the keys of a range or batch (defaults to one per core); 1 does everything in order on one thread. Output is the same, in the order of the input, however many there are.", "n");
+    opts.optflag("", "hwi-verify", "Check each key derived from -x against a connected hardware wallet using hwi, and have the wallet display the untweaked address for confirmation. Keys need an origin giving the wallet's master fingerprint.");
+    opts.optopt("", "contracts-file", "Specify a file of hex-encoded contracts, one per line, to tweak the keys at consecutive indices given with -x for, instead of a single contract.", "file");
+    opts.optopt("", "gap-limit", "Specify how many consecutive indices, from the one given with --range, to pair with contracts from --contracts-file.", "n");
+    opts.optopt("p", "private-key", "Specify a base58-encoded, raw 32-byte hex, Casascius mini or BIP38-encrypted private key, an extended private key, keychain:NAME for a key in the platform secret store, or keystore:NAME (keystore: for the default) for a key added with the keystore subcommand, or env:VAR to read one from an environment variable, for -c mode. With -p -, keys are read from stdin one per line and each tweaked key is printed on its own line. A key given on the command line itself needs --insecure-allow-cli-key.", "private key");
+    opts.optflag("", "insecure-allow-cli-key", "Accept a private key given to -p on the command line itself, despite its being left in shell history and visible in ps.");
+    opts.optopt("", "privkey-file", "Read the private key for -p from a file instead. Files ending in .age, .gpg or .asc are decrypted with age or gpg without writing the plaintext to disk.", "file");
+    opts.optopt("", "keystore", "Specify the keystore file which -p keystore:NAME reads (defaults to ~/.pacthash-keystore).", "file");
+    opts.optopt("", "mnemonic", "Specify a BIP39 mnemonic, in place of -p, whose master key to derive the private key from for -c mode.", "words");
+    opts.optopt("", "passphrase", "Specify the BIP39 passphrase of the mnemonic given with --mnemonic (defaults to empty), the BIP38 passphrase of the private key given with -p or output with --privkey-format bip38, or the SLIP-39 passphrase of --privkey-format slip39 (defaults to empty).", "passphrase");
+    opts.optopt("", "derivation-path", "Specify the path (e.g. m/45h/0/2, or m/45h/0/* with --index or --range) of the child keys to derive and tweak from extended keys given with -x, -p or --mnemonic. Defaults to m/* with -x and m otherwise.", "path");
+    opts.optopt("", "index", "Specify the child index to substitute for the * in the derivation path of an extended key given with -p or --mnemonic.", "index");
+    opts.optopt("", "privkey-format", "Format of the private key output by -c mode: wif (default), hex, bip38, or slip39 to split it into the SLIP-39 shares given by --shares, which the recover subcommand combines again.", "wif|hex|bip38|slip39");
+    opts.optopt("", "shares", "Specify the SLIP-39 shares of --privkey-format slip39 as THRESHOLD-of-COUNT, e.g. 2-of-3 for three shares, any two of which recover the key.", "m-of-n");
+    opts.optflag("", "yes", "Print the private key of -c mode to a terminal without asking first.");
+    opts.optopt("", "copy", "Put a result on the clipboard instead of printing it: the P2SH address or modified redeem script of -g mode with -r or -k, or the private key of -c mode, which is cleared from the clipboard again after 45 seconds.", "address|privkey|script");
+    opts.optflag("", "compressed", "Output the private key of -c mode as a compressed-key WIF, whatever the input was.");
+    opts.optflag("", "uncompressed", "Output the private key of -c mode as an uncompressed-key WIF, whatever the input was.");
+    opts.optopt("d", "p2sh-address", "Specify a contract as a P2SH address.", "P2SH address");
+    opts.optopt("a", "ascii-contract", "Specify a contract as an ASCII string.", "text");
+    opts.optopt("f", "hex-contract", "Specify a contract as an hexadecimal string.", "hex");
+    opts.optopt("n", "nonce", "Specify a hex-encoded nonce.", "nonce");
+    opts.optopt("", "custom", "Specify a contract of a type defined by a plugin, as its tag and the payload the plugin encodes, e.g. INVC=2015-0042.", "TAG=payload");
+    opts.optopt("", "plugins", "Specify the file declaring the plugins which define contract types for --custom and -f (defaults to ~/.pacthash-plugins, if it exists).", "file");
+    opts.optflag("h", "help", "Print this help message and exit.");
+    opts.optflag("V", "version", "Print the version, git commit, build date, secp256k1 backend and enabled features of this binary and exit.");
+    opts.optopt("", "format", "Format of failures: text (default), or json to also write each one to stderr as a JSON object giving its kind, the flag at fault and the message.", "text|json");
+    add_chain_options(&mut opts);
+    opts.optopt("", "script-encoding", "Encoding of redemption scripts given with -r and printed by -g: hex (default) or base64.", "hex|base64");
+    opts.optflag("", "strict", "Refuse to output a modified redeem script which fails sanity checks, to commit to a contract whose nonce is weak, or to output a --contracts-file batch which repeats a contract, nonce or address.");
+    opts.optopt("", "psbt", "Specify a file holding a PSBT (binary or base64) to attach the modified redeem and witness scripts of -g mode, and the tweak of each key, to the inputs and outputs which pay to them, printing the updated PSBT.", "file");
+    opts.optflag("", "importdescriptors", "Also print, for each address of -g mode, the descriptor of its output, as a JSON array ready to pass to Bitcoin Core's importdescriptors in a watch-only wallet.");
+    opts.optflag("", "importmulti", "Also print, for each address of -g mode, a JSON array ready to pass to importmulti in a wallet from before descriptors, with the redeem and witness scripts and keys; and the arguments of importaddress for its P2SH address.");
+    opts.optflag("", "import-to-core", "Import the addresses of -g mode into a watch-only wallet of a running bitcoind with importdescriptors, or importmulti for wallets from before descriptors.");
+    opts.optopt("", "electrum", "Ask an Electrum server, as host:port or tcp://host:port, for the balances and histories of the addresses of -g mode.", "server");
+    opts.optopt("", "electrum-wallet", "Also write an Electrum wallet file: watch-only, of the addresses of -g mode, or holding the tweaked key of -c mode.", "file");
+    opts.optopt("", "esplora-url", "Ask an Esplora server, by its http:// base URL, whether the addresses of -g mode have been used or funded.", "url");
+    opts.optflag("", "scan-balance", "Look up the coins held by the addresses of -g mode with scantxoutset on a running bitcoind, printing the balance for each contract.");
+    opts.optopt("", "rpc-url", "Specify the URL of bitcoind's RPC server for --import-to-core or --scan-balance, with /wallet/NAME to pick a wallet (defaults to http://127.0.0.1 on the usual port of the chain, e.g. 8332, or 18332 with -t).", "url");
+    opts.optopt("", "rpc-cookie", "Specify bitcoind's .cookie file, which authenticates --import-to-core or --scan-balance.", "file");
+    opts.optopt("", "bip329", "Also write a BIP329 label file of the addresses of -g mode, naming the contract, nonce and type of each.", "file");
+    opts.optopt("", "ots", "Write the serialized contracts of -g mode to a file, one per line in hex, and timestamp it with the OpenTimestamps client ots, which writes the proof to FILE.ots. The proof is completed and checked later with the ots subcommand.", "file");
+    opts.optopt("", "sign-with", "Sign the files written by --electrum-wallet and --bip329 with an operator key, minisign:SECRET-KEY-FILE or gpg:KEY-ID (or gpg, for gpg's default key), writing the signature beside each as FILE.minisig or FILE.asc.", "key");
+    opts.optopt("", "label", "Specify the label of the addresses imported with --importdescriptors, --importmulti or --import-to-core, or written with --bip329 (defaults to pacthash).", "label");
+    opts.optopt("", "timestamp", "Specify the Unix time from which Core should rescan for the addresses imported with --importdescriptors, --importmulti or --import-to-core (defaults to now).", "time");
+    opts.optopt("", "fund", "Also build an unsigned transaction paying this many satoshis to the modified redeem script of -g mode, printed as a PSBT for the payer's wallet to fund and sign. Without --funding-utxos it has no inputs.", "satoshis");
+    opts.optopt("", "fund-type", "How --fund pays the modified redeem script: p2sh (default), p2wsh or p2sh-p2wsh.", "type");
+    opts.optflag("", "op-return", "Also build an unsigned transaction committing to the contract of -g mode explicitly, in an OP_RETURN output holding the SHA256 of the serialized contract, printed as a PSBT for a wallet to fund and sign. Without -r or -k nothing is tweaked and only the transaction is output.");
+    opts.optopt("", "funding-utxos", "Specify a file of the outputs the --fund or --op-return transaction spends, one txid:vout amount per line, with amounts in satoshis. Without it the transaction has no inputs.", "file");
+    opts.optopt("", "fee", "Specify the fee in satoshis of the --fund or --op-return transaction with --funding-utxos.", "satoshis");
+    opts.optopt("", "change", "Specify the address the --fund or --op-return transaction sends change to with --funding-utxos.", "address");
+    opts.optopt("", "master-blinding-key", "Specify a hex SLIP-0077 master blinding key (as Elements' dumpmasterblindingkey prints) to derive the blinding key of the confidential address from, in place of --blinding-key. The derived blinding private key is printed too.", "key");
+    opts.optopt("", "blinding-key", "Specify a hex blinding public key to also output the modified redeem script of -g mode as an Elements confidential P2SH address with (Liquid, Liquid testnet with -t, or elementsregtest with --chain regtest).", "key");
+
+    let short_usage = format!("{0} [-t|--chain name] <-c|-g> <-f contract|-d p2sh -n nonce|-a ascii -n nonce>\n\
+                               {0} create-request [-g options] > REQUEST-FILE\n\
+                               {0} process-request REQUEST-FILE > RESPONSE-FILE\n\
+                               {0} import-response REQUEST-FILE RESPONSE-FILE\n\
+                               {0} sign-message [-c options] MESSAGE\n\
+                               {0} verify-message [options] ADDRESS SIGNATURE MESSAGE\n\
+                               {0} prove-reserves [-t|--chain name] KEYS-FILE MESSAGE > PROOF-FILE\n\
+                               {0} verify-reserves PROOF-FILE\n\
+                               {0} sign --sign-with key FILE...\n\
+                               {0} verify-signature --key key FILE [SIGNATURE]\n\
+                               {0} ots <upgrade|verify> PROOF-FILE\n\
+                               {0} sweep [-t|--chain name] -p key [-r script] --utxos file --to address --fee satoshis\n\
+                               {0} sign-tx [-t|--chain name] -p key <contract> [-r script] [--input n] TRANSACTION\n\
+                               {0} sighash -r script [--amounts a,b,...] [--signatures file [--address-type type]] TRANSACTION\n\
+                               {0} watch [-t|--chain name] --zmq endpoint [--json] [--webhook url] ADDRESSES-FILE\n\
+                               {0} balance [-t|--chain name] <--electrum server|--esplora-url url> ADDRESSES-FILE\n\
+                               {0} pegin-claim [-t|--chain name] -r script <-f contract|-d address -n nonce> FUNDING-TRANSACTION\n\
+                               {0} rotate [-t|--chain name] --old script --new script [--address-type type] CONTRACTS-FILE\n\
+                               {0} audit [-t|--chain name] RECORDS-FILE\n\
+                               {0} verify-batch [-t|--chain name] [--threads n] [--strict] FILE.csv\n\
+                               {0} serve [-t|--chain name] <--socket path|--http address [--allow-privkeys] [--tokens file]|--jsonl> [-r script] [--rate-limit RATE[/BURST]]\n\
+                               {0} daemon --config file [--pidfile file]\n\
+                               {0} convert-key [-t|--chain name] [--compressed|--uncompressed] <-p key|--privkey-file file>\n\
+                               {0} recover [-t|--chain name] [--uncompressed] [--passphrase passphrase] < SHARES\n\
+                               {0} keystore <init|add NAME|list|use NAME>\n\
+                               {0} registry fsck [-n] [--keystore file] [--electrum-wallet file] [--bip329 file] [--watch-file file]\n\
+                               {0} --version", prog);
+    let full_usage = opts.usage(&short_usage);
+
+    let matches = match opts.parse(&args[..]) {
+        Ok(m) => m,
+        Err(e) => {
+            fail("usage", "", &format!("Argument error: {}", e));
+            println!("{}", full_usage);
+            return;
+        }
+    };
+
+    if matches.opt_present("h") {
+        println!("{}", full_usage);
+        return;
+    }
+    if matches.opt_present("V") {
+        print_version();
+        return;
+    }
+
+    // ** Validate command-line options **
+    match matches.opt_str("format") {
+        None => {}
+        Some(ref format) if format == "text" || format == "json" => {}
+        Some(_) => {
+            fail("bad_value", "--format", "option to --format must be text or json.");
+            println!("{}", full_usage);
+            return;
+        }
+    }
+    let chain = match parse_chain(&matches) {
+        Some(chain) => chain,
+        None => { return; }
+    };
+    let network = chain.network();
+    let strict = matches.opt_present("strict");
+    let secp = context::secp();
+
+    // Script encoding
+    let script_encoding = match matches.opt_str("script-encoding") {
+        Some(name) => {
+            match asm::Encoding::from_name(&name) {
+                Some(encoding) => encoding,
+                None => {
+                    fail("bad_value", "--script-encoding", "option to --script-encoding must be hex or base64.");
+                    println!("{}", full_usage);
+                    return;
+                }
+            }
+        }
+        None => asm::Encoding::Hex
+    };
+
+    // Mode
+    let mode = match (matches.opt_present("c"), matches.opt_present("g")) {
+        _ if create_request => {
+            if matches.opt_present("c") || matches.opt_present("x") || matches.opt_str("sorted").map_or(false, |s| s == "after") {
+                fail("usage", "", "create-request takes the options of -g mode, except -x and --sorted after.");
+                println!("{}", full_usage);
+                return;
+            }
+            Mode::GenAddress
+        }
+        _ if sign_message => {
+            if matches.opt_present("g") || matches.opt_present("privkey-format") || matches.free.len() != 1 {
+                fail("usage", "", "sign-message takes the options of -c mode, except --privkey-format, and one message.");
+                println!("{}", full_usage);
+                return;
+            }
+            Mode::GenPrivkey
+        }
+        (false, false) => {
+            fail("usage", "", "One of -g or -c must be specified.");
+            println!("{}", full_usage);
+            return;
+        }
+        (true, true) => {
+            fail("usage", "", "At most one of -g or -c may be specified.");
+            println!("{}", full_usage);
+            return;
+        }
+        (true, false) => Mode::GenPrivkey,
+        (false, true) => Mode::GenAddress,
+    };
+    // Private keys are about to be read, and should not end up in a core dump
+    if mode == Mode::GenPrivkey {
+        memlock::disable_core_dumps();
+    }
+
+    // Liquid, hardware wallets, Electrum key wallets, signed messages and
+    // request files only know the chains of Bitcoin itself
+    if !chain.is_bitcoin() {
+        let mut bitcoin_only: Vec<String> = ["emergency-keys", "hwi-verify", "blinding-key", "master-blinding-key"].iter()
+                                                .filter(|opt| matches.opt_present(opt))
+                                                .map(|opt| format!("--{}", opt))
+                                                .collect();
+        if mode == Mode::GenPrivkey && matches.opt_present("electrum-wallet") {
+            bitcoin_only.push("--electrum-wallet".to_owned());
+        }
+        if create_request {
+            bitcoin_only.push("create-request".to_owned());
+        }
+        if sign_message {
+            bitcoin_only.push("sign-message".to_owned());
+        }
+        if let Some(what) = bitcoin_only.first() {
+            fail("usage", "--chain", &format!("{} may only be used on the bitcoin, testnet, signet and regtest chains.", what));
+            println!("{}", full_usage);
+            return;
+        }
+    }
+
+    // Multisig threshold and key sorting (only allowed with -k or -x)
+    let key_list = matches.opt_present("k") || matches.opt_present("x");
+    let threshold = match (key_list, matches.opt_str("m")) {
+        (true, Some(m)) => {
+            match m.parse::<usize>() {
+                Ok(m) => Some(m),
+                Err(e) => {
+                    fail("bad_number", "-m", &format!("option to -m could not be parsed as a number: {}.", e));
+                    return;
+                }
+            }
+        }
+        (_, None) => None,
+        (false, Some(_)) => {
+            fail("usage", "-m", "-m may only be used with -k or -x.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    if matches.opt_present("k") && threshold.is_none() {
+        fail("usage", "-m", "-m must be specified with -k.");
+        println!("{}", full_usage);
+        return;
+    }
+    let sorting = match (key_list, matches.opt_str("sorted")) {
+        (_, None) => Sorting::Unsorted,
+        (true, Some(ref when)) if when == "before" => Sorting::BeforeTweak,
+        (true, Some(ref when)) if when == "after" => Sorting::AfterTweak,
+        (true, Some(_)) => {
+            fail("bad_value", "--sorted", "option to --sorted must be before or after.");
+            println!("{}", full_usage);
+            return;
+        }
+        (false, Some(_)) => {
+            fail("usage", "--sorted", "--sorted may only be used with -k or -x.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+
+    // Emergency keys turn the multisig into a watchman script
+    let emergency = match matches.opt_str("emergency-keys") {
+        Some(_) if threshold.is_none() => {
+            fail("usage", "--emergency-keys", "--emergency-keys may only be used with -k or -x and -m.");
+            println!("{}", full_usage);
+            return;
+        }
+        Some(list) => {
+            let mut keys = Vec::new();
+            for hex in list.split(',') {
+                match hex.from_hex().ok().and_then(|data| PublicKey::from_slice(&secp, &data).ok()) {
+                    Some(key) => keys.push(key),
+                    None => {
+                        fail("bad_pubkey", "--emergency-keys", &format!("option to --emergency-keys could not be parsed as a list of public keys: bad key {}.", hex));
+                        return;
+                    }
+                }
+            }
+            let emergency_threshold = match matches.opt_str("emergency-threshold").map(|m| m.parse::<usize>()) {
+                Some(Ok(m)) => m,
+                Some(Err(e)) => {
+                    fail("bad_number", "--emergency-threshold", &format!("option to --emergency-threshold could not be parsed as a number: {}.", e));
+                    return;
+                }
+                None => keys.len()
+            };
+            if emergency_threshold == 0 || emergency_threshold > keys.len() || keys.len() > MAX_PUBKEYS_PER_MULTISIG {
+                fail("too_many_keys", "--emergency-keys", &format!("Cannot make a {}-of-{} emergency multisig (at most {} keys are allowed).",
+                                                                     emergency_threshold, keys.len(), MAX_PUBKEYS_PER_MULTISIG));
+                return;
+            }
+            let csv = match matches.opt_str("emergency-csv").map(|n| n.parse::<u16>()) {
+                Some(Ok(n)) if n > 0 => n as i64,
+                Some(_) => {
+                    fail("bad_value", "--emergency-csv", "option to --emergency-csv must be a number of blocks from 1 to 65535.");
+                    return;
+                }
+                None => 4032
+            };
+            Some((emergency_threshold, keys, csv))
+        }
+        None if matches.opt_present("emergency-threshold") || matches.opt_present("emergency-csv") => {
+            fail("usage", "--emergency-threshold", "--emergency-threshold and --emergency-csv may only be used with --emergency-keys.");
+            println!("{}", full_usage);
+            return;
+        }
+        None => None
+    };
+    if emergency.is_some() && matches.opt_present("k") && sorting == Sorting::AfterTweak {
+        fail("usage", "--sorted", "--sorted after cannot be used with -k and --emergency-keys, since only the normal keys are tweaked.");
+        println!("{}", full_usage);
+        return;
+    }
+    // The multisig of a threshold and keys, as the normal branch of a
+    // watchman script if there are emergency keys
+    let make_multisig = |m: usize, keys: &[PublicKey]| -> Script {
+        match emergency {
+            Some((em, ref emergency_keys, csv)) => template::liquid_fedpeg(&secp, m, keys, em, emergency_keys, csv),
+            None => template::multisig(&secp, m, keys)
+        }
+    };
+
+    // At most one source of keys for -g
+    if ["r", "k", "x"].iter().filter(|opt| matches.opt_present(opt)).count() > 1 {
+        fail("usage", "-r", "At most one of -r, -k or -x may be specified.");
+        println!("{}", full_usage);
+        return;
+    }
+
+    // Extended public keys (only allowed in -g mode)
+    let xpubs = match (mode, matches.opt_str("x")) {
+        (_, None) => None,
+        (Mode::GenAddress, Some(x)) => {
+            let mut xpubs = vec![];
+            for s in x.split(',') {
+                // Keys may be prefixed with their origin, as in descriptors
+                let (source, s) = match (s.starts_with('['), s.find(']')) {
+                    (true, Some(end)) => {
+                        match KeySource::parse(&s[1..end]) {
+                            Ok(source) => (Some(source), &s[end + 1..]),
+                            Err(_) => {
+                                fail("bad_xpub", "-x", &format!("option to -x has a bad key origin {}.", &s[..end + 1]));
+                                return;
+                            }
+                        }
+                    }
+                    _ => (None, s)
+                };
+                let decode: Result<ExtendedPubKey, _> = FromBase58::from_base58check(s);
+                match decode {
+                    Ok(xpub) => {
+                        if xpub.network != network {
+                            fail("wrong_network", "-x", "Extended public key network did not match tool mode (did you forget -t?).");
+                            return;
+                        }
+                        let source = source.unwrap_or(xpub.own_source(&secp));
+                        xpubs.push((xpub, source));
+                    }
+                    Err(e) => {
+                        fail("bad_xpub", "-x", &format!("option to -x could not be parsed as a list of extended public keys: {:?}.", e));
+                        return;
+                    }
+                }
+            }
+            if xpubs.len() > 1 && threshold.is_none() {
+                fail("usage", "-m", "-m must be specified with more than one extended public key.");
+                println!("{}", full_usage);
+                return;
+            }
+            if let Some(m) = threshold {
+                if m == 0 || m > xpubs.len() || xpubs.len() > MAX_PUBKEYS_PER_MULTISIG {
+                    fail("too_many_keys", "-m", &format!("Cannot make a {}-of-{} multisig (at most {} keys are allowed).", m, xpubs.len(), MAX_PUBKEYS_PER_MULTISIG));
+                    return;
+                }
+            }
+            Some(xpubs)
+        }
+        (Mode::GenPrivkey, Some(_)) => {
+            fail("usage", "-x", "-x may only be used in -g mode.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+
+    let hwi_verify = matches.opt_present("hwi-verify");
+    if hwi_verify && xpubs.is_none() {
+        fail("usage", "--hwi-verify", "--hwi-verify may only be used with -x.");
+        println!("{}", full_usage);
+        return;
+    }
+
+    // Derivation path (only allowed with extended keys)
+    let derivation_path = match matches.opt_str("derivation-path") {
+        Some(s) => {
+            match DerivationPath::parse(&s) {
+                Ok(path) => Some(path),
+                Err(_) => {
+                    fail("bad_value", "--derivation-path", "option to --derivation-path must be a path like m/45h/0/2 or m/45h/0/*.");
+                    return;
+                }
+            }
+        }
+        None => None
+    };
+    let xpub_path = match (&xpubs, derivation_path.clone()) {
+        (&Some(_), Some(path)) => {
+            if path.is_hardened() {
+                fail("bad_path", "--derivation-path", "Cannot derive hardened children of extended public keys.");
+                return;
+            }
+            path
+        }
+        (&Some(_), None) => DerivationPath { children: vec![], wildcard: Wildcard::Normal },
+        (&None, path) => {
+            if mode == Mode::GenAddress && (path.is_some() || matches.opt_present("index")) {
+                fail("usage", "--derivation-path", "--derivation-path and --index may only be used with -x, -p or --mnemonic.");
+                println!("{}", full_usage);
+                return;
+            }
+            DerivationPath { children: vec![], wildcard: Wildcard::None }
+        }
+    };
+    if xpubs.is_some() && matches.opt_present("index") {
+        fail("usage", "--index", "Use --range rather than --index with -x.");
+        return;
+    }
+
+    // Threads for work which is done in parallel
+    let threads = match matches.opt_str("threads") {
+        None => parallel::default_threads(),
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                fail("bad_value", "--threads", "option to --threads must be a positive number.");
+                return;
+            }
+        }
+    };
+
+    // Range of child indices to derive (only allowed with -x and a wildcard path)
+    let range = match (xpub_path.wildcard != Wildcard::None, matches.opt_str("range")) {
+        (_, None) => (0, 0),
+        (true, Some(r)) => {
+            let (first, last) = match r.find('-') {
+                Some(dash) => (r[..dash].parse::<u32>(), r[dash + 1..].parse::<u32>()),
+                None => (r.parse::<u32>(), r.parse::<u32>())
+            };
+            match (first, last) {
+                (Ok(first), Ok(last)) if first <= last && last < HARDENED => (first, last),
+                _ => {
+                    fail("bad_value", "--range", "option to --range must be an index or a range first-last of non-hardened indices.");
+                    return;
+                }
+            }
+        }
+        (false, Some(_)) => {
+            fail("usage", "--range", "--range may only be used with -x and a derivation path ending in *.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+
+    // Batch of contracts, one per line of a file, taking consecutive indices
+    // (only allowed with -x and a wildcard path). It is read a chunk at a
+    // time, once to check it and again to use it, so it may be any size
+    let batch = match (matches.opt_str("contracts-file"), matches.opt_str("gap-limit")) {
+        (None, None) => None,
+        (Some(filename), Some(n)) => {
+            if xpub_path.wildcard == Wildcard::None || range.0 != range.1 {
+                fail("usage", "--contracts-file", "--contracts-file may only be used with -x, a derivation path ending in * and a single starting index.");
+                println!("{}", full_usage);
+                return;
+            }
+            let n = match n.parse::<u32>() {
+                Ok(n) if n > 0 && n <= HARDENED - range.0 => n,
+                _ => {
+                    fail("bad_value", "--gap-limit", "option to --gap-limit must be a positive number of non-hardened indices.");
+                    return;
+                }
+            };
+            match File::open(&filename) {
+                Ok(file) => Some((filename, file, n as usize)),
+                Err(e) => {
+                    fail("io", "--contracts-file", &format!("Unable to read {}: {}", filename, e));
+                    return;
+                }
+            }
+        }
+        _ => {
+            fail("usage", "--contracts-file", "--contracts-file and --gap-limit must be used together.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+
+    // PSBT to update (only allowed for -g)
+    let mut psbt = match matches.opt_str("psbt") {
+        Some(_) if mode != Mode::GenAddress || create_request => {
+            fail("usage", "--psbt", "--psbt may only be used in -g mode.");
+            println!("{}", full_usage);
+            return;
+        }
+        Some(filename) => {
+            let mut data = vec![];
+            if let Err(e) = File::open(&filename).and_then(|mut f| f.read_to_end(&mut data)) {
+                fail("io", "--psbt", &format!("Unable to read {}: {}", filename, e));
+                return;
+            }
+            match psbt::Psbt::decode(&data) {
+                Ok(psbt) => Some(psbt),
+                Err(e) => {
+                    fail("bad_psbt", "--psbt", &format!("{} could not be parsed as a PSBT: {:?}", filename, e));
+                    return;
+                }
+            }
+        }
+        None => None
+    };
+    let mut psbt_updates = (0, 0);
+
+    // Blinding key (only allowed for -g with a script)
+    let blinding_key = matches.opt_str("blinding-key");
+    let master_blinding_key = matches.opt_str("master-blinding-key");
+    if (blinding_key.is_some() || master_blinding_key.is_some()) && (mode != Mode::GenAddress || xpubs.is_some()) {
+        fail("usage", "--blinding-key", "--blinding-key and --master-blinding-key may only be used in -g mode with -r or -k.");
+        println!("{}", full_usage);
+        return;
+    }
+    if blinding_key.is_some() && master_blinding_key.is_some() {
+        fail("usage", "--blinding-key", "At most one of --blinding-key or --master-blinding-key may be specified.");
+        println!("{}", full_usage);
+        return;
+    }
+    let master_blinding_key = match master_blinding_key.map(|hex| hex.from_hex()) {
+        Some(Ok(ref data)) if data.len() == 32 => Some(data.clone()),
+        Some(_) => {
+            fail("bad_value", "--master-blinding-key", "option to --master-blinding-key could not be parsed as a 32-byte hex key.");
+            return;
+        }
+        None => None
+    };
+    let blinding_key = match blinding_key {
+        Some(hex) => {
+            match hex.from_hex().ok().and_then(|data| PublicKey::from_slice(&secp, &data).ok()) {
+                Some(key) => Some(key),
+                None => {
+                    fail("bad_pubkey", "--blinding-key", "option to --blinding-key could not be parsed as a public key.");
+                    return;
+                }
+            }
+        }
+        None => None
+    };
+
+    // Watch-only import requests, printed or sent to a node (only allowed for -g)
+    let import_descriptors = matches.opt_present("importdescriptors");
+    let import_multi = matches.opt_present("importmulti");
+    if (import_descriptors || import_multi) && (mode != Mode::GenAddress || create_request) {
+        fail("usage", "--importdescriptors", "--importdescriptors and --importmulti may only be used in -g mode.");
+        println!("{}", full_usage);
+        return;
+    }
+    let electrum_server = matches.opt_str("electrum");
+    let esplora_url = matches.opt_str("esplora-url");
+    if (electrum_server.is_some() || esplora_url.is_some()) && (mode != Mode::GenAddress || create_request) {
+        fail("usage", "--electrum", "--electrum and --esplora-url may only be used in -g mode.");
+        println!("{}", full_usage);
+        return;
+    }
+    let electrum_wallet = matches.opt_str("electrum-wallet");
+    if electrum_wallet.is_some() && (create_request || sign_message) {
+        fail("usage", "--electrum-wallet", "--electrum-wallet may not be used with create-request or sign-message.");
+        println!("{}", full_usage);
+        return;
+    }
+    let import_to_core_flag = matches.opt_present("import-to-core");
+    let scan_balance = matches.opt_present("scan-balance");
+    let core = match (import_to_core_flag || scan_balance, matches.opt_str("rpc-cookie")) {
+        (false, None) if !matches.opt_present("rpc-url") => None,
+        (true, Some(_)) if mode != Mode::GenAddress || create_request => {
+            fail("usage", "--import-to-core", "--import-to-core and --scan-balance may only be used in -g mode.");
+            println!("{}", full_usage);
+            return;
+        }
+        (true, Some(cookie)) => {
+            let default_port = chain.rpc_port();
+            let url = matches.opt_str("rpc-url").unwrap_or(format!("http://127.0.0.1:{}", default_port));
+            match rpc::Client::new(&url, &cookie, default_port) {
+                Ok(client) => Some(client),
+                Err(e) => {
+                    fail("rpc_failed", "", &format!("Unable to configure RPC: {:?}", e));
+                    return;
+                }
+            }
+        }
+        _ => {
+            fail("usage", "--import-to-core", "--import-to-core and --scan-balance need --rpc-cookie, and --rpc-url and --rpc-cookie need one of them.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+    let bip329_file = matches.opt_str("bip329");
+    if bip329_file.is_some() && (mode != Mode::GenAddress || create_request) {
+        fail("usage", "--bip329", "--bip329 may only be used in -g mode.");
+        println!("{}", full_usage);
+        return;
+    }
+    let ots_file = matches.opt_str("ots");
+    if ots_file.is_some() && (mode != Mode::GenAddress || create_request) {
+        fail("usage", "--ots", "--ots may only be used in -g mode.");
+        println!("{}", full_usage);
+        return;
+    }
+    let signer = match matches.opt_str("sign-with").map(|s| signing::Key::parse(&s)) {
+        None => None,
+        Some(_) if electrum_wallet.is_none() && bip329_file.is_none() => {
+            fail("usage", "--sign-with", "--sign-with may only be used with --electrum-wallet or --bip329.");
+            println!("{}", full_usage);
+            return;
+        }
+        Some(Ok(key)) => Some(key),
+        Some(Err(_)) => {
+            fail("bad_value", "--sign-with", "option to --sign-with must be minisign:SECRET-KEY-FILE, gpg:KEY-ID or gpg.");
+            return;
+        }
+    };
+    if !import_descriptors && !import_multi && !import_to_core_flag && (matches.opt_present("timestamp") ||
+                                                                        (bip329_file.is_none() && matches.opt_present("label"))) {
+        fail("usage", "--label", "--label may only be used with --importdescriptors, --importmulti, --import-to-core or --bip329, and --timestamp with the first three.");
+        println!("{}", full_usage);
+        return;
+    }
+    let descriptor_label = matches.opt_str("label").unwrap_or("pacthash".to_owned());
+    let timestamp = match matches.opt_str("timestamp").map(|time| time.parse::<u64>()) {
+        Some(Ok(time)) => Some(time),
+        Some(Err(e)) => {
+            fail("bad_number", "--timestamp", &format!("option to --timestamp could not be parsed as a number: {}.", e));
+            return;
+        }
+        None => None
+    };
+    let mut import_requests = vec![];
+    let mut importmulti_requests = vec![];
+    let mut scan_descriptors = vec![];
+    let mut wallet_addresses = vec![];
+
+    // What to put on the clipboard instead of printing
+    let copy = matches.opt_str("copy");
+    match copy.as_ref().map(|c| &c[..]) {
+        None => {}
+        Some("address") | Some("script") if mode == Mode::GenAddress && xpubs.is_none() && !create_request => {}
+        Some("privkey") if mode == Mode::GenPrivkey && !sign_message && matches.opt_str("p").map_or(true, |p| p != "-") &&
+                           matches.opt_str("privkey-format").map_or(true, |f| f != "slip39") => {}
+        Some("address") | Some("script") | Some("privkey") => {
+            fail("usage", "--copy", "--copy address and --copy script may only be used in -g mode with -r or -k, and --copy privkey in -c mode with one key, not split into shares.");
+            println!("{}", full_usage);
+            return;
+        }
+        Some(other) => {
+            fail("bad_value", "--copy", &format!("option to --copy must be address, privkey or script, not {}.", other));
+            return;
+        }
+    }
+    // Report the outcome of putting something on the clipboard
+    let copy_to_clipboard = |what: &str, text: &str| -> bool {
+        match clipboard::copy(text) {
+            Ok(()) => {
+                println!("{}: (copied to the clipboard)", what);
+                true
+            }
+            Err(e) => {
+                println!("Unable to copy the {} to the clipboard: {:?}", what.to_lowercase(), e);
+                false
+            }
+        }
+    };
+
+    // Funding transaction (only allowed for -g with a script), and OP_RETURN
+    // commitment transaction (only for -g without -x); both take their
+    // inputs from --funding-utxos
+    let op_return = matches.opt_present("op-return");
+    if op_return && (mode != Mode::GenAddress || xpubs.is_some() || create_request || matches.opt_present("fund")) {
+        fail("usage", "--op-return", "--op-return may only be used in -g mode, without -x or --fund.");
+        println!("{}", full_usage);
+        return;
+    }
+    let funding = match matches.opt_str("fund") {
+        Some(_) if mode != Mode::GenAddress || xpubs.is_some() || create_request => {
+            fail("usage", "--fund", "--fund may only be used in -g mode with -r or -k.");
+            println!("{}", full_usage);
+            return;
+        }
+        Some(amount) => {
+            let amount = match amount.parse::<u64>() {
+                Ok(amount) => amount,
+                Err(e) => {
+                    fail("bad_number", "--fund", &format!("option to --fund could not be parsed as a number: {}.", e));
+                    return;
+                }
+            };
+            let spend_type = match matches.opt_str("fund-type") {
+                None => sweep::SpendType::P2sh,
+                Some(ref ty) if ty == "p2sh" => sweep::SpendType::P2sh,
+                Some(ref ty) if ty == "p2wsh" => sweep::SpendType::P2wsh,
+                Some(ref ty) if ty == "p2sh-p2wsh" => sweep::SpendType::P2shP2wsh,
+                Some(_) => {
+                    fail("bad_value", "--fund-type", "option to --fund-type must be p2sh, p2wsh or p2sh-p2wsh.");
+                    println!("{}", full_usage);
+                    return;
+                }
+            };
+            Some((amount, spend_type))
+        }
+        None => {
+            if matches.opt_present("fund-type") {
+                fail("usage", "--fund-type", "--fund-type may only be used with --fund.");
+                println!("{}", full_usage);
+                return;
+            }
+            None
+        }
+    };
+    let (funding_utxos, change, fee) = if funding.is_some() || op_return {
+        let utxos = match matches.opt_str("funding-utxos") {
+            Some(filename) => {
+                match read_utxos_file(&filename) {
+                    Some(utxos) => utxos,
+                    None => { return; }
+                }
+            }
+            None => vec![]
+        };
+        let fee = match (utxos.is_empty(), matches.opt_str("fee").map(|fee| fee.parse::<u64>())) {
+            (true, None) => 0,
+            (false, Some(Ok(fee))) => fee,
+            (false, Some(Err(e))) => {
+                fail("bad_number", "--fee", &format!("option to --fee could not be parsed as a number: {}.", e));
+                return;
+            }
+            (true, Some(_)) | (false, None) => {
+                fail("usage", "--fee", "--fee must be given with --funding-utxos, and only then.");
+                println!("{}", full_usage);
+                return;
+            }
+        };
+        let change = match matches.opt_str("change") {
+            Some(ref change) if !utxos.is_empty() => {
+                match parse_address(chain, "option to --change", change) {
+                    Some(script_pubkey) => Some(script_pubkey),
+                    None => { return; }
+                }
+            }
+            Some(_) => {
+                fail("usage", "--change", "--change may only be used with --funding-utxos.");
+                println!("{}", full_usage);
+                return;
+            }
+            None => None
+        };
+        (utxos, change, fee)
+    } else {
+        if matches.opt_present("funding-utxos") || matches.opt_present("fee") || matches.opt_present("change") {
+            fail("usage", "--funding-utxos", "--funding-utxos, --fee and --change may only be used with --fund or --op-return.");
+            println!("{}", full_usage);
+            return;
+        }
+        (vec![], None, 0)
+    };
+
+    // Redeem script (required for -g, not allowed for -c)
+    let redeem_script = match (mode, matches.opt_str("r"), matches.opt_str("k")) {
+        (Mode::GenAddress, Some(x), None) => {
+            // Scripts are given in the script encoding unless they can only be read as asm
+            match script_encoding.decode(&x) {
+                Ok(script) => Some(script),
+                Err(e) => {
+                    match asm::assemble(&x) {
+                        Ok(script) => Some(script),
+                        Err(asm_e) => {
+                            fail("bad_script", "-r", &format!("option to -r could not be parsed as {} ({:?}) or asm ({:?}).", script_encoding.name(), e, asm_e));
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+        (Mode::GenAddress, None, Some(x)) => {
+            let mut keys = Vec::new();
+            for hex in x.split(',') {
+                let key = match hex.from_hex() {
+                    Ok(data) => PublicKey::from_slice(&secp, &data).ok(),
+                    Err(_) => None
+                };
+                match key {
+                    // The script would hold the key compressed, and untemplate
+                    // refuses uncompressed keys, so there is nothing to tweak
+                    Some(_) if hex.len() != 66 => {
+                        fail("bad_pubkey", "-k", &format!("option to -k must be a list of compressed public keys: {} is uncompressed.", hex));
+                        return;
+                    }
+                    Some(key) => keys.push(key),
+                    None => {
+                        fail("bad_pubkey", "-k", &format!("option to -k could not be parsed as a list of public keys: bad key {}.", hex));
+                        return;
+                    }
+                }
+            }
+            let threshold = threshold.unwrap();
+            if threshold == 0 || threshold > keys.len() || keys.len() > MAX_PUBKEYS_PER_MULTISIG {
+                fail("too_many_keys", "-m", &format!("Cannot make a {}-of-{} multisig (at most {} keys are allowed).", threshold, keys.len(), MAX_PUBKEYS_PER_MULTISIG));
+                return;
+            }
+            if sorting == Sorting::BeforeTweak {
+                template::sort_keys(&secp, &mut keys);
+            }
+            Some(make_multisig(threshold, &keys))
+        }
+        (Mode::GenAddress, Some(_), Some(_)) => unreachable!(),
+        (Mode::GenAddress, None, None) => {
+            if xpubs.is_none() && !op_return {
+                fail("usage", "", "One of -r, -k, -x or --op-return must be specified in -g mode.");
+                println!("{}", full_usage);
+                return;
+            }
+            // With only --op-return there is no address to say anything about
+            if xpubs.is_none() {
+                for opt in &["copy", "psbt", "blinding-key", "master-blinding-key", "importdescriptors", "importmulti", "import-to-core",
+                             "scan-balance", "electrum", "esplora-url", "electrum-wallet", "bip329"] {
+                    if matches.opt_present(opt) {
+                        fail("usage", &format!("--{}", opt), &format!("--{} may not be used with --op-return unless -r or -k is too.", opt));
+                        println!("{}", full_usage);
+                        return;
+                    }
+                }
+            }
+            None
+        }
+        (Mode::GenPrivkey, None, None) => None,
+        (Mode::GenPrivkey, _, _) => {
+            fail("usage", "-r", "-r and -k may only be used in -g mode.");
+            println!("{}", full_usage);
+            return;
+        }
+    };
+
+    // Privkey string, given directly or read from a (possibly encrypted) file
+    let privkey_str = match read_privkey(&matches, &full_usage) {
+        Some(privkey_str) => privkey_str,
+        None => { return; }
+    };
+
+    // Extended privkey, from -p or a mnemonic (only allowed in -c mode)
+    let bip38_input = privkey_str.as_ref().map(|x| privkey::is_bip38(x)).unwrap_or(false);
+    let bip38_output = matches.opt_str("privkey-format").map(|f| f == "bip38").unwrap_or(false);
+    let slip39_output = matches.opt_str("privkey-format").map(|f| f == "slip39").unwrap_or(false);
+    let passphrase = matches.opt_str("passphrase");
+    if passphrase.is_some() && !(matches.opt_present("mnemonic") || bip38_input || bip38_output || slip39_output) {
+        fail("usage", "--passphrase", "--passphrase may only be used with --mnemonic, a BIP38 private key, or --privkey-format bip38 or slip39.");
+        return;
+    }
+    if passphrase.is_none() && (bip38_input || bip38_output) {
+        fail("usage", "--passphrase", "--passphrase must be specified with BIP38 private keys.");
+        return;
+    }
+    let xprv = match (mode, privkey_str.as_ref(), matches.opt_str("mnemonic")) {
+        (Mode::GenPrivkey, Some(x), None) if x.starts_with("xprv") || x.starts_with("tprv") => {
+            let decode: Result<ExtendedPrivKey, _> = FromBase58::from_base58check(&x[..]);
+            match decode {
+                Ok(xprv) => {
+                    if xprv.network != network {
+                        fail("wrong_network", "-p", "Extended private key network did not match tool mode (did you forget -t?).");
+                        return;
+                    }
+                    Some(xprv)
+                }
+                Err(e) => {
+                    fail("bad_privkey", "-p", &format!("option to -p could not be parsed as an extended private key: {:?}.", e));
+                    return;
+                }
+            }
+        }
+        (Mode::GenPrivkey, None, Some(words)) => {
+            let seed = match bip39::to_seed(&words, passphrase.as_ref().map(|p| &p[..]).unwrap_or("")) {
+                Ok(seed) => seed,
+                Err(e) => {
+                    fail("bad_mnemonic", "--mnemonic", &format!("option to --mnemonic could not be used as a BIP39 mnemonic: {:?}.", e));
+                    return;
+                }
+            };
+            match ExtendedPrivKey::new_master(&secp, network, &seed[..]) {
+                Ok(xprv) => Some(xprv),
+                Err(e) => {
+                    fail("bad_mnemonic", "--mnemonic", &format!("Unable to generate a master key from the mnemonic: {:?}", e));
+                    return;
+                }
+            }
+        }
+        (Mode::GenPrivkey, Some(_), Some(_)) => {
+            fail("usage", "-p", "At most one of -p or --mnemonic may be specified.");
+            println!("{}", full_usage);
+            return;
+        }
+        (Mode::GenAddress, _, Some(_)) => {
+            fail("usage", "--mnemonic", "--mnemonic may only be used in -c mode.");
+            println!("{}", full_usage);
+            return;
+        }
+        _ => None
+    };
+
+    // A mnemonic with a valid checksum may still be the wrong wallet's, so show
+    // which wallet it gave
+    let master_fingerprint = match (matches.opt_present("mnemonic"), xprv) {
+        (true, Some(xprv)) => xprv.to_xpub(&secp).ok().map(|xpub| xpub.fingerprint(&secp)),
+        _ => None
+    };
+
+    // Privkey (required for -c, not allowed for -g)
+    let private_key = match (mode, xprv, privkey_str) {
+        (Mode::GenPrivkey, Some(xprv), _) => {
+            let path = derivation_path.unwrap_or(DerivationPath { children: vec![], wildcard: Wildcard::None });
+            let index = match (path.wildcard, matches.opt_str("index")) {
+                (Wildcard::None, None) => 0,
+                (Wildcard::None, Some(_)) => {
+                    fail("usage", "--index", "--index may only be used with a derivation path ending in *.");
+                    return;
+                }
+                (_, None) => {
+                    fail("usage", "--index", "--index must be specified with a derivation path ending in *.");
+                    return;
+                }
+                (_, Some(i)) => {
+                    match i.parse::<u32>() {
+                        Ok(i) if i < HARDENED => i,
+                        _ => {
+                            fail("bad_value", "--index", "option to --index must be a non-hardened child index.");
+                            return;
+                        }
+                    }
+                }
+            };
+            let path_str = bip32::format_path(&path.at_index(index));
+            match xprv.derive_path(&secp, &path.at_index(index)) {
+                Ok(child) => {
+                    Some(Privkey {
+                        compressed: true,
+                        network: network,
+                        key: child.secret_key
+                    })
+                }
+                Err(e) => {
+                    fail("derivation_failed", "-p", &format!("Unable to derive {} from the extended private key: {:?}", path_str, e));
+                    return;
+                }
+            }
+        }
+        // Keys are streamed from stdin once everything else is parsed
+        (Mode::GenPrivkey, None, Some(ref x)) if &x[..] == "-" => None,
+        (Mode::GenPrivkey, None, Some(x)) => {
+            if derivation_path.is_some() || matches.opt_present("index") {
+                fail("usage", "--derivation-path", "--derivation-path and --index may only be used with an extended private key or a mnemonic.");
+                return;
+            }
+            let decode = privkey::decode(&secp, &x, chain, passphrase.as_ref().map(|p| &p[..]));
+            match decode {
+                Ok(key) => Some(key),
+                // A WIF key of another chain
+                Err(privkey::Error::Base58(base58::Error::InvalidVersion(_))) => {
+                    fail("wrong_network", "-p", "Private key network did not match tool mode (did you forget -t?).");
+                    println!("{} convert-key re-encodes a private key for another chain.", prog);
+                    return;
+                }
+                Err(e) => {
+                    fail("bad_privkey", "-p", &format!("option to -p could not be parsed as a private key: {:?}.", e));
+                    return;
+                }
+            }
+        }
+        (Mode::GenPrivkey, None, None) => {
+            fail("usage", "-p", "-p or --mnemonic must be specified in -c mode.");
+            println!("{}", full_usage);
+            return;
+        }
+        (Mode::GenAddress, _, None) => None,
+        (Mode::GenAddress, _, Some(_)) => {
+            fail("usage", "-p", "-p may only be used in -c mode.");
             println!("{}", full_usage);
             return;
         }
     };
 
-    if matches.opt_present("h") {
-        println!("{}", full_usage);
-        return;
-    }
-
-    // ** Validate command-line options **
-    let network = if matches.opt_present("t") { Network::Testnet } else { Network::Bitcoin };
-
-    // Mode
-    let mode = match (matches.opt_present("c"), matches.opt_present("g")) {
-        (false, false) => {
-            println!("One of -g or -c must be specified.");
+    // Compression of the output privkey (only allowed in -c mode, defaults to that of the input)
+    let compressed = match (mode, matches.opt_present("compressed"), matches.opt_present("uncompressed")) {
+        (_, true, true) => {
+            fail("usage", "--compressed", "At most one of --compressed or --uncompressed may be specified.");
             println!("{}", full_usage);
             return;
         }
-        (true, true) => {
-            println!("At most one of -g or -c may be specified.");
+        (_, false, false) => None,
+        (Mode::GenPrivkey, compressed, _) => Some(compressed),
+        (Mode::GenAddress, _, _) => {
+            fail("usage", "--compressed", "--compressed and --uncompressed may only be used in -c mode.");
             println!("{}", full_usage);
             return;
         }
-        (true, false) => Mode::GenPrivkey,
-        (false, true) => Mode::GenAddress,
     };
 
-    // Redeem script (required for -g, not allowed for -c)
-    let redeem_script = match (mode, matches.opt_str("r")) {
-        (Mode::GenAddress, Some(x)) => {
-            match x.from_hex() {
-                Ok(data) => Some(Script::from(data)),
-                Err(e) => {
-                    println!("option to -r could not be parsed as hex: {}.", e);
+    // Format of the output privkey (only allowed in -c mode)
+    let privkey_format = match (mode, matches.opt_str("privkey-format")) {
+        (_, None) => PrivkeyFormat::Wif,
+        (Mode::GenPrivkey, Some(ref format)) if format == "wif" => PrivkeyFormat::Wif,
+        (Mode::GenPrivkey, Some(ref format)) if format == "hex" => PrivkeyFormat::Hex,
+        (Mode::GenPrivkey, Some(ref format)) if format == "bip38" => PrivkeyFormat::Bip38,
+        (Mode::GenPrivkey, Some(ref format)) if format == "slip39" => {
+            let shares = matches.opt_str("shares").unwrap_or(String::new());
+            let counts: Vec<Option<usize>> = shares.split("-of-").map(|n| n.parse().ok()).collect();
+            match (counts.len(), counts.first().and_then(|&n| n), counts.last().and_then(|&n| n)) {
+                (2, Some(threshold), Some(count)) => PrivkeyFormat::Slip39(threshold, count),
+                _ if shares.is_empty() => {
+                    fail("usage", "--shares", "--shares must be specified with --privkey-format slip39.");
+                    println!("{}", full_usage);
+                    return;
+                }
+                _ => {
+                    fail("bad_value", "--shares", "option to --shares must be of the form THRESHOLD-of-COUNT, e.g. 2-of-3.");
                     return;
                 }
             }
         }
-        (Mode::GenAddress, None) => {
-            println!("-r must be specified in -g mode.");
+        (Mode::GenPrivkey, Some(_)) => {
+            fail("bad_value", "--privkey-format", "option to --privkey-format must be wif, hex, bip38 or slip39.");
             println!("{}", full_usage);
             return;
         }
-        (Mode::GenPrivkey, None) => None,
-        (Mode::GenPrivkey, Some(_)) => {
-            println!("-r may only be used in -g mode.");
+        (Mode::GenAddress, Some(_)) => {
+            fail("usage", "--privkey-format", "--privkey-format may only be used in -c mode.");
             println!("{}", full_usage);
             return;
         }
     };
 
-    // Privkey (required for -c, not allowed for -g)
-    let private_key = match (mode, matches.opt_str("p")) {
-        (Mode::GenPrivkey, Some(x)) => {
-            let decode: Result<Privkey, _> = FromBase58::from_base58check(&x[..]);
-            match decode {
-                Ok(key) => {
-                    if key.network != network {
-                        println!("Private key network did not match tool mode (did you forget -t?).");
-                        return;
-                    }
-                    Some(key)
-                }
+    if matches.opt_present("shares") && !slip39_output {
+        fail("usage", "--shares", "--shares may only be used with --privkey-format slip39.");
+        println!("{}", full_usage);
+        return;
+    }
+
+    // Contract types defined by plugins
+    let plugins = match parse_plugins(&matches) {
+        Some(plugins) => plugins,
+        None => { return; }
+    };
+    let custom = matches.opt_str("custom");
+
+    // full contract, nonce, p2sh-address contract, ascii contract
+    let contract = match (matches.opt_str("f"), matches.opt_str("n"), matches.opt_str("d"), matches.opt_str("a")) {
+        // A batch carries its own contracts
+        (None, None, None, None) if batch.is_some() && custom.is_none() => {
+            let (ref filename, ref file, n) = *batch.as_ref().unwrap();
+            let first = match contracts_file_lines(file) {
+                Ok(mut lines) => read_contracts_chunk(&mut lines, filename, &plugins.tags(), 1),
                 Err(e) => {
-                    println!("option to -p could not be parsed as a private key: {:?}.", e);
+                    fail("io", "--contracts-file", &format!("Unable to read {}: {}", filename, e));
                     return;
                 }
+            };
+            match first {
+                Some(mut first) => match first.pop() {
+                    Some((_, contract)) => contract,
+                    None => {
+                        fail("bad_contract", "--gap-limit", &format!("{} has only 0 contracts, fewer than the gap limit of {}.", filename, n));
+                        return;
+                    }
+                },
+                None => { return; }
             }
         }
-        (Mode::GenPrivkey, None) => {
-            println!("-p must be specified in -c mode.");
+        (_, _, _, _) if batch.is_some() => {
+            fail("usage", "-f", "-f, -a, -d, -n and --custom may not be used with --contracts-file.");
             println!("{}", full_usage);
             return;
         }
-        (Mode::GenAddress, None) => None,
-        (Mode::GenAddress, Some(_)) => {
-            println!("-p may only be used in -c mode.");
+        // A plugin encodes the payload, and we choose the nonce as usual
+        (None, nonce, None, None) if custom.is_some() => {
+            if mode == Mode::GenPrivkey && nonce.is_none() {
+                fail("usage", "-n", "-n is required when using -c and --custom");
+                println!("{}", full_usage);
+                return;
+            }
+            let custom = custom.unwrap();
+            let mut fields = custom.splitn(2, '=');
+            let (tag, payload) = match (fields.next(), fields.next()) {
+                (Some(tag), Some(payload)) => (tag, payload),
+                _ => {
+                    fail("bad_value", "--custom", "option to --custom must be of the form TAG=payload.");
+                    return;
+                }
+            };
+            let plugin = match plugin::parse_tag(tag).ok().and_then(|tag| plugins.get(&tag)) {
+                Some(plugin) => plugin,
+                None => {
+                    fail("plugin_failed", "--custom", &format!("No plugin is declared for the contract type {}.", tag));
+                    return;
+                }
+            };
+            let nonce = match nonce {
+                Some(hex) => {
+                    match Nonce::from_hex(&hex) {
+                        Ok(data) => data,
+                        Err(e) => {
+                            fail("bad_nonce", "-n", &format!("option to -n could not be parsed as a nonce: {:?}.", e));
+                            return;
+                        }
+                    }
+                }
+                None => match rng {
+                    Ok(ref mut rng) => rng.gen(),
+                    Err(ref e) => {
+                        fail("no_rng", "", &format!("Unable to generate a nonce ({}); give one with -n.", e));
+                        return;
+                    }
+                }
+            };
+            let data = match plugin.encode(payload) {
+                Ok(data) => data,
+                Err(e) => {
+                    fail("plugin_failed", "--custom", &format!("The plugin for {} could not encode the payload: {:?}", tag, e));
+                    return;
+                }
+            };
+            match Contract::from_custom(plugin.tag, nonce, &data) {
+                Ok(contract) => contract,
+                Err(e) => {
+                    fail("plugin_failed", "--custom", &format!("The plugin for {} gave an invalid contract: {:?}.", tag, e));
+                    return;
+                }
+            }
+        }
+        (_, _, _, _) if custom.is_some() => {
+            fail("usage", "--custom", "--custom may only be used with -n.");
             println!("{}", full_usage);
             return;
         }
-    };
-
-    // full contract, nonce, p2sh-address contract, ascii contract
-    let contract = match (matches.opt_str("f"), matches.opt_str("n"), matches.opt_str("d"), matches.opt_str("a")) {
         // Full contract obviates everything else
         (Some(hex), None, None, None) => {
-            match Contract::from_hex(&hex) {
+            match Contract::from_hex_custom(&hex, &plugins.tags()) {
                 Ok(data) => data,
                 Err(e) => {
-                    println!("option to -f could not be parsed as a contract: {:?}.", e);
+                    fail("bad_contract", "-f", &format!("option to -f could not be parsed as a contract: {:?}.", e));
                     return;
                 }
             }
@@ -184,7 +4906,7 @@ fn main() {
         // P2SH requires a nonce, but in generate mode we may make one
         (None, nonce, Some(hex), None) => {
             if mode == Mode::GenPrivkey && nonce.is_none() {
-                println!("-n is required when using -c and -d");
+                fail("usage", "-n", "-n is required when using -c and -d");
                 println!("{}", full_usage);
                 return;
             }
@@ -194,17 +4916,23 @@ fn main() {
                     match Nonce::from_hex(&hex) {
                         Ok(data) => data,
                         Err(e) => {
-                            println!("option to -n could not be parsed as a nonce: {:?}.", e);
+                            fail("bad_nonce", "-n", &format!("option to -n could not be parsed as a nonce: {:?}.", e));
                             return;
                         }
                     }
                 }
-                None => rng.gen()
+                None => match rng {
+                    Ok(ref mut rng) => rng.gen(),
+                    Err(ref e) => {
+                        fail("no_rng", "", &format!("Unable to generate a nonce ({}); give one with -n.", e));
+                        return;
+                    }
+                }
             };
-            match Contract::from_p2sh_base58_str(&hex, nonce, network) {
+            match Contract::from_p2sh_base58_str(&hex, nonce, chain) {
                 Ok(contract) => contract,
                 Err(e) => {
-                    println!("option to -d could not be parsed as a P2SH contract: {:?}.", e);
+                    fail("bad_contract", "-d", &format!("option to -d could not be parsed as a P2SH contract: {:?}.", e));
                     return;
                 }
             }
@@ -212,7 +4940,7 @@ fn main() {
         // ASCII requires a nonce, but in generate mode we may make one
         (None, nonce, None, Some(ascii)) => {
             if mode == Mode::GenPrivkey && nonce.is_none() {
-                println!("-n is required when using -c and -a");
+                fail("usage", "-n", "-n is required when using -c and -a");
                 println!("{}", full_usage);
                 return;
             }
@@ -222,93 +4950,805 @@ fn main() {
                     match Nonce::from_hex(&hex) {
                         Ok(data) => data,
                         Err(e) => {
-                            println!("option to -n could not be parsed as a nonce: {:?}.", e);
+                            fail("bad_nonce", "-n", &format!("option to -n could not be parsed as a nonce: {:?}.", e));
                             return;
                         }
                     }
                 }
-                None => rng.gen()
+                None => match rng {
+                    Ok(ref mut rng) => rng.gen(),
+                    Err(ref e) => {
+                        fail("no_rng", "", &format!("Unable to generate a nonce ({}); give one with -n.", e));
+                        return;
+                    }
+                }
             };
             match Contract::from_ascii_str(&ascii, nonce) {
                 Ok(contract) => contract,
                 Err(e) => {
-                    println!("option to -a could not be parsed as a contract: {:?}.", e);
+                    fail("bad_contract", "-a", &format!("option to -a could not be parsed as a contract: {:?}.", e));
                     return;
                 }
             }
         }
         // Every other usage is illegal
         _ => {
-            println!("Must specify exactly one of: -f; -a -n; or -d -n");
+            fail("usage", "", "Must specify exactly one of: -f; -a -n; -d -n; or --custom -n");
             println!("{}", full_usage);
             return;
         }
     };
 
+    // A nonce typed in, rather than made here, may be too predictable to
+    // hide its contract. Spending must use whatever nonce was committed to,
+    // so only new commitments are checked.
+    let check_nonces = mode == Mode::GenAddress && (matches.opt_present("n") || matches.opt_present("f") || batch.is_some());
+    let mut weak = 0;
+    if check_nonces && batch.is_none() {
+        weak += warn_weak_nonce(&contract) as usize;
+    }
+
+    // A batch naming one contract or nonce twice is summarised after its
+    // output, or refused with --strict before any; so is one giving the same
+    // address twice, which is looked for as the addresses are made. Only as
+    // much of the file as the gap limit covers is read.
+    let mut duplicates = dedup::Tracker::new();
+    if let Some((ref filename, ref file, n)) = batch {
+        let mut lines = match contracts_file_lines(file) {
+            Ok(lines) => lines,
+            Err(e) => {
+                fail("io", "--contracts-file", &format!("Unable to read {}: {}", filename, e));
+                return;
+            }
+        };
+        let mut count = 0;
+        while count < n {
+            let chunk = match read_contracts_chunk(&mut lines, filename, &plugins.tags(), cmp::min(n - count, JOB_CHUNK)) {
+                Some(chunk) => chunk,
+                None => { return; }
+            };
+            if chunk.is_empty() {
+                fail("bad_contract", "--gap-limit", &format!("{} has only {} contracts, fewer than the gap limit of {}.", filename, count, n));
+                return;
+            }
+            for &(line, ref contract) in &chunk {
+                if check_nonces {
+                    weak += warn_weak_nonce(contract) as usize;
+                }
+                duplicates.add_contract(contract, line);
+            }
+            count += chunk.len();
+        }
+    }
+    if strict && weak > 0 {
+        fail("weak_nonce", "-n", "Refusing to commit to a contract with a weak nonce (--strict).");
+        return;
+    }
+    if strict && !duplicates.duplicates().is_empty() {
+        for duplicate in duplicates.duplicates() {
+            let _ = writeln!(io::stderr(), "{}", describe_duplicate(duplicate, "line"));
+        }
+        fail("duplicate", "--contracts-file", "Refusing to output a batch with duplicates (--strict).");
+        return;
+    }
+
+    // Timestamp the contracts about to be committed to, so that when the
+    // commitment was made can be proven later
+    if let Some(ref filename) = ots_file {
+        let written = atomic::write_with(Path::new(filename), true, false, |out| {
+            let (batch_filename, file, n) = match batch {
+                Some((ref batch_filename, ref file, n)) => (batch_filename, file, n),
+                None => { return write!(out, "{:x}\n", contract); }
+            };
+            let mut lines = try!(contracts_file_lines(file));
+            let mut count = 0;
+            while count < n {
+                // The file was checked already, so can only fail here if it changed
+                let chunk = match read_contracts_chunk(&mut lines, batch_filename, &plugins.tags(), cmp::min(n - count, JOB_CHUNK)) {
+                    Some(chunk) => chunk,
+                    None => { return Err(io::Error::new(io::ErrorKind::InvalidData, "the contracts file changed while being read")); }
+                };
+                if chunk.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "the contracts file changed while being read"));
+                }
+                for &(_, ref contract) in &chunk {
+                    try!(write!(out, "{:x}\n", contract));
+                }
+                count += chunk.len();
+            }
+            Ok(())
+        });
+        if let Err(e) = written {
+            fail("io", "--ots", &format!("Unable to write {}: {}", filename, e));
+            return;
+        }
+        match ots::stamp(filename) {
+            Ok(proof) => println!("Timestamped the contracts in {}: wrote {}.", filename, proof),
+            Err(e) => {
+                fail("ots_failed", "--ots", &format!("Unable to timestamp {}: {:?}", filename, e));
+                return;
+            }
+        }
+    }
+
     // OKAY. At this point we have actually parsed everything and can be assured that we have what we need.
     // ** Actual program starts now **
-    let secp = Secp256k1::new();
     match mode {
         Mode::GenAddress => {
-            let redeem_script = redeem_script.unwrap();
-
-            match untemplate(&redeem_script) {
-                Ok((template, keys)) => {
-                    let keys = match tweak_keys(&secp, &keys, &contract.serialize()[..]) {
-                        Ok(keys) => keys,
+            // With extended keys, output one address per child index
+            if let Some(xpubs) = xpubs {
+                print_banner(chain);
+                let n_jobs = match batch {
+                    Some((_, _, n)) => n,
+                    None => {
+                        println!("Nonce: {:x}", Nonce::from_contract(&contract));
+                        println!("Full serialized contract: {:x}", contract);
+                        print_plugin_payload(&plugins, &contract);
+                        (range.1 - range.0) as usize + 1
+                    }
+                };
+                // What is only printed at the end is only kept if it will be
+                let keep_import = import_descriptors || import_to_core_flag;
+                let keep_importmulti = import_multi || import_to_core_flag;
+                let keep_scan = scan_balance || electrum_server.is_some() || esplora_url.is_some();
+                let keep_wallet = electrum_wallet.is_some() || bip329_file.is_some();
+                // Indices are taken a chunk at a time, so that however large the
+                // range, only one chunk's keys are held at once
+                let mut batch_lines = match batch {
+                    Some((ref filename, ref file, _)) => match contracts_file_lines(file) {
+                        Ok(lines) => Some(lines),
                         Err(e) => {
-                            println!("Unable to tweak keys: {:?}", e);
+                            fail("io", "--contracts-file", &format!("Unable to read {}: {}", filename, e));
                             return;
                         }
+                    },
+                    None => None
+                };
+                let mut start = 0;
+                while start < n_jobs {
+                    let end = cmp::min(start + JOB_CHUNK, n_jobs);
+                    // Jobs are (index, contract, line of the file it came from)
+                    let jobs: Vec<(u32, Contract, usize)> = match (&batch, batch_lines.as_mut()) {
+                        (&Some(ref batch), Some(lines)) => match read_contracts_chunk(lines, &batch.0, &plugins.tags(), end - start) {
+                            Some(chunk) => chunk.into_iter().enumerate().map(|(n, (line, contract))| (range.0 + (start + n) as u32, contract, line)).collect(),
+                            None => { return; }
+                        },
+                        _ => (start..end).map(|n| (range.0 + n as u32, contract.clone(), 0)).collect()
                     };
-                    let new_script = match template.to_script(&keys) {
+                    // The file was checked already, so can only run short here if it changed
+                    if jobs.len() < end - start {
+                        fail("io", "--contracts-file", "The contracts file changed while being read.");
+                        return;
+                    }
+                    start = end;
+                    // Each index is derived and tweaked independently, so on every
+                    // core; the rest, including talking to a device, is done in order
+                    let (job_xpubs, job_path) = (xpubs.clone(), xpub_path.clone());
+                    let derived = parallel::map(&jobs, threads, move |&(index, ref contract, _)| {
+                        let path = job_path.at_index(index);
+                        let mut keys = Vec::with_capacity(job_xpubs.len());
+                        let mut sources = Vec::with_capacity(job_xpubs.len());
+                        for &(ref xpub, ref source) in &job_xpubs {
+                            match xpub.derive_path(&secp, &path) {
+                                Ok(child) => {
+                                    keys.push(child.public_key);
+                                    sources.push((child.public_key, source.child(&path)));
+                                }
+                                Err(e) => {
+                                    return Err(format!("Unable to derive {} of {}: {:?}", bip32::format_path(&path), xpub.to_base58check(), e));
+                                }
+                            }
+                        }
+                        if sorting == Sorting::BeforeTweak {
+                            template::sort_keys(&secp, &mut keys);
+                        }
+                        match tweak_keys(&secp, &keys, &contract.to_bytes()[..]) {
+                            Ok(tweaked_keys) => Ok((keys, sources, tweaked_keys)),
+                            Err(e) => Err(format!("Unable to tweak keys: {:?}", e))
+                        }
+                    });
+                    for ((index, contract, line), derived) in jobs.into_iter().zip(derived) {
+                        let path = xpub_path.at_index(index);
+                        // Name the contract on each line when every line has its own,
+                        // and the line of the file it came from
+                        let label = match batch {
+                            Some(_) => format!("{} with contract {:x} from line {}", bip32::format_path(&path), contract, line),
+                            None => bip32::format_path(&path)
+                        };
+                        let (keys, sources, mut tweaked_keys) = match derived {
+                            Ok(derived) => derived,
+                            Err(e) => {
+                                fail("tweak_failed", "", &e);
+                                return;
+                            }
+                        };
+                        // Devices can only vouch for untweaked keys, so check those
+                        if hwi_verify {
+                            for &(ref key, ref source) in &sources {
+                                match hwi::get_pubkey(source, network) {
+                                    Ok(ref device_key) if device_key == key => {}
+                                    Ok(device_key) => {
+                                        fail("hwi_failed", "--hwi-verify", &format!("Device has key {} at {}, but {} was derived; refusing to continue.",
+                                                                                    device_key.serialize_vec(&secp, true)[..].to_hex(), source,
+                                                                                    key.serialize_vec(&secp, true)[..].to_hex()));
+                                        return;
+                                    }
+                                    Err(e) => {
+                                        fail("hwi_failed", "--hwi-verify", &format!("Unable to get the key at {} from the device: {:?}", source, e));
+                                        return;
+                                    }
+                                }
+                                let address = Address::from_key(network, key, true).to_base58check();
+                                println!("Confirm on the device that the untweaked key {} has address {}.", source, address);
+                                match hwi::display_address(source, network) {
+                                    Ok(ref shown) if *shown == address => {}
+                                    Ok(shown) => {
+                                        fail("hwi_failed", "--hwi-verify", &format!("Device showed address {} instead; refusing to continue.", shown));
+                                        return;
+                                    }
+                                    Err(e) => {
+                                        fail("hwi_failed", "--hwi-verify", &format!("Unable to display the address of {} on the device: {:?}", source, e));
+                                        return;
+                                    }
+                                }
+                            }
+                        }
+                        if let Some(ref mut psbt) = psbt {
+                            let tweaks: Vec<_> = keys.iter().zip(tweaked_keys.iter()).map(|(key, tweaked)| {
+                                psbt::TweakedKey {
+                                    tweaked: *tweaked,
+                                    base: *key,
+                                    contract: contract.clone(),
+                                    source: sources.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref source)| source.clone())
+                                }
+                            }).collect();
+                            let script = threshold.map(|m| {
+                                let mut sorted_keys = tweaked_keys.clone();
+                                if sorting == Sorting::AfterTweak {
+                                    template::sort_keys(&secp, &mut sorted_keys);
+                                }
+                                make_multisig(m, &sorted_keys)
+                            });
+                            let (n_inputs, n_outputs) = psbt.attach(&secp, network, script.as_ref(), &tweaks);
+                            psbt_updates = (psbt_updates.0 + n_inputs, psbt_updates.1 + n_outputs);
+                        }
+                        match threshold {
+                            // A single xpub without -m gives single keys
+                            None => {
+                                let key_address = Address::from_key(network, &tweaked_keys[0], true);
+                                let address = chain.encode_address(&key_address);
+                                let desc = descriptor::pkh(&secp, &tweaked_keys[0]);
+                                if keep_import {
+                                    import_requests.push(descriptor::import_request(&desc, &descriptor_label, timestamp));
+                                }
+                                if keep_scan {
+                                    scan_descriptors.push((desc, key_address.script_pubkey(), contract.clone()));
+                                }
+                                if keep_importmulti {
+                                    importmulti_requests.push(descriptor::importmulti_request(&secp, &address, None, None, &tweaked_keys[..1], &descriptor_label, timestamp));
+                                }
+                                if keep_wallet {
+                                    wallet_addresses.push((address.clone(), "P2PKH", contract.clone()));
+                                }
+                                if batch.is_some() && duplicates.add(dedup::Kind::Address, address.clone(), line).is_some() && strict {
+                                    fail("duplicate", "--contracts-file", &format!("Refusing to output the address {} twice (--strict).", address));
+                                    return;
+                                }
+                                println!("{}: key {}{} tweaked to {}, P2PKH address {}", label, sources[0].1,
+                                         keys[0].serialize_vec(&secp, true)[..].to_hex(),
+                                         tweaked_keys[0].serialize_vec(&secp, true)[..].to_hex(), address);
+                                println!("    Electrum scripthash: {}", electrum::scripthash(&key_address.script_pubkey()));
+                            }
+                            Some(m) => {
+                                let untweaked: Vec<(PublicKey, PublicKey)> = tweaked_keys.iter().cloned().zip(keys.iter().cloned()).collect();
+                                if sorting == Sorting::AfterTweak {
+                                    template::sort_keys(&secp, &mut tweaked_keys);
+                                }
+                                let new_script = make_multisig(m, &tweaked_keys);
+                                let warnings = check::check_script(&new_script);
+                                for warning in &warnings {
+                                    print_warning(warning);
+                                }
+                                if strict && !warnings.is_empty() {
+                                    fail("failed_check", "--strict", "Refusing to output a modified redeem script which failed sanity checks (--strict).");
+                                    return;
+                                }
+                                for &(wrapper, ref script_pubkey) in &[(descriptor::Wrapper::Sh, Address::from_script(network, &new_script).script_pubkey()),
+                                                                       (descriptor::Wrapper::Wsh, segwit::p2wsh_script_pubkey(&new_script))] {
+                                    let desc = descriptor::script(&secp, &new_script, script_pubkey, wrapper);
+                                    if keep_import {
+                                        import_requests.push(descriptor::import_request(&desc, &descriptor_label, timestamp));
+                                    }
+                                    if keep_scan {
+                                        scan_descriptors.push((desc, script_pubkey.clone(), contract.clone()));
+                                    }
+                                }
+                                let p2sh = chain.p2sh_address(&new_script);
+                                let p2wsh = chain.p2wsh_address(&new_script);
+                                if keep_importmulti {
+                                    importmulti_requests.push(descriptor::importmulti_request(&secp, &p2sh, Some(&new_script), None, &tweaked_keys, &descriptor_label, timestamp));
+                                    importmulti_requests.push(descriptor::importmulti_request(&secp, &p2wsh, None, Some(&new_script), &tweaked_keys, &descriptor_label, timestamp));
+                                }
+                                if batch.is_some() && duplicates.add(dedup::Kind::Address, p2sh.clone(), line).is_some() && strict {
+                                    fail("duplicate", "--contracts-file", &format!("Refusing to output the address {} twice (--strict).", p2sh));
+                                    return;
+                                }
+                                if keep_wallet {
+                                    wallet_addresses.push((p2sh, "P2SH", contract.clone()));
+                                    wallet_addresses.push((p2wsh, "P2WSH", contract.clone()));
+                                }
+                                println!("{}: P2SH address {}, P2WSH address {}, redeem script {}", label,
+                                         chain.p2sh_address(&new_script),
+                                         chain.p2wsh_address(&new_script),
+                                         script_encoding.encode(&new_script));
+                                if emergency.is_some() {
+                                    println!("    Peg-in address (P2SH-P2WSH): {}", chain.p2sh_p2wsh_address(&new_script));
+                                }
+                                println!("    Electrum scripthashes: P2SH {}, P2WSH {}",
+                                         electrum::scripthash(&Address::from_script(network, &new_script).script_pubkey()),
+                                         electrum::scripthash(&segwit::p2wsh_script_pubkey(&new_script)));
+                                // The origin of each key is that of the untweaked key, which
+                                // a signer needs along with the contract to find the tweaked one
+                                for (n, tweaked) in tweaked_keys.iter().enumerate() {
+                                    let key = untweaked.iter().find(|&&(ref t, _)| t == tweaked).unwrap().1;
+                                    let origin = &sources.iter().find(|&&(ref k, _)| *k == key).unwrap().1;
+                                    println!("    Key {}: {}{} tweaked to {}", n, origin,
+                                             key.serialize_vec(&secp, true)[..].to_hex(),
+                                             tweaked.serialize_vec(&secp, true)[..].to_hex());
+                                }
+                            }
+                        }
+                    }
+                }
+                if !duplicates.duplicates().is_empty() {
+                    let _ = writeln!(io::stderr(), "Batch summary: {} duplicates.", duplicates.duplicates().len());
+                    for duplicate in duplicates.duplicates() {
+                        let _ = writeln!(io::stderr(), "    {}", describe_duplicate(duplicate, "line"));
+                    }
+                }
+                if let Some(ref psbt) = psbt {
+                    println!("Updated PSBT ({} inputs and {} outputs): {}", psbt_updates.0, psbt_updates.1, psbt.to_base64());
+                }
+                if import_descriptors {
+                    println!("importdescriptors request:\n{}", Json::Array(import_requests.clone()).pretty());
+                }
+                if import_multi {
+                    println!("importmulti request:\n{}", Json::Array(importmulti_requests.clone()).pretty());
+                }
+                if let Some(ref client) = core {
+                    if scan_balance {
+                        scan_balances(client, &scan_descriptors);
+                    }
+                    if import_to_core_flag {
+                        import_to_core(client, import_requests, importmulti_requests);
+                    }
+                }
+                let scripts: Vec<_> = scan_descriptors.iter().map(|&(ref desc, ref spk, _)| (spk.clone(), desc.clone())).collect();
+                if let Some(ref server) = electrum_server {
+                    electrum_balances(server, &scripts);
+                }
+                if let Some(ref url) = esplora_url {
+                    esplora_balances(url, &scripts);
+                }
+                if let Some(ref filename) = electrum_wallet {
+                    let addresses: Vec<String> = wallet_addresses.iter().map(|&(ref address, _, _)| address.clone()).collect();
+                    write_electrum_wallet(filename, &electrum::watch_only_wallet(&addresses), signer.as_ref());
+                }
+                if let Some(ref filename) = bip329_file {
+                    write_bip329_labels(filename, &descriptor_label, &wallet_addresses, signer.as_ref());
+                }
+                return;
+            }
+
+            // With only --op-return, the contract is committed to explicitly
+            // and nothing is tweaked
+            let redeem_script = match redeem_script {
+                Some(redeem_script) => redeem_script,
+                None => {
+                    print_banner(chain);
+                    println!("Nonce: {:x}", Nonce::from_contract(&contract));
+                    println!("Full serialized contract: {:x}", contract);
+                    print_plugin_payload(&plugins, &contract);
+                    print_op_return_tx(&contract, &funding_utxos, change, fee);
+                    return;
+                }
+            };
+            if create_request {
+                let request = handoff::Request { network: network, script: redeem_script, contract: contract };
+                print!("{}", request.to_file());
+                return;
+            }
+
+            // Tweak the keys, keeping track of where each tweaked key came from
+            let liquid = template::is_liquid_fedpeg(&secp, &redeem_script);
+            let (new_script, locations, tweaked_keys) = if liquid {
+                match template::tweak_liquid_fedpeg(&secp, &redeem_script, &contract.to_bytes()[..]) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        fail("tweak_failed", "", &format!("Unable to tweak keys: {:?}", e));
+                        return;
+                    }
+                }
+            } else {
+                let (template, keys) = match untemplate(&redeem_script) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        fail("bad_script", "-r", &format!("Unable to extract keys from redemption script: {:?}", e));
+                        return;
+                    }
+                };
+                let tweaked_keys = match tweak_keys(&secp, &keys, &contract.to_bytes()[..]) {
+                    Ok(keys) => keys,
+                    Err(e) => {
+                        fail("tweak_failed", "", &format!("Unable to tweak keys: {:?}", e));
+                        return;
+                    }
+                };
+                let new_script = if sorting == Sorting::AfterTweak {
+                    let mut sorted_keys = tweaked_keys.clone();
+                    template::sort_keys(&secp, &mut sorted_keys);
+                    template::multisig(&secp, threshold.unwrap(), &sorted_keys)
+                } else {
+                    match template.to_script(&tweaked_keys) {
                         Ok(script) => script,
                         Err(e) => {
-                            println!("Unable to put tweaked keys back into the redemption script: {:?}", e);
+                            fail("tweak_failed", "", &format!("Unable to put tweaked keys back into the redemption script: {:?}", e));
                             return;
                         }
-                    };
-                    match network {
-                        Network::Bitcoin => println!("Using mainnet!"),
-                        Network::Testnet => println!("Using testnet!"),
                     }
-                    println!("Nonce: {:x}", Nonce::from_contract(&contract));
-                    println!("Full serialized contract: {:x}", contract);
-                    println!("Modified redeem script: {:x}", new_script);
-                    println!("Modified redeem script as P2SH address: {}", Address::from_script(network, &new_script).to_base58check());
+                };
+                let locations = match template::locate_extracted_keys(&secp, &redeem_script, tweaked_keys.len()) {
+                    Ok(locations) => locations,
+                    Err(e) => {
+                        fail("bad_script", "-r", &format!("Unable to locate the extracted keys in the redemption script: {:?}", e));
+                        return;
+                    }
+                };
+                (new_script, locations, tweaked_keys)
+            };
+
+            let warnings = check::check_script(&new_script);
+            for warning in &warnings {
+                print_warning(warning);
+            }
+            if strict && !warnings.is_empty() {
+                fail("failed_check", "--strict", "Refusing to output a modified redeem script which failed sanity checks (--strict).");
+                return;
+            }
+            print_banner(chain);
+            println!("Nonce: {:x}", Nonce::from_contract(&contract));
+            println!("Full serialized contract: {:x}", contract);
+            print_plugin_payload(&plugins, &contract);
+            println!("Original redeem script (asm): {}", asm::disassemble(&redeem_script));
+            if copy.as_ref().map(|c| &c[..]) == Some("script") {
+                if !copy_to_clipboard("Modified redeem script", &script_encoding.encode(&new_script)) {
+                    return;
                 }
-                Err(e) => {
-                    println!("Unable to extract keys from redemption script: {:?}", e);
+            } else {
+                println!("Modified redeem script: {}", script_encoding.encode(&new_script));
+            }
+            println!("Modified redeem script (asm): {}", asm::disassemble(&new_script));
+            let address = Address::from_script(network, &new_script);
+            let script_pubkey = address.script_pubkey();
+            if copy.as_ref().map(|c| &c[..]) == Some("address") {
+                if !copy_to_clipboard("Modified redeem script as P2SH address", &chain.encode_address(&address)) {
                     return;
                 }
+            } else {
+                println!("Modified redeem script as P2SH address: {}", chain.encode_address(&address));
+            }
+            // A master blinding key gives the blinding key of this scriptPubKey
+            let blinding_key = match master_blinding_key {
+                Some(ref master) => {
+                    let secret = match elements::slip77_blinding_key(&secp, master, &script_pubkey) {
+                        Ok(secret) => secret,
+                        Err(e) => {
+                            fail("derivation_failed", "--master-blinding-key", &format!("Unable to derive blinding key: {:?}", e));
+                            return;
+                        }
+                    };
+                    println!("Blinding private key (SLIP-0077): {}", secret[..].to_hex());
+                    PublicKey::from_secret_key(&secp, &secret).ok()
+                }
+                None => blinding_key
+            };
+            if let Some(blinding_key) = blinding_key {
+                let confidential = elements::ConfidentialAddress {
+                    chain: if chain == Chain::Regtest { elements::ElementsNetwork::ElementsRegtest } else { elements::ElementsNetwork::from_network(network) },
+                    blinding_key: blinding_key,
+                    address: address.clone()
+                };
+                println!("Modified redeem script as confidential P2SH address: {}", confidential.to_base58check(&secp));
+            }
+            println!("P2SH scriptPubKey: {}", script_encoding.encode(&script_pubkey));
+            println!("P2SH scriptPubKey (asm): {}", asm::disassemble(&script_pubkey));
+            println!("P2SH Electrum scripthash: {}", electrum::scripthash(&script_pubkey));
+            let wsh_script_pubkey = segwit::p2wsh_script_pubkey(&new_script);
+            println!("Modified redeem script as P2WSH address: {}", chain.p2wsh_address(&new_script));
+            println!("P2WSH scriptPubKey: {}", script_encoding.encode(&wsh_script_pubkey));
+            println!("P2WSH scriptPubKey (asm): {}", asm::disassemble(&wsh_script_pubkey));
+            println!("P2WSH Electrum scripthash: {}", electrum::scripthash(&wsh_script_pubkey));
+            let p2sh_p2wsh_script_pubkey = segwit::p2sh_p2wsh_address(network, &new_script).script_pubkey();
+            println!("Modified redeem script as P2SH-P2WSH address: {}", chain.p2sh_p2wsh_address(&new_script));
+            println!("P2SH-P2WSH scriptPubKey: {}", script_encoding.encode(&p2sh_p2wsh_script_pubkey));
+            println!("P2SH-P2WSH scriptPubKey (asm): {}", asm::disassemble(&p2sh_p2wsh_script_pubkey));
+            println!("P2SH-P2WSH Electrum scripthash: {}", electrum::scripthash(&p2sh_p2wsh_script_pubkey));
+            if template::is_p2pk(&secp, &new_script) {
+                println!("Bare P2PK scriptPubKey: {}", script_encoding.encode(&new_script));
+            }
+            // Only small multisigs may be paid to bare, which is no reason to
+            // refuse a script paid to by its hash
+            for warning in &check::check_bare_script(&new_script) {
+                print_warning(warning);
+            }
+            if liquid && tty::is_terminal(Stream::Stdout) {
+                println!("Liquid watchman script: emergency keys after OP_ELSE were not tweaked.");
+            }
+            println!("Extracted {} keys from the redeem script:", locations.len());
+            for (n, (location, tweaked)) in locations.iter().zip(tweaked_keys.iter()).enumerate() {
+                println!("    Key {} at byte {}: {} tweaked to {}", n, location.offset,
+                         location.data.to_hex(),
+                         tweaked.serialize_vec(&secp, true)[..].to_hex());
+            }
+            if let Some(ref mut psbt) = psbt {
+                let tweaks: Vec<_> = locations.iter().zip(tweaked_keys.iter()).map(|(location, tweaked)| {
+                    psbt::TweakedKey { tweaked: *tweaked, base: location.key, contract: contract.clone(), source: None }
+                }).collect();
+                let (n_inputs, n_outputs) = psbt.attach(&secp, network, Some(&new_script), &tweaks);
+                println!("Updated PSBT ({} inputs and {} outputs): {}", n_inputs, n_outputs, psbt.to_base64());
+            }
+            if let Some((amount, spend_type)) = funding {
+                let destination = match spend_type {
+                    sweep::SpendType::P2wsh => wsh_script_pubkey.clone(),
+                    sweep::SpendType::P2shP2wsh => p2sh_p2wsh_script_pubkey.clone(),
+                    _ => script_pubkey.clone()
+                };
+                match sweep::funding(&funding_utxos, destination, amount, change.clone(), fee) {
+                    Ok(tx) => {
+                        if !funding_utxos.is_empty() {
+                            println!("Unsigned funding transaction: {}", tx.serialize(&vec![vec![]; tx.inputs.len()], &[]).to_hex());
+                        }
+                        let tweaks: Vec<_> = locations.iter().zip(tweaked_keys.iter()).map(|(location, tweaked)| {
+                            psbt::TweakedKey { tweaked: *tweaked, base: location.key, contract: contract.clone(), source: None }
+                        }).collect();
+                        let mut funding_psbt = psbt::Psbt::from_unsigned(&tx);
+                        funding_psbt.attach(&secp, network, Some(&new_script), &tweaks);
+                        println!("Funding PSBT ({} inputs): {}", tx.inputs.len(), funding_psbt.to_base64());
+                    }
+                    Err(e) => fail("funding_failed", "--fund", &format!("Unable to build funding transaction: {:?}", e))
+                }
+            }
+            if op_return {
+                print_op_return_tx(&contract, &funding_utxos, change.clone(), fee);
+            }
+            for &(wrapper, script_pubkey) in &[(descriptor::Wrapper::Sh, &script_pubkey),
+                                               (descriptor::Wrapper::Wsh, &wsh_script_pubkey),
+                                               (descriptor::Wrapper::ShWsh, &p2sh_p2wsh_script_pubkey)] {
+                let desc = descriptor::script(&secp, &new_script, script_pubkey, wrapper);
+                import_requests.push(descriptor::import_request(&desc, &descriptor_label, timestamp));
+                scan_descriptors.push((desc, script_pubkey.clone(), contract.clone()));
+            }
+            let keys: Vec<PublicKey> = template::locate_keys(&secp, &new_script).into_iter().map(|location| location.key).collect();
+            let p2sh_p2wsh = chain.p2sh_p2wsh_address(&new_script);
+            importmulti_requests.push(descriptor::importmulti_request(&secp, &chain.encode_address(&address), Some(&new_script), None, &keys, &descriptor_label, timestamp));
+            importmulti_requests.push(descriptor::importmulti_request(&secp, &chain.p2wsh_address(&new_script), None, Some(&new_script), &keys, &descriptor_label, timestamp));
+            importmulti_requests.push(descriptor::importmulti_request(&secp, &p2sh_p2wsh, Some(&wsh_script_pubkey), Some(&new_script), &keys, &descriptor_label, timestamp));
+            wallet_addresses.push((chain.encode_address(&address), "P2SH", contract.clone()));
+            wallet_addresses.push((chain.p2wsh_address(&new_script), "P2WSH", contract.clone()));
+            wallet_addresses.push((p2sh_p2wsh, "P2SH-P2WSH", contract.clone()));
+            if import_descriptors {
+                println!("importdescriptors request:\n{}", Json::Array(import_requests.clone()).pretty());
+            }
+            if import_multi {
+                println!("importmulti request:\n{}", Json::Array(importmulti_requests.clone()).pretty());
+                println!("importaddress arguments for the P2SH address: {} {} false true", new_script[..].to_hex(), Json::String(descriptor_label.clone()));
+            }
+            if let Some(ref client) = core {
+                if scan_balance {
+                    scan_balances(client, &scan_descriptors);
+                }
+                if import_to_core_flag {
+                    import_to_core(client, import_requests, importmulti_requests);
+                }
+            }
+            let scripts: Vec<_> = scan_descriptors.iter().map(|&(ref desc, ref spk, _)| (spk.clone(), desc.clone())).collect();
+            if let Some(ref server) = electrum_server {
+                electrum_balances(server, &scripts);
+            }
+            if let Some(ref url) = esplora_url {
+                esplora_balances(url, &scripts);
+            }
+            if let Some(ref filename) = electrum_wallet {
+                let addresses: Vec<String> = wallet_addresses.iter().map(|&(ref address, _, _)| address.clone()).collect();
+                write_electrum_wallet(filename, &electrum::watch_only_wallet(&addresses), signer.as_ref());
+            }
+            if let Some(ref filename) = bip329_file {
+                write_bip329_labels(filename, &descriptor_label, &wallet_addresses, signer.as_ref());
             }
         }
         Mode::GenPrivkey => {
-            let private_key = private_key.unwrap();
+            // Tweak a key, and format it for output
+            let tweak_privkey = |private_key: &Privkey| -> Result<Privkey, String> {
+                let tweaked_key = try!(context::tweak_secret_key(&private_key.key, &contract.to_bytes()[..])
+                                           .map_err(|e| format!("Failed to tweak private key: {:?}", e)));
+                Ok(Privkey {
+                    compressed: compressed.unwrap_or(private_key.compressed),
+                    network: network,
+                    key: tweaked_key
+                })
+            };
+            let tweak = |private_key: &Privkey| -> Result<memlock::SecretText, String> {
+                let tweaked_privkey = memlock::Secret::new(try!(tweak_privkey(private_key)));
+                match privkey_format {
+                    PrivkeyFormat::Wif => Ok(memlock::SecretText::take(chain.encode_privkey(&tweaked_privkey))),
+                    PrivkeyFormat::Hex => Ok(memlock::SecretText::take(tweaked_privkey.key[..].to_hex())),
+                    PrivkeyFormat::Bip38 => {
+                        privkey::to_bip38(&secp, &tweaked_privkey, chain, passphrase.as_ref().unwrap())
+                            .map(memlock::SecretText::take)
+                            .map_err(|e| format!("Failed to encrypt private key: {:?}", e))
+                    }
+                    // One share per line
+                    PrivkeyFormat::Slip39(threshold, count) => {
+                        let mut rng = try!(OsRng::new().map_err(|e| format!("Unable to split private key: {}", e)));
+                        let mut shares = try!(slip39::split(&mut rng, &tweaked_privkey.key[..], passphrase.as_ref().map(|p| &p[..]).unwrap_or(""),
+                                                            threshold, count)
+                                                  .map_err(|e| format!("Failed to split private key: {:?}", e)));
+                        let mut ret = memlock::SecretText::with_capacity(shares.iter().map(|share| share.len() + 1).sum());
+                        for (n, share) in shares.iter_mut().enumerate() {
+                            if n > 0 {
+                                ret.push_str("\n");
+                            }
+                            ret.push_str(share);
+                            memlock::zero_str(share);
+                        }
+                        Ok(ret)
+                    }
+                }
+            };
+
+            // With -p -, tweak one key per line of stdin, printing only the tweaked keys
+            let private_key = match private_key {
+                Some(key) => key,
+                None if sign_message => {
+                    fail("usage", "-p", "sign-message cannot read keys from stdin with -p -.");
+                    return;
+                }
+                None if electrum_wallet.is_some() => {
+                    fail("usage", "--electrum-wallet", "--electrum-wallet cannot be used to read keys from stdin with -p -.");
+                    return;
+                }
+                None if slip39_output => {
+                    fail("usage", "--privkey-format", "--privkey-format slip39 cannot be used to read keys from stdin with -p -, which prints one line per key.");
+                    return;
+                }
+                None => {
+                    let reveal = confirm_reveal(matches.opt_present("yes"));
+                    if let Reveal::Cancel = reveal {
+                        fail("cancelled", "", "Not printing the private keys.");
+                        return;
+                    }
+                    // Keys for a file are gathered, so that it is written whole,
+                    // in locked chunks which are never moved to grow
+                    let mut output: Vec<memlock::SecretText> = vec![];
+                    let mut emit = |line: &str| match reveal {
+                        Reveal::File(_) => {
+                            if output.last().map(|chunk| chunk.remaining() <= line.len()).unwrap_or(true) {
+                                output.push(memlock::SecretText::with_capacity(cmp::max(SECRET_CHUNK, line.len() + 1)));
+                            }
+                            let chunk = output.last_mut().unwrap();
+                            chunk.push_str(line);
+                            chunk.push_str("\n");
+                        }
+                        _ => println!("{}", line)
+                    };
+                    let stdin = io::stdin();
+                    let mut stdin = stdin.lock();
+                    for n in 0.. {
+                        let line = match memlock::read_line(&mut stdin, privkey::MAX_KEY_TEXT) {
+                            Ok(Some(line)) => line,
+                            Ok(None) => break,
+                            Err(e) => fail_stream("io", &format!("Unable to read stdin: {}", e))
+                        };
+                        // Keep output lines matched with input lines
+                        if line.trim().is_empty() {
+                            emit("");
+                            continue;
+                        }
+                        let result = match privkey::decode(&secp, line.trim(), chain, passphrase.as_ref().map(|p| &p[..])) {
+                            Ok(key) => tweak(&memlock::Secret::new(key)),
+                            Err(privkey::Error::Base58(base58::Error::InvalidVersion(_))) => Err("has the wrong network (did you forget -t?)".to_owned()),
+                            Err(e) => Err(format!("could not be parsed as a private key: {:?}", e))
+                        };
+                        match result {
+                            Ok(tweaked) => emit(&tweaked),
+                            Err(e) => fail_stream("bad_privkey", &format!("Key on line {} {}.", n + 1, e))
+                        }
+                    }
+                    if let Reveal::File(ref filename) = reveal {
+                        let mut whole = memlock::SecretText::with_capacity(output.iter().map(|chunk| chunk.len()).sum());
+                        for chunk in &output {
+                            whole.push_str(chunk);
+                        }
+                        write_secret_file(filename, &whole);
+                    }
+                    return;
+                }
+            };
+
+            if sign_message {
+                let tweaked_privkey = match tweak_privkey(&private_key) {
+                    Ok(key) => memlock::Secret::new(key),
+                    Err(e) => {
+                        fail("tweak_failed", "", &e);
+                        return;
+                    }
+                };
+                let tweaked_pubkey = match PublicKey::from_secret_key(&secp, &tweaked_privkey.key) {
+                    Ok(key) => key,
+                    Err(e) => {
+                        fail("tweak_failed", "", &format!("Failed to compute tweaked public key: {:?}", e));
+                        return;
+                    }
+                };
+                let address = chain.p2pkh_address(&tweaked_pubkey, tweaked_privkey.compressed);
+                match message::sign(&tweaked_privkey, &matches.free[0]) {
+                    Ok(signature) => {
+                        println!("Address: {}", address);
+                        println!("Message: {}", matches.free[0]);
+                        println!("Signature: {}", signature);
+                    }
+                    Err(e) => fail("sign_failed", "", &format!("Failed to sign message: {:?}", e))
+                }
+                return;
+            }
 
-            // Compute tweaked key
-            let tweaked_key = match tweak_secret_key(&secp, &private_key.key, &contract.serialize()[..]) {
-                Ok(key) => key,
+            let tweaked = match tweak(&private_key) {
+                Ok(tweaked) => tweaked,
                 Err(e) => {
-                    println!("Failed to tweak private key: {:?}", e);
+                    fail("tweak_failed", "", &e);
                     return;
                 }
             };
-            // Turn it into a WIF privkey
-            let tweaked_privkey = Privkey {
-                compressed: true,
-                network: network,
-                key: tweaked_key
+            print_banner(chain);
+            if let Some(fingerprint) = master_fingerprint {
+                println!("Master key fingerprint of mnemonic: {}", fingerprint[..].to_hex());
+            }
+            // An encrypted key is safe to show
+            let reveal = match privkey_format {
+                _ if copy.is_some() => Reveal::Clipboard,
+                PrivkeyFormat::Bip38 => Reveal::Print,
+                _ => confirm_reveal(matches.opt_present("yes"))
             };
-
-            match network {
-                Network::Bitcoin => println!("Using mainnet!"),
-                Network::Testnet => println!("Using testnet!"),
+            let mut copied = false;
+            match (reveal, privkey_format) {
+                (Reveal::Print, PrivkeyFormat::Bip38) => println!("New secret key (BIP38-encrypted): {}", &tweaked[..]),
+                (Reveal::Print, PrivkeyFormat::Slip39(threshold, count)) => {
+                    println!("New secret key as SLIP-39 shares, any {} of the {} recovering it:", threshold, count);
+                    for (n, share) in tweaked.lines().enumerate() {
+                        println!("    Share {}: {}", n + 1, share);
+                    }
+                }
+                (Reveal::Print, _) => println!("New secret key: {}", &tweaked[..]),
+                (Reveal::File(ref filename), _) => write_secret_file(filename, &secret_line(&tweaked)),
+                (Reveal::Clipboard, _) => {
+                    copied = copy_to_clipboard("New secret key", &tweaked);
+                    if copied {
+                        println!("The clipboard will be cleared in {} seconds.", clipboard::CLEAR_SECONDS);
+                    }
+                }
+                (Reveal::Cancel, _) => fail("cancelled", "", "Not printing the new secret key.")
+            }
+            if let Some(ref filename) = electrum_wallet {
+                let wallet = tweak_privkey(&private_key)
+                                 .and_then(|key| electrum::key_wallet(&secp, &[key]).map_err(|e| format!("{:?}", e)));
+                match wallet {
+                    Ok(wallet) => write_electrum_wallet(filename, &wallet, signer.as_ref()),
+                    Err(e) => fail("io", "--electrum-wallet", &format!("Unable to make Electrum wallet: {}", e))
+                }
+            }
+            if copied {
+                clipboard::clear_after(&tweaked, clipboard::CLEAR_SECONDS);
             }
-            println!("New secret key: {}", tweaked_privkey.to_base58check());
         }
     }
 }
-
-
-
@@ -0,0 +1,417 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Secret Memory
+//! Keeping secret keys out of swap and crash dumps. A `Secret` holds its
+//! value on pages of its own, locked into memory with `mlock` (or
+//! `VirtualLock` on Windows) and zeroed when it is dropped; only what the
+//! value holds inline is protected, so it is for keys, not strings. Key
+//! text (WIF, hex, shares) goes in a `SecretText` instead, whose bytes
+//! are themselves on locked pages, and which never grows into a new
+//! allocation, as a `String` would, leaving a copy behind. Locking is best
+//! effort: where the platform refuses (e.g. over `RLIMIT_MEMLOCK`) the
+//! value is simply held unlocked.
+//!
+//! `disable_core_dumps` should be called before any secret is read, since
+//! a locked page is still written to a core dump.
+//!
+
+use std::alloc::{self, Layout};
+use std::io::{self, BufRead, Read};
+use std::ops::{Deref, DerefMut};
+use std::{cmp, fmt, mem, ptr, slice, str};
+
+/// The largest page size of the platforms we run on, to which secrets are
+/// aligned so that no two share a page
+const PAGE_SIZE: usize = 16384;
+
+#[cfg(unix)]
+mod sys {
+    use std::os::raw::{c_int, c_void};
+
+    #[cfg(target_os = "macos")]
+    type RlimT = u64;
+    #[cfg(not(target_os = "macos"))]
+    type RlimT = ::std::os::raw::c_ulong;
+
+    #[repr(C)]
+    struct Rlimit {
+        rlim_cur: RlimT,
+        rlim_max: RlimT
+    }
+
+    /// RLIMIT_CORE, which is the same on every Unix we run on
+    const RLIMIT_CORE: c_int = 4;
+    #[cfg(target_os = "linux")]
+    const PR_SET_DUMPABLE: c_int = 4;
+
+    extern "C" {
+        fn mlock(addr: *const c_void, len: usize) -> c_int;
+        fn munlock(addr: *const c_void, len: usize) -> c_int;
+        fn setrlimit(resource: c_int, rlim: *const Rlimit) -> c_int;
+        #[cfg(target_os = "linux")]
+        fn prctl(option: c_int, arg2: usize, arg3: usize, arg4: usize, arg5: usize) -> c_int;
+    }
+
+    pub fn lock(addr: *const u8, len: usize) -> bool {
+        unsafe { mlock(addr as *const c_void, len) == 0 }
+    }
+
+    pub fn unlock(addr: *const u8, len: usize) {
+        unsafe { munlock(addr as *const c_void, len); }
+    }
+
+    pub fn disable_core_dumps() -> bool {
+        let limit = Rlimit { rlim_cur: 0, rlim_max: 0 };
+        let ret = unsafe { setrlimit(RLIMIT_CORE, &limit) == 0 };
+        // Also keeps other processes of the same user from ptracing us
+        #[cfg(target_os = "linux")]
+        unsafe { prctl(PR_SET_DUMPABLE, 0, 0, 0, 0); }
+        ret
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::os::raw::c_void;
+
+    extern "system" {
+        fn VirtualLock(addr: *mut c_void, len: usize) -> i32;
+        fn VirtualUnlock(addr: *mut c_void, len: usize) -> i32;
+    }
+
+    pub fn lock(addr: *const u8, len: usize) -> bool {
+        unsafe { VirtualLock(addr as *mut c_void, len) != 0 }
+    }
+
+    pub fn unlock(addr: *const u8, len: usize) {
+        unsafe { VirtualUnlock(addr as *mut c_void, len); }
+    }
+
+    /// Crash dumps are configured system-wide, by Windows Error Reporting
+    pub fn disable_core_dumps() -> bool {
+        false
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    pub fn lock(_: *const u8, _: usize) -> bool { false }
+    pub fn unlock(_: *const u8, _: usize) {}
+    pub fn disable_core_dumps() -> bool { false }
+}
+
+/// Keep this process from writing core dumps, returning whether it could
+pub fn disable_core_dumps() -> bool {
+    sys::disable_core_dumps()
+}
+
+/// Zero some memory, in a way the compiler will not optimize away
+pub fn zero(data: &mut [u8]) {
+    for byte in data.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0); }
+    }
+}
+
+/// Zero a string, which stays valid UTF-8
+pub fn zero_str(text: &mut str) {
+    zero(unsafe { text.as_bytes_mut() })
+}
+
+/// Pages of their own, locked if the platform agrees, and zeroed when
+/// freed
+struct Pages {
+    ptr: *mut u8,
+    layout: Layout,
+    locked: bool
+}
+
+impl Pages {
+    /// Zeroed pages holding at least `len` bytes
+    fn new(len: usize) -> Pages {
+        let size = (cmp::max(1, len) + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        let layout = Layout::from_size_align(size, PAGE_SIZE).unwrap();
+        unsafe {
+            let ptr = alloc::alloc_zeroed(layout);
+            if ptr.is_null() {
+                alloc::handle_alloc_error(layout);
+            }
+            Pages { ptr: ptr, layout: layout, locked: sys::lock(ptr, size) }
+        }
+    }
+}
+
+impl Drop for Pages {
+    fn drop(&mut self) {
+        unsafe {
+            zero(slice::from_raw_parts_mut(self.ptr, self.layout.size()));
+            if self.locked {
+                sys::unlock(self.ptr, self.layout.size());
+            }
+            alloc::dealloc(self.ptr, self.layout);
+        }
+    }
+}
+
+/// A value held on locked pages of its own, and zeroed when dropped
+pub struct Secret<T> {
+    ptr: *mut T,
+    pages: Pages
+}
+
+impl<T> Secret<T> {
+    /// Move a value onto locked pages. Copies of it made before this, such
+    /// as the one passed in, are not protected.
+    pub fn new(value: T) -> Secret<T> {
+        let pages = Pages::new(mem::size_of::<T>());
+        unsafe {
+            ptr::write(pages.ptr as *mut T, value);
+        }
+        Secret { ptr: pages.ptr as *mut T, pages: pages }
+    }
+
+    /// Whether the platform agreed to lock the value's pages
+    pub fn is_locked(&self) -> bool {
+        self.pages.locked
+    }
+}
+
+impl<T> Deref for Secret<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T> DerefMut for Secret<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<T> Drop for Secret<T> {
+    fn drop(&mut self) {
+        // The pages are zeroed as they are freed
+        unsafe { ptr::drop_in_place(self.ptr); }
+    }
+}
+
+// A `Secret` owns its value as a `Box` would
+unsafe impl<T: Send> Send for Secret<T> {}
+unsafe impl<T: Sync> Sync for Secret<T> {}
+
+/// Text held on locked pages of its own, and zeroed when dropped. Its
+/// capacity is fixed when it is made, so it is never moved.
+pub struct SecretText {
+    pages: Pages,
+    capacity: usize,
+    len: usize
+}
+
+impl SecretText {
+    /// Empty text with room for `capacity` bytes
+    pub fn with_capacity(capacity: usize) -> SecretText {
+        SecretText { pages: Pages::new(capacity), capacity: capacity, len: 0 }
+    }
+
+    /// Move a string onto locked pages, zeroing it. Copies of it made
+    /// before this, e.g. as it grew, are not protected.
+    pub fn take(mut text: String) -> SecretText {
+        let ret = SecretText::from(&text[..]);
+        zero_str(&mut text);
+        ret
+    }
+
+    /// The number of bytes which can still be pushed
+    pub fn remaining(&self) -> usize {
+        self.capacity - self.len
+    }
+
+    /// Whether the platform agreed to lock the text's pages
+    pub fn is_locked(&self) -> bool {
+        self.pages.locked
+    }
+
+    /// Append bytes, which the caller has checked fit
+    fn push_bytes(&mut self, data: &[u8]) {
+        assert!(data.len() <= self.remaining(), "secret text is over its capacity");
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.pages.ptr.offset(self.len as isize), data.len());
+        }
+        self.len += data.len();
+    }
+
+    /// Append some text. Panics if it does not fit.
+    pub fn push_str(&mut self, text: &str) {
+        self.push_bytes(text.as_bytes())
+    }
+}
+
+impl<'a> From<&'a str> for SecretText {
+    /// Copy text onto locked pages
+    fn from(text: &'a str) -> SecretText {
+        let mut ret = SecretText::with_capacity(text.len());
+        ret.push_str(text);
+        ret
+    }
+}
+
+impl Deref for SecretText {
+    type Target = str;
+    fn deref(&self) -> &str {
+        // Only text is pushed, save by `read_line` and `read_all`, which
+        // check it
+        unsafe { str::from_utf8_unchecked(slice::from_raw_parts(self.pages.ptr, self.len)) }
+    }
+}
+
+impl fmt::Debug for SecretText {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretText({} bytes)", self.len)
+    }
+}
+
+unsafe impl Send for SecretText {}
+unsafe impl Sync for SecretText {}
+
+/// Read a line straight onto locked pages, without its line ending, or
+/// `None` at the end of the input. A line longer than `capacity` bytes is
+/// an error. The reader's own buffer still sees the line.
+pub fn read_line<R: BufRead>(reader: &mut R, capacity: usize) -> io::Result<Option<SecretText>> {
+    let mut ret = SecretText::with_capacity(capacity);
+    let mut read_any = false;
+    loop {
+        let (used, done) = {
+            let data = try!(reader.fill_buf());
+            if data.is_empty() {
+                break;
+            }
+            read_any = true;
+            let (line, used, done) = match data.iter().position(|&b| b == b'\n') {
+                Some(end) => (&data[..end], end + 1, true),
+                None => (data, data.len(), false)
+            };
+            if line.len() > ret.remaining() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("line is longer than {} bytes", capacity)));
+            }
+            ret.push_bytes(line);
+            (used, done)
+        };
+        reader.consume(used);
+        if done {
+            break;
+        }
+    }
+    if !read_any {
+        return Ok(None);
+    }
+    let valid = {
+        let mut bytes = unsafe { slice::from_raw_parts(ret.pages.ptr, ret.len) };
+        if bytes.last() == Some(&b'\r') {
+            bytes = &bytes[..bytes.len() - 1];
+        }
+        str::from_utf8(bytes).map(|line| line.len())
+    };
+    match valid {
+        Ok(len) => {
+            ret.len = len;
+            Ok(Some(ret))
+        }
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "line is not UTF-8"))
+    }
+}
+
+/// Read all of some input straight onto locked pages. Input longer than
+/// `capacity` bytes, or which is not UTF-8, is an error.
+pub fn read_all<R: Read>(reader: &mut R, capacity: usize) -> io::Result<SecretText> {
+    // One byte past the capacity shows whether there is more
+    let mut ret = SecretText { pages: Pages::new(capacity + 1), capacity: capacity, len: 0 };
+    loop {
+        let n = {
+            let space = unsafe { slice::from_raw_parts_mut(ret.pages.ptr.offset(ret.len as isize), ret.remaining() + 1) };
+            match reader.read(space) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => { return Err(e); }
+            }
+        };
+        if n == 0 {
+            break;
+        }
+        if n > ret.remaining() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("input is longer than {} bytes", capacity)));
+        }
+        ret.len += n;
+    }
+    let valid = str::from_utf8(unsafe { slice::from_raw_parts(ret.pages.ptr, ret.len) }).is_ok();
+    if !valid {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "input is not UTF-8"));
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn secret_text() {
+        let mut text = SecretText::with_capacity(8);
+        text.push_str("L1aW");
+        text.push_str("4aub");
+        assert_eq!(&text[..], "L1aW4aub");
+        assert_eq!(text.remaining(), 0);
+
+        let mut string = "5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ".to_owned();
+        let copy = string.clone();
+        string.reserve(1000);
+        let text = SecretText::take(string);
+        assert_eq!(&text[..], &copy[..]);
+        assert_eq!(format!("{:?}", text), "SecretText(51 bytes)");
+    }
+
+    #[test]
+    #[should_panic]
+    fn secret_text_overflow() {
+        let mut text = SecretText::with_capacity(3);
+        text.push_str("four");
+    }
+
+    #[test]
+    fn read_lines() {
+        let mut input = io::BufReader::with_capacity(4, &b"first line\r\nsecond\n\nlast"[..]);
+        let mut lines = vec![];
+        while let Some(line) = read_line(&mut input, 16).unwrap() {
+            lines.push(line[..].to_owned());
+        }
+        assert_eq!(lines, vec!["first line", "second", "", "last"]);
+
+        let mut input = &b"a line too long\nshort\n"[..];
+        assert_eq!(read_line(&mut input, 8).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut input = &b"\xff\xfe\n"[..];
+        assert_eq!(read_line(&mut input, 8).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn read_alls() {
+        let mut input = &b"whole\ninput\n"[..];
+        assert_eq!(&read_all(&mut input, 12).unwrap()[..], "whole\ninput\n");
+        let mut input = &b"whole\ninput\n"[..];
+        assert_eq!(read_all(&mut input, 11).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut input = &b""[..];
+        assert_eq!(&read_all(&mut input, 0).unwrap()[..], "");
+    }
+}
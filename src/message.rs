@@ -0,0 +1,151 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Signed Messages
+//! Bitcoin Core's `signmessage` format: a compact recoverable signature of
+//! a prefixed message hash, whose header byte records the recovery ID and
+//! whether the signing key is compressed
+//!
+
+use bitcoin::util::address::Privkey;
+use bitcoin::util::hash::Sha256dHash;
+use secp256k1::{self, Message, RecoverableSignature, RecoveryId, Secp256k1};
+use secp256k1::key::PublicKey;
+use serialize::base64::{self, FromBase64, ToBase64};
+
+use context;
+
+/// Prefix of every signed message, including its own length byte
+const MESSAGE_PREFIX: &'static [u8] = b"\x18Bitcoin Signed Message:\n";
+/// Header byte of a signature with recovery ID 0 by an uncompressed key
+const HEADER_BASE: u8 = 27;
+/// Amount added to the header byte for a compressed key
+const HEADER_COMPRESSED: u8 = 4;
+
+/// Signed message error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// Signature was not valid base64
+    Base64,
+    /// Signature was not 65 bytes with a valid header byte
+    BadSignature,
+    /// Secp256k1 error, e.g. no key could be recovered
+    Secp(secp256k1::Error)
+}
+
+/// The hash which a signed message signs
+pub fn message_hash(message: &str) -> Message {
+    let mut data = MESSAGE_PREFIX.to_vec();
+    // The message length is a Bitcoin varint
+    let len = message.len() as u64;
+    if len < 0xfd {
+        data.push(len as u8);
+    } else if len <= 0xffff {
+        data.push(0xfd);
+        data.extend((0..2).map(|i| (len >> (8 * i)) as u8));
+    } else if len <= 0xffffffff {
+        data.push(0xfe);
+        data.extend((0..4).map(|i| (len >> (8 * i)) as u8));
+    } else {
+        data.push(0xff);
+        data.extend((0..8).map(|i| (len >> (8 * i)) as u8));
+    }
+    data.extend(message.bytes());
+    Message::from_slice(&Sha256dHash::from_data(&data)[..]).unwrap()
+}
+
+/// Sign a message, giving the base64 signature `verifymessage` accepts. It
+/// is signed in this thread's signing context, freshly re-randomized.
+pub fn sign(key: &Privkey, message: &str) -> Result<String, Error> {
+    let (recid, compact) = try!(context::with_signing(|secp| {
+        secp.sign_recoverable(&message_hash(message), &key.key).map(|sig| sig.serialize_compact(secp))
+    }).map_err(Error::Secp));
+    let mut data = Vec::with_capacity(65);
+    data.push(HEADER_BASE + recid.to_i32() as u8 + if key.compressed { HEADER_COMPRESSED } else { 0 });
+    data.extend(compact.iter().cloned());
+    Ok(data.to_base64(base64::STANDARD))
+}
+
+/// Recover the key which signed a message, and whether the signature says
+/// it is used compressed
+pub fn recover(secp: &Secp256k1, signature: &str, message: &str) -> Result<(PublicKey, bool), Error> {
+    let data = try!(signature.from_base64().map_err(|_| Error::Base64));
+    if data.len() != 65 || data[0] < HEADER_BASE || data[0] >= HEADER_BASE + 2 * HEADER_COMPRESSED {
+        return Err(Error::BadSignature);
+    }
+    let compressed = data[0] >= HEADER_BASE + HEADER_COMPRESSED;
+    let recid = try!(RecoveryId::from_i32(((data[0] - HEADER_BASE) % HEADER_COMPRESSED) as i32).map_err(Error::Secp));
+    let sig = try!(RecoverableSignature::from_compact(secp, &data[1..], recid).map_err(Error::Secp));
+    let key = try!(secp.recover(&message_hash(message), &sig).map_err(Error::Secp));
+    Ok((key, compressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::network::constants::Network;
+    use bitcoin::util::address::Privkey;
+    use secp256k1::Secp256k1;
+    use secp256k1::key::{PublicKey, SecretKey};
+    use serialize::base64::{self, FromBase64, ToBase64};
+
+    use super::*;
+
+    /// rust-bitcoin's signed message: the message, secret key, public key
+    /// and signature, base64 encoded
+    const MESSAGE: &'static str = "rust-bitcoin MessageSignature test";
+    const SECRET: &'static str = "UuOGDsfLPr4HIMKQX0ipjJeRaj1geCq3yPUF2COP5ME=";
+    const PUBLIC: &'static str = "A1FTfMEntPpAty3qkEo0q2Dc1FEycI10a3jmwEFy+Qr6";
+    const SIGNATURE: &'static str = "IAM2qX24tYx/bdBTIgVLhD8QEAjrPlJpmjB4nZHdRYGIBa4DmVulAcwjPnWe6Q5iEwXH6F0pUCJP/ZeHPWS1h1o=";
+
+    #[test]
+    fn sign_and_recover() {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&secp, &SECRET.from_base64().unwrap()).unwrap();
+        let public = PublicKey::from_slice(&secp, &PUBLIC.from_base64().unwrap()).unwrap();
+
+        // The compressed key's header is 27 + 4 + the recovery ID, here 1
+        let compressed = Privkey { compressed: true, network: Network::Bitcoin, key: secret };
+        assert_eq!(sign(&compressed, MESSAGE).unwrap(), SIGNATURE);
+        assert_eq!(recover(&secp, SIGNATURE, MESSAGE).unwrap(), (public, true));
+
+        // The same signature by the uncompressed key differs only in its
+        // header, 27 + the recovery ID
+        let mut data = SIGNATURE.from_base64().unwrap();
+        assert_eq!(data[0], 32);
+        data[0] = 28;
+        let uncompressed_sig = data.to_base64(base64::STANDARD);
+        let uncompressed = Privkey { compressed: false, network: Network::Bitcoin, key: secret };
+        assert_eq!(sign(&uncompressed, MESSAGE).unwrap(), uncompressed_sig);
+        assert_eq!(recover(&secp, &uncompressed_sig, MESSAGE).unwrap(), (public, false));
+
+        // Another message recovers another key
+        assert!(recover(&secp, SIGNATURE, "a different message from what was signed").unwrap().0 != public);
+        // Headers outside 27 to 34, and truncated or non-base64 signatures, are refused
+        for &header in &[26, 35] {
+            data[0] = header;
+            match recover(&secp, &data.to_base64(base64::STANDARD), MESSAGE) {
+                Err(Error::BadSignature) => {}
+                other => panic!("unexpected result {:?}", other)
+            }
+        }
+        match recover(&secp, &SIGNATURE[..40], MESSAGE) {
+            Err(Error::BadSignature) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+        match recover(&secp, "not base64!", MESSAGE) {
+            Err(Error::Base64) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Metrics
+//! Counters and latencies of a long-running server or watcher, rendered in
+//! Prometheus' text format for a `/metrics` endpoint. Series are created
+//! the first time they are touched, so a fresh process exposes only the
+//! metrics it has something to say about.
+//!
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+/// Upper bounds, in seconds, of the latency histogram's buckets
+const LATENCY_BUCKETS: [f64; 8] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.1, 1.0];
+
+/// The help text of each metric, which also fixes its type
+const HELP: [(&'static str, &'static str, &'static str); 8] = [
+    ("pacthash_requests_total", "counter", "Requests answered, by method."),
+    ("pacthash_request_errors_total", "counter", "Requests which failed, by method."),
+    ("pacthash_rate_limited_total", "counter", "Requests refused for exceeding a rate limit, by which limit."),
+    ("pacthash_derivations_total", "counter", "Tweaked keys, scripts and addresses derived."),
+    ("pacthash_verify_failures_total", "counter", "Verifications of addresses which did not commit to their contract."),
+    ("pacthash_request_duration_seconds", "histogram", "Time taken to answer requests, by method."),
+    ("pacthash_watched_scripts", "gauge", "ScriptPubKeys being watched."),
+    ("pacthash_watch_events_total", "counter", "Payments to and spends from watched scriptPubKeys."),
+];
+
+/// A histogram of one method's latencies
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    /// Observations at or under each bucket's bound
+    buckets: [u64; 8],
+    /// All observations
+    count: u64,
+    /// Sum of the observations, in seconds
+    sum: f64
+}
+
+/// The metrics of a process
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Counters and gauges, by name and then labels
+    values: Mutex<BTreeMap<(&'static str, String), f64>>,
+    /// Latencies, by the label of what was timed
+    latencies: Mutex<BTreeMap<String, Histogram>>
+}
+
+impl Metrics {
+    /// No metrics yet
+    pub fn new() -> Metrics {
+        Default::default()
+    }
+
+    /// Add one to a counter with some labels, e.g. `method="verify"`
+    pub fn increment(&self, name: &'static str, labels: &str) {
+        *self.values.lock().unwrap().entry((name, labels.to_owned())).or_insert(0.0) += 1.0;
+    }
+
+    /// Set a gauge
+    pub fn set(&self, name: &'static str, labels: &str, value: f64) {
+        self.values.lock().unwrap().insert((name, labels.to_owned()), value);
+    }
+
+    /// Record how long a request took
+    pub fn observe_latency(&self, labels: &str, seconds: f64) {
+        let mut latencies = self.latencies.lock().unwrap();
+        let histogram = latencies.entry(labels.to_owned()).or_insert_with(Histogram::default);
+        for (n, &bound) in LATENCY_BUCKETS.iter().enumerate() {
+            if seconds <= bound {
+                histogram.buckets[n] += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    /// The metrics in Prometheus' text exposition format
+    pub fn render(&self) -> String {
+        let values = self.values.lock().unwrap();
+        let latencies = self.latencies.lock().unwrap();
+        let mut ret = String::new();
+        for &(name, ty, help) in HELP.iter() {
+            let series: Vec<(&String, &f64)> = values.iter().filter(|&(&(n, _), _)| n == name)
+                                                     .map(|(&(_, ref labels), value)| (labels, value)).collect();
+            if series.is_empty() && (ty != "histogram" || latencies.is_empty()) {
+                continue;
+            }
+            let _ = writeln!(ret, "# HELP {} {}", name, help);
+            let _ = writeln!(ret, "# TYPE {} {}", name, ty);
+            if ty == "histogram" {
+                for (labels, histogram) in latencies.iter() {
+                    let sep = if labels.is_empty() { "" } else { "," };
+                    for (n, bound) in LATENCY_BUCKETS.iter().enumerate() {
+                        let _ = writeln!(ret, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, sep, bound, histogram.buckets[n]);
+                    }
+                    let _ = writeln!(ret, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, sep, histogram.count);
+                    let _ = writeln!(ret, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+                    let _ = writeln!(ret, "{}_count{{{}}} {}", name, labels, histogram.count);
+                }
+                continue;
+            }
+            for (labels, value) in series {
+                if labels.is_empty() {
+                    let _ = writeln!(ret, "{} {}", name, value);
+                } else {
+                    let _ = writeln!(ret, "{}{{{}}} {}", name, labels, value);
+                }
+            }
+        }
+        ret
+    }
+}
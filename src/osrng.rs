@@ -0,0 +1,68 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # System Randomness
+//! The operating system's random number generator, which nonces and keys
+//! are made with. This is rand's `OsRng`, except under WASI, where rand
+//! has none (its `OsRng::new` always fails on wasm32) and the runtime's
+//! `random_get` is asked instead.
+//!
+
+#[cfg(not(target_os = "wasi"))]
+pub use rand::OsRng;
+#[cfg(target_os = "wasi")]
+pub use self::wasi::OsRng;
+
+#[cfg(target_os = "wasi")]
+mod wasi {
+    use rand::Rng;
+    use std::io;
+
+    #[link(wasm_import_module = "wasi_snapshot_preview1")]
+    extern "C" {
+        fn random_get(buf: *mut u8, len: usize) -> i32;
+    }
+
+    /// The WASI runtime's random number generator
+    pub struct OsRng(());
+
+    impl OsRng {
+        /// Every WASI runtime has `random_get`, so this does not fail
+        pub fn new() -> io::Result<OsRng> {
+            Ok(OsRng(()))
+        }
+    }
+
+    impl Rng for OsRng {
+        fn next_u32(&mut self) -> u32 {
+            let mut buf = [0; 4];
+            self.fill_bytes(&mut buf);
+            buf.iter().fold(0, |acc, &b| acc << 8 | b as u32)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            let mut buf = [0; 8];
+            self.fill_bytes(&mut buf);
+            buf.iter().fold(0, |acc, &b| acc << 8 | b as u64)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            // As rand's own OsRng does, fail loudly rather than give out
+            // bytes which may be predictable
+            if unsafe { random_get(dest.as_mut_ptr(), dest.len()) } != 0 {
+                panic!("random_get failed");
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # OpenTimestamps
+//! Proving when a commitment was made. A file, such as the serialized
+//! contracts of a batch, is timestamped by the `ots` client, which submits
+//! its hash to the public OpenTimestamps calendars and writes the proof
+//! beside it as `FILE.ots`. The proof is at first only the calendars'
+//! promise; once a calendar's commitment is in a block, `upgrade` fills in
+//! the path to it, after which `verify` can check it against the chain
+//! without trusting the calendars.
+//!
+
+use std::process::{Command, Stdio};
+
+/// Timestamping error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// The `ots` client could not be run
+    NoClient(String),
+    /// The client failed, with what it said
+    Failed(String)
+}
+
+/// Run the client, giving everything it said; it reports on stderr
+fn run(args: &[&str]) -> Result<String, Error> {
+    let output = try!(Command::new("ots").args(args)
+                          .stdin(Stdio::null())
+                          .output()
+                          .map_err(|e| Error::NoClient(format!("unable to run ots: {}", e))));
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    let text = text.trim().to_owned();
+    if output.status.success() {
+        Ok(text)
+    } else {
+        Err(Error::Failed(text))
+    }
+}
+
+/// Where the proof of a file is written
+pub fn proof_path(filename: &str) -> String {
+    format!("{}.ots", filename)
+}
+
+/// Timestamp a file, giving where its proof was written
+pub fn stamp(filename: &str) -> Result<String, Error> {
+    try!(run(&["stamp", filename]));
+    Ok(proof_path(filename))
+}
+
+/// Complete a proof whose calendar commitments are now in the chain,
+/// giving what the client said
+pub fn upgrade(proof: &str) -> Result<String, Error> {
+    run(&["upgrade", proof])
+}
+
+/// Check a proof against the chain, giving what the client said, which
+/// names the block attesting to the file. A proof still awaiting its block
+/// fails, since nothing attests to it yet
+pub fn verify(proof: &str) -> Result<String, Error> {
+    let said = try!(run(&["verify", proof]));
+    if said.contains("attests") {
+        Ok(said)
+    } else {
+        Err(Error::Failed(said))
+    }
+}
@@ -0,0 +1,87 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Parallel Maps
+//! Spreading independent work, such as deriving and tweaking the keys of
+//! each index of a range, over a few threads. The items are split into one
+//! contiguous run per thread, which is given its own copy of them, and the
+//! results are sent back over a channel and put in the order of the items,
+//! so output does not depend on how the threads were scheduled.
+//!
+
+use num_cpus;
+
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+
+/// The number of threads to use when none is asked for: one per core
+pub fn default_threads() -> usize {
+    let cpus = num_cpus::get();
+    if cpus == 0 { 1 } else { cpus }
+}
+
+/// Apply a function to every item, on up to `threads` threads, giving the
+/// results in the order of the items
+pub fn map<T, U, F>(items: &[T], threads: usize, f: F) -> Vec<U>
+    where T: Clone + Send + 'static, U: Send + 'static, F: Fn(&T) -> U + Send + Sync + 'static
+{
+    // WASI runtimes have no threads to spawn
+    if threads <= 1 || items.len() <= 1 || cfg!(target_os = "wasi") {
+        return items.iter().map(f).collect();
+    }
+    let run = (items.len() + threads - 1) / threads;
+    let f = Arc::new(f);
+    let (tx, rx) = mpsc::channel();
+    let handles: Vec<_> = items.chunks(run).enumerate().map(|(n, chunk)| {
+        let chunk = chunk.to_vec();
+        let (f, tx) = (f.clone(), tx.clone());
+        thread::spawn(move || {
+            let results: Vec<U> = chunk.iter().map(|item| f(item)).collect();
+            // The receiver is kept until every thread is done
+            tx.send((n, results)).unwrap();
+        })
+    }).collect();
+    drop(tx);
+
+    let mut runs: Vec<Option<Vec<U>>> = handles.iter().map(|_| None).collect();
+    for (n, results) in rx {
+        runs[n] = Some(results);
+    }
+    // A thread which panicked sent nothing; pass its panic on
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let mut ret = Vec::with_capacity(items.len());
+    for run in runs {
+        ret.extend(run.unwrap());
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::map;
+
+    #[test]
+    fn order() {
+        let items: Vec<u64> = (0..1000).collect();
+        let expected: Vec<u64> = items.iter().map(|&n| n * n).collect();
+        for threads in 1..9 {
+            assert_eq!(map(&items, threads, |&n| n * n), expected);
+        }
+        assert_eq!(map(&[7u64], 4, |&n| n + 1), vec![8]);
+        assert_eq!(map(&[] as &[u64], 4, |&n| n), vec![]);
+    }
+}
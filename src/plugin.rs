@@ -0,0 +1,161 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Plugins
+//! Contract types defined outside pacthash. A plugin is a program which
+//! turns a payload, in whatever format its authors like, into the 20 bytes
+//! of a contract's data, and back into something readable; pacthash still
+//! chooses the nonce and does the tweaking. Plugins are declared in a file
+//! (by default `~/.pacthash-plugins`), one per line, as a four-character
+//! type tag and the command to run:
+//!
+//! ```text
+//! # tag  program [args...]
+//! INVC   /usr/local/bin/invoice-contract --strict
+//! ```
+//!
+//! The program is run with `encode` or `decode` appended to its arguments.
+//! To encode, it is given the payload on stdin and prints the data in hex;
+//! to decode, it is given the data in hex and prints the payload. Exiting
+//! with a failure status rejects the input.
+//!
+
+use serialize::hex::{FromHex, ToHex};
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use contract::DATA_LEN;
+
+/// Plugin error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// The plugins file could not be read
+    Io(String),
+    /// A line of the plugins file was not a tag and a command
+    BadLine(usize),
+    /// A tag is not four characters, or is one of pacthash's own
+    BadTag(String),
+    /// The program could not be run, or failed
+    Failed(String),
+    /// The program printed something other than what was asked for
+    BadOutput(String)
+}
+
+/// A program which encodes and decodes one contract type
+#[derive(Clone, Debug)]
+pub struct Plugin {
+    /// The type tag of the contracts it handles
+    pub tag: [u8; 4],
+    /// The program to run
+    program: String,
+    /// Arguments to give before `encode` or `decode`
+    args: Vec<String>
+}
+
+impl Plugin {
+    /// Run the program in some mode, giving it some input
+    fn run(&self, mode: &str, input: &str) -> Result<String, Error> {
+        let mut child = try!(Command::new(&self.program).args(&self.args).arg(mode)
+                                 .stdin(Stdio::piped())
+                                 .stdout(Stdio::piped())
+                                 .stderr(Stdio::inherit())
+                                 .spawn()
+                                 .map_err(|e| Error::Failed(format!("unable to run {}: {}", self.program, e))));
+        try!(child.stdin.take().unwrap().write_all(input.as_bytes())
+                  .map_err(|e| Error::Failed(format!("{}: {}", self.program, e))));
+        let output = try!(child.wait_with_output().map_err(|e| Error::Failed(format!("{}: {}", self.program, e))));
+        if !output.status.success() {
+            return Err(Error::Failed(format!("{} {} failed ({})", self.program, mode, output.status)));
+        }
+        String::from_utf8(output.stdout).map(|s| s.trim().to_owned())
+                                        .map_err(|_| Error::BadOutput(format!("{} did not output text", self.program)))
+    }
+
+    /// Encode a payload as the data of a contract
+    pub fn encode(&self, payload: &str) -> Result<Vec<u8>, Error> {
+        let hex = try!(self.run("encode", payload));
+        match hex.from_hex() {
+            Ok(ref data) if data.len() == DATA_LEN => Ok(data.clone()),
+            _ => Err(Error::BadOutput(format!("{} encode did not output {} bytes of hex: {}", self.program, DATA_LEN, hex)))
+        }
+    }
+
+    /// Decode the data of a contract as its payload
+    pub fn decode(&self, data: &[u8]) -> Result<String, Error> {
+        self.run("decode", &data.to_hex())
+    }
+}
+
+/// The plugins declared in a file
+#[derive(Clone, Debug, Default)]
+pub struct Plugins {
+    plugins: Vec<Plugin>
+}
+
+impl Plugins {
+    /// Read a plugins file
+    pub fn read(filename: &str) -> Result<Plugins, Error> {
+        let mut text = String::new();
+        try!(File::open(filename).and_then(|mut f| f.read_to_string(&mut text))
+                 .map_err(|e| Error::Io(format!("{}: {}", filename, e))));
+        let mut ret = Plugins::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (tag, program) = match (fields.next(), fields.next()) {
+                (Some(tag), Some(program)) => (try!(parse_tag(tag)), program),
+                _ => { return Err(Error::BadLine(n + 1)); }
+            };
+            ret.plugins.push(Plugin {
+                tag: tag,
+                program: program.to_owned(),
+                args: fields.map(|s| s.to_owned()).collect()
+            });
+        }
+        Ok(ret)
+    }
+
+    /// The tags plugins are declared for
+    pub fn tags(&self) -> Vec<[u8; 4]> {
+        self.plugins.iter().map(|p| p.tag).collect()
+    }
+
+    /// The plugin for a tag
+    pub fn get(&self, tag: &[u8; 4]) -> Option<&Plugin> {
+        self.plugins.iter().find(|p| p.tag == *tag)
+    }
+}
+
+/// Check that a tag is four characters, and not one of pacthash's own types
+pub fn parse_tag(tag: &str) -> Result<[u8; 4], Error> {
+    let bytes = tag.as_bytes();
+    if bytes.len() != 4 || tag == "TEXT" || tag == "P2PH" || tag == "P2SH" {
+        return Err(Error::BadTag(tag.to_owned()));
+    }
+    Ok([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// The default plugins file, `~/.pacthash-plugins`
+pub fn default_path() -> PathBuf {
+    let mut ret = env::home_dir().unwrap_or(PathBuf::from("."));
+    ret.push(".pacthash-plugins");
+    ret
+}
@@ -0,0 +1,359 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Private Key Formats
+//! Decoding of private keys from the formats used alongside WIF, and BIP38
+//! encryption of private keys
+//!
+
+use bitcoin::util::address::Privkey;
+use bitcoin::util::base58::{self, FromBase58, ToBase58};
+use bitcoin::util::hash::Sha256dHash;
+use crypto::aessafe::{AesSafe256Decryptor, AesSafe256Encryptor};
+use crypto::digest::Digest;
+use crypto::scrypt::{scrypt, ScryptParams};
+use crypto::sha2::Sha256;
+use crypto::symmetriccipher::{BlockDecryptor, BlockEncryptor};
+
+use std::fs::File;
+use std::process::{Command, Stdio};
+use std::str;
+use secp256k1::{self, Secp256k1};
+use secp256k1::key::{PublicKey, SecretKey};
+use serialize::hex::FromHex;
+
+use chain::Chain;
+use memlock::{self, SecretText};
+
+/// The most key text read at once, e.g. from a key file: room for a set of
+/// SLIP-39 shares, far more than any single key needs
+pub const MAX_KEY_TEXT: usize = 4096;
+
+/// The base58 alphabet
+const BASE58_CHARS: &'static [u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Private key decoding error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// Mini private key failed its checksum
+    BadMiniKeyChecksum,
+    /// Base58 error
+    Base58(base58::Error),
+    /// Data was not a BIP38-encrypted key
+    NotBip38,
+    /// BIP38 key was made with EC multiplication, which is not supported
+    Bip38EcMultiply,
+    /// BIP38 key did not decrypt to the key of its address, i.e. the
+    /// passphrase was wrong
+    Bip38WrongPassphrase,
+    /// BIP38 key was given without a passphrase
+    MissingPassphrase,
+    /// Passphrase was not ASCII, and so would need Unicode normalization,
+    /// which is not supported
+    NonAsciiPassphrase,
+    /// Key file could not be read or decrypted
+    KeyFile(String),
+    /// Secp256k1 error, e.g. the key was out of range
+    Secp(secp256k1::Error)
+}
+
+/// The SHA256 of some data
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut sha = Sha256::new();
+    sha.input(data);
+    let mut ret = [0; 32];
+    sha.result(&mut ret);
+    ret
+}
+
+/// Decode a private key given as WIF of a chain, raw hex, a mini private
+/// key, or (with a passphrase) BIP38. Raw secrets have no network or
+/// compression flag of their own, so are given the chain's network and
+/// marked compressed.
+pub fn decode(secp: &Secp256k1, s: &str, chain: Chain, passphrase: Option<&str>) -> Result<Privkey, Error> {
+    match s.from_hex() {
+        Ok(ref data) if data.len() == 32 => {
+            let key = try!(SecretKey::from_slice(secp, data).map_err(Error::Secp));
+            Ok(Privkey { compressed: true, network: chain.network(), key: key })
+        }
+        _ if is_bip38(s) => {
+            match passphrase {
+                Some(passphrase) => from_bip38(secp, s, passphrase, chain),
+                None => Err(Error::MissingPassphrase)
+            }
+        }
+        _ if is_mini_key(s) => from_mini_key(secp, s, chain),
+        _ => chain.decode_privkey(s).map_err(Error::Base58)
+    }
+}
+
+/// Whether a string looks like a Casascius mini private key, i.e. is 22, 26
+/// or 30 base58 characters starting with `S`
+pub fn is_mini_key(s: &str) -> bool {
+    (s.len() == 22 || s.len() == 26 || s.len() == 30) &&
+        s.starts_with('S') &&
+        s.bytes().all(|b| BASE58_CHARS.contains(&b))
+}
+
+/// Decode a mini private key. These were used with uncompressed keys, so
+/// the result is marked uncompressed.
+pub fn from_mini_key(secp: &Secp256k1, s: &str, chain: Chain) -> Result<Privkey, Error> {
+    // The key is only valid if appending `?` gives a hash with a zero first byte
+    let mut check = s.to_owned();
+    check.push('?');
+    if sha256(check.as_bytes())[0] != 0 {
+        return Err(Error::BadMiniKeyChecksum);
+    }
+    let key = try!(SecretKey::from_slice(secp, &sha256(s.as_bytes())).map_err(Error::Secp));
+    Ok(Privkey {
+        compressed: false,
+        network: chain.network(),
+        key: key
+    })
+}
+
+/// Prefix of a BIP38 key made without EC multiplication, `6P` in base58
+const BIP38_PREFIX: [u8; 2] = [0x01, 0x42];
+/// Prefix of a BIP38 key made with EC multiplication
+const BIP38_EC_PREFIX: [u8; 2] = [0x01, 0x43];
+/// Flag byte of a BIP38 key made without EC multiplication
+const BIP38_FLAG: u8 = 0xc0;
+/// Flag bit of a BIP38 key which is used compressed
+const BIP38_FLAG_COMPRESSED: u8 = 0x20;
+
+/// Whether a string looks like a BIP38-encrypted private key
+pub fn is_bip38(s: &str) -> bool {
+    s.len() == 58 && s.starts_with("6P")
+}
+
+/// The first four bytes of the double-SHA256 of a key's address on a
+/// chain, which salts its BIP38 encryption and checks its decryption
+fn bip38_address_hash(secp: &Secp256k1, key: &Privkey, chain: Chain) -> Result<[u8; 4], Error> {
+    let public_key = try!(PublicKey::from_secret_key(secp, &key.key).map_err(Error::Secp));
+    let hash = Sha256dHash::from_data(chain.p2pkh_address(&public_key, key.compressed).as_bytes());
+    Ok([hash[0], hash[1], hash[2], hash[3]])
+}
+
+/// Stretch a passphrase into the 64 bytes which encrypt a key
+fn bip38_derive(passphrase: &str, address_hash: &[u8]) -> Result<[u8; 64], Error> {
+    if passphrase.bytes().any(|b| b >= 0x80) {
+        return Err(Error::NonAsciiPassphrase);
+    }
+    let mut ret = [0; 64];
+    scrypt(passphrase.as_bytes(), address_hash, &ScryptParams::new(14, 8, 8), &mut ret);
+    Ok(ret)
+}
+
+/// Decrypt a BIP38-encrypted private key of a chain
+pub fn from_bip38(secp: &Secp256k1, s: &str, passphrase: &str, chain: Chain) -> Result<Privkey, Error> {
+    let data: Vec<u8> = try!(FromBase58::from_base58check(s).map_err(Error::Base58));
+    if data.len() != 39 {
+        return Err(Error::NotBip38);
+    }
+    if &data[0..2] == &BIP38_EC_PREFIX[..] {
+        return Err(Error::Bip38EcMultiply);
+    }
+    if &data[0..2] != &BIP38_PREFIX[..] || data[2] & !BIP38_FLAG_COMPRESSED != BIP38_FLAG {
+        return Err(Error::NotBip38);
+    }
+
+    let derived = try!(bip38_derive(passphrase, &data[3..7]));
+    let aes = AesSafe256Decryptor::new(&derived[32..]);
+    let mut secret = [0; 32];
+    aes.decrypt_block(&data[7..23], &mut secret[..16]);
+    aes.decrypt_block(&data[23..39], &mut secret[16..]);
+    for (byte, mask) in secret.iter_mut().zip(derived[..32].iter()) {
+        *byte ^= *mask;
+    }
+
+    let key = Privkey {
+        compressed: data[2] & BIP38_FLAG_COMPRESSED != 0,
+        network: chain.network(),
+        key: try!(SecretKey::from_slice(secp, &secret).map_err(|_| Error::Bip38WrongPassphrase))
+    };
+    if &try!(bip38_address_hash(secp, &key, chain))[..] != &data[3..7] {
+        return Err(Error::Bip38WrongPassphrase);
+    }
+    Ok(key)
+}
+
+/// Encrypt a private key of a chain with BIP38
+pub fn to_bip38(secp: &Secp256k1, key: &Privkey, chain: Chain, passphrase: &str) -> Result<String, Error> {
+    let address_hash = try!(bip38_address_hash(secp, key, chain));
+    let derived = try!(bip38_derive(passphrase, &address_hash));
+    let mut secret = [0; 32];
+    for ((byte, key_byte), mask) in secret.iter_mut().zip(key.key[..].iter()).zip(derived[..32].iter()) {
+        *byte = *key_byte ^ *mask;
+    }
+
+    let mut ret = Vec::with_capacity(39);
+    ret.extend(BIP38_PREFIX.iter().cloned());
+    ret.push(if key.compressed { BIP38_FLAG | BIP38_FLAG_COMPRESSED } else { BIP38_FLAG });
+    ret.extend(address_hash.iter().cloned());
+    let aes = AesSafe256Encryptor::new(&derived[32..]);
+    let mut block = [0; 16];
+    aes.encrypt_block(&secret[..16], &mut block);
+    ret.extend(block.iter().cloned());
+    aes.encrypt_block(&secret[16..], &mut block);
+    ret.extend(block.iter().cloned());
+    Ok(ret.to_base58check())
+}
+
+/// Run a program which prints a key, letting it prompt on the terminal, and
+/// collect its output, trimmed
+fn run_for_key(program: &str, args: &[&str]) -> Result<SecretText, Error> {
+    let mut output = try!(Command::new(program).args(args)
+                              .stdin(Stdio::inherit())
+                              .stderr(Stdio::inherit())
+                              .output()
+                              .map_err(|e| Error::KeyFile(format!("unable to run {}: {}", program, e))));
+    let ret = if !output.status.success() {
+        Err(Error::KeyFile(format!("{} failed ({})", program, output.status)))
+    } else {
+        match str::from_utf8(&output.stdout) {
+            Ok(text) => Ok(SecretText::from(text.trim())),
+            Err(_) => Err(Error::KeyFile(format!("{} did not output text", program)))
+        }
+    };
+    memlock::zero(&mut output.stdout);
+    ret
+}
+
+/// Read a private key from a file. Files ending in `.age`, `.gpg` or `.asc`
+/// are decrypted by piping them through `age` or `gpg`, whose prompts go to
+/// the terminal; the plaintext is only ever held in memory.
+pub fn read_key_file(filename: &str) -> Result<SecretText, Error> {
+    let decryptor = if filename.ends_with(".age") {
+        Some(("age", vec!["--decrypt", filename]))
+    } else if filename.ends_with(".gpg") || filename.ends_with(".asc") {
+        Some(("gpg", vec!["--quiet", "--decrypt", filename]))
+    } else {
+        None
+    };
+
+    match decryptor {
+        Some((program, args)) => run_for_key(program, &args),
+        None => {
+            let text = try!(File::open(filename).and_then(|mut f| memlock::read_all(&mut f, MAX_KEY_TEXT))
+                                .map_err(|e| Error::KeyFile(format!("{}: {}", filename, e))));
+            Ok(SecretText::from(text.trim()))
+        }
+    }
+}
+
+/// Look up a private key stored under `name` in the macOS Keychain, as a
+/// generic password of the `pacthash` service
+#[cfg(target_os = "macos")]
+pub fn read_keychain(name: &str) -> Result<SecretText, Error> {
+    run_for_key("security", &["find-generic-password", "-s", "pacthash", "-a", name, "-w"])
+}
+
+/// Look up a private key stored under `name` with libsecret, with
+/// attributes `service pacthash key <name>`
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn read_keychain(name: &str) -> Result<SecretText, Error> {
+    run_for_key("secret-tool", &["lookup", "service", "pacthash", "key", name])
+}
+
+/// Look up a private key in the platform secret store. Windows Credential
+/// Manager has no standard tool which reveals stored secrets, so this is
+/// not supported there.
+#[cfg(not(unix))]
+pub fn read_keychain(_: &str) -> Result<SecretText, Error> {
+    Err(Error::KeyFile("no supported secret store on this platform".to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::util::base58::ToBase58;
+    use secp256k1::Secp256k1;
+    use serialize::hex::ToHex;
+
+    use chain::Chain;
+    use super::*;
+
+    /// The vectors of BIP38 without EC multiplication: passphrase,
+    /// encrypted key, WIF key and its secret
+    const BIP38_VECTORS: [(&'static str, &'static str, &'static str, &'static str); 4] = [
+        ("TestingOneTwoThree", "6PRVWUbkzzsbcVac2qwfssoUJAN1Xhrg6bNk8J7Nzm5H7kxEbn2Nh2ZoGg",
+         "5KN7MzqK5wt2TP1fQCYyHBtDrXdJuXbUzm4A9rKAteGu3Qi5CVR", "cbf4b9f70470856bb4f40f80b87edb90865997ffee6df315ab166d713af433a5"),
+        ("Satoshi", "6PRNFFkZc2NZ6dJqFfhRoFNMR9Lnyj7dYGrzdgXXVMXcxoKTePPX1dWByq",
+         "5HtasZ6ofTHP6HCwTqTkLDuLQisYPah7aUnSKfC7h4hMUVw2gi5", "09c2686880095b1a4c249ee3ac4eea8a014f11e6f986d0b5025ac1f39afbd9ae"),
+        ("TestingOneTwoThree", "6PYNKZ1EAgYgmQfmNVamxyXVWHzK5s6DGhwP4J5o44cvXdoY7sRzhtpUeo",
+         "L44B5gGEpqEDRS9vVPz7QT35jcBG2r3CZwSwQ4fCewXAhAhqGVpP", "cbf4b9f70470856bb4f40f80b87edb90865997ffee6df315ab166d713af433a5"),
+        ("Satoshi", "6PYLtMnXvfG3oJde97zRyLYFZCYizPU5T3LwgdYJz1fRhh16bU7u6PPmY7",
+         "KwYgW8gcxj1JWJXhPSu4Fqwzfhp5Yfi42mdYmMa4XqK7NJxXUSK7", "09c2686880095b1a4c249ee3ac4eea8a014f11e6f986d0b5025ac1f39afbd9ae")
+    ];
+
+    #[test]
+    fn bip38() {
+        let secp = Secp256k1::new();
+        let chain = Chain::Bitcoin;
+        for &(passphrase, encrypted, wif, secret) in &BIP38_VECTORS {
+            assert!(is_bip38(encrypted));
+            let key = from_bip38(&secp, encrypted, passphrase, chain).unwrap();
+            assert_eq!(key.key[..].to_hex(), secret);
+            assert_eq!(key.compressed, wif.len() == 52);
+            assert_eq!(chain.encode_privkey(&key), wif);
+            assert_eq!(decode(&secp, encrypted, chain, Some(passphrase)).unwrap().key, key.key);
+
+            let key = chain.decode_privkey(wif).unwrap();
+            assert_eq!(to_bip38(&secp, &key, chain, passphrase).unwrap(), encrypted);
+        }
+
+        let (_, encrypted, _, _) = BIP38_VECTORS[0];
+        match from_bip38(&secp, encrypted, "TestingOneTwoFour", chain).err() {
+            Some(Error::Bip38WrongPassphrase) => {}
+            other => panic!("unexpected error {:?}", other)
+        }
+        match decode(&secp, encrypted, chain, None).err() {
+            Some(Error::MissingPassphrase) => {}
+            other => panic!("unexpected error {:?}", other)
+        }
+        match from_bip38(&secp, encrypted, "Testing\u{e9}", chain).err() {
+            Some(Error::NonAsciiPassphrase) => {}
+            other => panic!("unexpected error {:?}", other)
+        }
+
+        // A key made with EC multiplication is refused before any work
+        let mut ec_multiply = vec![0x01, 0x43, 0x20];
+        ec_multiply.extend([0x55; 36].iter().cloned());
+        match from_bip38(&secp, &ec_multiply.to_base58check(), "Satoshi", chain).err() {
+            Some(Error::Bip38EcMultiply) => {}
+            other => panic!("unexpected error {:?}", other)
+        }
+    }
+
+    #[test]
+    fn mini_key() {
+        let secp = Secp256k1::new();
+        let mini = "S6c56bnXQiBjk9mqSYE7ykVQ7NzrRy";
+        assert!(is_mini_key(mini));
+        let key = from_mini_key(&secp, mini, Chain::Bitcoin).unwrap();
+        assert_eq!(key.key[..].to_hex(), "4c7a9640c72dc2099f23715d0c8a0d8a35f8906e3cab61dd3f78b67bf887c9ab");
+        assert!(!key.compressed);
+        assert_eq!(Chain::Bitcoin.encode_privkey(&key), "5JPy8Zg7z4P7RSLsiqcqyeAF1935zjNUdMxcDeVrtU1oarrgnB7");
+        assert_eq!(decode(&secp, mini, Chain::Bitcoin, None).unwrap().key, key.key);
+
+        // One character changed fails the checksum
+        let typo = "S6c56bnXQiBjk9mqSYE7ykVQ7NzrRz";
+        assert!(is_mini_key(typo));
+        match from_mini_key(&secp, typo, Chain::Bitcoin).err() {
+            Some(Error::BadMiniKeyChecksum) => {}
+            other => panic!("unexpected error {:?}", other)
+        }
+        assert!(!is_mini_key("S6c56bnXQiBjk9mqSYE7ykVQ7NzrR"));
+        assert!(!is_mini_key("T6c56bnXQiBjk9mqSYE7ykVQ7NzrRy"));
+    }
+}
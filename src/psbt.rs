@@ -0,0 +1,528 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT
+//! Just enough of BIP174 partially signed transactions to attach modified
+//! redeem and witness scripts to the inputs and outputs which pay to them.
+//! Maps are kept as raw key-value pairs, so fields this module does not
+//! understand pass through untouched.
+//!
+//! Tweaked keys are not BIP32 children of anything, so no BIP32 derivation
+//! fields are written for them; a signer could only be misled by the
+//! derivation of the untweaked key. Instead each tweaked key gets a
+//! proprietary field, keyed by `0xfc "pacthash" 0x00 <tweaked key>`, whose
+//! value is the untweaked key, the contract, and the untweaked key's
+//! fingerprint and path if known.
+//!
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::network::constants::Network;
+use bitcoin::util::address::Address;
+use secp256k1::Secp256k1;
+use secp256k1::key::PublicKey;
+use serialize::base64::{self, FromBase64, ToBase64};
+
+use bip32::KeySource;
+use contract::Contract;
+use segwit;
+use sweep::UnsignedTx;
+
+/// Magic bytes which start every PSBT
+const MAGIC: &'static [u8] = b"psbt\xff";
+/// Global key type of the unsigned transaction
+const GLOBAL_UNSIGNED_TX: u8 = 0x00;
+/// Input key type of the full transaction being spent
+const IN_NON_WITNESS_UTXO: u8 = 0x00;
+/// Input key type of the output being spent
+const IN_WITNESS_UTXO: u8 = 0x01;
+/// Input key type of the redeem script
+const IN_REDEEM_SCRIPT: u8 = 0x04;
+/// Input key type of the witness script
+const IN_WITNESS_SCRIPT: u8 = 0x05;
+/// Output key type of the redeem script
+const OUT_REDEEM_SCRIPT: u8 = 0x00;
+/// Output key type of the witness script
+const OUT_WITNESS_SCRIPT: u8 = 0x01;
+/// Key type of proprietary fields
+const PROPRIETARY: u8 = 0xfc;
+/// Identifier of pacthash's proprietary fields
+const PROPRIETARY_ID: &'static [u8] = b"pacthash";
+/// Proprietary subtype of a tweaked key
+const SUBTYPE_TWEAK: u8 = 0x00;
+
+/// PSBT error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// PSBT was neither binary nor valid base64
+    Base64,
+    /// Data did not start with the PSBT magic
+    BadMagic,
+    /// Data ended in the middle of a field
+    Truncated,
+    /// A transaction could not be parsed
+    BadTransaction,
+    /// The global map had no unsigned transaction
+    MissingTransaction
+}
+
+/// A map of raw keys to raw values
+pub type Map = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// A cursor over serialized data
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    /// Start reading some data
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data: data, pos: 0 }
+    }
+
+    /// How many bytes have been read
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Whether all the data has been read
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    /// Read some bytes
+    pub fn read(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() - self.pos < len {
+            return Err(Error::Truncated);
+        }
+        self.pos += len;
+        Ok(&self.data[self.pos - len..self.pos])
+    }
+
+    /// Read a little-endian number of some length
+    pub fn read_le(&mut self, len: usize) -> Result<u64, Error> {
+        let bytes = try!(self.read(len));
+        Ok(bytes.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u64))
+    }
+
+    /// Read a Bitcoin compact size
+    pub fn read_compact(&mut self) -> Result<u64, Error> {
+        match try!(self.read(1))[0] {
+            0xfd => self.read_le(2),
+            0xfe => self.read_le(4),
+            0xff => self.read_le(8),
+            n => Ok(n as u64)
+        }
+    }
+
+    /// Read a length-prefixed slice
+    pub fn read_slice(&mut self) -> Result<&'a [u8], Error> {
+        let len = try!(self.read_compact());
+        if len > (self.data.len() - self.pos) as u64 {
+            return Err(Error::Truncated);
+        }
+        self.read(len as usize)
+    }
+
+    /// Read a PSBT map
+    pub fn read_map(&mut self) -> Result<Map, Error> {
+        let mut map = vec![];
+        loop {
+            let key = try!(self.read_slice());
+            if key.is_empty() {
+                return Ok(map);
+            }
+            let value = try!(self.read_slice());
+            map.push((key.to_owned(), value.to_owned()));
+        }
+    }
+}
+
+/// Append a Bitcoin compact size
+pub fn write_compact(data: &mut Vec<u8>, n: u64) {
+    let len = if n < 0xfd {
+        data.push(n as u8);
+        0
+    } else if n <= 0xffff {
+        data.push(0xfd);
+        2
+    } else if n <= 0xffffffff {
+        data.push(0xfe);
+        4
+    } else {
+        data.push(0xff);
+        8
+    };
+    data.extend((0..len).map(|i| (n >> (8 * i)) as u8));
+}
+
+/// Append a length-prefixed slice
+pub fn write_slice(data: &mut Vec<u8>, slice: &[u8]) {
+    write_compact(data, slice.len() as u64);
+    data.extend(slice.iter().cloned());
+}
+
+/// The output index of a serialized outpoint
+fn input_vout(outpoint: &[u8]) -> u32 {
+    outpoint[32..].iter().rev().fold(0, |acc, &b| (acc << 8) | b as u32)
+}
+
+/// The spent output indices and the output scripts of a transaction
+fn parse_tx(data: &[u8]) -> Result<(Vec<u32>, Vec<Script>), Error> {
+    let tx = try!(UnsignedTx::parse(data).map_err(|_| Error::BadTransaction));
+    let vouts = tx.inputs.iter().map(|input| input_vout(&input.outpoint)).collect();
+    Ok((vouts, tx.outputs.into_iter().map(|(_, script_pubkey)| script_pubkey).collect()))
+}
+
+/// Insert a field into a map, replacing any with the same key
+fn set(map: &mut Map, key: Vec<u8>, value: Vec<u8>) {
+    match map.iter().position(|&(ref k, _)| *k == key) {
+        Some(pos) => map[pos].1 = value,
+        None => map.push((key, value))
+    }
+}
+
+/// What a tweaked key was tweaked from
+#[derive(Clone)]
+pub struct TweakedKey {
+    /// The tweaked key
+    pub tweaked: PublicKey,
+    /// The key before tweaking
+    pub base: PublicKey,
+    /// The contract it was tweaked for
+    pub contract: Contract,
+    /// Where the untweaked key came from, if known
+    pub source: Option<KeySource>
+}
+
+/// A partially signed transaction
+#[derive(Clone)]
+pub struct Psbt {
+    /// The global map
+    pub global: Map,
+    /// The map of each input
+    pub inputs: Vec<Map>,
+    /// The map of each output
+    pub outputs: Vec<Map>,
+    /// The index of the output spent by each input
+    vouts: Vec<u32>,
+    /// The script of each output
+    output_scripts: Vec<Script>
+}
+
+impl Psbt {
+    /// Parse a PSBT from its binary or base64 form
+    pub fn decode(data: &[u8]) -> Result<Psbt, Error> {
+        if data.starts_with(MAGIC) {
+            return Psbt::from_bytes(data);
+        }
+        let text: Vec<u8> = data.iter().cloned().filter(|b| !(*b as char).is_whitespace()).collect();
+        let bytes = try!(text.from_base64().map_err(|_| Error::Base64));
+        Psbt::from_bytes(&bytes)
+    }
+
+    /// An empty PSBT for an unsigned transaction
+    pub fn from_unsigned(tx: &UnsignedTx) -> Psbt {
+        Psbt {
+            global: vec![(vec![GLOBAL_UNSIGNED_TX], tx.serialize(&vec![vec![]; tx.inputs.len()], &[]))],
+            inputs: vec![vec![]; tx.inputs.len()],
+            outputs: vec![vec![]; tx.outputs.len()],
+            vouts: tx.inputs.iter().map(|input| input_vout(&input.outpoint)).collect(),
+            output_scripts: tx.outputs.iter().map(|&(_, ref script_pubkey)| script_pubkey.clone()).collect()
+        }
+    }
+
+    /// Parse a binary PSBT
+    pub fn from_bytes(data: &[u8]) -> Result<Psbt, Error> {
+        if !data.starts_with(MAGIC) {
+            return Err(Error::BadMagic);
+        }
+        let mut reader = Reader { data: data, pos: MAGIC.len() };
+        let global = try!(reader.read_map());
+        let (vouts, output_scripts) = match global.iter().find(|&&(ref k, _)| *k == [GLOBAL_UNSIGNED_TX]) {
+            Some(&(_, ref tx)) => try!(parse_tx(tx)),
+            None => { return Err(Error::MissingTransaction); }
+        };
+        let mut inputs = vec![];
+        for _ in 0..vouts.len() {
+            inputs.push(try!(reader.read_map()));
+        }
+        let mut outputs = vec![];
+        for _ in 0..output_scripts.len() {
+            outputs.push(try!(reader.read_map()));
+        }
+        Ok(Psbt { global: global, inputs: inputs, outputs: outputs, vouts: vouts, output_scripts: output_scripts })
+    }
+
+    /// Serialize the PSBT in binary
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        for map in Some(&self.global).into_iter().chain(self.inputs.iter()).chain(self.outputs.iter()) {
+            for &(ref key, ref value) in map {
+                write_slice(&mut data, key);
+                write_slice(&mut data, value);
+            }
+            data.push(0);
+        }
+        data
+    }
+
+    /// Serialize the PSBT in base64
+    pub fn to_base64(&self) -> String {
+        self.to_bytes().to_base64(base64::STANDARD)
+    }
+
+    /// The scriptPubKey of the output spent by an input, if the PSBT has it
+    fn spent_script_pubkey(&self, input: usize) -> Option<Script> {
+        let map = &self.inputs[input];
+        if let Some(&(_, ref txout)) = map.iter().find(|&&(ref k, _)| *k == [IN_WITNESS_UTXO]) {
+            let mut reader = Reader { data: txout, pos: 8 };
+            return reader.read_slice().ok().map(|s| Script::from(s.to_owned()));
+        }
+        if let Some(&(_, ref tx)) = map.iter().find(|&&(ref k, _)| *k == [IN_NON_WITNESS_UTXO]) {
+            if let Ok((_, scripts)) = parse_tx(tx) {
+                return scripts.get(self.vouts[input] as usize).cloned();
+            }
+        }
+        None
+    }
+
+    /// Attach a modified script to every input spending and output paying
+    /// its P2SH, P2WSH or P2SH-P2WSH forms (or, for a single key without a
+    /// script, its P2PKH address), along with the tweak of each of its keys.
+    /// Returns the number of inputs and outputs updated.
+    pub fn attach(&mut self, secp: &Secp256k1, network: Network, script: Option<&Script>, keys: &[TweakedKey]) -> (usize, usize) {
+        // Each scriptPubKey the script may be paid to, with its redeem and
+        // witness scripts
+        let forms = match script {
+            Some(script) => {
+                let p2wsh = segwit::p2wsh_script_pubkey(script);
+                vec![(Address::from_script(network, script).script_pubkey(), Some(script.clone()), None),
+                     (p2wsh.clone(), None, Some(script.clone())),
+                     (Address::from_script(network, &p2wsh).script_pubkey(), Some(p2wsh), Some(script.clone()))]
+            }
+            None => keys.iter().map(|key| (Address::from_key(network, &key.tweaked, true).script_pubkey(), None, None)).collect()
+        };
+        let mut tweak_fields = vec![];
+        for key in keys {
+            let mut field_key = vec![PROPRIETARY];
+            write_slice(&mut field_key, PROPRIETARY_ID);
+            field_key.push(SUBTYPE_TWEAK);
+            field_key.extend(key.tweaked.serialize_vec(secp, true).iter().cloned());
+            let mut value = key.base.serialize_vec(secp, true).to_vec();
+            value.extend(&key.contract.to_bytes()[..]);
+            if let Some(ref source) = key.source {
+                value.extend(source.fingerprint.iter().cloned());
+                for child in &source.path {
+                    value.extend((0..4).map(|i| (child >> (8 * i)) as u8));
+                }
+            }
+            tweak_fields.push((field_key, value));
+        }
+
+        let update = |map: &mut Map, redeem: &Option<Script>, witness: &Option<Script>, redeem_type: u8, witness_type: u8| {
+            if let Some(ref redeem) = *redeem {
+                set(map, vec![redeem_type], redeem[..].to_owned());
+            }
+            if let Some(ref witness) = *witness {
+                set(map, vec![witness_type], witness[..].to_owned());
+            }
+            for &(ref key, ref value) in &tweak_fields {
+                set(map, key.clone(), value.clone());
+            }
+        };
+        let (mut n_inputs, mut n_outputs) = (0, 0);
+        for n in 0..self.inputs.len() {
+            if let Some(spk) = self.spent_script_pubkey(n) {
+                if let Some(&(_, ref redeem, ref witness)) = forms.iter().find(|&&(ref form, _, _)| *form == spk) {
+                    update(&mut self.inputs[n], redeem, witness, IN_REDEEM_SCRIPT, IN_WITNESS_SCRIPT);
+                    n_inputs += 1;
+                }
+            }
+        }
+        for n in 0..self.outputs.len() {
+            if let Some(&(_, ref redeem, ref witness)) = forms.iter().find(|&&(ref form, _, _)| *form == self.output_scripts[n]) {
+                update(&mut self.outputs[n], redeem, witness, OUT_REDEEM_SCRIPT, OUT_WITNESS_SCRIPT);
+                n_outputs += 1;
+            }
+        }
+        (n_inputs, n_outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::script::Script;
+    use bitcoin::network::constants::Network;
+    use secp256k1::Secp256k1;
+    use secp256k1::key::PublicKey;
+    use serialize::hex::FromHex;
+
+    use bip32::KeySource;
+    use contract::Contract;
+    use segwit;
+    use sweep::{Input, UnsignedTx};
+
+    use super::*;
+
+    /// BIP174 test vector: a finalized P2SH-P2WPKH input with its redeem script
+    const REDEEM_VECTOR: &'static str = "\
+        cHNidP8BAKACAAAAAqsJSaCMWvfEm4IS9Bfi8Vqz9cM9zxU4IagTn4d6W3vkAAAAAAD+////qwlJoIxa98SbghL0F+LxWrP1wz3PFTghqBOfh3pbe+QBAAAAAP7///8C\
+        YDvqCwAAAAAZdqkUdopAu9dAy+gdmI5x3ipNXHE5ax2IrI4kAAAAAAAAGXapFG9GILVT+glechue4O/p+gOcykWXiKwAAAAAAAEHakcwRAIgR1lmF5fAGwNrJZKJSGhi\
+        GDR9iYZLcZ4ff89X0eURZYcCIFMJ6r9Wqk2Ikf/REf3xM286KdqGbX+EhtdVRs7tr5MZASEDXNxh/HupccC1AaZGoqg7ECy0OIEhfKaC3Ibi1z+ogpIAAQEgAOH1BQAA\
+        AAAXqRQ1RebjO4MsRwUPJNPuuTycA5SLx4cBBBYAFIXRNTfy4mVAWjTbr6nj3aAfuCMIAAAA";
+    /// BIP174 test vector: the updater's output, with a P2SH 2-of-2 input, a
+    /// P2SH-P2WSH 2-of-2 input and BIP32 derivations on every key
+    const UPDATER_VECTOR: &'static str = "\
+        cHNidP8BAJoCAAAAAljoeiG1ba8MI76OcHBFbDNvfLqlyHV5JPVFiHuyq911AAAAAAD/////g40EJ9DsZQpoqka7CwmK6kQiwHGyyng1Kgd5WdB86h0BAAAAAP////8C\
+        cKrwCAAAAAAWABTYXCtx0AYLCcmIauuBXlCZHdoSTQDh9QUAAAAAFgAUAK6pouXw+HaliN9VRuh0LR2HAI8AAAAAAAEAuwIAAAABqtc5MQGL0l+ErkALaISL4J23BurC\
+        rBgpi6vucatlb4sAAAAASEcwRAIgWPb8fGoz4bMVSNSByCbAFb0wE1qtQs1neQ2rZtKtJDsCIEoc7SYExnNbY5PltBaR3XiwDwxZQvufdRhW+qk4FX26Af7///8CgPD6\
+        AgAAAAAXqRQPuUY0IWlrgsgzryQceMF9295JNIfQ8gonAQAAABepFCnKdPigj4GZlCgYXJe12FLkBj9hh2UAAAABBEdSIQKVg785rgpgl0etGZrd1jT6YQhVnWxc05tM\
+        IYPxq5bgfyEC2rYf9JoU22p9ArDNH7t4/EsYMStbTlTa5Nui+/71NtdSriIGApWDvzmuCmCXR60Zmt3WNPphCFWdbFzTm0whg/GrluB/ENkMak8AAACAAAAAgAAAAIAi\
+        BgLath/0mhTban0CsM0fu3j8SxgxK1tOVNrk26L7/vU21xDZDGpPAAAAgAAAAIABAACAAAEBIADC6wsAAAAAF6kUt/X69A49QKWkWbHbNTXyty+pIeiHAQQiACCMI1MX\
+        N0O1ld+0oHtyuo5C43l9p06H/n2ddJfjsgKJAwEFR1IhAwidwQx6xttU+RMpr2FzM9s4jOrQwjH3IzedG5kDCwLcIQI63ZBPPW3PWd25BrDe4jUpt/+57VDl6GFRkmhg\
+        Ih8Oc1KuIgYCOt2QTz1tz1nduQaw3uI1Kbf/ue1Q5ehhUZJoYCIfDnMQ2QxqTwAAAIAAAACAAwAAgCIGAwidwQx6xttU+RMpr2FzM9s4jOrQwjH3IzedG5kDCwLcENkM\
+        ak8AAACAAAAAgAIAAIAAIgIDqaTDf1mW06ol26xrVwrwZQOUSSlCRgs1R1Ptnuylh3EQ2QxqTwAAAIAAAACABAAAgAAiAgJ/Y5l1fS7/VaE2rQLGhLGDi2VW5fG2s0KC\
+        qUtrUAUQlhDZDGpPAAAAgAAAAIAFAACAAA==";
+    /// The keys of the updater vector's first input, and their derivation
+    const KEY_1: &'static str = "029583bf39ae0a609747ad199addd634fa6108559d6c5cd39b4c2183f1ab96e07f";
+    const KEY_2: &'static str = "02dab61ff49a14db6a7d02b0cd1fbb78fc4b18312b5b4e54dae4dba2fbfef536d7";
+    const KEY_1_SOURCE: &'static str = "d90c6a4f000000800000008000000080";
+    /// A text contract
+    const CONTRACT: &'static str = "5445585400112233445566778899aabbccddeeff68656c6c6f2e2e2e2e2e2e2e2e2e2e2e2e2e2e00";
+
+    fn field<'a>(map: &'a Map, key: &[u8]) -> Option<&'a [u8]> {
+        map.iter().find(|&&(ref k, _)| *k == key).map(|&(_, ref v)| &v[..])
+    }
+
+    fn key(hex: &str) -> PublicKey {
+        PublicKey::from_slice(&Secp256k1::new(), &hex.from_hex().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn bip174_roundtrip() {
+        for vector in &[REDEEM_VECTOR, UPDATER_VECTOR] {
+            let psbt = Psbt::decode(vector.as_bytes()).unwrap();
+            assert_eq!(psbt.to_base64(), *vector);
+            assert!(Psbt::from_bytes(&psbt.to_bytes()).unwrap().to_bytes() == psbt.to_bytes());
+        }
+
+        let psbt = Psbt::decode(REDEEM_VECTOR.as_bytes()).unwrap();
+        assert_eq!(psbt.inputs.len(), 2);
+        assert_eq!(field(&psbt.inputs[1], &[IN_REDEEM_SCRIPT]), Some(&"001485d13537f2e265405a34dbafa9e3dda01fb82308".from_hex().unwrap()[..]));
+
+        // Derivations are kept under their own keys, and untouched
+        let psbt = Psbt::decode(UPDATER_VECTOR.as_bytes()).unwrap();
+        assert_eq!((psbt.inputs.len(), psbt.outputs.len()), (2, 2));
+        let mut derivation = vec![0x06];
+        derivation.extend(KEY_1.from_hex().unwrap());
+        assert_eq!(field(&psbt.inputs[0], &derivation), Some(&KEY_1_SOURCE.from_hex().unwrap()[..]));
+        assert_eq!(psbt.inputs[1].len(), 5);
+        let mut derivation = vec![0x02];
+        derivation.extend("03a9a4c37f5996d3aa25dbac6b570af0650394492942460b354753ed9eeca58771".from_hex().unwrap());
+        assert_eq!(field(&psbt.outputs[0], &derivation), Some(&"d90c6a4f000000800000008004000080".from_hex().unwrap()[..]));
+
+        // Whitespace in base64 is ignored
+        let wrapped: String = UPDATER_VECTOR.as_bytes().chunks(64).map(|line| format!("{}\n", String::from_utf8_lossy(line))).collect();
+        assert_eq!(Psbt::decode(wrapped.as_bytes()).unwrap().to_base64(), UPDATER_VECTOR);
+    }
+
+    #[test]
+    fn malformed() {
+        let bytes = Psbt::decode(UPDATER_VECTOR.as_bytes()).unwrap().to_bytes();
+        match Psbt::from_bytes(&bytes[1..]) {
+            Err(Error::BadMagic) => {}
+            other => panic!("unexpected result {:?}", other.map(|p| p.to_base64()))
+        }
+        match Psbt::from_bytes(&bytes[..bytes.len() - 1]) {
+            Err(Error::Truncated) => {}
+            other => panic!("unexpected result {:?}", other.map(|p| p.to_base64()))
+        }
+        match Psbt::decode(b"not a psbt!") {
+            Err(Error::Base64) => {}
+            other => panic!("unexpected result {:?}", other.map(|p| p.to_base64()))
+        }
+        // Magic followed by an empty global map
+        match Psbt::from_bytes(b"psbt\xff\x00") {
+            Err(Error::MissingTransaction) => {}
+            other => panic!("unexpected result {:?}", other.map(|p| p.to_base64()))
+        }
+    }
+
+    #[test]
+    fn attach_scripts() {
+        let secp = Secp256k1::new();
+        let original = Psbt::decode(UPDATER_VECTOR.as_bytes()).unwrap();
+        let p2sh_script = Script::from(field(&original.inputs[0], &[IN_REDEEM_SCRIPT]).unwrap().to_owned());
+        let p2wsh_script = Script::from(field(&original.inputs[1], &[IN_WITNESS_SCRIPT]).unwrap().to_owned());
+
+        // Strip the scripts, and check the updater's are put back
+        let mut psbt = original.clone();
+        for map in &mut psbt.inputs {
+            map.retain(|&(ref k, _)| *k != [IN_REDEEM_SCRIPT] && *k != [IN_WITNESS_SCRIPT]);
+        }
+        // The first input spends a P2SH output of a full previous transaction
+        assert_eq!(psbt.attach(&secp, Network::Testnet, Some(&p2sh_script), &[]), (1, 0));
+        assert_eq!(field(&psbt.inputs[0], &[IN_REDEEM_SCRIPT]), field(&original.inputs[0], &[IN_REDEEM_SCRIPT]));
+        assert_eq!(field(&psbt.inputs[0], &[IN_WITNESS_SCRIPT]), None);
+        assert_eq!(field(&psbt.inputs[1], &[IN_REDEEM_SCRIPT]), None);
+        // The second spends a P2SH-P2WSH witness UTXO
+        assert_eq!(psbt.attach(&secp, Network::Testnet, Some(&p2wsh_script), &[]), (1, 0));
+        for &k in &[IN_REDEEM_SCRIPT, IN_WITNESS_SCRIPT] {
+            assert_eq!(field(&psbt.inputs[1], &[k]), field(&original.inputs[1], &[k]));
+        }
+        for (map, original) in psbt.inputs.iter().zip(original.inputs.iter()) {
+            assert_eq!(map.len(), original.len());
+        }
+
+        // Outputs paying each form of a script get its redeem and witness scripts
+        let p2wsh = segwit::p2wsh_script_pubkey(&p2sh_script);
+        let tx = UnsignedTx {
+            version: 2,
+            inputs: vec![Input { outpoint: vec![0; 36], sequence: 0xffffffff }],
+            outputs: vec![(1000, Address::from_script(Network::Testnet, &p2sh_script).script_pubkey()),
+                          (2000, p2wsh.clone()),
+                          (3000, Address::from_script(Network::Testnet, &p2wsh).script_pubkey()),
+                          (4000, p2wsh_script.clone())],
+            lock_time: 0
+        };
+        let mut psbt = Psbt::from_unsigned(&tx);
+        let tweaked = TweakedKey {
+            tweaked: key(KEY_1),
+            base: key(KEY_2),
+            contract: Contract::from_hex(CONTRACT).unwrap(),
+            source: Some(KeySource { fingerprint: [0xd9, 0x0c, 0x6a, 0x4f], path: vec![0x80000000, 0x80000000, 0x80000000] })
+        };
+        assert_eq!(psbt.attach(&secp, Network::Testnet, Some(&p2sh_script), &[tweaked]), (0, 3));
+        assert_eq!(field(&psbt.outputs[0], &[OUT_REDEEM_SCRIPT]), Some(&p2sh_script[..]));
+        assert_eq!(field(&psbt.outputs[0], &[OUT_WITNESS_SCRIPT]), None);
+        assert_eq!(field(&psbt.outputs[1], &[OUT_REDEEM_SCRIPT]), None);
+        assert_eq!(field(&psbt.outputs[1], &[OUT_WITNESS_SCRIPT]), Some(&p2sh_script[..]));
+        assert_eq!(field(&psbt.outputs[2], &[OUT_REDEEM_SCRIPT]), Some(&p2wsh[..]));
+        assert_eq!(field(&psbt.outputs[2], &[OUT_WITNESS_SCRIPT]), Some(&p2sh_script[..]));
+        assert!(psbt.outputs[3].is_empty());
+
+        // The tweak field is keyed by the tweaked key, and holds the base key,
+        // the contract and the derivation of the base key
+        let field_key = format!("fc087061637468617368{}{}", "00", KEY_1).from_hex().unwrap();
+        let value = format!("{}{}{}", KEY_2, CONTRACT, KEY_1_SOURCE).from_hex().unwrap();
+        for (map, n_fields) in psbt.outputs.iter().zip(&[2, 2, 3]) {
+            assert_eq!(map.len(), *n_fields);
+            assert_eq!(field(map, &field_key), Some(&value[..]));
+        }
+
+        // The result still round-trips
+        assert_eq!(Psbt::decode(psbt.to_base64().as_bytes()).unwrap().to_bytes(), psbt.to_bytes());
+    }
+}
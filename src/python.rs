@@ -0,0 +1,245 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Python Module
+//! The `pacthash` Python module, enabled by the `python` feature and built
+//! with maturin (`maturin build --release`, as pyproject.toml configures),
+//! so that Python scripts use this crate's tweaks instead of their own.
+//!
+//! It has `Contract` and `Nonce` classes, and the server's methods as
+//! functions: `gen_address`, `tweak_privkey` and `verify`. Results are
+//! dictionaries of the fields the server returns, and failures raise
+//! `pacthash.Error`.
+//!
+
+use bitcoin::network::constants::Network;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList};
+use rand::Rng;
+use serialize::hex::ToHex;
+use serialize::json::Json;
+
+use std::collections::BTreeMap;
+
+use chain::Chain;
+use contract::{self, NONCE_LEN};
+use osrng::OsRng;
+use server::Server;
+
+create_exception!(pacthash, Error, PyException, "A call into pacthash failed");
+
+/// The network of a call
+fn network(testnet: bool) -> Network {
+    if testnet { Network::Testnet } else { Network::Bitcoin }
+}
+
+/// A JSON result as the Python object it reads as
+fn to_py(py: Python, json: &Json) -> PyResult<PyObject> {
+    Ok(match *json {
+        Json::Null => py.None(),
+        Json::Boolean(b) => b.into_py(py),
+        Json::I64(n) => n.into_py(py),
+        Json::U64(n) => n.into_py(py),
+        Json::F64(n) => n.into_py(py),
+        Json::String(ref s) => s.into_py(py),
+        Json::Array(ref array) => {
+            let list = PyList::empty_bound(py);
+            for item in array {
+                try!(list.append(try!(to_py(py, item))));
+            }
+            list.into_py(py)
+        }
+        Json::Object(ref object) => {
+            let dict = PyDict::new_bound(py);
+            for (key, value) in object {
+                try!(dict.set_item(key, try!(to_py(py, value))));
+            }
+            dict.into_py(py)
+        }
+    })
+}
+
+/// Call a method of a server for one network, with some string arguments
+/// as named parameters, skipping those not given
+fn call(testnet: bool, method: &str, args: &[(&str, Option<&str>)]) -> PyResult<Json> {
+    let mut params = BTreeMap::new();
+    for &(name, arg) in args {
+        if let Some(arg) = arg {
+            params.insert(name.to_owned(), Json::String(arg.to_owned()));
+        }
+    }
+    Server::new(Chain::from_network(network(testnet)), None).call(method, &Json::Object(params))
+                                      .map_err(|(_, message)| Error::new_err(message))
+}
+
+/// The 16-byte nonce of a contract
+#[pyclass(name = "Nonce", module = "pacthash")]
+#[derive(Clone)]
+pub struct PyNonce {
+    nonce: contract::Nonce
+}
+
+#[pymethods]
+impl PyNonce {
+    /// A nonce from its bytes, or their hex
+    #[new]
+    fn new(data: &Bound<PyAny>) -> PyResult<PyNonce> {
+        let hex = match data.extract::<String>() {
+            Ok(hex) => hex,
+            Err(_) => try!(data.extract::<Vec<u8>>()).to_hex()
+        };
+        contract::Nonce::from_hex(&hex).map(|nonce| PyNonce { nonce: nonce })
+                                       .map_err(|e| PyValueError::new_err(format!("nonce could not be parsed: {:?}", e)))
+    }
+
+    /// A random nonce
+    #[staticmethod]
+    fn random() -> PyResult<PyNonce> {
+        let mut rng = try!(OsRng::new().map_err(|e| Error::new_err(format!("unable to make a nonce: {}", e))));
+        Ok(PyNonce { nonce: rng.gen() })
+    }
+
+    /// The nonce's bytes
+    #[getter]
+    fn data<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.nonce[..])
+    }
+
+    /// The nonce's hex
+    fn hex(&self) -> String {
+        format!("{:x}", self.nonce)
+    }
+
+    fn __eq__(&self, other: &Bound<PyAny>) -> bool {
+        other.extract::<PyRef<PyNonce>>().map(|other| other.nonce == self.nonce).unwrap_or(false)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Nonce('{:x}')", self.nonce)
+    }
+}
+
+/// A contract, as its type, nonce and data
+#[pyclass(name = "Contract", module = "pacthash")]
+pub struct PyContract {
+    contract: contract::Contract
+}
+
+#[pymethods]
+impl PyContract {
+    /// A contract from its hex
+    #[new]
+    fn new(hex: &str) -> PyResult<PyContract> {
+        contract::Contract::from_hex(hex).map(|contract| PyContract { contract: contract })
+                                         .map_err(|e| PyValueError::new_err(format!("contract could not be parsed: {:?}", e)))
+    }
+
+    /// A text contract of 20 ASCII characters
+    #[staticmethod]
+    fn from_ascii(text: &str, nonce: PyRef<PyNonce>) -> PyResult<PyContract> {
+        contract::Contract::from_ascii_str(text, nonce.nonce).map(|contract| PyContract { contract: contract })
+                                                             .map_err(|e| PyValueError::new_err(format!("contract could not be made: {:?}", e)))
+    }
+
+    /// A P2SH or P2PH contract paying to an address
+    #[staticmethod]
+    #[pyo3(signature = (address, nonce, testnet=false))]
+    fn from_address(address: &str, nonce: PyRef<PyNonce>, testnet: bool) -> PyResult<PyContract> {
+        contract::Contract::from_p2sh_base58_str(address, nonce.nonce, Chain::from_network(network(testnet)))
+            .map(|contract| PyContract { contract: contract })
+            .map_err(|e| PyValueError::new_err(format!("contract could not be made: {:?}", e)))
+    }
+
+    /// The contract's type, e.g. TEXT or P2SH
+    #[getter]
+    #[pyo3(name = "type")]
+    fn type_name(&self) -> String {
+        self.contract.type_name()
+    }
+
+    /// The contract's nonce
+    #[getter]
+    fn nonce(&self) -> PyNonce {
+        PyNonce { nonce: contract::Nonce::from_contract(&self.contract) }
+    }
+
+    /// The contract's data
+    #[getter]
+    fn data<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, self.contract.data())
+    }
+
+    /// The text of a contract which pays to no address, or None
+    #[getter]
+    fn text(&self) -> Option<String> {
+        match self.contract.destination_script() {
+            Some(_) => None,
+            None => Some(String::from_utf8_lossy(self.contract.data()).into_owned())
+        }
+    }
+
+    /// The contract's hex
+    fn hex(&self) -> String {
+        format!("{:x}", self.contract)
+    }
+
+    fn __eq__(&self, other: &Bound<PyAny>) -> bool {
+        other.extract::<PyRef<PyContract>>().map(|other| other.contract == self.contract).unwrap_or(false)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Contract('{:x}')", self.contract)
+    }
+}
+
+/// The tweaked script and its addresses, given a redeem script (hex or
+/// asm), or the tweaked key and its P2PKH address, given a hex key
+#[pyfunction]
+#[pyo3(signature = (contract, script=None, key=None, testnet=false))]
+fn gen_address(py: Python, contract: PyRef<PyContract>, script: Option<&str>, key: Option<&str>, testnet: bool) -> PyResult<PyObject> {
+    let result = try!(call(testnet, "gen_address", &[("contract", Some(&contract.hex())), ("script", script), ("key", key)]));
+    to_py(py, &result)
+}
+
+/// The tweaked private key of a WIF key, with its public key and address
+#[pyfunction]
+#[pyo3(signature = (contract, privkey, testnet=false))]
+fn tweak_privkey(py: Python, contract: PyRef<PyContract>, privkey: &str, testnet: bool) -> PyResult<PyObject> {
+    let result = try!(call(testnet, "tweak_privkey", &[("contract", Some(&contract.hex())), ("privkey", Some(privkey))]));
+    to_py(py, &result)
+}
+
+/// Whether an address commits to a contract under a script or key
+#[pyfunction]
+#[pyo3(signature = (contract, address, script=None, key=None, testnet=false))]
+fn verify(contract: PyRef<PyContract>, address: &str, script: Option<&str>, key: Option<&str>, testnet: bool) -> PyResult<bool> {
+    let args = [("contract", Some(&contract.hex()[..])), ("script", script), ("key", key), ("address", Some(address))];
+    let result = try!(call(testnet, "verify", &args));
+    Ok(result.find("valid").and_then(|v| v.as_boolean()) == Some(true))
+}
+
+/// The `pacthash` module
+#[pymodule]
+fn pacthash(m: &Bound<PyModule>) -> PyResult<()> {
+    try!(m.add("Error", m.py().get_type_bound::<Error>()));
+    try!(m.add("NONCE_LEN", NONCE_LEN));
+    try!(m.add_class::<PyNonce>());
+    try!(m.add_class::<PyContract>());
+    try!(m.add_function(try!(wrap_pyfunction!(::python::gen_address, m))));
+    try!(m.add_function(try!(wrap_pyfunction!(::python::tweak_privkey, m))));
+    try!(m.add_function(try!(wrap_pyfunction!(::python::verify, m))));
+    Ok(())
+}
@@ -0,0 +1,308 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+
+//! # Rate Limiting
+//! Keeping one client of a server from taking all of it. Each client (a
+//! bearer token, or an address when there are no tokens) has a bucket of
+//! requests, which refills at a steady rate up to a burst size, and every
+//! request takes one from it; a global bucket, shared by every client,
+//! caps the server's total. A request is refused unless both buckets have
+//! one to give, and then takes from neither.
+//!
+//! Rates are given as `RATE[/BURST]`, in requests per second, e.g. `5/20`
+//! for five a second with bursts of up to twenty. The burst defaults to
+//! the rate, and is at least one.
+//!
+//! Connections, each of which holds a thread, are capped separately: a
+//! server has only so many open at once, however slowly their clients send.
+//!
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Buckets kept for clients before full ones, which would allow as much as
+/// a new bucket, are forgotten
+const MAX_CLIENTS: usize = 4096;
+
+/// Rate limit error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// A rate was not `RATE[/BURST]` with positive numbers
+    BadRate(String)
+}
+
+/// A steady rate and the burst allowed above it
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Rate {
+    /// Requests per second
+    pub per_second: f64,
+    /// Requests which may be made at once
+    pub burst: f64
+}
+
+impl Rate {
+    /// Parse a rate given as `RATE[/BURST]`
+    pub fn parse(s: &str) -> Result<Rate, Error> {
+        let mut fields = s.splitn(2, '/');
+        let per_second = fields.next().and_then(|r| r.trim().parse::<f64>().ok());
+        let burst = match fields.next() {
+            Some(b) => b.trim().parse::<f64>().ok(),
+            None => per_second.map(|r| r.ceil())
+        };
+        match (per_second, burst) {
+            (Some(per_second), Some(burst)) if per_second > 0.0 && per_second.is_finite() && burst > 0.0 && burst.is_finite() => {
+                Ok(Rate { per_second: per_second, burst: burst.max(1.0) })
+            }
+            _ => Err(Error::BadRate(s.to_owned()))
+        }
+    }
+}
+
+/// The requests a client, or the server, has left
+#[derive(Clone, Debug)]
+struct Bucket {
+    level: f64,
+    last: Instant
+}
+
+impl Bucket {
+    /// A full bucket
+    fn new(rate: &Rate, now: Instant) -> Bucket {
+        Bucket { level: rate.burst, last: now }
+    }
+
+    /// Refill the bucket for the time since it was last touched
+    fn refill(&mut self, rate: &Rate, now: Instant) {
+        let elapsed = now.duration_since(self.last);
+        let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        self.level = (self.level + elapsed * rate.per_second).min(rate.burst);
+        self.last = now;
+    }
+
+    /// Seconds until the bucket has a request to give, if it has none now
+    fn wait(&self, rate: &Rate) -> Option<f64> {
+        if self.level >= 1.0 {
+            None
+        } else {
+            Some((1.0 - self.level) / rate.per_second)
+        }
+    }
+}
+
+/// Which limit refused a request
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Limit {
+    /// The client's own
+    Client,
+    /// The server's total
+    Global
+}
+
+impl Limit {
+    /// The name of the limit, as logged and in metrics
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Limit::Client => "client",
+            Limit::Global => "global"
+        }
+    }
+}
+
+/// The limits of a server, and the buckets of its clients
+#[derive(Debug)]
+pub struct Limiter {
+    client_rate: Option<Rate>,
+    global_rate: Option<Rate>,
+    clients: Mutex<HashMap<String, Bucket>>,
+    global: Mutex<Bucket>
+}
+
+impl Limiter {
+    /// A limiter with a rate for each client and one for all of them, either
+    /// of which may be absent
+    pub fn new(client_rate: Option<Rate>, global_rate: Option<Rate>) -> Limiter {
+        let now = Instant::now();
+        Limiter {
+            client_rate: client_rate,
+            global_rate: global_rate,
+            clients: Mutex::new(HashMap::new()),
+            global: Mutex::new(Bucket { level: global_rate.map_or(0.0, |r| r.burst), last: now })
+        }
+    }
+
+    /// Take a request from a client's bucket and the global one, or give
+    /// which limit refused it and the seconds until it would not
+    pub fn check(&self, client: &str) -> Result<(), (Limit, u64)> {
+        self.check_at(client, Instant::now())
+    }
+
+    /// Check a request as `check` does, as though it were made at `now`,
+    /// which may not be before any earlier request or the limiter itself
+    pub fn check_at(&self, client: &str, now: Instant) -> Result<(), (Limit, u64)> {
+        // Locked in this order everywhere, so the two cannot deadlock
+        let mut clients = self.clients.lock().unwrap();
+        let mut global = self.global.lock().unwrap();
+
+        if let Some(ref rate) = self.client_rate {
+            if !clients.contains_key(client) && clients.len() >= MAX_CLIENTS {
+                for bucket in clients.values_mut() {
+                    bucket.refill(rate, now);
+                }
+                clients.retain(|_, bucket| bucket.level < rate.burst);
+            }
+            let bucket = clients.entry(client.to_owned()).or_insert_with(|| Bucket::new(rate, now));
+            bucket.refill(rate, now);
+            if let Some(wait) = bucket.wait(rate) {
+                return Err((Limit::Client, wait.ceil() as u64));
+            }
+        }
+        if let Some(ref rate) = self.global_rate {
+            global.refill(rate, now);
+            if let Some(wait) = global.wait(rate) {
+                return Err((Limit::Global, wait.ceil() as u64));
+            }
+            global.level -= 1.0;
+        }
+        if self.client_rate.is_some() {
+            clients.get_mut(client).unwrap().level -= 1.0;
+        }
+        Ok(())
+    }
+}
+
+/// The connections a server has open, and how many it may have
+#[derive(Debug)]
+pub struct Connections {
+    open: Arc<AtomicUsize>,
+    max: usize
+}
+
+impl Connections {
+    /// A cap of `max` connections open at once
+    pub fn new(max: usize) -> Connections {
+        Connections {
+            open: Arc::new(AtomicUsize::new(0)),
+            max: max
+        }
+    }
+
+    /// Count a new connection, unless as many as allowed are already open
+    pub fn open(&self) -> Option<Connection> {
+        if self.open.fetch_add(1, Ordering::SeqCst) >= self.max {
+            self.open.fetch_sub(1, Ordering::SeqCst);
+            None
+        } else {
+            Some(Connection { open: self.open.clone() })
+        }
+    }
+}
+
+/// An open connection, counted until it is dropped
+#[derive(Debug)]
+pub struct Connection {
+    open: Arc<AtomicUsize>
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn millis(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Rate::parse("5/20").unwrap(), Rate { per_second: 5.0, burst: 20.0 });
+        assert_eq!(Rate::parse("2.5").unwrap(), Rate { per_second: 2.5, burst: 3.0 });
+        assert_eq!(Rate::parse("0.1").unwrap(), Rate { per_second: 0.1, burst: 1.0 });
+        assert_eq!(Rate::parse("1/0.5").unwrap(), Rate { per_second: 1.0, burst: 1.0 });
+        for bad in &["", "0", "-1", "1/0", "x/2", "inf", "1/NaN"] {
+            assert!(Rate::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn client_limit() {
+        // Two a second, in bursts of up to three
+        let limiter = Limiter::new(Some(Rate::parse("2/3").unwrap()), None);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at("alice", start), Ok(()));
+        }
+        // Past the burst, a request waits half a second, rounded up
+        assert_eq!(limiter.check_at("alice", start), Err((Limit::Client, 1)));
+        // Other clients have buckets of their own
+        assert_eq!(limiter.check_at("bob", start), Ok(()));
+
+        // Half a second later there is one more, and no second one
+        assert_eq!(limiter.check_at("alice", millis(start, 400)), Err((Limit::Client, 1)));
+        assert_eq!(limiter.check_at("alice", millis(start, 500)), Ok(()));
+        assert_eq!(limiter.check_at("alice", millis(start, 500)), Err((Limit::Client, 1)));
+
+        // However long the wait, the bucket refills only to the burst
+        let later = millis(start, 100000);
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at("alice", later), Ok(()));
+        }
+        assert_eq!(limiter.check_at("alice", later), Err((Limit::Client, 1)));
+    }
+
+    #[test]
+    fn global_limit() {
+        // One every two seconds between them, the clients being allowed more
+        let limiter = Limiter::new(Some(Rate::parse("10").unwrap()), Some(Rate::parse("0.5/2").unwrap()));
+        let start = Instant::now();
+        assert_eq!(limiter.check_at("alice", start), Ok(()));
+        assert_eq!(limiter.check_at("bob", start), Ok(()));
+        assert_eq!(limiter.check_at("carol", start), Err((Limit::Global, 2)));
+        // A refused request takes from neither bucket
+        assert_eq!(limiter.check_at("carol", millis(start, 2000)), Ok(()));
+        assert_eq!(limiter.check_at("alice", millis(start, 2000)), Err((Limit::Global, 2)));
+        assert_eq!(limiter.check_at("alice", millis(start, 3000)), Err((Limit::Global, 1)));
+        assert_eq!(limiter.check_at("alice", millis(start, 4000)), Ok(()));
+
+        // Without limits everything is allowed
+        let unlimited = Limiter::new(None, None);
+        for _ in 0..100 {
+            assert_eq!(unlimited.check_at("alice", start), Ok(()));
+        }
+    }
+
+    #[test]
+    fn connections() {
+        let connections = Connections::new(2);
+        let first = connections.open().unwrap();
+        let second = connections.open().unwrap();
+        assert!(connections.open().is_none());
+        // Closing one makes room for another
+        drop(first);
+        let third = connections.open().unwrap();
+        assert!(connections.open().is_none());
+        drop(second);
+        drop(third);
+        assert!(connections.open().is_some());
+    }
+}
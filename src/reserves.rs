@@ -0,0 +1,180 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Proof of Reserves
+//! Files proving control of a set of tweaked P2PKH addresses. Each entry
+//! gives an address with its untweaked key, its contract (which includes
+//! the nonce) and a signmessage signature of the proof's message by the
+//! tweaked key, so a verifier can check both that the address is controlled
+//! and that it commits to the contract.
+//!
+//! ```text
+//! pacthash-reserves-proof 1
+//! network mainnet
+//! message <hex>
+//! entry <address> <untweaked key> <contract> <signature>
+//! checksum <hex>
+//! ```
+//!
+
+use bitcoin::network::constants::Network;
+use bitcoin::util::address::{Address, Privkey};
+use bitcoin::util::base58::ToBase58;
+use bitcoin::util::contracthash::tweak_keys;
+use secp256k1::Secp256k1;
+use secp256k1::key::PublicKey;
+use serialize::hex::{FromHex, ToHex};
+
+use context;
+use contract::Contract;
+use handoff;
+use message;
+
+/// First line of a proof file
+const PROOF_MAGIC: &'static str = "pacthash-reserves-proof 1";
+
+/// Proof of reserves error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// File could not be parsed
+    File(handoff::Error),
+    /// A key could not be tweaked
+    Tweak(String),
+    /// A message could not be signed
+    Message(message::Error)
+}
+
+/// One address of a proof
+#[derive(Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The tweaked P2PKH address
+    pub address: String,
+    /// The key before tweaking
+    pub base_key: PublicKey,
+    /// The contract the key was tweaked for
+    pub contract: Contract,
+    /// Signature of the proof's message by the tweaked key
+    pub signature: String
+}
+
+/// The result of checking one entry of a proof
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Status {
+    /// The signature is by the address's key, which commits to the contract
+    Valid,
+    /// The signature is not by the address's key
+    BadSignature,
+    /// The address's key is not the base key tweaked for the contract
+    BadCommitment
+}
+
+/// A proof of control of a set of tweaked addresses
+#[derive(Clone, PartialEq, Eq)]
+pub struct Proof {
+    /// Network of the addresses
+    pub network: Network,
+    /// Message signed for every address, e.g. naming the auditor and date
+    pub message: String,
+    /// The addresses
+    pub entries: Vec<Entry>
+}
+
+impl Proof {
+    /// Build a proof by tweaking each untweaked private key for its
+    /// contract and signing the message with it
+    pub fn create(secp: &Secp256k1, network: Network, message: &str, keys: &[(Privkey, Contract)]) -> Result<Proof, Error> {
+        let mut entries = Vec::with_capacity(keys.len());
+        for &(ref key, ref contract) in keys {
+            let base_key = try!(PublicKey::from_secret_key(secp, &key.key).map_err(|e| Error::Tweak(format!("{:?}", e))));
+            let tweaked = Privkey {
+                compressed: key.compressed,
+                network: network,
+                key: try!(context::tweak_secret_key(&key.key, &contract.to_bytes()[..]).map_err(|e| Error::Tweak(format!("{:?}", e))))
+            };
+            let tweaked_key = try!(PublicKey::from_secret_key(secp, &tweaked.key).map_err(|e| Error::Tweak(format!("{:?}", e))));
+            entries.push(Entry {
+                address: Address::from_key(network, &tweaked_key, key.compressed).to_base58check(),
+                base_key: base_key,
+                contract: contract.clone(),
+                signature: try!(message::sign(&tweaked, message).map_err(Error::Message))
+            });
+        }
+        Ok(Proof { network: network, message: message.to_owned(), entries: entries })
+    }
+
+    /// Check every entry of a proof
+    pub fn verify(&self, secp: &Secp256k1) -> Vec<Status> {
+        self.entries.iter().map(|entry| {
+            match message::recover(secp, &entry.signature, &self.message) {
+                Ok((key, compressed)) if Address::from_key(self.network, &key, compressed).to_base58check() == entry.address => {
+                    match tweak_keys(secp, &[entry.base_key], &entry.contract.to_bytes()[..]) {
+                        Ok(ref tweaked) if tweaked[0] == key => Status::Valid,
+                        _ => Status::BadCommitment
+                    }
+                }
+                _ => Status::BadSignature
+            }
+        }).collect()
+    }
+
+    /// Serialize the proof as a file
+    pub fn to_file(&self, secp: &Secp256k1) -> String {
+        let mut body = format!("{}\nnetwork {}\nmessage {}\n", PROOF_MAGIC,
+                               match self.network { Network::Bitcoin => "mainnet", Network::Testnet => "testnet" },
+                               self.message.as_bytes().to_hex());
+        for entry in &self.entries {
+            body.push_str(&format!("entry {} {} {:x} {}\n", entry.address,
+                                   entry.base_key.serialize_vec(secp, true)[..].to_hex(), entry.contract, entry.signature));
+        }
+        handoff::add_checksum(body)
+    }
+
+    /// Parse a proof file, checking its checksum
+    pub fn from_file(secp: &Secp256k1, text: &str) -> Result<Proof, Error> {
+        let (mut network, mut message, mut entries) = (None, None, vec![]);
+        for (n, fields) in try!(handoff::check_file(text, PROOF_MAGIC).map_err(Error::File)) {
+            let bad_line = Error::File(handoff::Error::BadLine(n));
+            match (fields[0], fields.len()) {
+                ("network", 2) if fields[1] == "mainnet" => network = Some(Network::Bitcoin),
+                ("network", 2) if fields[1] == "testnet" => network = Some(Network::Testnet),
+                ("message", 2) => {
+                    match fields[1].from_hex().ok().and_then(|data| String::from_utf8(data).ok()) {
+                        Some(text) => message = Some(text),
+                        None => { return Err(bad_line); }
+                    }
+                }
+                // An empty message has no hex to write
+                ("message", 1) => message = Some(String::new()),
+                ("entry", 5) => {
+                    let base_key = fields[2].from_hex().ok().and_then(|data| PublicKey::from_slice(secp, &data).ok());
+                    match (base_key, Contract::from_hex(fields[3])) {
+                        (Some(base_key), Ok(contract)) => entries.push(Entry {
+                            address: fields[1].to_owned(),
+                            base_key: base_key,
+                            contract: contract,
+                            signature: fields[4].to_owned()
+                        }),
+                        _ => { return Err(bad_line); }
+                    }
+                }
+                _ => { return Err(bad_line); }
+            }
+        }
+        Ok(Proof {
+            network: try!(network.ok_or(Error::File(handoff::Error::MissingField("network")))),
+            message: try!(message.ok_or(Error::File(handoff::Error::MissingField("message")))),
+            entries: entries
+        })
+    }
+}
@@ -0,0 +1,175 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Bitcoin Core RPC
+//! A minimal JSON-RPC client for talking to a local bitcoind over plain
+//! HTTP, authenticated with its cookie file, for posting alerts to
+//! webhooks, and for fetching from REST APIs. Nodes should not expose RPC
+//! beyond localhost, so TLS is not supported.
+//!
+
+use serialize::base64::{self, ToBase64};
+use serialize::json::Json;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+/// RPC error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// URL was not of the form `http://host[:port][/path]`
+    BadUrl(String),
+    /// Cookie file could not be read
+    Cookie(String),
+    /// Connection to the node failed
+    Io(String),
+    /// Node replied with an HTTP error and no JSON-RPC error
+    Http(String),
+    /// Node reported an error, with its code and message
+    Rpc(i64, String),
+    /// Node's reply could not be understood
+    BadResponse(String)
+}
+
+/// A connection to a node's RPC interface
+pub struct Client {
+    /// Host to connect to
+    host: String,
+    /// Port to connect to
+    port: u16,
+    /// Path of the request, e.g. `/wallet/NAME` for a particular wallet
+    path: String,
+    /// Base64 of the `user:password` to authenticate with
+    auth: String
+}
+
+impl Client {
+    /// Configure a client for an `http://` URL, using the credentials in a
+    /// cookie file. Without a port the default for the network is used.
+    pub fn new(url: &str, cookie_file: &str, default_port: u16) -> Result<Client, Error> {
+        let (host, port, path) = try!(parse_url(url, default_port));
+        let mut cookie = String::new();
+        try!(File::open(cookie_file).and_then(|mut f| f.read_to_string(&mut cookie))
+                 .map_err(|e| Error::Cookie(format!("{}: {}", cookie_file, e))));
+        Ok(Client {
+            host: host,
+            port: port,
+            path: path,
+            auth: cookie.trim().as_bytes().to_base64(base64::STANDARD)
+        })
+    }
+
+    /// Call a method, returning its result
+    pub fn call(&self, method: &str, params: Vec<Json>) -> Result<Json, Error> {
+        let mut request = BTreeMap::new();
+        request.insert("jsonrpc".to_owned(), Json::String("1.0".to_owned()));
+        request.insert("id".to_owned(), Json::String("pacthash".to_owned()));
+        request.insert("method".to_owned(), Json::String(method.to_owned()));
+        request.insert("params".to_owned(), Json::Array(params));
+        let body = Json::Object(request).to_string();
+
+        let (status, body) = try!(post(&self.host, self.port, &self.path, Some(&self.auth), &body));
+        // Errors come with an HTTP error status but still a JSON-RPC body
+        let json = match Json::from_str(&body) {
+            Ok(json) => json,
+            Err(_) if !status.contains(" 200 ") => { return Err(Error::Http(status)); }
+            Err(_) => { return Err(Error::BadResponse(body)); }
+        };
+        if let Some(error) = json.find("error").and_then(|e| e.as_object()) {
+            let code = error.get("code").and_then(|c| c.as_i64()).unwrap_or(0);
+            let message = error.get("message").and_then(|m| m.as_string()).unwrap_or("").to_owned();
+            return Err(Error::Rpc(code, message));
+        }
+        match json.find("result") {
+            Some(result) => Ok(result.clone()),
+            None => Err(Error::BadResponse(body))
+        }
+    }
+}
+
+/// Split an `http://host[:port][/path]` URL into its host, port and path
+fn parse_url(url: &str, default_port: u16) -> Result<(String, u16, String), Error> {
+    if !url.starts_with("http://") {
+        return Err(Error::BadUrl(url.to_owned()));
+    }
+    let rest = &url[7..];
+    let (authority, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/")
+    };
+    let (host, port) = match authority.rfind(':') {
+        Some(pos) => {
+            match authority[pos + 1..].parse() {
+                Ok(port) => (&authority[..pos], port),
+                Err(_) => { return Err(Error::BadUrl(url.to_owned())); }
+            }
+        }
+        None => (authority, default_port)
+    };
+    if host.is_empty() {
+        return Err(Error::BadUrl(url.to_owned()));
+    }
+    Ok((host.to_owned(), port, path.to_owned()))
+}
+
+/// POST a JSON body, giving the status line and body of the reply
+fn post(host: &str, port: u16, path: &str, auth: Option<&str>, body: &str) -> Result<(String, String), Error> {
+    let authorization = match auth {
+        Some(auth) => format!("Authorization: Basic {}\r\n", auth),
+        None => String::new()
+    };
+    let http = format!("POST {} HTTP/1.1\r\nHost: {}\r\n{}Content-Type: application/json\r\n\
+                        Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                       path, host, authorization, body.len(), body);
+    send(host, port, &http)
+}
+
+/// Send an HTTP request, giving the status line and body of the reply
+fn send(host: &str, port: u16, http: &str) -> Result<(String, String), Error> {
+    let mut stream = try!(TcpStream::connect((host, port))
+                              .map_err(|e| Error::Io(format!("unable to connect to {}:{}: {}", host, port, e))));
+    let mut reply = vec![];
+    try!(stream.write_all(http.as_bytes()).and_then(|_| stream.read_to_end(&mut reply))
+               .map_err(|e| Error::Io(e.to_string())));
+    let reply = String::from_utf8_lossy(&reply).into_owned();
+    match reply.find("\r\n\r\n") {
+        Some(pos) => Ok((reply[..pos].lines().next().unwrap_or("").to_owned(), reply[pos + 4..].to_owned())),
+        None => Err(Error::BadResponse(reply.clone()))
+    }
+}
+
+/// POST some JSON to a webhook, checking only that it was accepted
+pub fn post_json(url: &str, json: &Json) -> Result<(), Error> {
+    let (host, port, path) = try!(parse_url(url, 80));
+    let (status, _) = try!(post(&host, port, &path, None, &json.to_string()));
+    // Any 2xx status is success
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(Error::Http(status))
+    }
+}
+
+/// GET some JSON from an http:// URL
+pub fn get_json(url: &str) -> Result<Json, Error> {
+    let (host, port, path) = try!(parse_url(url, 80));
+    // HTTP/1.0 keeps servers from chunking the reply
+    let http = format!("GET {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\n\r\n", path, host);
+    let (status, body) = try!(send(&host, port, &http));
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Json::from_str(&body).map_err(|_| Error::BadResponse(body.clone())),
+        _ => Err(Error::Http(status))
+    }
+}
@@ -0,0 +1,238 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Segwit
+//! Pay-to-witness-script-hash outputs and their bech32 addresses
+//!
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::{Builder, Script};
+use bitcoin::network::constants::Network;
+use bitcoin::util::address::Address;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+/// The bech32 character set, indexed by 5-bit value
+const CHARSET: &'static [u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+/// Generator coefficients of the bech32 checksum
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+/// Checksum constant of bech32m, which witness versions after 0 use
+const BECH32M_CONST: u32 = 0x2bc830a3;
+
+/// The bech32 human-readable part for a network
+pub fn hrp(network: Network) -> &'static str {
+    match network {
+        Network::Bitcoin => "bc",
+        Network::Testnet => "tb"
+    }
+}
+
+/// Computes the bech32 checksum polynomial over some 5-bit values
+fn polymod(values: &[u8]) -> u32 {
+    let mut chk: u32 = 1;
+    for &v in values {
+        let b = chk >> 25;
+        chk = ((chk & 0x1ffffff) << 5) ^ v as u32;
+        for (i, gen) in GENERATOR.iter().enumerate() {
+            if (b >> i) & 1 == 1 {
+                chk ^= *gen;
+            }
+        }
+    }
+    chk
+}
+
+/// Encode a witness program as a bech32 (version 0) or bech32m (later
+/// versions) address
+pub fn encode_address(hrp: &str, version: u8, program: &[u8]) -> String {
+    // Regroup the program into 5-bit values, padding the last with zeroes
+    let mut data = vec![version];
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for &byte in program {
+        acc = (acc << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            data.push(((acc >> bits) & 0x1f) as u8);
+        }
+    }
+    if bits > 0 {
+        data.push(((acc << (5 - bits)) & 0x1f) as u8);
+    }
+
+    // Checksum covers the expanded HRP, the data and six zeroes
+    let mut values: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|c| c & 0x1f));
+    values.extend(data.iter().cloned());
+    values.extend([0u8; 6].iter().cloned());
+    let checksum = polymod(&values) ^ if version == 0 { 1 } else { BECH32M_CONST };
+    for i in 0..6 {
+        data.push(((checksum >> (5 * (5 - i))) & 0x1f) as u8);
+    }
+
+    let mut ret = hrp.to_owned();
+    ret.push('1');
+    ret.extend(data.iter().map(|&d| CHARSET[d as usize] as char));
+    ret
+}
+
+/// Decode a bech32 (version 0) or bech32m (later versions) address with a
+/// given HRP into its witness version and program
+pub fn decode_address(hrp: &str, address: &str) -> Option<(u8, Vec<u8>)> {
+    // Addresses may be all upper or all lower case
+    if address.to_lowercase() != address && address.to_uppercase() != address {
+        return None;
+    }
+    let address = address.to_lowercase();
+    let split = match address.rfind('1') {
+        Some(split) if &address[..split] == hrp && address.len() - split > 7 => split,
+        _ => { return None; }
+    };
+    let mut data = vec![];
+    for c in address[split + 1..].bytes() {
+        match CHARSET.iter().position(|&d| d == c) {
+            Some(d) => data.push(d as u8),
+            None => { return None; }
+        }
+    }
+    let mut values: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|c| c & 0x1f));
+    values.extend(data.iter().cloned());
+    let version = data[0];
+    let expected = if version == 0 { 1 } else { BECH32M_CONST };
+    if version > 16 || polymod(&values) != expected {
+        return None;
+    }
+
+    // Regroup the 5-bit values after the version into bytes, which must
+    // leave fewer than 5 bits of zero padding
+    let mut program = vec![];
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for &d in &data[1..data.len() - 6] {
+        acc = (acc << 5) | d as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            program.push((acc >> bits) as u8);
+        }
+    }
+    if bits >= 5 || acc & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    match (version, program.len()) {
+        (0, 20) | (0, 32) => Some((version, program)),
+        (0, _) => None,
+        (_, n) if n >= 2 && n <= 40 => Some((version, program)),
+        _ => None
+    }
+}
+
+/// The scriptPubKey of a witness program
+pub fn witness_script_pubkey(version: u8, program: &[u8]) -> Script {
+    // Versions 1 to 16 are OP_1 to OP_16
+    let mut ret = vec![if version == 0 { 0 } else { 0x50 + version }];
+    ret.extend(Builder::new().push_slice(program).into_script()[..].iter().cloned());
+    Script::from(ret)
+}
+
+/// The SHA256 of a witness script, i.e. its version 0 witness program
+pub fn witness_program(script: &Script) -> [u8; 32] {
+    let mut sha = Sha256::new();
+    sha.input(&script[..]);
+    let mut ret = [0; 32];
+    sha.result(&mut ret);
+    ret
+}
+
+/// Generates a P2WSH scriptPubKey spending to a witness script
+pub fn p2wsh_script_pubkey(script: &Script) -> Script {
+    Builder::new().push_opcode(opcodes::All::OP_PUSHBYTES_0)
+                  .push_slice(&witness_program(script)[..])
+                  .into_script()
+}
+
+/// Generates the bech32 P2WSH address of a witness script
+pub fn p2wsh_address(network: Network, script: &Script) -> String {
+    encode_address(hrp(network), 0, &witness_program(script)[..])
+}
+
+/// Generates the P2SH-wrapped P2WSH address of a witness script, which is
+/// the form Elements uses for peg-in addresses under dynamic federations
+pub fn p2sh_p2wsh_address(network: Network, script: &Script) -> Address {
+    Address::from_script(network, &p2wsh_script_pubkey(script))
+}
+
+#[cfg(test)]
+mod tests {
+    use serialize::hex::ToHex;
+
+    use super::*;
+
+    #[test]
+    fn valid_addresses() {
+        // BIP350's valid segwit addresses, and their scriptPubKeys
+        let vectors = [
+            ("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4", "0014751e76e8199196d454941c45d1b3a323f1433bd6"),
+            ("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+             "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262"),
+            ("bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kt5nd6y",
+             "5128751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6"),
+            ("BC1SW50QGDZ25J", "6002751e"),
+            ("bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs", "5210751e76e8199196d454941c45d1b3a323"),
+            ("tb1qqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesrxh6hy",
+             "0020000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433"),
+            ("tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c",
+             "5120000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433"),
+            ("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+             "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+        ];
+        for &(address, script_pubkey) in &vectors {
+            let hrp = &address[..2].to_lowercase();
+            let (version, program) = decode_address(hrp, address).unwrap();
+            assert_eq!(witness_script_pubkey(version, &program)[..].to_hex(), script_pubkey);
+            assert_eq!(encode_address(hrp, version, &program), address.to_lowercase());
+        }
+    }
+
+    #[test]
+    fn invalid_addresses() {
+        // BIP173's and BIP350's invalid addresses, with the reason each is
+        let vectors = [
+            ("tc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq5zuyut", "wrong HRP"),
+            ("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd", "version 1 with a bech32 checksum"),
+            ("tb1z0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqglt7rf", "version 2 with a bech32 checksum"),
+            ("BC1S0XLXVLHEMJA6C4DQV22UAPCTQUPFHLXM9H8Z3K2E72Q4K9HCZ7VQ54WELL", "version 16 with a bech32 checksum"),
+            ("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh", "version 0 with a bech32m checksum"),
+            ("tb1q0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq24jc47", "version 0 with a bech32m checksum"),
+            ("bc1p38j9r5y49hruaue7wxjce0updqjuyyx0kh56v8s25huc6995vvpql3jow4", "character not in the charset"),
+            ("BC130XLXVLHEMJA6C4DQV22UAPCTQUPFHLXM9H8Z3K2E72Q4K9HCZ7VQ7ZWS8R", "version 17"),
+            ("bc1pw5dgrnzv", "program of 1 byte"),
+            ("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7v8n0nx0muaewav253zgeav", "program of 41 bytes"),
+            ("BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P", "version 0 program of 16 bytes"),
+            ("tb1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq47Zagq", "mixed case"),
+            ("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7v07qwwzcrf", "more than 4 bits of padding"),
+            ("tb1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vpggkg4j", "non-zero padding"),
+            ("bc1gmk9yu", "empty data"),
+            ("bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcT", "mixed case checksum")
+        ];
+        for &(address, reason) in &vectors {
+            let hrp = if address.to_lowercase().starts_with("tb") { "tb" } else { "bc" };
+            assert_eq!(decode_address(hrp, address), None, "{}: {}", address, reason);
+        }
+    }
+}
@@ -0,0 +1,640 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # JSON-RPC Server
+//! Answering requests for tweaks as JSON-RPC 2.0, one request per line, so
+//! that services can make many without starting a process for each. The
+//! secp context, and any redeem script loaded at startup, are kept between
+//! requests. Parameters are given by name; a contract is given as
+//! `contract` (hex), or as `ascii` or `p2sh` with a `nonce`.
+//!
+//! Methods:
+//!
+//! * `gen_address` (contract, and `script` or `key`): the tweaked script and
+//!   its addresses, or the tweaked key and its P2PKH address, with
+//!   `warnings` if the contract's nonce is too predictable to hide it
+//! * `tweak_privkey` (contract, `privkey`): the tweaked private key
+//! * `verify` (contract, `script` or `key`, `address`): whether the address
+//!   commits to the contract
+//! * `decode_contract` (contract): the parts of a contract
+//!
+//! Over HTTP each method is instead POSTed to its own path, e.g.
+//! `/gen_address`, with the parameters as the JSON body, and answered with
+//! the result alone. Since an HTTP port is easier to reach by mistake,
+//! `tweak_privkey` is only served there when it has been enabled. A GET of
+//! `/metrics` gives the server's metrics for Prometheus.
+//!
+//! HTTP requests may be required to carry a bearer token, from a file of
+//! one token per line followed by the comma-separated methods (or
+//! `metrics`, or `*` for everything) it may call. TLS is not supported, so
+//! tokens and private keys cross the connection in plaintext; a server
+//! with tokens, or serving `tweak_privkey`, therefore only listens on a
+//! loopback address (see `is_loopback`), behind a proxy which terminates
+//! TLS if it is to be reached from elsewhere.
+//!
+//! Requests over HTTP or the unix socket may be rate limited, for each
+//! client and in total (see the `ratelimit` module). Over HTTP a client is
+//! its token, or its address when tokens are not required, and a refused
+//! request is answered `429 Too Many Requests` with a `Retry-After`; on the
+//! socket, whose clients cannot be told apart, every connection counts as
+//! the same client, and a refused request gets a `RATE_LIMITED` error.
+//!
+//! With `--jsonl` requests are read from stdin and answered on stdout, for
+//! parents which keep pacthash as a child process. Each line is an object
+//! naming its method as `op`, alongside its parameters, e.g.
+//! `{"op":"decode_contract","contract":"..."}`, and is answered by a line
+//! with its `result` or `error`, and its `id` if it had one.
+//!
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::util::address::Privkey;
+use bitcoin::util::contracthash::tweak_keys;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use crypto::util::fixed_time_eq;
+use secp256k1::Secp256k1;
+use secp256k1::key::PublicKey;
+use serialize::hex::{FromHex, ToHex};
+use serialize::json::Json;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::time::Instant;
+
+use asm;
+use chain::Chain;
+use context;
+use contract::{Contract, Nonce};
+use memlock;
+use metrics::Metrics;
+use privkey;
+use template;
+
+/// JSON-RPC error code of a request which is not JSON
+pub const PARSE_ERROR: i64 = -32700;
+/// JSON-RPC error code of a request which is not a request
+pub const INVALID_REQUEST: i64 = -32600;
+/// JSON-RPC error code of an unknown method
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// JSON-RPC error code of missing or unparseable parameters
+pub const INVALID_PARAMS: i64 = -32602;
+/// Error code of a tweak which failed
+pub const TWEAK_FAILED: i64 = -1;
+/// Error code of a request refused for exceeding a rate limit
+pub const RATE_LIMITED: i64 = -2;
+
+/// Whether an address to listen on, like `127.0.0.1:8080`, is reachable
+/// only from this machine: every address it resolves to is a loopback
+/// one. One which does not resolve is not.
+pub fn is_loopback(address: &str) -> bool {
+    match address.to_socket_addrs() {
+        Ok(addresses) => {
+            let addresses: Vec<_> = addresses.collect();
+            !addresses.is_empty() && addresses.iter().all(|a| a.ip().is_loopback())
+        }
+        Err(_) => false
+    }
+}
+
+/// A failed call, with its error code and message
+pub type CallError = (i64, String);
+
+/// What a token may use besides the methods
+const SCOPES: [&'static str; 6] = ["gen_address", "tweak_privkey", "verify", "decode_contract", "metrics", "*"];
+
+/// Token file error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// The file could not be read
+    Io(String),
+    /// A line was not a token and its scopes
+    BadLine(usize),
+    /// A scope is neither a method nor `metrics` nor `*`
+    UnknownScope(String)
+}
+
+/// The bearer tokens HTTP requests may use, and what each may call
+pub struct Tokens {
+    /// The SHA256 of each token, with its scopes
+    tokens: Vec<([u8; 32], Vec<String>)>
+}
+
+/// The SHA256 of a token, which is what tokens are compared by
+fn token_digest(token: &str) -> [u8; 32] {
+    let mut sha = Sha256::new();
+    sha.input_str(token);
+    let mut ret = [0; 32];
+    sha.result(&mut ret);
+    ret
+}
+
+impl Tokens {
+    /// Read a token file
+    pub fn read(filename: &str) -> Result<Tokens, Error> {
+        let mut text = String::new();
+        try!(File::open(filename).and_then(|mut f| f.read_to_string(&mut text))
+                 .map_err(|e| Error::Io(format!("{}: {}", filename, e))));
+        let mut tokens = vec![];
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 2 {
+                // The line is not echoed, since it may hold a token
+                return Err(Error::BadLine(n + 1));
+            }
+            let scopes: Vec<String> = fields[1].split(',').map(|scope| scope.to_owned()).collect();
+            if let Some(scope) = scopes.iter().find(|scope| !SCOPES.contains(&&scope[..])) {
+                return Err(Error::UnknownScope(scope.clone()));
+            }
+            tokens.push((token_digest(fields[0]), scopes));
+        }
+        Ok(Tokens { tokens: tokens })
+    }
+
+    /// Check that the `Authorization` header of a request allows some
+    /// scope, giving which token it carries (by its place in the file), or
+    /// the HTTP status to refuse it with if not
+    pub fn check(&self, authorization: Option<&str>, scope: &str) -> Result<usize, u16> {
+        let token = match authorization {
+            Some(header) if header.starts_with("Bearer ") => header[7..].trim(),
+            _ => { return Err(401); }
+        };
+        // Tokens are compared by their digests, which are all the same
+        // length, so that the time taken tells neither how long any token
+        // is nor how much of one a guess got right. Every token is compared.
+        let digest = token_digest(token);
+        let mut found = None;
+        for (n, &(ref candidate, ref scopes)) in self.tokens.iter().enumerate() {
+            if fixed_time_eq(candidate, &digest) {
+                found = Some((n, scopes));
+            }
+        }
+        match found {
+            Some((n, scopes)) if scopes.iter().any(|s| s == scope || s == "*") => Ok(n),
+            Some(_) => Err(403),
+            None => Err(401)
+        }
+    }
+}
+
+/// The state kept between requests
+pub struct Server {
+    /// Context for all key operations
+    secp: &'static Secp256k1,
+    /// Chain addresses and keys are for
+    chain: Chain,
+    /// Redeem script used when a request gives none
+    script: Option<Script>,
+    /// Counts and latencies of the requests answered
+    metrics: Arc<Metrics>
+}
+
+/// Make an error of missing or unparseable parameters
+fn invalid_params(message: String) -> CallError {
+    (INVALID_PARAMS, message)
+}
+
+/// A string parameter of a call
+fn param<'a>(params: &'a Json, name: &str) -> Option<&'a str> {
+    params.find(name).and_then(|p| p.as_string())
+}
+
+impl Server {
+    /// A server for some chain, with the redeem script to use when a
+    /// request gives none
+    pub fn new(chain: Chain, script: Option<Script>) -> Server {
+        Server::with_metrics(chain, script, Arc::new(Metrics::new()))
+    }
+
+    /// A server which counts its requests in some metrics, which may outlive
+    /// it (e.g. across a reload)
+    pub fn with_metrics(chain: Chain, script: Option<Script>, metrics: Arc<Metrics>) -> Server {
+        Server {
+            secp: context::secp(),
+            chain: chain,
+            script: script,
+            metrics: metrics
+        }
+    }
+
+    /// The metrics of the requests answered
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Answer one line of JSON-RPC with a line (without its newline)
+    pub fn handle_line(&self, line: &str) -> String {
+        let request = match Json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => { return response(Json::Null, Err((PARSE_ERROR, format!("{}", e)))).to_string(); }
+        };
+        let id = request.find("id").cloned().unwrap_or(Json::Null);
+        let result = match request.find("method").and_then(|m| m.as_string()) {
+            Some(method) => {
+                let no_params = Json::Object(BTreeMap::new());
+                self.call(method, request.find("params").unwrap_or(&no_params))
+            }
+            None => Err((INVALID_REQUEST, "request has no method".to_owned()))
+        };
+        response(id, result).to_string()
+    }
+
+    /// Call a method with some parameters
+    pub fn call(&self, method: &str, params: &Json) -> Result<Json, CallError> {
+        let start = Instant::now();
+        let result = self.dispatch(method, params);
+        let elapsed = start.elapsed();
+
+        // Unknown methods are counted together, so that junk requests
+        // cannot make ever more series
+        let labels = match result {
+            Err((METHOD_NOT_FOUND, _)) => "method=\"unknown\"".to_owned(),
+            _ => format!("method=\"{}\"", method)
+        };
+        self.metrics.increment("pacthash_requests_total", &labels);
+        self.metrics.observe_latency(&labels, elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9);
+        match result {
+            Ok(ref result) if method == "verify" => {
+                if result.find("valid").and_then(|v| v.as_boolean()) == Some(false) {
+                    self.metrics.increment("pacthash_verify_failures_total", "");
+                }
+            }
+            Ok(_) if method == "gen_address" || method == "tweak_privkey" => {
+                self.metrics.increment("pacthash_derivations_total", "");
+            }
+            Ok(_) => {}
+            Err(_) => self.metrics.increment("pacthash_request_errors_total", &labels)
+        }
+        result
+    }
+
+    /// Call a method, without counting it
+    fn dispatch(&self, method: &str, params: &Json) -> Result<Json, CallError> {
+        if !params.is_object() {
+            return Err(invalid_params("parameters must be given by name".to_owned()));
+        }
+        match method {
+            "gen_address" => self.gen_address(params),
+            "tweak_privkey" => self.tweak_privkey(params),
+            "verify" => self.verify(params),
+            "decode_contract" => self.decode_contract(params),
+            _ => Err((METHOD_NOT_FOUND, format!("no method {}", method)))
+        }
+    }
+
+    /// The contract of a call
+    fn contract(&self, params: &Json) -> Result<Contract, CallError> {
+        let nonce = match param(params, "nonce").map(Nonce::from_hex) {
+            Some(Ok(nonce)) => Some(nonce),
+            Some(Err(e)) => { return Err(invalid_params(format!("nonce could not be parsed: {:?}", e))); }
+            None => None
+        };
+        let contract = match (param(params, "contract"), nonce, param(params, "ascii"), param(params, "p2sh")) {
+            (Some(hex), None, None, None) => Contract::from_hex(hex),
+            (None, Some(nonce), Some(ascii), None) => Contract::from_ascii_str(ascii, nonce),
+            (None, Some(nonce), None, Some(p2sh)) => Contract::from_p2sh_base58_str(p2sh, nonce, self.chain),
+            _ => { return Err(invalid_params("give exactly one of: contract; ascii and nonce; or p2sh and nonce".to_owned())); }
+        };
+        contract.map_err(|e| invalid_params(format!("contract could not be parsed: {:?}", e)))
+    }
+
+    /// The untweaked key of a call, if it gives one
+    fn key(&self, params: &Json) -> Result<Option<PublicKey>, CallError> {
+        match param(params, "key") {
+            Some(hex) => {
+                match hex.from_hex().ok().and_then(|data| PublicKey::from_slice(&self.secp, &data).ok()) {
+                    Some(key) => Ok(Some(key)),
+                    None => Err(invalid_params(format!("key {} could not be parsed", hex)))
+                }
+            }
+            None => Ok(None)
+        }
+    }
+
+    /// The untweaked redeem script of a call, or the loaded one
+    fn script(&self, params: &Json) -> Result<Script, CallError> {
+        match param(params, "script") {
+            Some(s) => {
+                asm::Encoding::Hex.decode(s).or_else(|_| asm::assemble(s))
+                                  .map_err(|e| invalid_params(format!("script could not be parsed as hex or asm: {:?}", e)))
+            }
+            None => self.script.clone().ok_or(invalid_params("no script was given or loaded".to_owned()))
+        }
+    }
+
+    /// Tweak a key for a contract
+    fn tweak_key(&self, key: &PublicKey, contract: &Contract) -> Result<PublicKey, CallError> {
+        tweak_keys(&self.secp, &[*key], &contract.to_bytes()[..])
+            .map(|mut keys| keys.pop().unwrap())
+            .map_err(|e| (TWEAK_FAILED, format!("unable to tweak key: {:?}", e)))
+    }
+
+    /// Tweak a script for a contract
+    fn tweak_script(&self, script: &Script, contract: &Contract) -> Result<(Script, Vec<(PublicKey, PublicKey)>), CallError> {
+        match template::tweak_script(&self.secp, script, &contract.to_bytes()[..]) {
+            Ok((new_script, locations, tweaked_keys)) => {
+                Ok((new_script, locations.into_iter().map(|location| location.key).zip(tweaked_keys.into_iter()).collect()))
+            }
+            Err(e) => Err((TWEAK_FAILED, format!("unable to tweak script: {:?}", e)))
+        }
+    }
+
+    /// Hex of a compressed key
+    fn key_hex(&self, key: &PublicKey) -> String {
+        key.serialize_vec(&self.secp, true)[..].to_hex()
+    }
+
+    /// The `gen_address` method
+    fn gen_address(&self, params: &Json) -> Result<Json, CallError> {
+        let contract = try!(self.contract(params));
+        let mut ret = BTreeMap::new();
+        ret.insert("contract".to_owned(), Json::String(format!("{:x}", contract)));
+        ret.insert("nonce".to_owned(), Json::String(format!("{:x}", Nonce::from_contract(&contract))));
+        if let Some(weakness) = Nonce::from_contract(&contract).weakness() {
+            ret.insert("warnings".to_owned(), Json::Array(vec![Json::String(format!("the nonce is weak: {}", weakness))]));
+        }
+        if let Some(key) = try!(self.key(params)) {
+            let tweaked = try!(self.tweak_key(&key, &contract));
+            ret.insert("key".to_owned(), Json::String(self.key_hex(&tweaked)));
+            ret.insert("p2pkh".to_owned(), Json::String(self.chain.p2pkh_address(&tweaked, true)));
+            return Ok(Json::Object(ret));
+        }
+        let (new_script, keys) = try!(self.tweak_script(&try!(self.script(params)), &contract));
+        ret.insert("script".to_owned(), Json::String(new_script[..].to_hex()));
+        ret.insert("p2sh".to_owned(), Json::String(self.chain.p2sh_address(&new_script)));
+        ret.insert("p2wsh".to_owned(), Json::String(self.chain.p2wsh_address(&new_script)));
+        ret.insert("p2sh_p2wsh".to_owned(), Json::String(self.chain.p2sh_p2wsh_address(&new_script)));
+        ret.insert("keys".to_owned(), Json::Array(keys.iter().map(|&(ref original, ref tweaked)| {
+            let mut key = BTreeMap::new();
+            key.insert("original".to_owned(), Json::String(self.key_hex(original)));
+            key.insert("tweaked".to_owned(), Json::String(self.key_hex(tweaked)));
+            Json::Object(key)
+        }).collect()));
+        Ok(Json::Object(ret))
+    }
+
+    /// The `tweak_privkey` method
+    fn tweak_privkey(&self, params: &Json) -> Result<Json, CallError> {
+        let contract = try!(self.contract(params));
+        let key = match param(params, "privkey").map(|s| privkey::decode(&self.secp, s, self.chain, None)) {
+            Some(Ok(ref key)) if key.network != self.chain.network() => { return Err(invalid_params("privkey is for the wrong network".to_owned())); }
+            Some(Ok(key)) => memlock::Secret::new(key),
+            Some(Err(e)) => { return Err(invalid_params(format!("privkey could not be parsed: {:?}", e))); }
+            None => { return Err(invalid_params("privkey must be given".to_owned())); }
+        };
+        let tweaked = memlock::Secret::new(Privkey {
+            compressed: key.compressed,
+            network: self.chain.network(),
+            key: try!(context::tweak_secret_key(&key.key, &contract.to_bytes()[..])
+                          .map_err(|e| (TWEAK_FAILED, format!("unable to tweak key: {:?}", e))))
+        });
+        let public_key = try!(PublicKey::from_secret_key(&self.secp, &tweaked.key)
+                                  .map_err(|e| (TWEAK_FAILED, format!("unable to compute public key: {:?}", e))));
+        let mut ret = BTreeMap::new();
+        ret.insert("privkey".to_owned(), Json::String(self.chain.encode_privkey(&tweaked)));
+        ret.insert("pubkey".to_owned(), Json::String(public_key.serialize_vec(&self.secp, tweaked.compressed)[..].to_hex()));
+        ret.insert("p2pkh".to_owned(), Json::String(self.chain.p2pkh_address(&public_key, tweaked.compressed)));
+        Ok(Json::Object(ret))
+    }
+
+    /// The `verify` method
+    fn verify(&self, params: &Json) -> Result<Json, CallError> {
+        let contract = try!(self.contract(params));
+        let address = try!(param(params, "address").ok_or(invalid_params("address must be given".to_owned())));
+        let candidates = match try!(self.key(params)) {
+            Some(key) => {
+                let tweaked = try!(self.tweak_key(&key, &contract));
+                vec![("P2PKH", self.chain.p2pkh_address(&tweaked, true))]
+            }
+            None => {
+                let (new_script, _) = try!(self.tweak_script(&try!(self.script(params)), &contract));
+                vec![("P2SH", self.chain.p2sh_address(&new_script)),
+                     ("P2WSH", self.chain.p2wsh_address(&new_script)),
+                     ("P2SH-P2WSH", self.chain.p2sh_p2wsh_address(&new_script))]
+            }
+        };
+        let found = candidates.iter().find(|&&(_, ref candidate)| *candidate == address);
+        let mut ret = BTreeMap::new();
+        ret.insert("valid".to_owned(), Json::Boolean(found.is_some()));
+        ret.insert("address_type".to_owned(), found.map_or(Json::Null, |&(ty, _)| Json::String(ty.to_owned())));
+        Ok(Json::Object(ret))
+    }
+
+    /// The `decode_contract` method
+    fn decode_contract(&self, params: &Json) -> Result<Json, CallError> {
+        let contract = try!(self.contract(params));
+        let mut ret = BTreeMap::new();
+        ret.insert("contract".to_owned(), Json::String(format!("{:x}", contract)));
+        ret.insert("type".to_owned(), Json::String(contract.type_name()));
+        ret.insert("nonce".to_owned(), Json::String(format!("{:x}", Nonce::from_contract(&contract))));
+        ret.insert("data".to_owned(), Json::String(contract.data().to_hex()));
+        match contract.destination_script() {
+            Some(script) => { ret.insert("destination_script".to_owned(), Json::String(script[..].to_hex())); }
+            None => { ret.insert("text".to_owned(), Json::String(String::from_utf8_lossy(contract.data()).into_owned())); }
+        }
+        Ok(Json::Object(ret))
+    }
+}
+
+impl Server {
+    /// Answer a request from a JSON line, as a JSON line
+    pub fn handle_jsonl(&self, line: &str) -> String {
+        let mut ret = BTreeMap::new();
+        let result = match Json::from_str(line) {
+            Ok(request) => {
+                if let Some(id) = request.find("id") {
+                    ret.insert("id".to_owned(), id.clone());
+                }
+                match request.find("op").and_then(|op| op.as_string()) {
+                    Some(op) => self.call(op, &request),
+                    None => Err((INVALID_REQUEST, "request has no op".to_owned()))
+                }
+            }
+            Err(e) => Err((PARSE_ERROR, format!("{}", e)))
+        };
+        match result {
+            Ok(result) => { ret.insert("result".to_owned(), result); }
+            Err((code, message)) => {
+                let mut error = BTreeMap::new();
+                error.insert("code".to_owned(), Json::I64(code));
+                error.insert("message".to_owned(), Json::String(message));
+                ret.insert("error".to_owned(), Json::Object(error));
+            }
+        }
+        Json::Object(ret).to_string()
+    }
+
+    /// Answer an HTTP request for a method, giving the status and body
+    pub fn handle_http(&self, method: &str, path: &str, body: &str, allow_privkeys: bool) -> (u16, Json) {
+        let error = |code: i64, message: &str| {
+            let mut error = BTreeMap::new();
+            error.insert("code".to_owned(), Json::I64(code));
+            error.insert("message".to_owned(), Json::String(message.to_owned()));
+            let mut ret = BTreeMap::new();
+            ret.insert("error".to_owned(), Json::Object(error));
+            Json::Object(ret)
+        };
+        let name = path.trim_left_matches('/');
+        if name == "tweak_privkey" && !allow_privkeys {
+            return (403, error(METHOD_NOT_FOUND, "private key operations are not enabled"));
+        }
+        if method != "POST" {
+            return (405, error(INVALID_REQUEST, "methods must be POSTed"));
+        }
+        let params = match Json::from_str(body) {
+            Ok(params) => params,
+            Err(e) => { return (400, error(PARSE_ERROR, &format!("{}", e))); }
+        };
+        match self.call(name, &params) {
+            Ok(result) => (200, result),
+            Err((METHOD_NOT_FOUND, message)) => (404, error(METHOD_NOT_FOUND, &message)),
+            Err((TWEAK_FAILED, message)) => (422, error(TWEAK_FAILED, &message)),
+            Err((code, message)) => (400, error(code, &message))
+        }
+    }
+}
+
+/// A JSON-RPC 2.0 response
+pub fn response(id: Json, result: Result<Json, CallError>) -> Json {
+    let mut ret = BTreeMap::new();
+    ret.insert("jsonrpc".to_owned(), Json::String("2.0".to_owned()));
+    ret.insert("id".to_owned(), id);
+    match result {
+        Ok(result) => { ret.insert("result".to_owned(), result); }
+        Err((code, message)) => {
+            let mut error = BTreeMap::new();
+            error.insert("code".to_owned(), Json::I64(code));
+            error.insert("message".to_owned(), Json::String(message));
+            ret.insert("error".to_owned(), Json::Object(error));
+        }
+    }
+    Json::Object(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::script::Script;
+    use bitcoin::network::constants::Network;
+    use bitcoin::util::address::Privkey;
+    use rand::{Rng, SeedableRng, StdRng};
+    use secp256k1::Secp256k1;
+    use secp256k1::key::{PublicKey, SecretKey};
+    use serialize::hex::ToHex;
+    use serialize::json::Json;
+
+    use std::collections::BTreeMap;
+
+    use chain::{Chain, Registry};
+    use contract::{Contract, Nonce};
+    use super::{is_loopback, token_digest, Server, Tokens};
+
+    fn params(args: &[(&str, &str)]) -> Json {
+        let mut params = BTreeMap::new();
+        for &(name, arg) in args {
+            params.insert(name.to_owned(), Json::String(arg.to_owned()));
+        }
+        Json::Object(params)
+    }
+
+    #[test]
+    fn token_check() {
+        let tokens = Tokens { tokens: vec![(token_digest("s3cret-token"), vec!["verify".to_owned()]),
+                                           (token_digest("admin"), vec!["*".to_owned()])] };
+        assert_eq!(tokens.check(Some("Bearer s3cret-token"), "verify"), Ok(0));
+        assert_eq!(tokens.check(Some("Bearer admin"), "metrics"), Ok(1));
+        // Known, but not allowed the scope
+        assert_eq!(tokens.check(Some("Bearer s3cret-token"), "tweak_privkey"), Err(403));
+        // Prefixes, extensions and guesses of the same length are all unknown
+        assert_eq!(tokens.check(Some("Bearer s3cret"), "verify"), Err(401));
+        assert_eq!(tokens.check(Some("Bearer s3cret-token2"), "verify"), Err(401));
+        assert_eq!(tokens.check(Some("Bearer s3cret-tokeN"), "verify"), Err(401));
+        assert_eq!(tokens.check(Some("Basic s3cret-token"), "verify"), Err(401));
+        assert_eq!(tokens.check(None, "verify"), Err(401));
+    }
+
+    #[test]
+    fn loopback() {
+        assert!(is_loopback("127.0.0.1:8080"));
+        assert!(is_loopback("127.1.2.3:8080"));
+        assert!(is_loopback("[::1]:8080"));
+        assert!(!is_loopback("0.0.0.0:8080"));
+        assert!(!is_loopback("[::]:8080"));
+        assert!(!is_loopback("192.0.2.1:8080"));
+        // Without a port it cannot be listened on at all
+        assert!(!is_loopback("127.0.0.1"));
+    }
+
+    #[test]
+    fn gen_address_verifies() {
+        let secp = Secp256k1::new();
+        let server = Server::new(Chain::Testnet, None);
+        let seed: &[_] = &[1, 8, 7];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        for _ in 0..32 {
+            let sk = SecretKey::new(&secp, &mut rng);
+            let key = PublicKey::from_secret_key(&secp, &sk).unwrap().serialize_vec(&secp, true).to_hex();
+            let script = format!("5121{}51ae", key);
+            let text: String = (0..20).map(|_| rng.gen_range(b'a', b'z' + 1) as char).collect();
+            let contract = format!("{:x}", Contract::from_ascii_str(&text, rng.gen::<Nonce>()).unwrap());
+            let other = format!("{:x}", Contract::from_ascii_str(&text, rng.gen::<Nonce>()).unwrap());
+
+            // Each address gen_address derives is one verify accepts, for
+            // that contract only
+            let by_key = server.call("gen_address", &params(&[("contract", &contract), ("key", &key)])).unwrap();
+            let by_script = server.call("gen_address", &params(&[("contract", &contract), ("script", &script)])).unwrap();
+            let cases = [(&by_key, "key", &key, "p2pkh"), (&by_script, "script", &script, "p2sh"),
+                         (&by_script, "script", &script, "p2wsh"), (&by_script, "script", &script, "p2sh_p2wsh")];
+            for &(result, name, value, ty) in &cases {
+                let address = result.find(ty).and_then(|a| a.as_string()).unwrap();
+                for &(contract, valid) in &[(&contract, true), (&other, false)] {
+                    let check = params(&[("contract", contract), (name, value), ("address", address)]);
+                    let verified = server.call("verify", &check).unwrap();
+                    assert_eq!(verified.find("valid").and_then(|v| v.as_boolean()), Some(valid), "{} {}", ty, address);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn declared_chain() {
+        // A chain from a registry file is the one keys and addresses are
+        // read and written for, and Bitcoin's are refused
+        let chain = Registry::parse("mycoin mainnet 50 55 178 my").unwrap().get("mycoin").unwrap();
+        let server = Server::new(chain, None);
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&secp, &[1; 32]).unwrap();
+        let privkey = Privkey { compressed: true, network: Network::Bitcoin, key: sk };
+        let tweaked = server.call("tweak_privkey", &params(&[("ascii", "the quick brown fox!"), ("nonce", "000102030405060708090a0b0c0d0e0f"),
+                                                            ("privkey", &chain.encode_privkey(&privkey))])).unwrap();
+        let tweaked_key = chain.decode_privkey(tweaked.find("privkey").and_then(|k| k.as_string()).unwrap()).unwrap();
+        let public_key = PublicKey::from_secret_key(&secp, &tweaked_key.key).unwrap();
+        assert_eq!(tweaked.find("p2pkh").and_then(|a| a.as_string()), Some(&chain.p2pkh_address(&public_key, true)[..]));
+        assert!(server.call("tweak_privkey", &params(&[("ascii", "the quick brown fox!"), ("nonce", "000102030405060708090a0b0c0d0e0f"),
+                                                       ("privkey", &Chain::Bitcoin.encode_privkey(&privkey))])).is_err());
+
+        let key = PublicKey::from_secret_key(&secp, &sk).unwrap().serialize_vec(&secp, true).to_hex();
+        let script = Script::from(vec![0x51]);
+        let by_p2sh = server.call("gen_address", &params(&[("p2sh", &chain.p2sh_address(&script)), ("nonce", "000102030405060708090a0b0c0d0e0f"),
+                                                           ("key", &key)])).unwrap();
+        assert!(by_p2sh.find("p2pkh").and_then(|a| a.as_string()).unwrap().starts_with('M'));
+        assert!(server.call("gen_address", &params(&[("p2sh", &Chain::Bitcoin.p2sh_address(&script)), ("nonce", "000102030405060708090a0b0c0d0e0f"),
+                                                     ("key", &key)])).is_err());
+    }
+}
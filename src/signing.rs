@@ -0,0 +1,140 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Signed Files
+//! Signing the files pacthash writes with an operator's key, so that the
+//! records which later justify moving funds are tamper-evident, and checking
+//! those signatures. The signing is done by `minisign` or `gpg`, which ask
+//! on the terminal for the key's passphrase if it has one. A signature is
+//! written beside its file, as `FILE.minisig` or `FILE.asc`.
+//!
+//! Keys are named as `minisign:FILE`, for a minisign secret key file (or,
+//! to verify, a public key file), or `gpg:ID` for a key of the gpg keyring.
+//! To sign, `ID` is anything gpg accepts, or may be left out for gpg's
+//! default key; to verify, it is the fingerprint the signature must be made
+//! by, or left out to accept any key of the keyring.
+//!
+
+use std::process::{Command, Stdio};
+
+/// Signing error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// A key was not named as `minisign:FILE` or `gpg:ID`
+    BadKey(String),
+    /// The signing program could not be run, or failed
+    Failed(String),
+    /// The signature was made by some key other than the one asked for
+    WrongKey(String)
+}
+
+/// A key to sign or verify with
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Key {
+    /// A minisign key file
+    Minisign(String),
+    /// A key of the gpg keyring, or gpg's default
+    Gpg(Option<String>)
+}
+
+/// Run a program, letting it prompt on the terminal, and collect its output
+fn run(program: &str, args: &[&str]) -> Result<String, Error> {
+    let output = try!(Command::new(program).args(args)
+                          .stdin(Stdio::inherit())
+                          .stderr(Stdio::inherit())
+                          .output()
+                          .map_err(|e| Error::Failed(format!("unable to run {}: {}", program, e))));
+    if !output.status.success() {
+        return Err(Error::Failed(format!("{} failed ({})", program, output.status)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// A fingerprint as gpg prints it, without spaces and in upper case
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_uppercase()
+}
+
+impl Key {
+    /// Parse a key named as `minisign:FILE`, `gpg:ID` or `gpg`
+    pub fn parse(s: &str) -> Result<Key, Error> {
+        if s.starts_with("minisign:") && s.len() > "minisign:".len() {
+            Ok(Key::Minisign(s["minisign:".len()..].to_owned()))
+        } else if s == "gpg" {
+            Ok(Key::Gpg(None))
+        } else if s.starts_with("gpg:") && s.len() > "gpg:".len() {
+            Ok(Key::Gpg(Some(s["gpg:".len()..].to_owned())))
+        } else {
+            Err(Error::BadKey(s.to_owned()))
+        }
+    }
+
+    /// Where the signature of a file is written
+    pub fn signature_path(&self, filename: &str) -> String {
+        match *self {
+            Key::Minisign(_) => format!("{}.minisig", filename),
+            Key::Gpg(_) => format!("{}.asc", filename)
+        }
+    }
+
+    /// Sign a file, giving where the signature was written
+    pub fn sign(&self, filename: &str) -> Result<String, Error> {
+        let signature = self.signature_path(filename);
+        match *self {
+            Key::Minisign(ref key) => {
+                try!(run("minisign", &["-S", "-s", key, "-m", filename, "-x", &signature]));
+            }
+            Key::Gpg(ref id) => {
+                let mut args = vec!["--armor", "--detach-sign", "--yes", "--output", &signature];
+                if let Some(ref id) = *id {
+                    args.push("--local-user");
+                    args.push(id);
+                }
+                args.push(filename);
+                try!(run("gpg", &args));
+            }
+        }
+        Ok(signature)
+    }
+
+    /// Check the signature of a file, giving what the program said of it:
+    /// minisign's trusted comment, or the fingerprint gpg found
+    pub fn verify(&self, filename: &str, signature: &str) -> Result<String, Error> {
+        match *self {
+            Key::Minisign(ref key) => {
+                let output = try!(run("minisign", &["-V", "-p", key, "-m", filename, "-x", signature]));
+                Ok(output.lines().find(|line| line.starts_with("Trusted comment:"))
+                         .unwrap_or("signature verified").to_owned())
+            }
+            Key::Gpg(ref id) => {
+                let output = try!(run("gpg", &["--status-fd", "1", "--verify", signature, filename]));
+                // VALIDSIG gives the signing key's fingerprint first and the
+                // primary key's last, either of which may be the one asked for
+                let fingerprints: Vec<String> = match output.lines().find(|line| line.starts_with("[GNUPG:] VALIDSIG ")) {
+                    Some(line) => {
+                        let fields: Vec<&str> = line.split_whitespace().collect();
+                        vec![fields[2].to_owned(), fields[fields.len() - 1].to_owned()]
+                    }
+                    None => { return Err(Error::Failed("gpg found no valid signature".to_owned())); }
+                };
+                if let Some(ref id) = *id {
+                    if !fingerprints.iter().any(|f| *f == normalize_fingerprint(id)) {
+                        return Err(Error::WrongKey(fingerprints[0].clone()));
+                    }
+                }
+                Ok(format!("good signature by {}", fingerprints[0]))
+            }
+        }
+    }
+}
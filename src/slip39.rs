@@ -0,0 +1,548 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # SLIP-39
+//! Splitting secrets into Shamir shares written as SLIP-39 mnemonics, and
+//! combining the shares back into the secret. Splitting makes a single
+//! group of shares; combining accepts any group layout.
+//!
+
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::pbkdf2::pbkdf2;
+use crypto::sha2::Sha256;
+use rand::Rng;
+
+/// The SLIP-39 wordlist, one word per line in alphabetical order
+const WORDLIST: &'static str = include_str!("wordlists/slip39-english.txt");
+
+/// Bits encoded by each word
+const RADIX_BITS: usize = 10;
+/// Words of a share which are not its value: two of identifier and
+/// iteration exponent, two of group and member parameters, and three of
+/// checksum
+const METADATA_WORDS: usize = 7;
+/// Words in the checksum
+const CHECKSUM_WORDS: usize = 3;
+/// Number of words in the shortest share, of a 128-bit secret
+const MIN_MNEMONIC_WORDS: usize = METADATA_WORDS + 13;
+/// Most shares, or groups, that a secret can be split into
+const MAX_SHARE_COUNT: usize = 16;
+/// Iteration exponent of the shares this module makes, as other
+/// implementations default to
+const ITERATION_EXPONENT: u8 = 1;
+/// PBKDF2 iterations of the encryption, at iteration exponent zero
+const BASE_ITERATION_COUNT: u32 = 10000;
+/// Rounds of the Feistel network encrypting the secret
+const ROUND_COUNT: u8 = 4;
+/// The x coordinate of the shared secret
+const SECRET_INDEX: u8 = 255;
+/// The x coordinate of the digest of the shared secret
+const DIGEST_INDEX: u8 = 254;
+/// Length of the digest of the shared secret
+const DIGEST_LEN: usize = 4;
+
+/// SLIP-39-related error
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// Mnemonic contained a word which is not on the wordlist
+    UnknownWord(String),
+    /// Mnemonic had too few words to be a share
+    TooShort(usize),
+    /// Mnemonic had a number of words which cannot encode any share
+    BadLength(usize),
+    /// Mnemonic checksum was wrong, e.g. because words were mistyped
+    BadChecksum,
+    /// The padding bits of the share value were not zero
+    BadPadding,
+    /// Share claimed a group threshold greater than its group count
+    BadGroupThreshold,
+    /// No shares were given
+    NoShares,
+    /// Shares had different identifiers, iteration exponents or group
+    /// parameters, so are not shares of the same secret
+    MismatchedShares,
+    /// Shares of one group had different member thresholds
+    MismatchedMemberThresholds,
+    /// Shares of the wrong number of groups were given: (needed, given)
+    WrongGroupCount(usize, usize),
+    /// The wrong number of shares of a group were given: (group index,
+    /// needed, given)
+    WrongShareCount(u8, usize, usize),
+    /// Two different shares had the same index
+    DuplicateIndex(u8),
+    /// The recovered secret did not match its digest, so some share is
+    /// wrong
+    BadDigest,
+    /// Asked for a threshold and count which cannot be made
+    BadThreshold(usize, usize),
+    /// Secret was shorter than 128 bits or not a whole number of 16-bit words
+    BadSecretLength(usize),
+    /// Passphrase contained characters other than printable ASCII
+    BadPassphrase
+}
+
+/// A single share, as decoded from a mnemonic
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Share {
+    identifier: u16,
+    extendable: bool,
+    iteration_exponent: u8,
+    group_index: u8,
+    group_threshold: u8,
+    group_count: u8,
+    member_index: u8,
+    member_threshold: u8,
+    value: Vec<u8>
+}
+
+/// Look up the index of a word in the wordlist
+fn word_index(words: &[&str], word: &str) -> Option<usize> {
+    words.binary_search(&word).ok()
+}
+
+/// The RS1024 checksum state of a sequence of 10-bit values
+fn rs1024_polymod(values: &[u32]) -> u32 {
+    const GEN: [u32; 10] = [0xe0e040, 0x1c1c080, 0x3838100, 0x7070200, 0xe0e0009,
+                            0x1c0c2412, 0x38086c24, 0x3090fc48, 0x21b1f890, 0x3f3f120];
+    let mut chk = 1;
+    for &v in values {
+        let b = chk >> 20;
+        chk = (chk & 0xfffff) << 10 ^ v;
+        for (i, gen) in GEN.iter().enumerate() {
+            if (b >> i) & 1 == 1 {
+                chk ^= *gen;
+            }
+        }
+    }
+    chk
+}
+
+/// The customization string the checksum of a share commits to
+fn customization(extendable: bool) -> &'static [u8] {
+    if extendable { b"shamir_extendable" } else { b"shamir" }
+}
+
+/// Append `count` bits of `value`, most significant first
+fn push_bits(bits: &mut Vec<bool>, value: u32, count: usize) {
+    for i in (0..count).rev() {
+        bits.push((value >> i) & 1 == 1);
+    }
+}
+
+/// Read bits, most significant first, as a number
+fn read_bits(bits: &[bool]) -> u32 {
+    bits.iter().fold(0, |acc, &bit| (acc << 1) | bit as u32)
+}
+
+impl Share {
+    /// Decode a share from its mnemonic
+    fn from_mnemonic(mnemonic: &str) -> Result<Share, Error> {
+        let wordlist: Vec<&str> = WORDLIST.lines().collect();
+        let mut indices = vec![];
+        for word in mnemonic.split_whitespace() {
+            match word_index(&wordlist, &word.to_lowercase()) {
+                Some(index) => indices.push(index as u32),
+                None => { return Err(Error::UnknownWord(word.to_owned())); }
+            }
+        }
+        if indices.len() < MIN_MNEMONIC_WORDS {
+            return Err(Error::TooShort(indices.len()));
+        }
+        // The value is padded at the front to whole words, and is a whole
+        // number of 16-bit words, so the padding is at most 8 bits
+        let value_words = indices.len() - METADATA_WORDS;
+        let padding = (RADIX_BITS * value_words) % 16;
+        if padding > 8 {
+            return Err(Error::BadLength(indices.len()));
+        }
+
+        let mut bits = vec![];
+        for &index in &indices {
+            push_bits(&mut bits, index, RADIX_BITS);
+        }
+        let extendable = bits[15];
+        let mut values: Vec<u32> = customization(extendable).iter().map(|&b| b as u32).collect();
+        values.extend(indices.iter().cloned());
+        if rs1024_polymod(&values) != 1 {
+            return Err(Error::BadChecksum);
+        }
+
+        let value_bits = &bits[4 * RADIX_BITS..(indices.len() - CHECKSUM_WORDS) * RADIX_BITS];
+        if value_bits[..padding].iter().any(|&bit| bit) {
+            return Err(Error::BadPadding);
+        }
+        let value: Vec<u8> = value_bits[padding..].chunks(8).map(|byte| read_bits(byte) as u8).collect();
+
+        let share = Share {
+            identifier: read_bits(&bits[0..15]) as u16,
+            extendable: extendable,
+            iteration_exponent: read_bits(&bits[16..20]) as u8,
+            group_index: read_bits(&bits[20..24]) as u8,
+            group_threshold: read_bits(&bits[24..28]) as u8 + 1,
+            group_count: read_bits(&bits[28..32]) as u8 + 1,
+            member_index: read_bits(&bits[32..36]) as u8,
+            member_threshold: read_bits(&bits[36..40]) as u8 + 1,
+            value: value
+        };
+        if share.group_threshold > share.group_count {
+            return Err(Error::BadGroupThreshold);
+        }
+        Ok(share)
+    }
+
+    /// Encode a share as its mnemonic
+    fn to_mnemonic(&self) -> String {
+        let mut bits = vec![];
+        push_bits(&mut bits, self.identifier as u32, 15);
+        bits.push(self.extendable);
+        push_bits(&mut bits, self.iteration_exponent as u32, 4);
+        push_bits(&mut bits, self.group_index as u32, 4);
+        push_bits(&mut bits, self.group_threshold as u32 - 1, 4);
+        push_bits(&mut bits, self.group_count as u32 - 1, 4);
+        push_bits(&mut bits, self.member_index as u32, 4);
+        push_bits(&mut bits, self.member_threshold as u32 - 1, 4);
+        let value_words = (8 * self.value.len() + RADIX_BITS - 1) / RADIX_BITS;
+        push_bits(&mut bits, 0, RADIX_BITS * value_words - 8 * self.value.len());
+        for &byte in &self.value {
+            push_bits(&mut bits, byte as u32, 8);
+        }
+
+        let mut indices: Vec<u32> = bits.chunks(RADIX_BITS).map(read_bits).collect();
+        let mut values: Vec<u32> = customization(self.extendable).iter().map(|&b| b as u32).collect();
+        values.extend(indices.iter().cloned());
+        values.extend([0; CHECKSUM_WORDS].iter().cloned());
+        let checksum = rs1024_polymod(&values) ^ 1;
+        for i in (0..CHECKSUM_WORDS).rev() {
+            indices.push((checksum >> (RADIX_BITS * i)) & 1023);
+        }
+
+        let wordlist: Vec<&str> = WORDLIST.lines().collect();
+        let words: Vec<&str> = indices.iter().map(|&index| wordlist[index as usize]).collect();
+        words.join(" ")
+    }
+}
+
+/// Exponent and logarithm tables of GF(256), with the polynomial
+/// x^8 + x^4 + x^3 + x + 1 and generator x + 1
+struct Field {
+    exp: [u8; 255],
+    log: [u8; 256]
+}
+
+impl Field {
+    fn new() -> Field {
+        let mut ret = Field { exp: [0; 255], log: [0; 256] };
+        let mut poly: u16 = 1;
+        for i in 0..255 {
+            ret.exp[i] = poly as u8;
+            ret.log[poly as usize] = i as u8;
+            // Multiply by the generator
+            poly = (poly << 1) ^ poly;
+            if poly & 0x100 != 0 {
+                poly ^= 0x11b;
+            }
+        }
+        ret
+    }
+
+    /// Evaluate at `x` the polynomial through the given points, whose x
+    /// coordinates must be distinct
+    fn interpolate(&self, shares: &[(u8, Vec<u8>)], x: u8) -> Result<Vec<u8>, Error> {
+        for (n, &(index, ref value)) in shares.iter().enumerate() {
+            if shares[..n].iter().any(|&(other, _)| other == index) {
+                return Err(Error::DuplicateIndex(index));
+            }
+            if value.len() != shares[0].1.len() {
+                return Err(Error::MismatchedShares);
+            }
+            if index == x {
+                return Ok(value.clone());
+            }
+        }
+
+        let log_prod: i64 = shares.iter().map(|&(index, _)| self.log[(index ^ x) as usize] as i64).sum();
+        let mut ret = vec![0; shares[0].1.len()];
+        for &(index, ref value) in shares {
+            // The Lagrange basis polynomial of this share, evaluated at x
+            let log_basis: i64 = log_prod - self.log[(index ^ x) as usize] as i64 -
+                shares.iter().map(|&(other, _)| self.log[(index ^ other) as usize] as i64).sum::<i64>();
+            let log_basis = ((log_basis % 255) + 255) % 255;
+            for (out, &byte) in ret.iter_mut().zip(value.iter()) {
+                if byte != 0 {
+                    *out ^= self.exp[((self.log[byte as usize] as i64 + log_basis) % 255) as usize];
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// The digest of a shared secret, keyed by random data
+fn digest(random: &[u8], secret: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut hmac = Hmac::new(Sha256::new(), random);
+    hmac.input(secret);
+    let mut result = [0; 32];
+    hmac.raw_result(&mut result);
+    [result[0], result[1], result[2], result[3]]
+}
+
+/// Split a secret into `count` shares, any `threshold` of which recover it
+fn split_secret<R: Rng>(rng: &mut R, field: &Field, threshold: usize, count: usize, secret: &[u8])
+                        -> Result<Vec<(u8, Vec<u8>)>, Error> {
+    if threshold == 0 || threshold > count || count > MAX_SHARE_COUNT {
+        return Err(Error::BadThreshold(threshold, count));
+    }
+    if threshold == 1 {
+        return Ok((0..count).map(|i| (i as u8, secret.to_owned())).collect());
+    }
+
+    // Random points fix the polynomial, along with the secret and its digest
+    let mut ret = vec![];
+    for i in 0..threshold - 2 {
+        let mut value = vec![0; secret.len()];
+        rng.fill_bytes(&mut value);
+        ret.push((i as u8, value));
+    }
+    let mut digest_share = vec![0; secret.len()];
+    rng.fill_bytes(&mut digest_share[DIGEST_LEN..]);
+    let digest = digest(&digest_share[DIGEST_LEN..], secret);
+    digest_share[..DIGEST_LEN].clone_from_slice(&digest);
+
+    let mut base = ret.clone();
+    base.push((DIGEST_INDEX, digest_share));
+    base.push((SECRET_INDEX, secret.to_owned()));
+    for i in threshold - 2..count {
+        ret.push((i as u8, try!(field.interpolate(&base, i as u8))));
+    }
+    Ok(ret)
+}
+
+/// Recover a secret from `threshold` of its shares, checking its digest
+fn recover_secret(field: &Field, threshold: usize, shares: &[(u8, Vec<u8>)]) -> Result<Vec<u8>, Error> {
+    if threshold == 1 {
+        return Ok(shares[0].1.clone());
+    }
+    let secret = try!(field.interpolate(shares, SECRET_INDEX));
+    let digest_share = try!(field.interpolate(shares, DIGEST_INDEX));
+    if digest_share[..DIGEST_LEN] != digest(&digest_share[DIGEST_LEN..], &secret)[..] {
+        return Err(Error::BadDigest);
+    }
+    Ok(secret)
+}
+
+/// Run the Feistel network which encrypts a secret with a passphrase, in
+/// the given order of rounds; decryption is the rounds in reverse
+fn feistel(secret: &[u8], passphrase: &str, iteration_exponent: u8, identifier: u16,
+           extendable: bool, rounds: &[u8]) -> Vec<u8> {
+    let half = secret.len() / 2;
+    let mut l = secret[..half].to_owned();
+    let mut r = secret[half..].to_owned();
+    // Non-extendable shares also tie the encryption to their identifier
+    let mut salt = vec![];
+    if !extendable {
+        salt.extend(b"shamir".iter().cloned());
+        salt.push((identifier >> 8) as u8);
+        salt.push(identifier as u8);
+    }
+    let iterations = (BASE_ITERATION_COUNT << iteration_exponent) / ROUND_COUNT as u32;
+    for &round in rounds {
+        let mut key = vec![round];
+        key.extend(passphrase.bytes());
+        let mut round_salt = salt.clone();
+        round_salt.extend(r.iter().cloned());
+        let mut f = vec![0; r.len()];
+        pbkdf2(&mut Hmac::new(Sha256::new(), &key), &round_salt, iterations, &mut f);
+        let new_r: Vec<u8> = l.iter().zip(f.iter()).map(|(a, b)| a ^ b).collect();
+        l = r;
+        r = new_r;
+    }
+    r.extend(l.iter().cloned());
+    r
+}
+
+/// Check that a passphrase is printable ASCII, as SLIP-39 requires
+fn check_passphrase(passphrase: &str) -> Result<(), Error> {
+    if passphrase.bytes().all(|b| b >= 32 && b <= 126) {
+        Ok(())
+    } else {
+        Err(Error::BadPassphrase)
+    }
+}
+
+/// Split a secret into `count` SLIP-39 mnemonics, any `threshold` of which
+/// recover it with the (possibly empty) passphrase
+pub fn split<R: Rng>(rng: &mut R, secret: &[u8], passphrase: &str, threshold: usize, count: usize)
+                     -> Result<Vec<String>, Error> {
+    if secret.len() < 16 || secret.len() % 2 != 0 {
+        return Err(Error::BadSecretLength(secret.len()));
+    }
+    // As other implementations insist, several shares of a 1-of-n split
+    // would just be copies of the secret
+    if threshold == 1 && count > 1 {
+        return Err(Error::BadThreshold(threshold, count));
+    }
+    try!(check_passphrase(passphrase));
+
+    let identifier = rng.gen::<u16>() & 0x7fff;
+    let encrypted = feistel(secret, passphrase, ITERATION_EXPONENT, identifier, false, &[0, 1, 2, 3]);
+    let field = Field::new();
+    let shares = try!(split_secret(rng, &field, threshold, count, &encrypted));
+    Ok(shares.into_iter().map(|(index, value)| {
+        Share {
+            identifier: identifier,
+            extendable: false,
+            iteration_exponent: ITERATION_EXPONENT,
+            group_index: 0,
+            group_threshold: 1,
+            group_count: 1,
+            member_index: index,
+            member_threshold: threshold as u8,
+            value: value
+        }.to_mnemonic()
+    }).collect())
+}
+
+/// Combine SLIP-39 mnemonics into the secret they share, decrypting it with
+/// the (possibly empty) passphrase. Exactly the threshold number of groups
+/// must be given, each with exactly its threshold number of shares.
+pub fn combine(mnemonics: &[&str], passphrase: &str) -> Result<Vec<u8>, Error> {
+    try!(check_passphrase(passphrase));
+    let mut shares: Vec<Share> = vec![];
+    for mnemonic in mnemonics {
+        let share = try!(Share::from_mnemonic(mnemonic));
+        // The same share given twice counts once
+        if !shares.contains(&share) {
+            shares.push(share);
+        }
+    }
+    if shares.is_empty() {
+        return Err(Error::NoShares);
+    }
+    let first = shares[0].clone();
+    if shares.iter().any(|share| share.identifier != first.identifier ||
+                                 share.extendable != first.extendable ||
+                                 share.iteration_exponent != first.iteration_exponent ||
+                                 share.group_threshold != first.group_threshold ||
+                                 share.group_count != first.group_count) {
+        return Err(Error::MismatchedShares);
+    }
+
+    let mut group_indices: Vec<u8> = shares.iter().map(|share| share.group_index).collect();
+    group_indices.sort();
+    group_indices.dedup();
+    if group_indices.len() != first.group_threshold as usize {
+        return Err(Error::WrongGroupCount(first.group_threshold as usize, group_indices.len()));
+    }
+
+    let field = Field::new();
+    let mut group_shares = vec![];
+    for group_index in group_indices {
+        let members: Vec<&Share> = shares.iter().filter(|share| share.group_index == group_index).collect();
+        let member_threshold = members[0].member_threshold;
+        if members.iter().any(|share| share.member_threshold != member_threshold) {
+            return Err(Error::MismatchedMemberThresholds);
+        }
+        if members.len() != member_threshold as usize {
+            return Err(Error::WrongShareCount(group_index, member_threshold as usize, members.len()));
+        }
+        let points: Vec<(u8, Vec<u8>)> = members.iter().map(|share| (share.member_index, share.value.clone())).collect();
+        group_shares.push((group_index, try!(recover_secret(&field, member_threshold as usize, &points))));
+    }
+    let encrypted = try!(recover_secret(&field, first.group_threshold as usize, &group_shares));
+    Ok(feistel(&encrypted, passphrase, first.iteration_exponent, first.identifier, first.extendable, &[3, 2, 1, 0]))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, StdRng};
+    use serialize::hex::{FromHex, ToHex};
+    use serialize::json::Json;
+
+    use super::*;
+    use super::Share;
+
+    #[test]
+    fn wordlist() {
+        let words: Vec<&str> = super::WORDLIST.lines().collect();
+        assert_eq!(words.len(), 1024);
+        let mut sorted = words.clone();
+        sorted.sort();
+        assert_eq!(sorted, words);
+    }
+
+    /// The test vectors published with SLIP-39, as [description, mnemonics,
+    /// secret], where the secret is empty if the mnemonics are invalid. The
+    /// passphrase of every vector is "TREZOR".
+    #[test]
+    fn published_vectors() {
+        let vectors = Json::from_str(include_str!("testdata/slip39-vectors.json")).unwrap();
+        let vectors = vectors.as_array().unwrap();
+        assert_eq!(vectors.len(), 40);
+        for vector in vectors {
+            let vector = vector.as_array().unwrap();
+            let description = vector[0].as_string().unwrap();
+            let mnemonics: Vec<&str> = vector[1].as_array().unwrap().iter().map(|m| m.as_string().unwrap()).collect();
+            let secret = vector[2].as_string().unwrap();
+            match combine(&mnemonics, "TREZOR") {
+                Ok(result) => assert_eq!(result.to_hex(), secret, "{}", description),
+                Err(e) => assert!(secret.is_empty(), "{}: {:?}", description, e)
+            }
+        }
+    }
+
+    #[test]
+    fn split_and_combine() {
+        let seed: &[_] = &[1, 2, 3, 4];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let secret = "1ab6c4ee9ab5b8e74c7e09f1d74025b2d72f8a81acdd9ec1c6bea5d1fd0ad1d4".from_hex().unwrap();
+
+        let shares = split(&mut rng, &secret, "", 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+        for share in &shares {
+            assert_eq!(share.split_whitespace().count(), 33);
+            assert_eq!(Share::from_mnemonic(share).unwrap().to_mnemonic(), *share);
+        }
+        // Any three shares, in any order, give the secret back
+        for &(a, b, c) in &[(0, 1, 2), (4, 2, 0), (1, 3, 4), (3, 0, 2)] {
+            let chosen = [&shares[a][..], &shares[b][..], &shares[c][..]];
+            assert_eq!(combine(&chosen, "").unwrap(), secret);
+            // The passphrase is part of the encryption, so a wrong one gives
+            // a different secret rather than an error
+            assert!(combine(&chosen, "TREZOR").unwrap() != secret);
+        }
+        assert_eq!(combine(&[&shares[0][..], &shares[1][..]], ""), Err(Error::WrongShareCount(0, 3, 2)));
+        assert_eq!(combine(&[&shares[0][..], &shares[1][..], &shares[1][..]], ""), Err(Error::WrongShareCount(0, 3, 2)));
+
+        let shares = split(&mut rng, &secret, "TREZOR", 1, 1).unwrap();
+        assert_eq!(combine(&[&shares[0][..]], "TREZOR").unwrap(), secret);
+
+        assert_eq!(split(&mut rng, &secret, "", 1, 2), Err(Error::BadThreshold(1, 2)));
+        assert_eq!(split(&mut rng, &secret, "", 4, 3), Err(Error::BadThreshold(4, 3)));
+        assert_eq!(split(&mut rng, &secret, "", 2, 17), Err(Error::BadThreshold(2, 17)));
+        assert_eq!(split(&mut rng, &secret[..15], "", 2, 3), Err(Error::BadSecretLength(15)));
+        assert_eq!(split(&mut rng, &secret, "caf\u{e9}", 2, 3), Err(Error::BadPassphrase));
+    }
+
+    #[test]
+    fn bad_mnemonics() {
+        let valid = "duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard";
+        assert!(Share::from_mnemonic(valid).is_ok());
+        assert_eq!(Share::from_mnemonic(&valid.to_uppercase()).unwrap(), Share::from_mnemonic(valid).unwrap());
+        assert_eq!(Share::from_mnemonic(&valid.replace("keyboard", "keyboards")), Err(Error::UnknownWord("keyboards".to_owned())));
+        assert_eq!(Share::from_mnemonic(&valid.replace("keyboard", "academic")), Err(Error::BadChecksum));
+        assert_eq!(Share::from_mnemonic("duckling enlarge academic"), Err(Error::TooShort(3)));
+        assert_eq!(combine(&[], ""), Err(Error::NoShares));
+    }
+}
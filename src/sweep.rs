@@ -0,0 +1,698 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Sweeping
+//! Building and signing a transaction which moves every coin paid to a
+//! tweaked key, or to a modified redeem script which that key alone can
+//! satisfy, to one destination, optionally with change. Only P2PK scripts
+//! and 1-of-n multisigs can be satisfied with one key; anything else needs
+//! a real wallet.
+//!
+
+use bitcoin::blockdata::script::{Builder, Script};
+use bitcoin::util::address::{Address, Privkey};
+use bitcoin::util::hash::Sha256dHash;
+use secp256k1::{self, Message, Secp256k1};
+use secp256k1::key::PublicKey;
+use serialize::hex::FromHex;
+
+use context;
+use psbt::{self, write_compact, write_slice, Reader};
+use segwit;
+use template;
+
+/// Sighash type signing every input and output
+const SIGHASH_ALL: u32 = 1;
+/// Sequence number of inputs which do not signal replaceability
+const SEQUENCE_FINAL: u32 = 0xffffffff;
+/// Sequence number of inputs which signal BIP125 replaceability
+const SEQUENCE_RBF: u32 = 0xfffffffd;
+/// Largest size of a DER signature with its sighash byte, as fee rates
+/// are estimated with
+const MAX_SIG_LEN: usize = 73;
+/// Smallest change output which is not dropped into the fee
+pub const DUST_LIMIT: u64 = 546;
+/// Opcode of a 1-of-n multisig's threshold
+const OP_1: u8 = 0x51;
+/// The opcode pushing 16
+const OP_16: u8 = 0x60;
+/// Opcode ending a multisig
+const OP_CHECKMULTISIG: u8 = 0xae;
+
+/// How the coins being swept were paid
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SpendType {
+    /// To the P2PKH address of the tweaked key
+    P2pkh,
+    /// To the P2SH address of a modified redeem script
+    P2sh,
+    /// To the P2WSH address of a modified redeem script
+    P2wsh,
+    /// To the P2SH-wrapped P2WSH address of a modified redeem script
+    P2shP2wsh
+}
+
+/// Sweeping error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// A redeem script is required for this spend type, or not allowed
+    WrongScript,
+    /// The script cannot be satisfied by a single key
+    UnsignableScript,
+    /// The key is not in the script
+    KeyNotInScript,
+    /// The fee and amount together are more than the total being swept
+    /// (total)
+    InsufficientFunds(u64),
+    /// An amount was given to send, but no change address
+    MissingChange,
+    /// There was nothing to sweep
+    NoUtxos,
+    /// A transaction could not be parsed
+    BadTransaction,
+    /// Secp256k1 error
+    Secp(secp256k1::Error)
+}
+
+/// How to pay the fee
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Fee {
+    /// A fixed number of satoshis
+    Absolute(u64),
+    /// A number of satoshis per virtual byte
+    Rate(u64)
+}
+
+/// Where and how to send the coins being swept
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// The scriptPubKey to send to
+    pub destination: Script,
+    /// How much to send, or everything after the fee if `None`
+    pub amount: Option<u64>,
+    /// The scriptPubKey to send the rest to, if an amount is given
+    pub change: Option<Script>,
+    /// The fee to pay
+    pub fee: Fee,
+    /// Whether to signal BIP125 replaceability
+    pub rbf: bool
+}
+
+/// A signed sweep
+#[derive(Clone, Debug)]
+pub struct Signed {
+    /// The serialized transaction
+    pub tx: Vec<u8>,
+    /// Its virtual size, assuming the largest signatures
+    pub vsize: u64,
+    /// The fee it pays
+    pub fee: u64,
+    /// The amount sent to change, if any
+    pub change: Option<u64>
+}
+
+/// An output being swept
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Utxo {
+    /// Txid of the transaction holding it, in the order it is displayed
+    pub txid: [u8; 32],
+    /// Index of the output
+    pub vout: u32,
+    /// Its value in satoshis
+    pub amount: u64
+}
+
+impl Utxo {
+    /// Parse a line of the form `txid:vout amount`, with the amount in
+    /// satoshis
+    pub fn parse(line: &str) -> Option<Utxo> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 2 {
+            return None;
+        }
+        let mut outpoint = fields[0].split(':');
+        let (txid, vout) = match (outpoint.next(), outpoint.next(), outpoint.next()) {
+            (Some(txid), Some(vout), None) => (txid, vout),
+            _ => { return None; }
+        };
+        match (txid.from_hex(), vout.parse(), fields[1].parse()) {
+            (Ok(ref data), Ok(vout), Ok(amount)) if data.len() == 32 => {
+                let mut txid = [0; 32];
+                txid.clone_from_slice(data);
+                Some(Utxo { txid: txid, vout: vout, amount: amount })
+            }
+            _ => None
+        }
+    }
+
+    /// The serialized outpoint, whose txid is reversed from its display
+    pub fn outpoint(&self) -> Vec<u8> {
+        let mut ret: Vec<u8> = self.txid.iter().rev().cloned().collect();
+        ret.extend((0..4).map(|i| (self.vout >> (8 * i)) as u8));
+        ret
+    }
+}
+
+/// Little-endian bytes of a number
+fn le(n: u64, len: usize) -> Vec<u8> {
+    (0..len).map(|i| (n >> (8 * i)) as u8).collect()
+}
+
+/// An input of a transaction being signed
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Input {
+    /// The serialized outpoint being spent
+    pub outpoint: Vec<u8>,
+    /// The sequence number
+    pub sequence: u32
+}
+
+/// A transaction without its scriptSigs and witnesses, as signatures commit
+/// to it
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UnsignedTx {
+    /// Version
+    pub version: u32,
+    /// Inputs
+    pub inputs: Vec<Input>,
+    /// Value and scriptPubKey of each output
+    pub outputs: Vec<(u64, Script)>,
+    /// Lock time
+    pub lock_time: u32
+}
+
+impl UnsignedTx {
+    /// Parse a transaction in the legacy or segwit serialization, dropping
+    /// any scriptSigs and witnesses. A transaction with no inputs (as in a
+    /// funding PSBT) looks like the start of a segwit one, so is tried both
+    /// ways.
+    pub fn parse(data: &[u8]) -> Result<UnsignedTx, Error> {
+        match UnsignedTx::parse_inner(data, true).or_else(|_| UnsignedTx::parse_inner(data, false)) {
+            Ok((tx, _, len)) if len == data.len() => Ok(tx),
+            _ => Err(Error::BadTransaction)
+        }
+    }
+
+    /// Parse a transaction from the start of some data, as in a block,
+    /// giving it with its txid and its length
+    pub fn parse_prefix(data: &[u8]) -> Result<(UnsignedTx, Sha256dHash, usize), Error> {
+        UnsignedTx::parse_inner(data, true).map_err(|_| Error::BadTransaction)
+    }
+
+    /// Parse a transaction from the start of some data
+    fn parse_inner(data: &[u8], allow_segwit: bool) -> Result<(UnsignedTx, Sha256dHash, usize), psbt::Error> {
+        let mut reader = Reader::new(data);
+        let version = try!(reader.read_le(4)) as u32;
+        let mut n_inputs = try!(reader.read_compact());
+        let segwit = allow_segwit && n_inputs == 0;
+        if segwit {
+            if try!(reader.read(1))[0] != 1 {
+                return Err(psbt::Error::BadTransaction);
+            }
+            n_inputs = try!(reader.read_compact());
+        }
+        let mut inputs = vec![];
+        for _ in 0..n_inputs {
+            let outpoint = try!(reader.read(36)).to_owned();
+            try!(reader.read_slice());
+            inputs.push(Input { outpoint: outpoint, sequence: try!(reader.read_le(4)) as u32 });
+        }
+        let mut outputs = vec![];
+        for _ in 0..try!(reader.read_compact()) {
+            let value = try!(reader.read_le(8));
+            outputs.push((value, Script::from(try!(reader.read_slice()).to_owned())));
+        }
+        let outputs_end = reader.position();
+        if segwit {
+            for _ in 0..n_inputs {
+                for _ in 0..try!(reader.read_compact()) {
+                    try!(reader.read_slice());
+                }
+            }
+        }
+        let lock_time = try!(reader.read_le(4)) as u32;
+        let len = reader.position();
+
+        // The txid is of the serialization without witnesses
+        let txid = if segwit {
+            let mut stripped = data[..4].to_owned();
+            stripped.extend(data[6..outputs_end].iter().cloned());
+            stripped.extend(data[len - 4..len].iter().cloned());
+            Sha256dHash::from_data(&stripped)
+        } else {
+            Sha256dHash::from_data(&data[..len])
+        };
+        let tx = UnsignedTx { version: version, inputs: inputs, outputs: outputs, lock_time: lock_time };
+        Ok((tx, txid, len))
+    }
+
+    /// Serialize the transaction with the given scriptSigs and, if any is
+    /// nonempty, witnesses
+    pub fn serialize(&self, script_sigs: &[Vec<u8>], witnesses: &[Vec<Vec<u8>>]) -> Vec<u8> {
+        let segwit = witnesses.iter().any(|w| !w.is_empty());
+        let mut ret = le(self.version as u64, 4);
+        if segwit {
+            ret.extend([0, 1].iter().cloned());
+        }
+        write_compact(&mut ret, self.inputs.len() as u64);
+        for (input, script_sig) in self.inputs.iter().zip(script_sigs.iter()) {
+            ret.extend(input.outpoint.iter().cloned());
+            write_slice(&mut ret, script_sig);
+            ret.extend(le(input.sequence as u64, 4));
+        }
+        ret.extend(self.serialize_outputs(true));
+        if segwit {
+            for witness in witnesses {
+                write_compact(&mut ret, witness.len() as u64);
+                for item in witness {
+                    write_slice(&mut ret, item);
+                }
+            }
+        }
+        ret.extend(le(self.lock_time as u64, 4));
+        ret
+    }
+
+    /// Serialize the outputs, with or without their count
+    fn serialize_outputs(&self, with_count: bool) -> Vec<u8> {
+        let mut ret = vec![];
+        if with_count {
+            write_compact(&mut ret, self.outputs.len() as u64);
+        }
+        for &(amount, ref script_pubkey) in &self.outputs {
+            ret.extend(le(amount, 8));
+            write_slice(&mut ret, &script_pubkey[..]);
+        }
+        ret
+    }
+
+    /// The legacy SIGHASH_ALL signature hash of an input
+    pub fn legacy_sighash(&self, input: usize, script_code: &Script) -> Sha256dHash {
+        let script_sigs: Vec<Vec<u8>> = (0..self.inputs.len()).map(|n| {
+            if n == input { script_code[..].to_owned() } else { vec![] }
+        }).collect();
+        let mut data = self.serialize(&script_sigs, &[]);
+        data.extend(le(SIGHASH_ALL as u64, 4));
+        Sha256dHash::from_data(&data)
+    }
+
+    /// The BIP143 SIGHASH_ALL signature hash of a segwit input spending
+    /// `amount` satoshis
+    pub fn segwit_sighash(&self, input: usize, script_code: &Script, amount: u64) -> Sha256dHash {
+        let prevouts: Vec<u8> = self.inputs.iter().flat_map(|input| input.outpoint.clone().into_iter()).collect();
+        let sequences: Vec<u8> = self.inputs.iter().flat_map(|input| le(input.sequence as u64, 4).into_iter()).collect();
+
+        let mut data = le(self.version as u64, 4);
+        data.extend(Sha256dHash::from_data(&prevouts)[..].iter().cloned());
+        data.extend(Sha256dHash::from_data(&sequences)[..].iter().cloned());
+        data.extend(self.inputs[input].outpoint.iter().cloned());
+        write_slice(&mut data, &script_code[..]);
+        data.extend(le(amount, 8));
+        data.extend(le(self.inputs[input].sequence as u64, 4));
+        data.extend(Sha256dHash::from_data(&self.serialize_outputs(false))[..].iter().cloned());
+        data.extend(le(self.lock_time as u64, 4));
+        data.extend(le(SIGHASH_ALL as u64, 4));
+        Sha256dHash::from_data(&data)
+    }
+}
+
+/// Build an unsigned transaction paying `amount` to a script from some
+/// funding outputs, with any remainder over `fee` sent to change. Without
+/// funding outputs it has no inputs, to be completed by the payer's wallet.
+pub fn funding(utxos: &[Utxo], destination: Script, amount: u64, change: Option<Script>, fee: u64) -> Result<UnsignedTx, Error> {
+    let mut outputs = vec![(amount, destination)];
+    if !utxos.is_empty() {
+        let total = utxos.iter().fold(0, |acc, utxo| acc + utxo.amount);
+        if amount + fee > total {
+            return Err(Error::InsufficientFunds(total));
+        }
+        if total - amount - fee >= DUST_LIMIT {
+            match change {
+                Some(change) => outputs.push((total - amount - fee, change)),
+                None => { return Err(Error::MissingChange); }
+            }
+        }
+    }
+    Ok(UnsignedTx {
+        version: 2,
+        inputs: utxos.iter().map(|utxo| Input { outpoint: utxo.outpoint(), sequence: SEQUENCE_FINAL }).collect(),
+        outputs: outputs,
+        lock_time: 0
+    })
+}
+
+/// A tweaked key and what it signs for
+pub struct Signer<'a> {
+    /// The key
+    key: &'a Privkey,
+    /// Its serialized public key
+    serialized_key: Vec<u8>,
+    /// How the coins being spent were paid
+    spend_type: SpendType,
+    /// The script being signed for
+    pub script_code: Script,
+    /// If the key alone satisfies the script, whether CHECKMULTISIG needs
+    /// its extra stack item
+    pub placeholder: Option<bool>
+}
+
+impl<'a> Signer<'a> {
+    /// Find what a key signs for when spending coins paid to its P2PKH
+    /// address, or to a script containing it
+    pub fn new(secp: &Secp256k1, key: &'a Privkey, script: Option<&Script>, spend_type: SpendType) -> Result<Signer<'a>, Error> {
+        let public_key = try!(PublicKey::from_secret_key(secp, &key.key).map_err(Error::Secp));
+        let (script_code, placeholder) = match (spend_type, script) {
+            (SpendType::P2pkh, None) => (Address::from_key(key.network, &public_key, key.compressed).script_pubkey(), Some(false)),
+            (SpendType::P2pkh, Some(_)) | (_, None) => { return Err(Error::WrongScript); }
+            (_, Some(script)) => {
+                let keys = template::locate_keys(secp, script);
+                if !keys.iter().any(|location| location.key == public_key) {
+                    return Err(Error::KeyNotInScript);
+                }
+                let bytes = &script[..];
+                if template::is_p2pk(secp, script) {
+                    (script.clone(), Some(false))
+                } else if bytes.first() == Some(&OP_1) && bytes.last() == Some(&OP_CHECKMULTISIG) {
+                    (script.clone(), Some(true))
+                } else {
+                    (script.clone(), None)
+                }
+            }
+        };
+        Ok(Signer {
+            key: key,
+            serialized_key: public_key.serialize_vec(secp, key.compressed).to_vec(),
+            spend_type: spend_type,
+            script_code: script_code,
+            placeholder: placeholder
+        })
+    }
+
+    /// Sign an input spending `amount` satoshis, giving the signature with
+    /// its sighash type. It is signed in this thread's signing context,
+    /// freshly re-randomized.
+    pub fn sign(&self, tx: &UnsignedTx, input: usize, amount: u64) -> Result<Vec<u8>, Error> {
+        let sighash = match self.spend_type {
+            SpendType::P2wsh | SpendType::P2shP2wsh => tx.segwit_sighash(input, &self.script_code, amount),
+            SpendType::P2pkh | SpendType::P2sh => tx.legacy_sighash(input, &self.script_code)
+        };
+        let mut sig = try!(context::with_signing(|secp| {
+            secp.sign(&Message::from_slice(&sighash[..]).unwrap(), &self.key.key).map(|sig| sig.serialize_der(secp))
+        }).map_err(Error::Secp));
+        sig.push(SIGHASH_ALL as u8);
+        Ok(sig)
+    }
+
+    /// The scriptSig and witness of an input signed by the key, if it alone
+    /// satisfies the script
+    pub fn satisfy(&self, sig: Vec<u8>) -> Option<(Vec<u8>, Vec<Vec<u8>>)> {
+        let placeholder = match self.placeholder {
+            Some(placeholder) => placeholder,
+            None => { return None; }
+        };
+        let mut stack = vec![];
+        if placeholder {
+            stack.push(vec![]);
+        }
+        stack.push(sig);
+        if self.spend_type == SpendType::P2pkh {
+            stack.push(self.serialized_key.clone());
+            Some((push_all(&stack), vec![]))
+        } else {
+            Some(satisfy(&self.script_code, self.spend_type, stack))
+        }
+    }
+}
+
+/// Build and sign a transaction sweeping some outputs, all paid to the same
+/// key or script
+pub fn sweep(secp: &Secp256k1, key: &Privkey, script: Option<&Script>, spend_type: SpendType,
+             utxos: &[Utxo], options: &Options) -> Result<Signed, Error> {
+    if utxos.is_empty() {
+        return Err(Error::NoUtxos);
+    }
+    if options.amount.is_some() && options.change.is_none() {
+        return Err(Error::MissingChange);
+    }
+    let total = utxos.iter().fold(0, |acc, utxo| acc + utxo.amount);
+    let signer = try!(Signer::new(secp, key, script, spend_type));
+    if signer.placeholder.is_none() {
+        return Err(Error::UnsignableScript);
+    }
+    let segwit = spend_type == SpendType::P2wsh || spend_type == SpendType::P2shP2wsh;
+
+    // Sign for some fee, giving the transaction and its largest weight
+    let sign = |fee: u64| -> Result<(Vec<u8>, u64, Option<u64>), Error> {
+        let mut outputs = vec![];
+        let mut change = None;
+        match options.amount {
+            Some(amount) => {
+                if amount + fee > total {
+                    return Err(Error::InsufficientFunds(total));
+                }
+                outputs.push((amount, options.destination.clone()));
+                if total - amount - fee >= DUST_LIMIT {
+                    change = Some(total - amount - fee);
+                    outputs.push((total - amount - fee, options.change.clone().unwrap()));
+                }
+            }
+            None => {
+                if fee >= total {
+                    return Err(Error::InsufficientFunds(total));
+                }
+                outputs.push((total - fee, options.destination.clone()));
+            }
+        }
+        let sequence = if options.rbf { SEQUENCE_RBF } else { SEQUENCE_FINAL };
+        let tx = UnsignedTx {
+            version: 2,
+            inputs: utxos.iter().map(|utxo| Input { outpoint: utxo.outpoint(), sequence: sequence }).collect(),
+            outputs: outputs,
+            lock_time: 0
+        };
+
+        let mut script_sigs = vec![];
+        let mut witnesses = vec![];
+        // Signatures shorter than the largest make the weight less
+        let mut shortfall = 0;
+        for n in 0..utxos.len() {
+            let sig = try!(signer.sign(&tx, n, utxos[n].amount));
+            shortfall += (MAX_SIG_LEN - sig.len()) as u64 * if segwit { 1 } else { 4 };
+            let (script_sig, witness) = signer.satisfy(sig).unwrap();
+            script_sigs.push(script_sig);
+            witnesses.push(witness);
+        }
+        // Weight counts the serialization without witnesses three more times
+        let weight = 3 * tx.serialize(&script_sigs, &[]).len() as u64 +
+                     tx.serialize(&script_sigs, &witnesses).len() as u64 + shortfall;
+        Ok((tx.serialize(&script_sigs, &witnesses), weight, change))
+    };
+
+    let (tx, weight, change, fee) = match options.fee {
+        Fee::Absolute(fee) => {
+            let (tx, weight, change) = try!(sign(fee));
+            (tx, weight, change, fee)
+        }
+        Fee::Rate(rate) => {
+            // Size does not depend on the fee, unless the change becomes dust
+            let (_, weight, _) = try!(sign(0));
+            let fee = rate * ((weight + 3) / 4);
+            let (tx, weight, change) = try!(sign(fee));
+            (tx, weight, change, fee)
+        }
+    };
+    // Dust change goes to the fee
+    let fee = match (options.amount, change) {
+        (Some(amount), None) => total - amount,
+        _ => fee
+    };
+    Ok(Signed { tx: tx, vsize: (weight + 3) / 4, fee: fee, change: change })
+}
+
+/// The scriptSig and witness spending a script with a stack of signatures
+/// (including any extra stack item CHECKMULTISIG needs)
+fn satisfy(script: &Script, spend_type: SpendType, mut stack: Vec<Vec<u8>>) -> (Vec<u8>, Vec<Vec<u8>>) {
+    stack.push(script[..].to_owned());
+    match spend_type {
+        SpendType::P2pkh | SpendType::P2sh => (push_all(&stack), vec![]),
+        SpendType::P2wsh => (vec![], stack),
+        SpendType::P2shP2wsh => (push_all(&[segwit::p2wsh_script_pubkey(script)[..].to_owned()]), stack)
+    }
+}
+
+/// Assemble a transaction spending outputs paid to a script from the
+/// signatures for each input, given in the order the script checks them
+pub fn assemble(tx: &UnsignedTx, script: &Script, spend_type: SpendType, signatures: &[Vec<Vec<u8>>]) -> Result<Vec<u8>, Error> {
+    if spend_type == SpendType::P2pkh {
+        return Err(Error::WrongScript);
+    }
+    if signatures.len() != tx.inputs.len() {
+        return Err(Error::BadTransaction);
+    }
+    let bytes = &script[..];
+    let multisig = bytes.first().map_or(false, |&op| op >= OP_1 && op <= OP_16) && bytes.last() == Some(&OP_CHECKMULTISIG);
+
+    let mut script_sigs = vec![];
+    let mut witnesses = vec![];
+    for sigs in signatures {
+        let mut stack = vec![];
+        if multisig {
+            stack.push(vec![]);
+        }
+        stack.extend(sigs.iter().cloned());
+        let (script_sig, witness) = satisfy(script, spend_type, stack);
+        script_sigs.push(script_sig);
+        witnesses.push(witness);
+    }
+    Ok(tx.serialize(&script_sigs, &witnesses))
+}
+
+/// A scriptSig pushing some items
+fn push_all(items: &[Vec<u8>]) -> Vec<u8> {
+    let mut builder = Builder::new();
+    for item in items {
+        builder = builder.push_slice(item);
+    }
+    builder.into_script()[..].to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::script::Script;
+    use bitcoin::network::constants::Network;
+    use bitcoin::util::address::Privkey;
+    use secp256k1::{Message, Secp256k1, Signature};
+    use secp256k1::key::{PublicKey, SecretKey};
+    use serialize::hex::{FromHex, ToHex};
+
+    use super::*;
+
+    /// The generator's public key, whose secret key is 1
+    const KEY: &'static str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    /// A P2PKH scriptPubKey to sweep to
+    const DESTINATION: &'static str = "76a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688ac";
+
+    fn script(hex: &str) -> Script {
+        Script::from(hex.from_hex().unwrap())
+    }
+
+    fn sweep_with(spend_type: SpendType, n_utxos: usize, amount: Option<u64>, fee: Fee) -> Result<Signed, Error> {
+        let secp = Secp256k1::new();
+        let mut secret = [0; 32];
+        secret[31] = 1;
+        let key = Privkey { compressed: true, network: Network::Bitcoin, key: SecretKey::from_slice(&secp, &secret).unwrap() };
+        let multisig = script(&format!("5121{}51ae", KEY));
+        let redeem_script = if spend_type == SpendType::P2pkh { None } else { Some(&multisig) };
+        let utxos: Vec<Utxo> = (0..n_utxos).map(|n| Utxo { txid: [n as u8 + 1; 32], vout: n as u32, amount: 100000 }).collect();
+        let options = Options {
+            destination: script(DESTINATION),
+            amount: amount,
+            change: Some(script(DESTINATION)),
+            fee: fee,
+            rbf: false
+        };
+        sweep(&secp, &key, redeem_script, spend_type, &utxos, &options)
+    }
+
+    #[test]
+    fn segwit_sighash_vector() {
+        // BIP143's P2SH-P2WSH example, a 6-of-6 multisig, signed with SIGHASH_ALL
+        let tx = UnsignedTx::parse(&"010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e0100000000ffffffff\
+                                     0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688acc0832f05000000001976\
+                                     a9147480a33f950689af511e6e84c138dbbd3c3ee41588ac00000000".from_hex().unwrap()).unwrap();
+        let witness_script = script("56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e07e8c3ba32103b28f0c28bfab54554ae8c658\
+                                     ac5c3e0ce6e79ad336331f78c428dd43eea8449b21034b8113d703413d57761b8b9781957b8c0ac1dfe69f492580ca41\
+                                     95f50376ba4a21033400f6afecb833092a9a21cfdf1ed1376e58c5d1f47de74683123987e967a8f42103a6d48b1131e9\
+                                     4ba04d9737d61acdaa1322008af9602b3b14862c07a1789aac162102d8b661b0b3302ee2f162b09e07a55ad5dfbe673a\
+                                     9f01d9f0c19617681024306b56ae");
+        assert_eq!(tx.segwit_sighash(0, &witness_script, 987654321)[..].to_hex(),
+                   "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c");
+    }
+
+    #[test]
+    fn legacy_sighash_vector() {
+        // The P2PK input of BIP143's native P2WPKH example, whose published
+        // signature (of deterministic nonce) commits to the legacy sighash
+        let secp = Secp256k1::new();
+        let tx = UnsignedTx::parse(&"0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffff\
+                                     ef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb2\
+                                     06000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde\
+                                     42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000".from_hex().unwrap()).unwrap();
+        let script_pubkey = script("2103c9f4836b9a4f77fc0d81f7bcb01b7f1b35916864b9476c241ce9fc198bd25432ac");
+        let sig = "30450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f92\
+                   81a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed".from_hex().unwrap();
+        let sighash = tx.legacy_sighash(0, &script_pubkey);
+        let key = PublicKey::from_slice(&secp, &script_pubkey[1..34]).unwrap();
+        secp.verify(&Message::from_slice(&sighash[..]).unwrap(), &Signature::from_der(&secp, &sig).unwrap(), &key).unwrap();
+
+        let secret = SecretKey::from_slice(&secp, &"bbc27228ddcb9209d7fd6f36b02f7dfa6252af40bb2f1cbc7a557da8027ff866".from_hex().unwrap()).unwrap();
+        let ours = secp.sign(&Message::from_slice(&sighash[..]).unwrap(), &secret).unwrap();
+        assert_eq!(ours.serialize_der(&secp), sig);
+    }
+
+    #[test]
+    fn vsize_and_fee() {
+        // One P2PKH input with a 73-byte signature and one P2PKH output
+        let signed = sweep_with(SpendType::P2pkh, 1, None, Fee::Rate(10)).unwrap();
+        assert_eq!(signed.vsize, 193);
+        assert_eq!(signed.fee, 1930);
+        // The largest signature is assumed, so the real size is at most this
+        assert!(signed.tx.len() as u64 <= signed.vsize);
+        let tx = UnsignedTx::parse(&signed.tx).unwrap();
+        assert_eq!(tx.outputs, vec![(100000 - 1930, script(DESTINATION))]);
+
+        // Two P2WSH inputs weigh 734, which rounds up to 184 vbytes
+        let signed = sweep_with(SpendType::P2wsh, 2, None, Fee::Rate(10)).unwrap();
+        assert_eq!(signed.vsize, 184);
+        assert_eq!(signed.fee, 1840);
+        // An absolute fee is paid as given
+        let signed = sweep_with(SpendType::P2shP2wsh, 1, None, Fee::Absolute(1234)).unwrap();
+        assert_eq!(signed.vsize, 149);
+        assert_eq!(signed.fee, 1234);
+
+        match sweep_with(SpendType::P2pkh, 1, None, Fee::Absolute(100000)) {
+            Err(Error::InsufficientFunds(100000)) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+        match sweep_with(SpendType::P2pkh, 0, None, Fee::Rate(1)) {
+            Err(Error::NoUtxos) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn dust_change() {
+        // Change of the dust limit or more is kept
+        let signed = sweep_with(SpendType::P2pkh, 1, Some(90000), Fee::Absolute(10000 - DUST_LIMIT)).unwrap();
+        assert_eq!(signed.change, Some(DUST_LIMIT));
+        assert_eq!(signed.fee, 10000 - DUST_LIMIT);
+        assert_eq!(UnsignedTx::parse(&signed.tx).unwrap().outputs.len(), 2);
+        // Less is dropped into the fee
+        let signed = sweep_with(SpendType::P2pkh, 1, Some(90000), Fee::Absolute(10000 - DUST_LIMIT + 1)).unwrap();
+        assert_eq!(signed.change, None);
+        assert_eq!(signed.fee, 10000);
+        assert_eq!(UnsignedTx::parse(&signed.tx).unwrap().outputs, vec![(90000, script(DESTINATION))]);
+
+        // The same holds for funding transactions
+        let utxos = [Utxo { txid: [1; 32], vout: 0, amount: 100000 }];
+        let tx = funding(&utxos, script(DESTINATION), 90000, Some(script(DESTINATION)), 10000 - DUST_LIMIT).unwrap();
+        assert_eq!(tx.outputs.len(), 2);
+        let tx = funding(&utxos, script(DESTINATION), 90000, None, 10000 - DUST_LIMIT + 1).unwrap();
+        assert_eq!(tx.outputs.len(), 1);
+        match funding(&utxos, script(DESTINATION), 90000, None, 1000) {
+            Err(Error::MissingChange) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,327 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Templates
+//! Locating the public keys in a redemption script, building multisig
+//! redemption scripts from lists of keys, and handling Liquid watchman
+//! (fedpeg) scripts
+//!
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::{Builder, Instruction, Script};
+use bitcoin::util::contracthash::{self, tweak_keys, untemplate};
+use secp256k1::Secp256k1;
+use secp256k1::key::PublicKey;
+
+use asm;
+
+/// A public key found in a script
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct KeyLocation {
+    /// Byte offset of the key data within the script (after the push opcode)
+    pub offset: usize,
+    /// The key data exactly as it is pushed in the script
+    pub data: Vec<u8>,
+    /// The key itself
+    pub key: PublicKey
+}
+
+/// Finds every push in a script which is a public key, in the order that
+/// `untemplate` extracts them
+pub fn locate_keys(secp: &Secp256k1, script: &Script) -> Vec<KeyLocation> {
+    let start = script[..].as_ptr() as usize;
+    let mut ret = vec![];
+    for instruction in script.into_iter() {
+        match instruction {
+            Instruction::PushBytes(data) => {
+                if let Ok(key) = PublicKey::from_slice(secp, data) {
+                    ret.push(KeyLocation {
+                        // Pushes are subslices of the script, so this is the offset
+                        offset: data.as_ptr() as usize - start,
+                        data: data.to_owned(),
+                        key: key
+                    });
+                }
+            }
+            Instruction::Op(_) => {}
+            Instruction::Error(_) => break
+        }
+    }
+    ret
+}
+
+/// Finds the keys of a script which `untemplate` extracted `count` keys
+/// from, checking that there is exactly one location for each
+pub fn locate_extracted_keys(secp: &Secp256k1, script: &Script, count: usize)
+                             -> Result<Vec<KeyLocation>, contracthash::Error> {
+    let ret = locate_keys(secp, script);
+    if ret.len() < count {
+        Err(contracthash::Error::TooFewKeys(ret.len()))
+    } else if ret.len() > count {
+        Err(contracthash::Error::TooManyKeys(ret.len()))
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Whether a script is a bare pay-to-pubkey output, `<pubkey> OP_CHECKSIG`
+pub fn is_p2pk(secp: &Secp256k1, script: &Script) -> bool {
+    let mut iter = script.into_iter();
+    let key_ok = match iter.next() {
+        Some(Instruction::PushBytes(data)) => PublicKey::from_slice(secp, data).is_ok(),
+        _ => false
+    };
+    let checksig_ok = match iter.next() {
+        Some(Instruction::Op(opcodes::All::OP_CHECKSIG)) => true,
+        _ => false
+    };
+    key_ok && checksig_ok && iter.next().is_none()
+}
+
+/// Sort keys lexicographically by their compressed serialization, per BIP67
+pub fn sort_keys(secp: &Secp256k1, keys: &mut Vec<PublicKey>) {
+    keys.sort_by(|a, b| a.serialize_vec(secp, true)[..].cmp(&b.serialize_vec(secp, true)[..]));
+}
+
+/// Build a `threshold`-of-`keys.len()` CHECKMULTISIG script, keeping the
+/// keys in the order given
+pub fn multisig(secp: &Secp256k1, threshold: usize, keys: &[PublicKey]) -> Script {
+    let mut ret = asm::push_int(Builder::new(), threshold as i64);
+    for key in keys {
+        ret = ret.push_slice(&key.serialize_vec(secp, true)[..]);
+    }
+    asm::push_int(ret, keys.len() as i64)
+        .push_opcode(opcodes::All::OP_CHECKMULTISIG)
+        .into_script()
+}
+
+/// Build a Liquid watchman (fedpeg) script, in the form `is_liquid_fedpeg`
+/// recognizes, from its normal and emergency multisigs and the relative
+/// timelock of the emergency branch
+pub fn liquid_fedpeg(secp: &Secp256k1, threshold: usize, keys: &[PublicKey],
+                     emergency_threshold: usize, emergency_keys: &[PublicKey], csv: i64) -> Script {
+    // The normal branch is taken when there are its signatures and the dummy
+    let mut ret = asm::push_int(Builder::new().push_opcode(opcodes::All::OP_DEPTH), threshold as i64 + 1)
+                      .push_opcode(opcodes::All::OP_EQUAL)
+                      .push_opcode(opcodes::All::OP_IF);
+    ret = asm::push_int(ret, threshold as i64);
+    for key in keys {
+        ret = ret.push_slice(&key.serialize_vec(secp, true)[..]);
+    }
+    ret = asm::push_int(ret, keys.len() as i64).push_opcode(opcodes::All::OP_ELSE);
+    ret = asm::push_int(ret, csv).push_opcode(opcodes::All::OP_NOP3).push_opcode(opcodes::All::OP_DROP);
+    ret = asm::push_int(ret, emergency_threshold as i64);
+    for key in emergency_keys {
+        ret = ret.push_slice(&key.serialize_vec(secp, true)[..]);
+    }
+    asm::push_int(ret, emergency_keys.len() as i64)
+        .push_opcode(opcodes::All::OP_ENDIF)
+        .push_opcode(opcodes::All::OP_CHECKMULTISIG)
+        .into_script()
+}
+
+/// Whether an instruction is the given non-push opcode
+fn is_op(instruction: Option<&Instruction>, op: opcodes::All) -> bool {
+    match instruction {
+        Some(&Instruction::Op(found)) => found == op,
+        _ => false
+    }
+}
+
+/// Whether an instruction pushes a number, either as a small-number opcode
+/// or as data of at most four bytes
+fn is_number(instruction: Option<&Instruction>) -> bool {
+    match instruction {
+        Some(&Instruction::Op(op)) => match op.classify() {
+            opcodes::Class::PushNum(_) => true,
+            _ => false
+        },
+        Some(&Instruction::PushBytes(data)) => data.len() <= 4,
+        _ => false
+    }
+}
+
+/// Matches `<m> <key>... <n>` starting at `pos`, returning the position after it
+fn match_multisig_keys(secp: &Secp256k1, instructions: &[Instruction], mut pos: usize) -> Option<usize> {
+    if !is_number(instructions.get(pos)) {
+        return None;
+    }
+    pos += 1;
+    let start = pos;
+    while let Some(&Instruction::PushBytes(data)) = instructions.get(pos) {
+        if data.len() != 33 || PublicKey::from_slice(secp, data).is_err() {
+            break;
+        }
+        pos += 1;
+    }
+    if pos == start || !is_number(instructions.get(pos)) {
+        return None;
+    }
+    Some(pos + 1)
+}
+
+/// Whether a script is a Liquid watchman (fedpeg) script, which has a normal
+/// multisig branch and a timelocked emergency branch:
+///
+/// `OP_DEPTH <n> OP_EQUAL OP_IF <m> <key>... <n> OP_ELSE <csv> OP_CHECKSEQUENCEVERIFY
+/// OP_DROP <m> <key>... <n> OP_ENDIF OP_CHECKMULTISIG`
+pub fn is_liquid_fedpeg(secp: &Secp256k1, script: &Script) -> bool {
+    let instructions: Vec<Instruction> = script.into_iter().collect();
+    if !is_op(instructions.get(0), opcodes::All::OP_DEPTH) ||
+       !is_number(instructions.get(1)) ||
+       !is_op(instructions.get(2), opcodes::All::OP_EQUAL) ||
+       !is_op(instructions.get(3), opcodes::All::OP_IF) {
+        return false;
+    }
+    let pos = match match_multisig_keys(secp, &instructions, 4) {
+        Some(pos) => pos,
+        None => return false
+    };
+    if !is_op(instructions.get(pos), opcodes::All::OP_ELSE) ||
+       !is_number(instructions.get(pos + 1)) ||
+       !is_op(instructions.get(pos + 2), opcodes::All::OP_NOP3) ||
+       !is_op(instructions.get(pos + 3), opcodes::All::OP_DROP) {
+        return false;
+    }
+    let pos = match match_multisig_keys(secp, &instructions, pos + 4) {
+        Some(pos) => pos,
+        None => return false
+    };
+    is_op(instructions.get(pos), opcodes::All::OP_ENDIF) &&
+        is_op(instructions.get(pos + 1), opcodes::All::OP_CHECKMULTISIG) &&
+        instructions.len() == pos + 2
+}
+
+/// Tweak a Liquid watchman script the way Elements does: keys in the normal
+/// branch are tweaked, while the emergency keys after `OP_ELSE` are left
+/// alone so that the emergency branch stays usable without the contract.
+/// Returns the modified script, the locations of the keys which were tweaked,
+/// and what each was tweaked to.
+pub fn tweak_liquid_fedpeg(secp: &Secp256k1, script: &Script, contract: &[u8])
+                           -> Result<(Script, Vec<KeyLocation>, Vec<PublicKey>), contracthash::Error> {
+    let start = script[..].as_ptr() as usize;
+    let mut ret = Builder::new();
+    let mut locations = vec![];
+    let mut tweaked_keys = vec![];
+    let mut else_found = false;
+    for instruction in script.into_iter() {
+        ret = match instruction {
+            Instruction::PushBytes(data) => {
+                match PublicKey::from_slice(secp, data) {
+                    Ok(key) if data.len() == 33 && !else_found => {
+                        let tweaked = try!(tweak_keys(secp, &[key], contract)).pop().unwrap();
+                        locations.push(KeyLocation {
+                            offset: data.as_ptr() as usize - start,
+                            data: data.to_owned(),
+                            key: key
+                        });
+                        tweaked_keys.push(tweaked);
+                        ret.push_slice(&tweaked.serialize_vec(secp, true)[..])
+                    }
+                    _ => ret.push_slice(data)
+                }
+            }
+            Instruction::Op(op) => {
+                if op == opcodes::All::OP_ELSE {
+                    else_found = true;
+                }
+                ret.push_opcode(op)
+            }
+            Instruction::Error(e) => { return Err(contracthash::Error::Script(e)); }
+        };
+    }
+    Ok((ret.into_script(), locations, tweaked_keys))
+}
+
+/// Tweak every key of a script for a contract, treating Liquid watchman
+/// scripts as Elements does. Returns the modified script, the locations of
+/// the keys which were tweaked, and what each was tweaked to.
+pub fn tweak_script(secp: &Secp256k1, script: &Script, contract: &[u8])
+                    -> Result<(Script, Vec<KeyLocation>, Vec<PublicKey>), contracthash::Error> {
+    if is_liquid_fedpeg(secp, script) {
+        return tweak_liquid_fedpeg(secp, script, contract);
+    }
+    let (template, keys) = try!(untemplate(script));
+    let tweaked_keys = try!(tweak_keys(secp, &keys, contract));
+    let new_script = try!(template.to_script(&tweaked_keys));
+    let locations = try!(locate_extracted_keys(secp, script, tweaked_keys.len()));
+    Ok((new_script, locations, tweaked_keys))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::util::contracthash;
+    use rand::{Rng, SeedableRng, StdRng};
+    use secp256k1::Secp256k1;
+    use secp256k1::key::{PublicKey, SecretKey};
+
+    use asm;
+    use super::*;
+
+    const KEY_1: &'static str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const KEY_2: &'static str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+    #[test]
+    fn key_locations() {
+        let secp = Secp256k1::new();
+        let script = asm::assemble(&format!("2 {} {} 2 OP_CHECKMULTISIG", KEY_1, KEY_2)).unwrap();
+        let locations = locate_keys(&secp, &script);
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations[0].offset, 2);
+        assert_eq!(locations[1].offset, 36);
+        assert_eq!(&script[2..35], &locations[0].data[..]);
+        assert_eq!(&script[36..69], &locations[1].data[..]);
+
+        assert_eq!(locate_extracted_keys(&secp, &script, 2).unwrap(), locations);
+        match locate_extracted_keys(&secp, &script, 3) {
+            Err(contracthash::Error::TooFewKeys(2)) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+        match locate_extracted_keys(&secp, &script, 1) {
+            Err(contracthash::Error::TooManyKeys(2)) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn tweak_round_trip() {
+        let secp = Secp256k1::new();
+        let seed: &[_] = &[1, 8, 7];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        for _ in 0..64 {
+            let n = rng.gen_range(1, 6);
+            let threshold = rng.gen_range(1, n + 1);
+            let secret_keys: Vec<SecretKey> = (0..n).map(|_| SecretKey::new(&secp, &mut rng)).collect();
+            let keys: Vec<PublicKey> = secret_keys.iter().map(|sk| PublicKey::from_secret_key(&secp, sk).unwrap()).collect();
+            let contract: Vec<u8> = rng.gen_iter().take(40).collect();
+
+            let script = multisig(&secp, threshold, &keys);
+            let (tweaked, locations, tweaked_keys) = tweak_script(&secp, &script, &contract).unwrap();
+            // The keys found are the original ones, and untemplating the
+            // tweaked script gives the tweaked ones in the same places
+            let original: Vec<PublicKey> = locations.iter().map(|location| location.key).collect();
+            let (template, extracted) = untemplate(&tweaked).unwrap();
+            assert_eq!(extracted, tweaked_keys);
+            assert_eq!(template.to_script(&original).unwrap(), script);
+            assert_eq!(tweak_keys(&secp, &original, &contract).unwrap(), tweaked_keys);
+            // Tweaking the secret keys gives the same keys
+            for (sk, pk) in secret_keys.iter().zip(keys.iter()) {
+                let tweaked_sk = contracthash::tweak_secret_key(&secp, sk, &contract).unwrap();
+                let tweaked_pk = PublicKey::from_secret_key(&secp, &tweaked_sk).unwrap();
+                assert!(tweaked_keys.contains(&tweaked_pk));
+                assert!(original.contains(pk));
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Terminals
+//! Whether the standard streams are terminals, which decides if prompts,
+//! banners and warnings are shown or only the machine-readable lines.
+//! This asks `isatty` itself rather than using `std::io::IsTerminal`, which
+//! compilers older than 1.70 do not have; elsewhere the answer is always
+//! "no", so output is as it would be into a pipe.
+//!
+
+/// One of the three standard streams
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stream {
+    /// Standard input
+    Stdin,
+    /// Standard output
+    Stdout,
+    /// Standard error
+    Stderr
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::os::raw::c_int;
+
+    use super::Stream;
+
+    extern "C" {
+        fn isatty(fd: c_int) -> c_int;
+    }
+
+    pub fn is_terminal(stream: Stream) -> bool {
+        let fd = match stream {
+            Stream::Stdin => 0,
+            Stream::Stdout => 1,
+            Stream::Stderr => 2
+        };
+        unsafe { isatty(fd) == 1 }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use super::Stream;
+
+    pub fn is_terminal(_: Stream) -> bool { false }
+}
+
+/// Whether a standard stream is a terminal
+pub fn is_terminal(stream: Stream) -> bool {
+    sys::is_terminal(stream)
+}
@@ -0,0 +1,195 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Watching
+//! Spotting transactions which pay to, or spend from, a set of watched
+//! scriptPubKeys. Spends are recognized by the outpoints paid to watched
+//! scripts, so only coins received while watching, or listed as already
+//! held, are noticed being spent.
+//!
+
+use bitcoin::blockdata::script::Script;
+use serialize::hex::ToHex;
+use serialize::json::Json;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use psbt::Reader;
+use sweep::{self, UnsignedTx, Utxo};
+
+/// Length of a block header
+const HEADER_LEN: usize = 80;
+
+/// What happened to a watched scriptPubKey
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Kind {
+    /// An output paid it
+    Receive {
+        /// Index of the output
+        vout: u32,
+        /// Its value in satoshis
+        amount: u64
+    },
+    /// An input spent a coin it had received
+    Spend {
+        /// Index of the input
+        input: u32,
+        /// Txid of the coin spent
+        spent_txid: String,
+        /// Output index of the coin spent
+        spent_vout: u32
+    }
+}
+
+/// A transaction touching a watched scriptPubKey
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Event {
+    /// Txid of the transaction, as displayed
+    pub txid: String,
+    /// Whether this is the transaction being confirmed in a block rather
+    /// than first being seen
+    pub confirmed: bool,
+    /// Label of the watched scriptPubKey
+    pub label: String,
+    /// What happened
+    pub kind: Kind
+}
+
+impl Event {
+    /// The event as a JSON object, for machines and webhooks
+    pub fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("txid".to_owned(), Json::String(self.txid.clone()));
+        obj.insert("status".to_owned(), Json::String(if self.confirmed { "confirmed" } else { "seen" }.to_owned()));
+        obj.insert("label".to_owned(), Json::String(self.label.clone()));
+        match self.kind {
+            Kind::Receive { vout, amount } => {
+                obj.insert("event".to_owned(), Json::String("receive".to_owned()));
+                obj.insert("vout".to_owned(), Json::U64(vout as u64));
+                obj.insert("amount".to_owned(), Json::U64(amount));
+            }
+            Kind::Spend { input, ref spent_txid, spent_vout } => {
+                obj.insert("event".to_owned(), Json::String("spend".to_owned()));
+                obj.insert("input".to_owned(), Json::U64(input as u64));
+                obj.insert("spent".to_owned(), Json::String(format!("{}:{}", spent_txid, spent_vout)));
+            }
+        }
+        Json::Object(obj)
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = if self.confirmed { "Confirmed" } else { "Seen" };
+        match self.kind {
+            Kind::Receive { vout, amount } => {
+                write!(f, "{}: {}:{} pays {} satoshis to {}", status, self.txid, vout, amount, self.label)
+            }
+            Kind::Spend { input, ref spent_txid, spent_vout } => {
+                write!(f, "{}: input {} of {} spends {}:{} from {}", status, input, self.txid, spent_txid, spent_vout, self.label)
+            }
+        }
+    }
+}
+
+/// The scriptPubKeys being watched, and the coins they hold
+pub struct Watcher {
+    /// Label of each watched scriptPubKey
+    scripts: HashMap<Vec<u8>, String>,
+    /// Label of the script each known coin was paid to, by serialized outpoint
+    coins: HashMap<Vec<u8>, String>,
+    /// Txids already reported as seen
+    seen: HashSet<Vec<u8>>
+}
+
+impl Watcher {
+    /// Watch some labelled scriptPubKeys
+    pub fn new(scripts: Vec<(Script, String)>) -> Watcher {
+        Watcher {
+            scripts: scripts.into_iter().map(|(script, label)| (script[..].to_owned(), label)).collect(),
+            coins: HashMap::new(),
+            seen: HashSet::new()
+        }
+    }
+
+    /// Also watch a coin already held by a watched scriptPubKey
+    pub fn add_coin(&mut self, utxo: &Utxo, label: &str) {
+        self.coins.insert(utxo.outpoint(), label.to_owned());
+    }
+
+    /// Check a transaction, as from a `rawtx` notification
+    pub fn check_tx(&mut self, data: &[u8]) -> Result<Vec<Event>, sweep::Error> {
+        let (tx, txid, _) = try!(UnsignedTx::parse_prefix(data));
+        Ok(self.check(&tx, &txid[..], false))
+    }
+
+    /// Check every transaction of a block, as from a `rawblock` notification
+    pub fn check_block(&mut self, data: &[u8]) -> Result<Vec<Event>, sweep::Error> {
+        if data.len() < HEADER_LEN {
+            return Err(sweep::Error::BadTransaction);
+        }
+        let mut reader = Reader::new(&data[HEADER_LEN..]);
+        let n_txs = try!(reader.read_compact().map_err(|_| sweep::Error::BadTransaction));
+        let mut pos = HEADER_LEN + reader.position();
+        let mut ret = vec![];
+        for _ in 0..n_txs {
+            let (tx, txid, len) = try!(UnsignedTx::parse_prefix(&data[pos..]));
+            ret.extend(self.check(&tx, &txid[..], true));
+            pos += len;
+        }
+        Ok(ret)
+    }
+
+    /// Find the events of a transaction, noting the coins it pays to
+    /// watched scripts
+    fn check(&mut self, tx: &UnsignedTx, txid: &[u8], confirmed: bool) -> Vec<Event> {
+        let display_txid: Vec<u8> = txid.iter().rev().cloned().collect();
+        let display_txid = display_txid.to_hex();
+        let mut ret = vec![];
+        for (n, input) in tx.inputs.iter().enumerate() {
+            if let Some(label) = self.coins.get(&input.outpoint) {
+                let spent_txid: Vec<u8> = input.outpoint[..32].iter().rev().cloned().collect();
+                ret.push(Event {
+                    txid: display_txid.clone(),
+                    confirmed: confirmed,
+                    label: label.clone(),
+                    kind: Kind::Spend {
+                        input: n as u32,
+                        spent_txid: spent_txid.to_hex(),
+                        spent_vout: input.outpoint[32..].iter().rev().fold(0, |acc, &b| (acc << 8) | b as u32)
+                    }
+                });
+            }
+        }
+        for (n, &(amount, ref script_pubkey)) in tx.outputs.iter().enumerate() {
+            if let Some(label) = self.scripts.get(&script_pubkey[..]) {
+                let mut outpoint = txid.to_owned();
+                outpoint.extend((0..4).map(|i| (n >> (8 * i)) as u8));
+                self.coins.insert(outpoint, label.clone());
+                ret.push(Event {
+                    txid: display_txid.clone(),
+                    confirmed: confirmed,
+                    label: label.clone(),
+                    kind: Kind::Receive { vout: n as u32, amount: amount }
+                });
+            }
+        }
+        // Mempool transactions are announced again when they are mined
+        if !confirmed && !self.seen.insert(txid.to_owned()) {
+            return vec![];
+        }
+        ret
+    }
+}
@@ -0,0 +1,197 @@
+// PactHash
+// Written in 2015 by
+//   Andrew Poelstra <apoelstra@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # ZeroMQ
+//! Just enough of ZMTP 3.0 to subscribe to bitcoind's notifications: a SUB
+//! socket over TCP with the NULL security mechanism, which is all bitcoind
+//! offers. bitcoind sends each notification as a topic, a body and a
+//! little-endian sequence number.
+//!
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+/// Frame flag: more frames of the message follow
+const FLAG_MORE: u8 = 0x01;
+/// Frame flag: the size is eight bytes rather than one
+const FLAG_LONG: u8 = 0x02;
+/// Frame flag: the frame is a command rather than part of a message
+const FLAG_COMMAND: u8 = 0x04;
+/// Largest frame body read: a serialized block, the largest thing bitcoind
+/// publishes, is at most 4 MB
+const MAX_FRAME_LEN: u64 = 4000000;
+/// Most frames read as one message; bitcoind's have three
+const MAX_FRAMES: usize = 16;
+
+/// ZeroMQ error
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// Endpoint was not of the form `tcp://host:port`
+    BadEndpoint(String),
+    /// Connection failed or was closed
+    Io(String),
+    /// Peer did not speak ZMTP 3 with the NULL mechanism
+    BadHandshake,
+    /// Peer sent a frame larger than any notification (its size)
+    FrameTooLarge(u64),
+    /// Peer sent a message of more frames than any notification
+    TooManyFrames
+}
+
+/// A subscription to a publisher
+pub struct Subscriber {
+    stream: TcpStream
+}
+
+/// Map an IO error into a ZeroMQ one
+fn io_error(e: ::std::io::Error) -> Error {
+    Error::Io(e.to_string())
+}
+
+/// A single short frame
+fn frame(flags: u8, body: &[u8]) -> Vec<u8> {
+    let mut ret = vec![flags, body.len() as u8];
+    ret.extend(body.iter().cloned());
+    ret
+}
+
+impl Subscriber {
+    /// Connect to a `tcp://host:port` endpoint and subscribe to some topics
+    pub fn connect(endpoint: &str, topics: &[&str]) -> Result<Subscriber, Error> {
+        if !endpoint.starts_with("tcp://") || !endpoint[6..].contains(':') {
+            return Err(Error::BadEndpoint(endpoint.to_owned()));
+        }
+        let stream = try!(TcpStream::connect(&endpoint[6..]).map_err(io_error));
+        let mut ret = Subscriber { stream: stream };
+
+        // Greeting: signature, version 3.0, mechanism NULL, not a server
+        let mut greeting = vec![0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0x7f, 3, 0];
+        greeting.extend(b"NULL".iter().cloned());
+        greeting.extend([0; 16 + 1 + 31].iter().cloned());
+        try!(ret.stream.write_all(&greeting).map_err(io_error));
+        let mut peer = [0; 64];
+        try!(ret.stream.read_exact(&mut peer).map_err(io_error));
+        if peer[0] != 0xff || peer[9] != 0x7f || peer[10] < 3 || &peer[12..17] != b"NULL\0" {
+            return Err(Error::BadHandshake);
+        }
+
+        // The NULL handshake is each side sending READY with its socket type
+        let mut ready = b"\x05READY\x0bSocket-Type".to_vec();
+        ready.extend([0, 0, 0, 3].iter().cloned());
+        ready.extend(b"SUB".iter().cloned());
+        try!(ret.stream.write_all(&frame(FLAG_COMMAND, &ready)).map_err(io_error));
+        let (flags, command) = try!(ret.read_frame());
+        if flags & FLAG_COMMAND == 0 || !command.starts_with(b"\x05READY") {
+            return Err(Error::BadHandshake);
+        }
+
+        // In ZMTP 3.0 a subscription is a message of 1 then the topic
+        for topic in topics {
+            let mut subscribe = vec![1];
+            subscribe.extend(topic.bytes());
+            try!(ret.stream.write_all(&frame(0, &subscribe)).map_err(io_error));
+        }
+        Ok(ret)
+    }
+
+    /// Read one frame, giving its flags and body
+    fn read_frame(&mut self) -> Result<(u8, Vec<u8>), Error> {
+        let mut flags = [0];
+        try!(self.stream.read_exact(&mut flags).map_err(io_error));
+        let len = if flags[0] & FLAG_LONG != 0 {
+            let mut len = [0; 8];
+            try!(self.stream.read_exact(&mut len).map_err(io_error));
+            len.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64)
+        } else {
+            let mut len = [0];
+            try!(self.stream.read_exact(&mut len).map_err(io_error));
+            len[0] as u64
+        };
+        // The size is the peer's to choose, so is checked before allocating
+        if len > MAX_FRAME_LEN {
+            return Err(Error::FrameTooLarge(len));
+        }
+        let mut body = vec![0; len as usize];
+        try!(self.stream.read_exact(&mut body).map_err(io_error));
+        Ok((flags[0], body))
+    }
+
+    /// Wait for the next message, giving its frames
+    pub fn recv(&mut self) -> Result<Vec<Vec<u8>>, Error> {
+        let mut ret = vec![];
+        loop {
+            let (flags, body) = try!(self.read_frame());
+            // Commands (e.g. heartbeats) between messages are ignored
+            if flags & FLAG_COMMAND != 0 {
+                continue;
+            }
+            ret.push(body);
+            if flags & FLAG_MORE == 0 {
+                return Ok(ret);
+            }
+            if ret.len() == MAX_FRAMES {
+                return Err(Error::TooManyFrames);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    /// A subscriber reading what a peer sends, without the handshake
+    fn subscriber(sent: &[u8]) -> Subscriber {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        listener.accept().unwrap().0.write_all(sent).unwrap();
+        Subscriber { stream: stream }
+    }
+
+    #[test]
+    fn frames() {
+        // A notification of a topic, a body and a sequence number
+        let mut sent = frame(FLAG_MORE, b"hashblock");
+        sent.extend(frame(FLAG_COMMAND, b"\x04PING"));
+        sent.extend(vec![FLAG_MORE | FLAG_LONG, 0, 0, 0, 0, 0, 0, 0, 32]);
+        sent.extend(vec![0xab; 32]);
+        sent.extend(frame(0, &[1, 0, 0, 0]));
+        let mut sub = subscriber(&sent);
+        assert_eq!(sub.recv().unwrap(), vec![b"hashblock".to_vec(), vec![0xab; 32], vec![1, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn oversized_frames() {
+        // A frame larger than a block is refused before its body is read
+        let mut sub = subscriber(&[FLAG_LONG, 0, 0, 0, 0, 0, 0x3d, 0x09, 0x01]);
+        match sub.recv() {
+            Err(Error::FrameTooLarge(4000001)) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+        let mut sub = subscriber(&[FLAG_LONG, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        match sub.recv() {
+            Err(Error::FrameTooLarge(0xffffffffffffffff)) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+        // As are messages of endless frames
+        let sent: Vec<u8> = (0..MAX_FRAMES + 1).flat_map(|_| frame(FLAG_MORE, b"x")).collect();
+        match subscriber(&sent).recv() {
+            Err(Error::TooManyFrames) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+}
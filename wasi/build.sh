@@ -0,0 +1,66 @@
+#!/bin/sh
+# PactHash
+# Written in 2015 by
+#   Andrew Poelstra <apoelstra@wpsoftware.net>
+#
+# To the extent possible under law, the author(s) have dedicated all
+# copyright and related and neighboring rights to this software to
+# the public domain worldwide. This software is distributed without
+# any warranty.
+#
+# You should have received a copy of the CC0 Public Domain Dedication
+# along with this software.
+# If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+#
+
+# Build the CLI for wasm32-wasip1 (the target once called wasm32-wasi),
+# leaving target/wasm32-wasip1/release/pacthash.wasm.
+#
+# Four dependencies do not build for WASI as released, so they are
+# unpacked into target/wasi-vendor with the patches beside this script
+# applied, and used in place of the released ones:
+#
+#   bitcoin          a literal too large for a 32-bit usize, and macros
+#                    which newer compilers (as any with this target are)
+#                    refuse
+#   rustc-serialize  Path is only encodable on Unix and Windows
+#   secp256k1        libc 0.1 knows nothing of WASI; 0.2 does
+#   num_cpus         has no answer for WASI; it is now 1
+#
+# libsecp256k1 and rust-crypto's helpers are C, so a C compiler for the
+# target is needed too: WASI_SDK_PATH names a wasi-sdk, or CC_wasm32_wasip1
+# and AR_wasm32_wasip1 can be set directly. The binary runs under any
+# WASI runtime, e.g. `wasmtime --dir . pacthash.wasm -g ...`; there are no
+# sockets, threads or subprocesses, so the commands which need them fail.
+
+set -e
+
+TARGET=wasm32-wasip1
+ROOT=$(cd "$(dirname "$0")/.." && pwd)
+VENDOR=$ROOT/target/wasi-vendor
+CACHE=${CARGO_HOME:-$HOME/.cargo}/registry/cache
+
+if [ -n "$WASI_SDK_PATH" ]; then
+    : "${CC_wasm32_wasip1:=$WASI_SDK_PATH/bin/clang}"
+    : "${AR_wasm32_wasip1:=$WASI_SDK_PATH/bin/llvm-ar}"
+    : "${CFLAGS_wasm32_wasip1:=--sysroot=$WASI_SDK_PATH/share/wasi-sysroot}"
+fi
+if [ -z "$CC_wasm32_wasip1" ]; then
+    echo "Set WASI_SDK_PATH, or CC_wasm32_wasip1, to a C compiler for $TARGET." >&2
+    exit 1
+fi
+export CC_wasm32_wasip1 AR_wasm32_wasip1 CFLAGS_wasm32_wasip1
+
+cd "$ROOT"
+cargo fetch --target $TARGET
+mkdir -p "$VENDOR"
+set --
+for crate in bitcoin-0.4.5 rustc-serialize-0.3.25 secp256k1-0.5.6 num_cpus-0.2.13; do
+    name=${crate%-*}
+    if [ ! -d "$VENDOR/$crate" ]; then
+        tar xzf "$(ls "$CACHE"/*/"$crate.crate" | head -n 1)" -C "$VENDOR"
+        patch -d "$VENDOR/$crate" -p1 < "$ROOT/wasi/$crate.patch"
+    fi
+    set -- "$@" --config "patch.crates-io.$name.path=\"$VENDOR/$crate\""
+done
+cargo build --release --target $TARGET --bin pacthash "$@"